@@ -1,4 +1,6 @@
 use std::path::Path;
+#[cfg(feature = "jwt")]
+use std::str::FromStr;
 #[allow(unused_imports)]
 use std::sync::Arc;
 use std::{env, fs};
@@ -8,10 +10,18 @@ use crate::app_state::AppMailerConfig;
 use crate::app_state::{AppHelpers, AppServices, MedullahState};
 #[cfg(feature = "database")]
 use crate::database::DBPool;
+use crate::helpers::ip::IpRanges;
+#[cfg(feature = "jwt")]
+use crate::helpers::jwks::RemoteJwks;
 #[cfg(feature = "jwt")]
-use crate::helpers::jwt::Jwt;
+use crate::helpers::jwt::{Algorithm, Jwt, JwtKey};
+#[cfg(feature = "jwt")]
+use crate::helpers::keyring::JwtKeyring;
 #[cfg(feature = "crypto")]
 use crate::helpers::password::Password;
+#[cfg(feature = "jwt")]
+use crate::http::extractors::auth::AuthConfig;
+use crate::http::response::problem::{ProblemDetails, ProblemMode};
 use crate::http::Method;
 #[cfg(feature = "rabbitmq")]
 use crate::prelude::RabbitMQ;
@@ -21,9 +31,18 @@ use crate::prelude::Redis;
 use crate::rabbitmq::conn::establish_rabbit_connection_pool;
 #[cfg(feature = "redis")]
 use crate::redis::conn::establish_redis_connection_pool;
+use crate::reporting::make_error_reporter;
 #[cfg(feature = "redis")]
 use crate::services::cache_service::CacheService;
+#[cfg(all(feature = "pat", feature = "database"))]
+use crate::services::pat_service::DatabasePatStore;
+#[cfg(all(feature = "pat", feature = "redis"))]
+use crate::services::pat_service::RedisPatStore;
+#[cfg(feature = "pat")]
+use crate::services::pat_service::{PatService, PatStore};
 use crate::MEDULLAH;
+#[cfg(feature = "jwt")]
+use base64::{engine::general_purpose::STANDARD, Engine};
 #[cfg(feature = "database")]
 use diesel::r2d2::ConnectionManager;
 #[cfg(feature = "database")]
@@ -51,6 +70,8 @@ pub async fn make_app_state(setup: MedullahSetup) -> MedullahState {
 
 async fn create_app_state(setup: MedullahSetup) -> MedullahState {
     let helpers = make_helpers(&setup.env_prefix, &setup);
+    configure_problem_details(&setup.env_prefix);
+    crate::i18n::configure(&setup.env_prefix);
     let env_prefix = setup.env_prefix;
 
     #[cfg(feature = "database")]
@@ -61,6 +82,15 @@ async fn create_app_state(setup: MedullahSetup) -> MedullahState {
     #[cfg(feature = "redis")]
     let redis = Arc::new(Redis::new(redis_pool.clone()));
 
+    #[cfg(feature = "pat")]
+    let pat = make_pat_service(
+        &env_prefix,
+        #[cfg(feature = "redis")]
+        redis.clone(),
+        #[cfg(feature = "database")]
+        database_pool.clone(),
+    );
+
     // RabbitMQ
     #[cfg(feature = "rabbitmq")]
     let rabbitmq_pool = establish_rabbit_connection_pool(&env_prefix).await;
@@ -70,11 +100,21 @@ async fn create_app_state(setup: MedullahSetup) -> MedullahState {
         RabbitMQ::new(rabbitmq_pool.clone()).await.unwrap(),
     ));
 
+    let errors = make_error_reporter(
+        &env_prefix,
+        #[cfg(feature = "redis")]
+        redis.clone(),
+        #[cfg(feature = "rabbitmq")]
+        rabbitmq.clone(),
+    );
+
     // templating
     #[cfg(feature = "templating")]
     let tera_templating = {
         let tpl_dir = crate::helpers::fs::get_cwd() + "/resources/templates/**/*.tera.html";
-        Tera::new(tpl_dir.as_str()).unwrap()
+        let mut tera = Tera::new(tpl_dir.as_str()).unwrap();
+        crate::i18n::register_tera_function(&mut tera);
+        tera
     };
 
     MedullahState {
@@ -107,6 +147,8 @@ async fn create_app_state(setup: MedullahSetup) -> MedullahState {
         #[cfg(feature = "jwt")]
         auth_iss_public_key: setup.auth_iss_public_key,
         #[cfg(feature = "jwt")]
+        auth: AuthConfig::from_env(&env_prefix),
+        #[cfg(feature = "jwt")]
         auth_pat_prefix: env::var(format!("{}_AUTH_PAT_PREFIX", env_prefix)).unwrap(),
         #[cfg(feature = "jwt")]
         auth_token_lifetime: env::var(format!("{}_AUTH_TOKEN_LIFETIME", env_prefix))
@@ -116,13 +158,19 @@ async fn create_app_state(setup: MedullahSetup) -> MedullahState {
 
         allowed_origins: setup.allowed_origins,
         allowed_methods: setup.allowed_methods,
+        trusted_proxies: make_trusted_proxies(&env_prefix),
 
         #[cfg(feature = "mailer")]
         mailer_config: make_mailer_config(&env_prefix),
 
         services: AppServices {
+            #[cfg(feature = "pat")]
+            pat,
+            #[cfg(feature = "multipart")]
+            storage: crate::storage::make_storage(&env_prefix),
             #[cfg(feature = "redis")]
             cache: Arc::new(CacheService::new(redis)),
+            errors,
         },
 
         app_env_prefix: env_prefix,
@@ -147,24 +195,108 @@ fn make_helpers(env_prefix: &str, setup: &MedullahSetup) -> AppHelpers {
     #[cfg(feature = "crypto")]
     let app_key = env::var(format!("{}_APP_KEY", env_prefix)).unwrap();
 
-    #[cfg(feature = "jwt")]
-    let token_lifetime: i64 = env::var(format!("{}_AUTH_TOKEN_LIFETIME", env_prefix))
-        .unwrap()
-        .parse()
-        .unwrap();
-
     AppHelpers {
         #[cfg(feature = "jwt")]
-        jwt: Arc::new(Jwt::new(
-            setup.auth_iss_public_key.clone(),
-            setup.private_key.clone(),
-            token_lifetime,
-        )),
+        jwt: Arc::new(make_jwt(env_prefix, setup)),
         #[cfg(feature = "crypto")]
         password: Arc::new(Password::new(app_key)),
     }
 }
 
+/// Signs tokens with the app keys and verifies them with the app and issuer public keys
+///
+/// Loaded from the environment:
+/// - `{PREFIX}_AUTH_TOKEN_LIFETIME`: token lifetime (in minutes)
+/// - `{PREFIX}_AUTH_JWT_ALGORITHM`: defaults to RS256
+/// - `{PREFIX}_AUTH_JWT_KEY_FORMAT`: `pem` (default) or `der`, DER keys being base64 encoded
+/// - `{PREFIX}_AUTH_JWT_KEY_ID`: `kid` of the app key, defaults to its thumbprint
+/// - `{PREFIX}_AUTH_JWT_SECRET`: shared secret signing the tokens with HS256, HS384 or HS512
+/// - `{PREFIX}_AUTH_JWT_KEYRING`: keyring file replacing the app key, see [JwtKeyring]
+/// - `{PREFIX}_AUTH_ISSUER_ALGORITHM`: algorithm of the issuer public key, defaults to the
+///   app algorithm or RS256 when the app uses a shared secret
+/// - `{PREFIX}_AUTH_JWKS`: comma separated JWKS urls or files of trusted issuers
+#[cfg(feature = "jwt")]
+fn make_jwt(env_prefix: &str, setup: &MedullahSetup) -> Jwt {
+    let var = |name: &str| {
+        env::var(format!("{}_AUTH_{}", env_prefix, name))
+            .ok()
+            .filter(|v| !v.trim().is_empty())
+    };
+
+    let algorithm = |name: &str| {
+        var(name).map(|alg| Algorithm::from_str(alg.trim()).expect("invalid jwt algorithm"))
+    };
+
+    let token_lifetime: i64 = var("TOKEN_LIFETIME").unwrap().parse().unwrap();
+    let app_algorithm = algorithm("JWT_ALGORITHM").unwrap_or(Algorithm::RS256);
+    let is_der = var("JWT_KEY_FORMAT").is_some_and(|format| format.eq_ignore_ascii_case("der"));
+
+    let make_key = |algorithm, private_key: &str, public_key: &str| {
+        let private_key = Some(private_key.trim()).filter(|key| !key.is_empty());
+        let public_key = Some(public_key.trim()).filter(|key| !key.is_empty());
+
+        match is_der {
+            false => JwtKey::pem(algorithm, private_key, public_key),
+            true => {
+                let decode = |key: &str| STANDARD.decode(key).expect("invalid base64 DER key");
+                JwtKey::der(
+                    algorithm,
+                    private_key.map(decode).as_deref(),
+                    public_key.map(decode).as_deref(),
+                )
+            }
+        }
+    };
+
+    let mut jwt = match var("JWT_KEYRING") {
+        Some(path) => {
+            let keyring = JwtKeyring::load(&path).expect("failed to load jwt keyring");
+            Jwt::with_keyring(keyring, token_lifetime)
+        }
+        None => {
+            let signing_key = match var("JWT_SECRET") {
+                Some(secret) => JwtKey::secret(app_algorithm, secret.as_bytes()),
+                None => make_key(app_algorithm, &setup.private_key, &setup.public_key),
+            };
+
+            let signing_key = match var("JWT_KEY_ID") {
+                Some(kid) => signing_key.kid(&kid),
+                None => signing_key,
+            };
+
+            Jwt::with_key(signing_key, token_lifetime)
+        }
+    };
+
+    // only the issuer's tokens are accepted unless the app verifies the ones it signs too
+    let verify_own_tokens = var("JWT_VERIFY_OWN_TOKENS")
+        .is_some_and(|verify| verify.trim().eq_ignore_ascii_case("true"));
+    jwt = jwt.verify_own_tokens(verify_own_tokens);
+
+    if !setup.auth_iss_public_key.trim().is_empty() {
+        let issuer_algorithm = algorithm("ISSUER_ALGORITHM").unwrap_or(match app_algorithm {
+            Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => Algorithm::RS256,
+            algorithm => algorithm,
+        });
+
+        jwt = jwt.verify_with(make_key(issuer_algorithm, "", &setup.auth_iss_public_key));
+    }
+
+    for source in var("JWKS").unwrap_or_default().split(',').map(str::trim) {
+        #[cfg(feature = "reqwest")]
+        if source.starts_with("http://") || source.starts_with("https://") {
+            jwt = jwt.trust(RemoteJwks::url(source));
+            continue;
+        }
+
+        if !source.is_empty() {
+            jwt = jwt.trust(RemoteJwks::file(source));
+        }
+    }
+
+    jwt
+}
+
 #[cfg(feature = "mailer")]
 fn make_mailer_config(env_prefix: &str) -> AppMailerConfig {
     AppMailerConfig {
@@ -177,6 +309,60 @@ fn make_mailer_config(env_prefix: &str) -> AppMailerConfig {
     }
 }
 
+/// Personal access tokens are kept in redis or the database, per `{PREFIX}_AUTH_PAT_STORE`,
+/// `none` (the default when neither is enabled) disables them
+#[cfg(feature = "pat")]
+fn make_pat_service(
+    env_prefix: &str,
+    #[cfg(feature = "redis")] redis: Arc<Redis>,
+    #[cfg(feature = "database")] database: DBPool,
+) -> Option<Arc<PatService>> {
+    let default_store = match (cfg!(feature = "redis"), cfg!(feature = "database")) {
+        (true, _) => "redis",
+        (false, true) => "database",
+        (false, false) => "none",
+    };
+
+    let store = env::var(format!("{}_AUTH_PAT_STORE", env_prefix))
+        .unwrap_or_else(|_| default_store.to_string());
+
+    let store: Option<Arc<dyn PatStore>> = match store.as_str() {
+        #[cfg(feature = "redis")]
+        "redis" => Some(Arc::new(RedisPatStore::new(redis, "pat"))),
+        #[cfg(feature = "database")]
+        "database" => Some(Arc::new(DatabasePatStore::new(database))),
+        "none" => None,
+        store => panic!("unsupported personal access token store '{}'", store),
+    };
+
+    store.map(|store| {
+        Arc::new(PatService::new(
+            &env::var(format!("{}_AUTH_PAT_PREFIX", env_prefix)).unwrap(),
+            &env::var(format!("{}_APP_KEY", env_prefix)).unwrap(),
+            store,
+        ))
+    })
+}
+
+/// `{PREFIX}_PROBLEM_DETAILS` (`never`, `negotiate` or `always`) and `{PREFIX}_PROBLEM_TYPE_BASE`
+fn configure_problem_details(env_prefix: &str) {
+    if let Ok(mode) = env::var(format!("{}_PROBLEM_DETAILS", env_prefix)) {
+        ProblemDetails::set_mode(ProblemMode::parse(&mode).expect("invalid problem details mode"));
+    }
+
+    if let Ok(base) = env::var(format!("{}_PROBLEM_TYPE_BASE", env_prefix)) {
+        ProblemDetails::set_type_base(&base);
+    }
+}
+
+/// Comma-separated CIDR blocks/addresses of trusted proxies, nothing is trusted when not set
+fn make_trusted_proxies(env_prefix: &str) -> IpRanges {
+    match env::var(format!("{}_TRUSTED_PROXIES", env_prefix)) {
+        Ok(list) => IpRanges::parse(&list).expect("invalid trusted proxies list"),
+        Err(_) => IpRanges::default(),
+    }
+}
+
 #[cfg(feature = "database")]
 pub fn establish_database_connection(env_prefix: &String) -> DBPool {
     let db_url: String = env::var(format!("{}_DATABASE_DSN", env_prefix)).unwrap();
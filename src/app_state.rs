@@ -1,11 +1,13 @@
 use std::fmt::{Debug, Formatter};
-#[allow(unused_imports)]
 use std::sync::Arc;
 
+use crate::helpers::ip::IpRanges;
 #[cfg(feature = "jwt")]
 use crate::helpers::jwt::Jwt;
 #[cfg(feature = "crypto")]
 use crate::helpers::password::Password;
+#[cfg(feature = "jwt")]
+use crate::http::extractors::auth::AuthConfig;
 use crate::http::Method;
 #[cfg(feature = "rabbitmq")]
 use crate::rabbitmq::RabbitMQ;
@@ -55,12 +57,19 @@ pub struct MedullahState {
     #[cfg(feature = "jwt")]
     pub auth_iss_public_key: String,
 
+    /// how bearer tokens are found and validated
+    #[cfg(feature = "jwt")]
+    pub auth: AuthConfig,
+
     /// list of allowed origins
     pub allowed_origins: Vec<String>,
 
     /// list of allowed methods
     pub allowed_methods: Vec<Method>,
 
+    /// proxies whose forwarding headers (X-Forwarded-For, Forwarded, X-Real-IP) are honoured
+    pub trusted_proxies: IpRanges,
+
     #[cfg(feature = "mailer")]
     pub mailer_config: AppMailerConfig,
 
@@ -91,6 +100,12 @@ pub struct AppHelpers {
 pub struct AppServices {
     #[cfg(feature = "redis")]
     pub cache: Arc<CacheService>,
+    /// `None` when no personal access token store is configured
+    #[cfg(feature = "pat")]
+    pub pat: Option<Arc<crate::services::pat_service::PatService>>,
+    #[cfg(feature = "multipart")]
+    pub storage: Arc<dyn crate::storage::Storage>,
+    pub errors: Arc<crate::reporting::ErrorReporter>,
 }
 
 impl MedullahState {
@@ -0,0 +1,78 @@
+use std::fmt::Debug;
+
+use log::Level;
+use ntex::http::StatusCode;
+
+use crate::enums::ResponseCode;
+
+/// Application error types, rendered and logged like the built-in [AppMessage](crate::prelude::AppMessage) variants
+///
+/// Implementors convert into `AppMessage` (and therefore work with `?` in anything returning `AppResult`):
+///
+/// ```
+/// use medullah_web::prelude::{AppErrorContract, AppResult};
+/// use ntex::http::StatusCode;
+///
+/// #[derive(Debug)]
+/// enum PaymentError {
+///     Declined,
+///     Gateway(String),
+/// }
+///
+/// impl AppErrorContract for PaymentError {
+///     fn status(&self) -> StatusCode {
+///         match self {
+///             PaymentError::Declined => StatusCode::PAYMENT_REQUIRED,
+///             PaymentError::Gateway(_) => StatusCode::BAD_GATEWAY,
+///         }
+///     }
+///
+///     fn public_message(&self) -> Option<String> {
+///         match self {
+///             PaymentError::Declined => Some("your card was declined".to_string()),
+///             PaymentError::Gateway(_) => None,
+///         }
+///     }
+///
+///     fn details(&self) -> Option<String> {
+///         match self {
+///             PaymentError::Gateway(reason) => Some(reason.clone()),
+///             _ => None,
+///         }
+///     }
+/// }
+///
+/// fn charge(amount: u64) -> AppResult<()> {
+///     if amount > 100 {
+///         return Err(PaymentError::Declined.into());
+///     }
+///
+///     Ok(())
+/// }
+///
+/// assert!(charge(500).is_err());
+/// ```
+pub trait AppErrorContract: Debug + Send + Sync + 'static {
+    fn status(&self) -> StatusCode;
+
+    /// Response code sent in the json envelope, defaults to the one matching the status
+    fn code(&self) -> ResponseCode {
+        ResponseCode::from_status_or_class(self.status())
+    }
+
+    /// Message safe to show to clients, `None` sends the generic message of the status
+    fn public_message(&self) -> Option<String>;
+
+    /// Level the error is logged at when rendered, server errors are logged as errors
+    fn log_level(&self) -> Level {
+        match self.status().is_server_error() {
+            true => Level::Error,
+            false => Level::Info,
+        }
+    }
+
+    /// Internal details, only ever logged
+    fn details(&self) -> Option<String> {
+        None
+    }
+}
@@ -1,3 +1,5 @@
+mod app_error_contract;
 mod response_code_contract;
 
+pub use app_error_contract::AppErrorContract;
 pub use response_code_contract::ResponseCodeContract;
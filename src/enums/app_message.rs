@@ -1,14 +1,18 @@
-use crate::contracts::ResponseCodeContract;
+use crate::contracts::{AppErrorContract, ResponseCodeContract};
 use crate::enums::ResponseCode;
+use crate::helpers::request::RequestHelper;
 #[cfg(feature = "reqwest")]
 use crate::helpers::reqwest::ReqwestResponseError;
 use crate::helpers::responder::Responder;
+use crate::http::response::problem::{into_problem_response, ProblemDetails};
+use crate::reporting::{self, attach_error_id, ErrorReport};
+use crate::t;
 use log::error;
 #[cfg(feature = "multipart")]
 use medullah_multipart::{ErrorMessage as MultipartErrorMessage, MultipartError};
 use ntex::http::error::BlockingError;
 use ntex::http::StatusCode;
-use ntex::web::{HttpRequest, WebResponseError};
+use ntex::web::{ErrorRenderer, HttpRequest, WebResponseError};
 use std::fmt::{Debug, Display, Formatter};
 use std::io;
 use std::ops::Deref;
@@ -35,6 +39,8 @@ pub enum AppMessage {
     ForbiddenMessageString(String),
     #[cfg(feature = "validator")]
     FormValidationError(validator::ValidationErrors),
+    #[cfg(feature = "validator")]
+    FieldErrors(crate::helpers::validation::FieldErrors, StatusCode),
     EntityNotFound(String),
     #[cfg(feature = "reqwest")]
     ReqwestError(reqwest::Error),
@@ -76,6 +82,8 @@ pub enum AppMessage {
     R2d2Error(r2d2::Error),
     #[cfg(feature = "database")]
     DatabaseError(diesel::result::Error),
+    /// Error type of the application, see [AppErrorContract]
+    Custom(Box<dyn AppErrorContract>),
 }
 
 fn format_message(status: &AppMessage, f: &mut Formatter<'_>) -> std::fmt::Result {
@@ -86,14 +94,10 @@ fn get_message(status: &AppMessage) -> String {
     match status {
         AppMessage::Anyhow(err) => err.to_string(),
         AppMessage::UuidError(err) => err.to_string(),
-        AppMessage::UnAuthorized => {
-            String::from("You are not authorized to access requested resource(s)")
-        }
-        AppMessage::Forbidden => {
-            String::from("You don't have sufficient permissions to access requested resource(s)")
-        }
+        AppMessage::UnAuthorized => t!("errors.unauthorized"),
+        AppMessage::Forbidden => t!("errors.forbidden"),
         AppMessage::Redirect(url) => format!("Redirecting to '{}'...", url),
-        AppMessage::EntityNotFound(entity) => format!("Such {} does not exits", entity),
+        AppMessage::EntityNotFound(entity) => t!("errors.entity_not_found", entity = entity),
         #[cfg(feature = "database")]
         AppMessage::R2d2Error(error) => error.to_string(),
         #[cfg(feature = "rabbitmq")]
@@ -142,7 +146,7 @@ fn get_message(status: &AppMessage) -> String {
         | AppMessage::ErrorMessage(message, _) => message.to_string(),
         #[cfg(feature = "database")]
         AppMessage::DatabaseError(err) => match err {
-            diesel::result::Error::NotFound => String::from("Such entity not found"),
+            diesel::result::Error::NotFound => t!("errors.entity_missing"),
             diesel::result::Error::DatabaseError(err, info) => match err {
                 diesel::result::DatabaseErrorKind::UniqueViolation => {
                     error!(
@@ -152,7 +156,7 @@ fn get_message(status: &AppMessage) -> String {
                         info.details()
                     );
 
-                    "conflicted with existing entity".to_string()
+                    t!("errors.conflict")
                 }
                 _ => {
                     error!(
@@ -161,12 +165,12 @@ fn get_message(status: &AppMessage) -> String {
                         info.message(),
                         info.details()
                     );
-                    "something went wrong".to_string()
+                    t!("errors.something_went_wrong")
                 }
             },
             _ => {
                 error!("database error: {:?}", err);
-                String::from("Something went wrong")
+                t!("errors.something_went_wrong")
             }
         },
         #[cfg(feature = "hmac")]
@@ -175,7 +179,10 @@ fn get_message(status: &AppMessage) -> String {
         AppMessage::StrumParseError(message) => message.to_string(),
         #[cfg(feature = "validator")]
         AppMessage::FormValidationError(e) => String::from(e.to_string().as_str()),
-        _ => String::from("Internal Server Error"),
+        #[cfg(feature = "validator")]
+        AppMessage::FieldErrors(errors, _) => errors.to_string(),
+        AppMessage::Custom(err) => public_message(err.as_ref()),
+        _ => t!("errors.internal_server_error"),
     }
 }
 
@@ -192,10 +199,14 @@ pub fn get_middleware_level_message(app: &AppMessage) -> String {
         AppMessage::InternalServerErrorMessage(message) => message.to_string(),
         AppMessage::Anyhow(message) => message.to_string(),
         #[cfg(feature = "jwt")]
-        AppMessage::JwtError(_) => "failed to authenticate your jwt token".to_string(),
+        AppMessage::JwtError(_) => t!("errors.jwt_failed"),
+        AppMessage::Custom(err) if err.public_message().is_some() => {
+            log_custom_error(err.as_ref());
+            public_message(err.as_ref())
+        }
         _ => {
             error!("[middleware-level-error] {:?}", app);
-            String::from("Something isn't right, our engineers are on it")
+            t!("errors.engineers_on_it")
         }
     }
 }
@@ -226,7 +237,7 @@ fn send_response(message: &AppMessage) -> ntex::web::HttpResponse {
         #[cfg(feature = "jwt")]
         AppMessage::JwtError(message) => {
             log::error!("Jwt Error: {}", message);
-            Responder::message("invalid jwt token", ResponseCode::Unauthorized)
+            Responder::message(&t!("errors.invalid_jwt"), ResponseCode::Unauthorized)
         }
         #[cfg(feature = "crypto")]
         AppMessage::ArgonError(message) => {
@@ -318,7 +329,7 @@ fn send_response(message: &AppMessage) -> ntex::web::HttpResponse {
         AppMessage::SuccessMessage(message) => Responder::ok_message(message),
         AppMessage::SuccessMessageString(message) => Responder::ok_message(message),
         AppMessage::ErrorMessage(message, status) => {
-            Responder::message(message, ResponseCode::from_status(*status))
+            Responder::message(message, ResponseCode::from_status_or_class(*status))
         }
         AppMessage::UnAuthorized => {
             Responder::message(&message.message(), ResponseCode::Unauthorized)
@@ -342,9 +353,20 @@ fn send_response(message: &AppMessage) -> ntex::web::HttpResponse {
             Responder::message(&message, ResponseCode::BadRequest)
         }
         #[cfg(feature = "validator")]
-        AppMessage::FormValidationError(e) => {
-            Responder::send_msg(e, ResponseCode::BadRequest, "Validation Error")
-        }
+        AppMessage::FormValidationError(e) => Responder::send_msg(
+            crate::helpers::validation::FieldErrors::from(e),
+            ResponseCode::UnprocessableEntity,
+            &t!("errors.validation_failed"),
+        ),
+        #[cfg(feature = "validator")]
+        AppMessage::FieldErrors(errors, status) => Responder::send_msg(
+            errors,
+            ResponseCode::from_status_or_class(*status),
+            &match *status == StatusCode::UNPROCESSABLE_ENTITY {
+                true => t!("errors.validation_failed"),
+                false => t!("errors.invalid_payload"),
+            },
+        ),
         #[cfg(feature = "multipart")]
         AppMessage::MultipartError(e) => {
             Responder::send_msg(e.to_string(), ResponseCode::BadRequest, "File Upload Error")
@@ -352,7 +374,7 @@ fn send_response(message: &AppMessage) -> ntex::web::HttpResponse {
         #[cfg(feature = "database")]
         AppMessage::DatabaseError(err) => match err {
             diesel::result::Error::NotFound => {
-                Responder::not_found_message("Such entity not found")
+                Responder::not_found_message(&t!("errors.entity_missing"))
             }
             diesel::result::Error::DatabaseError(err, _) => {
                 error!("database error: {:?}", err);
@@ -373,10 +395,30 @@ fn send_response(message: &AppMessage) -> ntex::web::HttpResponse {
             error!("{:?}", err);
             Responder::internal_server_error()
         }
+        AppMessage::Custom(err) => {
+            log_custom_error(err.as_ref());
+            Responder::message(&public_message(err.as_ref()), err.code())
+        }
         _ => Responder::bad_req_message(get_message(message).as_str()),
     }
 }
 
+fn public_message(err: &dyn AppErrorContract) -> String {
+    err.public_message().unwrap_or_else(|| {
+        err.status()
+            .canonical_reason()
+            .unwrap_or("Internal Server Error")
+            .to_string()
+    })
+}
+
+fn log_custom_error(err: &dyn AppErrorContract) {
+    match err.details() {
+        Some(details) => log::log!(err.log_level(), "[app-error] {:?}: {}", err, details),
+        None => log::log!(err.log_level(), "[app-error] {:?}", err),
+    }
+}
+
 fn get_status_code(status: &AppMessage) -> StatusCode {
     #[cfg(feature = "database")]
     use diesel::result::Error as DieselError;
@@ -410,7 +452,9 @@ fn get_status_code(status: &AppMessage) -> StatusCode {
         #[cfg(feature = "jwt")]
         AppMessage::JwtError(_) => StatusCode::UNAUTHORIZED,
         #[cfg(feature = "validator")]
-        AppMessage::FormValidationError(_) => StatusCode::BAD_REQUEST,
+        AppMessage::FormValidationError(_) => StatusCode::UNPROCESSABLE_ENTITY,
+        #[cfg(feature = "validator")]
+        AppMessage::FieldErrors(_, status) => *status,
         AppMessage::ErrorMessage(_, status) => *status,
         AppMessage::UnAuthorized
         | AppMessage::UnAuthorizedMessage(_)
@@ -418,6 +462,7 @@ fn get_status_code(status: &AppMessage) -> StatusCode {
         AppMessage::Forbidden
         | AppMessage::ForbiddenMessage(_)
         | AppMessage::ForbiddenMessageString(_) => StatusCode::FORBIDDEN,
+        AppMessage::Custom(err) => err.status(),
         _ => StatusCode::INTERNAL_SERVER_ERROR, // all database-related errors are 500
     }
 }
@@ -441,6 +486,12 @@ impl Display for AppMessage {
     }
 }
 
+impl<E: AppErrorContract> From<E> for AppMessage {
+    fn from(value: E) -> Self {
+        AppMessage::Custom(Box::new(value))
+    }
+}
+
 impl From<anyhow::Error> for AppMessage {
     fn from(value: anyhow::Error) -> Self {
         AppMessage::Anyhow(value)
@@ -630,17 +681,190 @@ impl AppMessage {
     pub fn message(&self) -> String {
         get_message(self)
     }
+
+    /// The error followed by its sources, used in error reports
+    pub fn error_chain(&self) -> Vec<String> {
+        let mut chain = vec![self.message()];
+        if let AppMessage::Custom(err) = self {
+            chain.extend(err.details());
+        }
+
+        let mut source = self.source_error();
+        while let Some(err) = source {
+            let message = err.to_string();
+            if chain.last() != Some(&message) {
+                chain.push(message);
+            }
+
+            source = err.source();
+        }
+
+        chain
+    }
+
+    fn response_code(&self) -> ResponseCode {
+        match self {
+            AppMessage::Custom(err) => err.code(),
+            _ => ResponseCode::from_status_or_class(self.status_code()),
+        }
+    }
+
+    fn source_error(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            AppMessage::Anyhow(err) => Some(err.as_ref()),
+            AppMessage::IoError(err) => Some(err),
+            AppMessage::UuidError(err) => Some(err),
+            AppMessage::SerdeError(err) | AppMessage::SerdeError500(err) => Some(err),
+            AppMessage::JoinError(err) => Some(err),
+            AppMessage::ChronoParseError(err) => Some(err),
+            AppMessage::FromUtf8Error(err) => Some(err),
+            AppMessage::StrUtf8Error(err) => Some(err),
+            AppMessage::PayloadError(err) => Some(err),
+            AppMessage::BlockingNtexIoError(err) => Some(err),
+            #[cfg(feature = "reqwest")]
+            AppMessage::ReqwestError(err) => Some(err),
+            #[cfg(feature = "redis")]
+            AppMessage::RedisError(err) => Some(err),
+            #[cfg(feature = "redis")]
+            AppMessage::RedisPoolError(err) => Some(err),
+            #[cfg(feature = "rabbitmq")]
+            AppMessage::RabbitmqError(err) => Some(err),
+            #[cfg(feature = "rabbitmq")]
+            AppMessage::RmqPoolError(err) => Some(err),
+            #[cfg(feature = "database")]
+            AppMessage::DatabaseError(err) => Some(err),
+            #[cfg(feature = "database")]
+            AppMessage::R2d2Error(err) => Some(err),
+            #[cfg(feature = "jwt")]
+            AppMessage::JwtError(err) => Some(err),
+            #[cfg(feature = "base64")]
+            AppMessage::Base64Error(err) => Some(err),
+            _ => None,
+        }
+    }
 }
 
-impl WebResponseError for AppMessage {
+impl<Err: ErrorRenderer> WebResponseError<Err> for AppMessage {
     fn status_code(&self) -> StatusCode {
         let code = self.status_code();
         log::info!("[error-code] {}", code);
         code
     }
 
-    fn error_response(&self, _: &HttpRequest) -> ntex::web::HttpResponse {
+    fn error_response(&self, req: &HttpRequest) -> ntex::web::HttpResponse {
         log::info!("[error-body] {}", self);
-        self.http_response()
+        let mut response = self.http_response();
+        if response.status().is_server_error() {
+            let report = ErrorReport::new(
+                response.status(),
+                self.response_code().code(),
+                &self.message(),
+                self.error_chain(),
+            )
+            .with_request(req);
+
+            response = attach_error_id(response, &report.id);
+            reporting::report(report);
+        }
+
+        match ProblemDetails::is_wanted(req.headers()) {
+            true => into_problem_response(response, req.request_id().as_deref()),
+            false => response,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ntex::http::body::{Body, ResponseBody};
+    use serde_json::Value;
+
+    use super::*;
+    use crate::results::AppResult;
+
+    #[derive(Debug)]
+    enum PaymentError {
+        Declined,
+        Gateway(String),
+    }
+
+    impl AppErrorContract for PaymentError {
+        fn status(&self) -> StatusCode {
+            match self {
+                PaymentError::Declined => StatusCode::PAYMENT_REQUIRED,
+                PaymentError::Gateway(_) => StatusCode::BAD_GATEWAY,
+            }
+        }
+
+        fn public_message(&self) -> Option<String> {
+            match self {
+                PaymentError::Declined => Some("your card was declined".to_string()),
+                PaymentError::Gateway(_) => None,
+            }
+        }
+
+        fn details(&self) -> Option<String> {
+            match self {
+                PaymentError::Gateway(reason) => Some(reason.clone()),
+                _ => None,
+            }
+        }
+    }
+
+    fn charge(declined: bool) -> AppResult<()> {
+        match declined {
+            true => Err(PaymentError::Declined)?,
+            false => Err(PaymentError::Gateway("connection reset".to_string()))?,
+        }
+    }
+
+    fn body(response: &ntex::web::HttpResponse) -> Value {
+        match response.body() {
+            ResponseBody::Body(Body::Bytes(bytes)) => serde_json::from_slice(bytes).unwrap(),
+            _ => panic!("expected a bytes body"),
+        }
+    }
+
+    #[test]
+    fn test_custom_error() {
+        let err = charge(true).unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::PAYMENT_REQUIRED);
+        assert_eq!(err.message(), "your card was declined");
+        assert_eq!(get_middleware_level_message(&err), "your card was declined");
+
+        let response = err.http_response();
+        assert_eq!(response.status(), StatusCode::PAYMENT_REQUIRED);
+        assert_eq!(
+            body(&response)["code"],
+            ResponseCode::PaymentRequired.code()
+        );
+        assert_eq!(body(&response)["message"], "your card was declined");
+    }
+
+    #[test]
+    fn test_error_chain() {
+        let err: AppMessage = anyhow::Error::new(io::Error::other("connection reset"))
+            .context("failed to charge card")
+            .into();
+
+        assert_eq!(
+            err.error_chain(),
+            vec!["failed to charge card", "connection reset"]
+        );
+        assert_eq!(
+            AppMessage::from(PaymentError::Gateway("timeout".to_string())).error_chain(),
+            vec!["Bad Gateway", "timeout"]
+        );
+    }
+
+    #[test]
+    fn test_custom_error_details_are_not_leaked() {
+        let err = charge(false).unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::BAD_GATEWAY);
+        assert_eq!(err.message(), "Bad Gateway");
+        assert_ne!(get_middleware_level_message(&err), "connection reset");
+
+        let response = err.http_response();
+        assert_eq!(body(&response)["message"], "Bad Gateway");
     }
 }
@@ -1,7 +1,16 @@
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+
 use crate::contracts::ResponseCodeContract;
+use crate::enums::app_message::AppMessage;
+use crate::results::AppResult;
+use log::warn;
 use ntex::http::StatusCode;
 
-#[derive(Clone)]
+/// Domain codes registered by the application, see [ResponseCode::register]
+static CUSTOM_CODES: RwLock<BTreeMap<String, StatusCode>> = RwLock::new(BTreeMap::new());
+
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum ResponseCode {
     Ok,
     Created,
@@ -16,6 +25,24 @@ pub enum ResponseCode {
     InternalServerError,
     ServiceUnavailable,
     NotImplemented,
+    UnprocessableEntity,
+    MethodNotAllowed,
+    PayloadTooLarge,
+    UnsupportedMediaType,
+    NotAcceptable,
+    RequestTimeout,
+    Gone,
+    PreconditionFailed,
+    Locked,
+    TooManyRequests,
+    BadGateway,
+    GatewayTimeout,
+    /// 409, the entity being created already exists
+    AlreadyExists,
+    /// 409, the entity was modified since it was read
+    VersionConflict,
+    /// Application defined code, see [ResponseCode::register]
+    Custom(String, StatusCode),
 }
 
 impl ResponseCodeContract for ResponseCode {
@@ -34,6 +61,21 @@ impl ResponseCodeContract for ResponseCode {
             ResponseCode::InternalServerError => "010",
             ResponseCode::ServiceUnavailable => "011",
             ResponseCode::NotImplemented => "012",
+            ResponseCode::UnprocessableEntity => "013",
+            ResponseCode::MethodNotAllowed => "014",
+            ResponseCode::PayloadTooLarge => "015",
+            ResponseCode::UnsupportedMediaType => "016",
+            ResponseCode::NotAcceptable => "017",
+            ResponseCode::RequestTimeout => "018",
+            ResponseCode::Gone => "019",
+            ResponseCode::PreconditionFailed => "020",
+            ResponseCode::Locked => "021",
+            ResponseCode::TooManyRequests => "022",
+            ResponseCode::BadGateway => "023",
+            ResponseCode::GatewayTimeout => "024",
+            ResponseCode::AlreadyExists => "025",
+            ResponseCode::VersionConflict => "026",
+            ResponseCode::Custom(code, _) => code,
         }
     }
 
@@ -52,11 +94,75 @@ impl ResponseCodeContract for ResponseCode {
             ResponseCode::InternalServerError => StatusCode::INTERNAL_SERVER_ERROR,
             ResponseCode::ServiceUnavailable => StatusCode::SERVICE_UNAVAILABLE,
             ResponseCode::NotImplemented => StatusCode::NOT_IMPLEMENTED,
+            ResponseCode::UnprocessableEntity => StatusCode::UNPROCESSABLE_ENTITY,
+            ResponseCode::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            ResponseCode::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ResponseCode::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ResponseCode::NotAcceptable => StatusCode::NOT_ACCEPTABLE,
+            ResponseCode::RequestTimeout => StatusCode::REQUEST_TIMEOUT,
+            ResponseCode::Gone => StatusCode::GONE,
+            ResponseCode::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
+            ResponseCode::Locked => StatusCode::LOCKED,
+            ResponseCode::TooManyRequests => StatusCode::TOO_MANY_REQUESTS,
+            ResponseCode::BadGateway => StatusCode::BAD_GATEWAY,
+            ResponseCode::GatewayTimeout => StatusCode::GATEWAY_TIMEOUT,
+            ResponseCode::AlreadyExists => StatusCode::CONFLICT,
+            ResponseCode::VersionConflict => StatusCode::CONFLICT,
+            ResponseCode::Custom(_, status) => *status,
         }
     }
 
+    /// Unknown codes are logged and treated as [ResponseCode::InternalServerError],
+    /// use [ResponseCode::try_from_code] to handle them yourself
     fn from_code(code: &str) -> Self {
-        match code {
+        Self::try_from_code(code).unwrap_or_else(|| {
+            warn!("[response-code] unknown response code '{}'", code);
+            ResponseCode::InternalServerError
+        })
+    }
+
+    /// Statuses without a dedicated code fall back to the generic code of their class
+    fn from_status(status: StatusCode) -> Self {
+        Self::from_status_or_class(status)
+    }
+}
+
+impl ResponseCode {
+    /// Registers an application defined code, e.g. `"PAY-001"` answered with a 402,
+    /// which can then be resolved by [ResponseCodeContract::from_code]
+    ///
+    /// Codes of the built-in variants can't be overridden.
+    pub fn register(code: &str, status: StatusCode) -> AppResult<Self> {
+        if Self::try_from_builtin_code(code).is_some() {
+            return Err(AppMessage::ErrorMessage(
+                format!("response code '{}' is reserved", code),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            ));
+        }
+
+        CUSTOM_CODES
+            .write()
+            .unwrap()
+            .insert(code.to_string(), status);
+
+        Ok(ResponseCode::Custom(code.to_string(), status))
+    }
+
+    /// Previously registered code, `None` if it was never registered
+    pub fn custom(code: &str) -> Option<Self> {
+        CUSTOM_CODES
+            .read()
+            .unwrap()
+            .get(code)
+            .map(|status| ResponseCode::Custom(code.to_string(), *status))
+    }
+
+    pub fn try_from_code(code: &str) -> Option<Self> {
+        Self::try_from_builtin_code(code).or_else(|| Self::custom(code))
+    }
+
+    fn try_from_builtin_code(code: &str) -> Option<Self> {
+        let code = match code {
             "000" => ResponseCode::Ok,
             "001" => ResponseCode::Created,
             "002" => ResponseCode::Accepted,
@@ -70,12 +176,29 @@ impl ResponseCodeContract for ResponseCode {
             "010" => ResponseCode::InternalServerError,
             "011" => ResponseCode::ServiceUnavailable,
             "012" => ResponseCode::NotImplemented,
-            _ => panic!("Invalid response code"),
-        }
+            "013" => ResponseCode::UnprocessableEntity,
+            "014" => ResponseCode::MethodNotAllowed,
+            "015" => ResponseCode::PayloadTooLarge,
+            "016" => ResponseCode::UnsupportedMediaType,
+            "017" => ResponseCode::NotAcceptable,
+            "018" => ResponseCode::RequestTimeout,
+            "019" => ResponseCode::Gone,
+            "020" => ResponseCode::PreconditionFailed,
+            "021" => ResponseCode::Locked,
+            "022" => ResponseCode::TooManyRequests,
+            "023" => ResponseCode::BadGateway,
+            "024" => ResponseCode::GatewayTimeout,
+            "025" => ResponseCode::AlreadyExists,
+            "026" => ResponseCode::VersionConflict,
+            _ => return None,
+        };
+
+        Some(code)
     }
 
-    fn from_status(status: StatusCode) -> Self {
-        match status {
+    /// Built-in code of the status, registered codes are never picked
+    pub fn try_from_status(status: StatusCode) -> Option<Self> {
+        let code = match status {
             StatusCode::OK => ResponseCode::Ok,
             StatusCode::CREATED => ResponseCode::Created,
             StatusCode::ACCEPTED => ResponseCode::Accepted,
@@ -89,7 +212,107 @@ impl ResponseCodeContract for ResponseCode {
             StatusCode::INTERNAL_SERVER_ERROR => ResponseCode::InternalServerError,
             StatusCode::SERVICE_UNAVAILABLE => ResponseCode::ServiceUnavailable,
             StatusCode::NOT_IMPLEMENTED => ResponseCode::NotImplemented,
-            _ => panic!("Invalid status code"),
+            StatusCode::UNPROCESSABLE_ENTITY => ResponseCode::UnprocessableEntity,
+            StatusCode::METHOD_NOT_ALLOWED => ResponseCode::MethodNotAllowed,
+            StatusCode::PAYLOAD_TOO_LARGE => ResponseCode::PayloadTooLarge,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE => ResponseCode::UnsupportedMediaType,
+            StatusCode::NOT_ACCEPTABLE => ResponseCode::NotAcceptable,
+            StatusCode::REQUEST_TIMEOUT => ResponseCode::RequestTimeout,
+            StatusCode::GONE => ResponseCode::Gone,
+            StatusCode::PRECONDITION_FAILED => ResponseCode::PreconditionFailed,
+            StatusCode::LOCKED => ResponseCode::Locked,
+            StatusCode::TOO_MANY_REQUESTS => ResponseCode::TooManyRequests,
+            StatusCode::BAD_GATEWAY => ResponseCode::BadGateway,
+            StatusCode::GATEWAY_TIMEOUT => ResponseCode::GatewayTimeout,
+            _ => return None,
+        };
+
+        Some(code)
+    }
+
+    /// Like [ResponseCode::try_from_status], statuses without a dedicated code
+    /// fall back to the generic code of their class
+    pub fn from_status_or_class(status: StatusCode) -> Self {
+        Self::try_from_status(status).unwrap_or(match status {
+            s if s.is_server_error() => ResponseCode::InternalServerError,
+            s if s.is_client_error() => ResponseCode::BadRequest,
+            _ => ResponseCode::Ok,
+        })
+    }
+}
+
+impl TryFrom<&str> for ResponseCode {
+    type Error = AppMessage;
+
+    fn try_from(code: &str) -> Result<Self, Self::Error> {
+        Self::try_from_code(code).ok_or_else(|| {
+            AppMessage::ErrorMessage(
+                format!("unknown response code '{}'", code),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            )
+        })
+    }
+}
+
+impl TryFrom<StatusCode> for ResponseCode {
+    type Error = AppMessage;
+
+    fn try_from(status: StatusCode) -> Result<Self, Self::Error> {
+        Self::try_from_status(status).ok_or_else(|| {
+            AppMessage::ErrorMessage(
+                format!("no response code for status {}", status.as_u16()),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codes_round_trip() {
+        for status in 100..600 {
+            let status = StatusCode::from_u16(status).unwrap();
+            if let Some(code) = ResponseCode::try_from_status(status) {
+                assert_eq!(code.status(), status);
+                assert_eq!(ResponseCode::from_code(code.code()), code);
+            }
         }
     }
+
+    #[test]
+    fn test_unknown_input_does_not_panic() {
+        assert_eq!(
+            ResponseCode::from_code("nope"),
+            ResponseCode::InternalServerError
+        );
+        assert!(ResponseCode::try_from("nope").is_err());
+        assert_eq!(
+            ResponseCode::from_status(StatusCode::IM_A_TEAPOT),
+            ResponseCode::BadRequest
+        );
+        assert_eq!(
+            ResponseCode::try_from(StatusCode::TOO_MANY_REQUESTS).unwrap(),
+            ResponseCode::TooManyRequests
+        );
+    }
+
+    #[test]
+    fn test_register() {
+        assert!(ResponseCode::register("004", StatusCode::PAYMENT_REQUIRED).is_err());
+
+        let code = ResponseCode::register("PAY-001", StatusCode::PAYMENT_REQUIRED).unwrap();
+        assert_eq!(code.code(), "PAY-001");
+        assert_eq!(code.status(), StatusCode::PAYMENT_REQUIRED);
+        assert!(!code.success());
+        assert_eq!(ResponseCode::from_code("PAY-001"), code);
+
+        // registered codes are never picked from a status
+        assert_eq!(
+            ResponseCode::from_status(StatusCode::PAYMENT_REQUIRED),
+            ResponseCode::PaymentRequired
+        );
+    }
 }
@@ -1,13 +1,20 @@
 use crate::results::AppResult;
 use chrono::Utc;
 use hmac::{Hmac as HHmac, Mac};
-use sha2::Sha256;
+use sha2::{Sha256, Sha512};
 
 #[derive(Clone)]
 pub struct Hmac {
     secret: String,
 }
 
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum HmacAlgorithm {
+    #[default]
+    Sha256,
+    Sha512,
+}
+
 impl Hmac {
     pub fn new(secret: &str) -> Self {
         Hmac {
@@ -36,11 +43,48 @@ impl Hmac {
         let mac = self.hash(value)?;
         Ok(provided_hmac == &mac)
     }
+
+    /// Compute the raw signature of the given bytes
+    pub fn sign(&self, algorithm: HmacAlgorithm, data: &[u8]) -> AppResult<Vec<u8>> {
+        Ok(match algorithm {
+            HmacAlgorithm::Sha256 => {
+                let mut mac = HHmac::<Sha256>::new_from_slice(self.secret.as_bytes())?;
+                mac.update(data);
+                mac.finalize().into_bytes().to_vec()
+            }
+            HmacAlgorithm::Sha512 => {
+                let mut mac = HHmac::<Sha512>::new_from_slice(self.secret.as_bytes())?;
+                mac.update(data);
+                mac.finalize().into_bytes().to_vec()
+            }
+        })
+    }
+
+    /// Verify a raw signature of the given bytes, the comparison is done in constant time
+    pub fn verify_bytes(
+        &self,
+        algorithm: HmacAlgorithm,
+        data: &[u8],
+        signature: &[u8],
+    ) -> AppResult<bool> {
+        Ok(match algorithm {
+            HmacAlgorithm::Sha256 => {
+                let mut mac = HHmac::<Sha256>::new_from_slice(self.secret.as_bytes())?;
+                mac.update(data);
+                mac.verify_slice(signature).is_ok()
+            }
+            HmacAlgorithm::Sha512 => {
+                let mut mac = HHmac::<Sha512>::new_from_slice(self.secret.as_bytes())?;
+                mac.update(data);
+                mac.verify_slice(signature).is_ok()
+            }
+        })
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::Hmac;
+    use super::{Hmac, HmacAlgorithm};
 
     #[test]
     fn test_hash() {
@@ -121,4 +165,32 @@ mod tests {
             "HMACs for different values should not be the same."
         );
     }
+
+    #[test]
+    fn test_sign_and_verify_bytes() {
+        let hmac = Hmac::new("mysecret");
+        let data = b"my message";
+
+        let sha256 = hmac.sign(HmacAlgorithm::Sha256, data).unwrap();
+        assert_eq!(
+            hex::encode(&sha256),
+            "6df7d0cf7d3a52a08acbd7c12a2ab86b15820de24a78bd51e264e257de3316b0"
+        );
+
+        let sha512 = hmac.sign(HmacAlgorithm::Sha512, data).unwrap();
+        assert_eq!(sha512.len(), 64);
+
+        assert!(hmac
+            .verify_bytes(HmacAlgorithm::Sha256, data, &sha256)
+            .unwrap());
+        assert!(hmac
+            .verify_bytes(HmacAlgorithm::Sha512, data, &sha512)
+            .unwrap());
+        assert!(!hmac
+            .verify_bytes(HmacAlgorithm::Sha512, data, &sha256)
+            .unwrap());
+        assert!(!hmac
+            .verify_bytes(HmacAlgorithm::Sha256, b"other message", &sha256)
+            .unwrap());
+    }
 }
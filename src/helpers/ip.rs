@@ -0,0 +1,414 @@
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use ipnet::IpNet;
+use ntex::http::header::{HeaderMap, FORWARDED};
+
+use crate::prelude::{AppMessage, AppResult};
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+const X_FORWARDED_PROTO: &str = "x-forwarded-proto";
+const X_FORWARDED_HOST: &str = "x-forwarded-host";
+const X_REAL_IP: &str = "x-real-ip";
+
+/// A list of ip ranges (CIDR blocks or single addresses)
+#[derive(Clone, Debug, Default)]
+pub struct IpRanges {
+    ranges: Vec<IpNet>,
+}
+
+/// Client connection details, resolved through trusted proxies only
+#[derive(Clone, Debug)]
+pub struct ClientConnection {
+    /// the real client ip
+    pub ip: Option<IpAddr>,
+    /// scheme the client used to reach the (first) proxy
+    pub scheme: String,
+    /// host the client requested
+    pub host: String,
+}
+
+/// Proxy-provided details of the original request
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ForwardedInfo {
+    pub ip: Option<IpAddr>,
+    pub scheme: Option<String>,
+    pub host: Option<String>,
+}
+
+impl IpRanges {
+    pub fn new(ranges: Vec<IpNet>) -> Self {
+        IpRanges { ranges }
+    }
+
+    /// Parse a comma-separated list of CIDR blocks and/or single addresses
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use medullah_web::helpers::ip::IpRanges;
+    ///
+    /// let ranges = IpRanges::parse("10.0.0.0/8, 192.168.1.10, fd00::/8").unwrap();
+    /// assert!(ranges.contains(&"10.1.2.3".parse().unwrap()));
+    /// ```
+    pub fn parse(list: &str) -> AppResult<Self> {
+        let mut ranges = vec![];
+        for entry in list.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let range = match entry.contains('/') {
+                true => IpNet::from_str(entry).ok(),
+                false => IpAddr::from_str(entry).ok().map(IpNet::from),
+            };
+
+            match range {
+                Some(range) => ranges.push(range.trunc()),
+                None => {
+                    return Err(AppMessage::WarningMessageString(format!(
+                        "invalid ip range: {}",
+                        entry
+                    )))
+                }
+            }
+        }
+
+        Ok(IpRanges { ranges })
+    }
+
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        let ip = ip.to_canonical();
+        self.ranges.iter().any(|range| range.contains(&ip))
+    }
+
+    pub fn extend(&mut self, other: IpRanges) {
+        self.ranges.extend(other.ranges);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ranges.is_empty()
+    }
+
+    pub fn ranges(&self) -> &Vec<IpNet> {
+        &self.ranges
+    }
+}
+
+/// Resolves the original client details from forwarding headers
+///
+/// Headers are only honoured when the direct peer is a trusted proxy, the forwarding chain
+/// is then walked right-to-left and the first address that is not a trusted proxy is the client.
+/// `Forwarded` takes precedence over `X-Forwarded-For`, `X-Real-IP` is used when neither is sent.
+/// `proto`/`host` are taken from the `Forwarded` element the walk stopped at (or a later one),
+/// elements on its left are client-supplied. `X-Forwarded-Proto`/`X-Forwarded-Host` are taken
+/// from the value appended by the nearest proxy.
+pub fn resolve_forwarded(
+    peer: Option<IpAddr>,
+    headers: &HeaderMap,
+    trusted: &IpRanges,
+) -> ForwardedInfo {
+    let peer = peer.map(|ip| ip.to_canonical());
+    let is_trusted_peer = peer.map(|ip| trusted.contains(&ip)).unwrap_or(false);
+    if !is_trusted_peer {
+        return ForwardedInfo {
+            ip: peer,
+            scheme: None,
+            host: None,
+        };
+    }
+
+    let elements = forwarded_elements(headers);
+    let chain = match &elements {
+        Some(elements) => elements.iter().map(|el| el.ip).collect::<Vec<_>>(),
+        None => x_forwarded_for(headers),
+    };
+
+    let mut client = peer;
+    if chain.is_empty() {
+        if let Some(ip) = first_header_value(headers, X_REAL_IP).and_then(|v| parse_node(&v)) {
+            client = Some(ip);
+        }
+    }
+
+    // index of the hop the walk stopped at, hops from there on were added by trusted proxies
+    let mut stop = chain.len();
+    for (index, hop) in chain.into_iter().enumerate().rev() {
+        match hop {
+            Some(ip) => {
+                client = Some(ip);
+                stop = index;
+                if !trusted.contains(&ip) {
+                    break;
+                }
+            }
+            // hidden/unknown hop, nothing beyond it can be trusted
+            None => break,
+        }
+    }
+
+    let (mut scheme, mut host) = match &elements {
+        Some(elements) => {
+            let elements = &elements[stop..];
+            (
+                elements.iter().find_map(|el| el.scheme.clone()),
+                elements.iter().find_map(|el| el.host.clone()),
+            )
+        }
+        None => (None, None),
+    };
+
+    if scheme.is_none() {
+        scheme = last_header_value(headers, X_FORWARDED_PROTO);
+    }
+
+    if host.is_none() {
+        host = last_header_value(headers, X_FORWARDED_HOST);
+    }
+
+    ForwardedInfo {
+        ip: client,
+        scheme: scheme.map(|s| s.to_lowercase()),
+        host,
+    }
+}
+
+struct ForwardedElement {
+    ip: Option<IpAddr>,
+    scheme: Option<String>,
+    host: Option<String>,
+}
+
+/// Parse RFC 7239 `Forwarded` header(s), one element per hop
+fn forwarded_elements(headers: &HeaderMap) -> Option<Vec<ForwardedElement>> {
+    let mut elements = vec![];
+    for value in headers.get_all(FORWARDED) {
+        let value = value.to_str().ok()?;
+        for element in value.split(',') {
+            let mut parsed = ForwardedElement {
+                ip: None,
+                scheme: None,
+                host: None,
+            };
+
+            for pair in element.split(';') {
+                let mut items = pair.trim().splitn(2, '=');
+                let (name, val) = match (items.next(), items.next()) {
+                    (Some(name), Some(val)) => (name.trim(), val.trim().trim_matches('"')),
+                    _ => continue,
+                };
+
+                match name.to_lowercase().as_str() {
+                    "for" => parsed.ip = parse_node(val),
+                    "proto" => parsed.scheme = Some(val.to_string()),
+                    "host" => parsed.host = Some(val.to_string()),
+                    _ => {}
+                }
+            }
+
+            elements.push(parsed);
+        }
+    }
+
+    match elements.is_empty() {
+        true => None,
+        false => Some(elements),
+    }
+}
+
+fn x_forwarded_for(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    headers
+        .get_all(X_FORWARDED_FOR)
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|node| parse_node(node.trim().trim_matches('"')))
+        .collect()
+}
+
+fn first_header_value(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get(name)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(',').next())
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+/// Right-most value of a comma-separated header, the one appended by the nearest proxy
+fn last_header_value(headers: &HeaderMap, name: &str) -> Option<String> {
+    headers
+        .get_all(name)
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .last()
+        .map(|v| v.trim().to_string())
+        .filter(|v| !v.is_empty())
+}
+
+/// Parse a forwarding node, e.g. `1.2.3.4`, `1.2.3.4:80`, `[2001:db8::1]:443` or `2001:db8::1`
+fn parse_node(node: &str) -> Option<IpAddr> {
+    if let Some(rest) = node.strip_prefix('[') {
+        return rest
+            .split(']')
+            .next()
+            .and_then(|ip| IpAddr::from_str(ip).ok())
+            .map(|ip| ip.to_canonical());
+    }
+
+    if let Ok(ip) = IpAddr::from_str(node) {
+        return Some(ip.to_canonical());
+    }
+
+    // ipv4 with port
+    node.rsplit_once(':')
+        .and_then(|(ip, _)| IpAddr::from_str(ip).ok())
+        .map(|ip| ip.to_canonical())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ntex::http::header::{HeaderName, HeaderValue};
+
+    fn headers(items: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in items {
+            map.append(
+                HeaderName::from_static(name),
+                HeaderValue::from_static(value),
+            );
+        }
+
+        map
+    }
+
+    fn ip(value: &str) -> Option<IpAddr> {
+        Some(value.parse().unwrap())
+    }
+
+    #[test]
+    fn test_ip_ranges_parse() {
+        let ranges = IpRanges::parse("10.0.0.0/8, 192.168.1.10,fd00::/8").unwrap();
+        assert!(ranges.contains(&"10.20.30.40".parse().unwrap()));
+        assert!(ranges.contains(&"192.168.1.10".parse().unwrap()));
+        assert!(ranges.contains(&"fd00::1".parse().unwrap()));
+        assert!(ranges.contains(&"::ffff:10.0.0.1".parse().unwrap()));
+        assert!(!ranges.contains(&"192.168.1.11".parse().unwrap()));
+
+        assert!(IpRanges::parse("").unwrap().is_empty());
+        assert!(IpRanges::parse("10.0.0.0/33").is_err());
+        assert!(IpRanges::parse("localhost").is_err());
+    }
+
+    #[test]
+    fn test_parse_node() {
+        assert_eq!(parse_node("1.2.3.4"), ip("1.2.3.4"));
+        assert_eq!(parse_node("1.2.3.4:8080"), ip("1.2.3.4"));
+        assert_eq!(parse_node("[2001:db8::1]:443"), ip("2001:db8::1"));
+        assert_eq!(parse_node("2001:db8::1"), ip("2001:db8::1"));
+        assert_eq!(parse_node("unknown"), None);
+        assert_eq!(parse_node("_hidden"), None);
+    }
+
+    #[test]
+    fn test_untrusted_peer_ignores_headers() {
+        let trusted = IpRanges::parse("10.0.0.0/8").unwrap();
+        let headers = headers(&[("x-forwarded-for", "6.6.6.6"), ("x-real-ip", "6.6.6.6")]);
+
+        let info = resolve_forwarded(ip("8.8.8.8"), &headers, &trusted);
+        assert_eq!(info.ip, ip("8.8.8.8"));
+        assert_eq!(info.scheme, None);
+    }
+
+    #[test]
+    fn test_x_forwarded_for_right_to_left() {
+        let trusted = IpRanges::parse("10.0.0.0/8").unwrap();
+        let headers = headers(&[
+            ("x-forwarded-for", "6.6.6.6, 203.0.113.7, 10.0.0.2"),
+            ("x-forwarded-proto", "https"),
+            ("x-forwarded-host", "api.example.com"),
+        ]);
+
+        // the spoofed left-most entry is never reached
+        let info = resolve_forwarded(ip("10.0.0.1"), &headers, &trusted);
+        assert_eq!(info.ip, ip("203.0.113.7"));
+        assert_eq!(info.scheme, Some("https".to_string()));
+        assert_eq!(info.host, Some("api.example.com".to_string()));
+    }
+
+    #[test]
+    fn test_x_forwarded_proto_and_host_from_nearest_proxy() {
+        let trusted = IpRanges::parse("10.0.0.0/8").unwrap();
+        let headers = headers(&[
+            ("x-forwarded-for", "203.0.113.7"),
+            ("x-forwarded-proto", "http, https"),
+            ("x-forwarded-host", "evil.example"),
+            ("x-forwarded-host", "api.example.com"),
+        ]);
+
+        let info = resolve_forwarded(ip("10.0.0.1"), &headers, &trusted);
+        assert_eq!(info.scheme, Some("https".to_string()));
+        assert_eq!(info.host, Some("api.example.com".to_string()));
+
+        // not even the nearest one when the peer isn't trusted
+        let info = resolve_forwarded(ip("8.8.8.8"), &headers, &trusted);
+        assert_eq!(info.scheme, None);
+        assert_eq!(info.host, None);
+    }
+
+    #[test]
+    fn test_all_hops_trusted() {
+        let trusted = IpRanges::parse("10.0.0.0/8").unwrap();
+        let headers = headers(&[("x-forwarded-for", "10.0.0.5, 10.0.0.2")]);
+
+        let info = resolve_forwarded(ip("10.0.0.1"), &headers, &trusted);
+        assert_eq!(info.ip, ip("10.0.0.5"));
+    }
+
+    #[test]
+    fn test_forwarded_header() {
+        let trusted = IpRanges::parse("10.0.0.0/8").unwrap();
+        let headers = headers(&[
+            (
+                "forwarded",
+                "for=6.6.6.6, for=\"[2001:db8:cafe::17]:4711\";proto=https;host=example.com",
+            ),
+            ("x-forwarded-for", "7.7.7.7"),
+        ]);
+
+        let info = resolve_forwarded(ip("10.0.0.1"), &headers, &trusted);
+        assert_eq!(info.ip, ip("2001:db8:cafe::17"));
+        assert_eq!(info.scheme, Some("https".to_string()));
+        assert_eq!(info.host, Some("example.com".to_string()));
+    }
+
+    #[test]
+    fn test_forwarded_header_ignores_spoofed_elements() {
+        let trusted = IpRanges::parse("10.0.0.0/8").unwrap();
+        let headers = headers(&[(
+            "forwarded",
+            "for=6.6.6.6;proto=http;host=evil.example, for=203.0.113.7;proto=https;host=example.com, for=10.0.0.2",
+        )]);
+
+        let info = resolve_forwarded(ip("10.0.0.1"), &headers, &trusted);
+        assert_eq!(info.ip, ip("203.0.113.7"));
+        assert_eq!(info.scheme, Some("https".to_string()));
+        assert_eq!(info.host, Some("example.com".to_string()));
+
+        // only the client-supplied element carries them
+        let headers = self::headers(&[(
+            "forwarded",
+            "for=6.6.6.6;proto=http;host=evil.example, for=203.0.113.7",
+        )]);
+
+        let info = resolve_forwarded(ip("10.0.0.1"), &headers, &trusted);
+        assert_eq!(info.ip, ip("203.0.113.7"));
+        assert_eq!(info.scheme, None);
+        assert_eq!(info.host, None);
+    }
+
+    #[test]
+    fn test_x_real_ip() {
+        let trusted = IpRanges::parse("10.0.0.1").unwrap();
+        let headers = headers(&[("x-real-ip", "203.0.113.9")]);
+
+        let info = resolve_forwarded(ip("10.0.0.1"), &headers, &trusted);
+        assert_eq!(info.ip, ip("203.0.113.9"));
+    }
+}
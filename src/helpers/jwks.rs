@@ -0,0 +1,454 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use jsonwebtoken::errors::{Error, ErrorKind};
+use jsonwebtoken::jwk::{
+    AlgorithmParameters, CommonParameters, EllipticCurve, EllipticCurveKeyParameters, Jwk, JwkSet,
+    KeyAlgorithm, OctetKeyPairParameters, PublicKeyUse, RSAKeyParameters,
+};
+use jsonwebtoken::Algorithm;
+use log::{info, warn};
+use sha2::{Digest, Sha256};
+
+use crate::helpers::jwt::JwtKey;
+use crate::prelude::{AppMessage, AppResult};
+
+const SEQUENCE: u8 = 0x30;
+const INTEGER: u8 = 0x02;
+const BIT_STRING: u8 = 0x03;
+const OBJECT_IDENTIFIER: u8 = 0x06;
+
+/// 1.2.840.113549.1.1.1
+const OID_RSA: &[u8] = &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x01];
+/// 1.2.840.10045.2.1
+const OID_EC: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x02, 0x01];
+/// 1.2.840.10045.3.1.7
+const OID_P256: &[u8] = &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+/// 1.3.132.0.34
+const OID_P384: &[u8] = &[0x2b, 0x81, 0x04, 0x00, 0x22];
+/// 1.3.101.112
+const OID_ED25519: &[u8] = &[0x2b, 0x65, 0x70];
+
+/// JWKS published by another service, tokens it issues are verified against these keys
+///
+/// Keys are fetched when first needed and cached for `ttl`, a token signed with an unknown key
+/// triggers a refetch, at most once per `cooldown`. A failed fetch keeps the cached keys.
+///
+/// ```ignore
+/// let jwt = Jwt::new(public_key, private_key, 60)
+///     .trust(RemoteJwks::url("https://accounts.internal/.well-known/jwks.json"));
+/// ```
+pub struct RemoteJwks {
+    source: JwksSource,
+    ttl: Duration,
+    cooldown: Duration,
+    state: RwLock<CachedJwks>,
+    refreshing: tokio::sync::Mutex<()>,
+}
+
+#[derive(Clone, Debug)]
+pub enum JwksSource {
+    #[cfg(feature = "reqwest")]
+    Url(String),
+    File(PathBuf),
+}
+
+#[derive(Default)]
+struct CachedJwks {
+    keys: Vec<JwtKey>,
+    fetched_at: Option<Instant>,
+    attempted_at: Option<Instant>,
+}
+
+impl RemoteJwks {
+    pub fn new(source: JwksSource) -> Self {
+        RemoteJwks {
+            source,
+            ttl: Duration::from_secs(600),
+            cooldown: Duration::from_secs(30),
+            state: RwLock::new(CachedJwks::default()),
+            refreshing: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    #[cfg(feature = "reqwest")]
+    pub fn url(url: &str) -> Self {
+        Self::new(JwksSource::Url(url.to_string()))
+    }
+
+    /// Local JWKS file, handy in tests and for keys distributed with the deployment
+    pub fn file<P: Into<PathBuf>>(path: P) -> Self {
+        Self::new(JwksSource::File(path.into()))
+    }
+
+    /// How long fetched keys are used before being fetched again, defaults to 10 minutes
+    pub fn ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Minimum delay between two fetches, defaults to 30 seconds
+    pub fn cooldown(mut self, cooldown: Duration) -> Self {
+        self.cooldown = cooldown;
+        self
+    }
+
+    pub fn source(&self) -> &JwksSource {
+        &self.source
+    }
+
+    /// Cached keys, empty until the first fetch
+    pub fn keys(&self) -> Vec<JwtKey> {
+        self.state.read().unwrap().keys.clone()
+    }
+
+    pub fn is_stale(&self) -> bool {
+        self.state
+            .read()
+            .unwrap()
+            .fetched_at
+            .is_none_or(|at| at.elapsed() >= self.ttl)
+    }
+
+    /// Fetches the keys right away
+    pub async fn refresh(&self) -> AppResult<()> {
+        self.state.write().unwrap().attempted_at = Some(Instant::now());
+
+        let set = self.fetch().await?;
+        let keys: Vec<JwtKey> = set
+            .keys
+            .into_iter()
+            .filter(|jwk| jwk.common.public_key_use != Some(PublicKeyUse::Encryption))
+            .filter_map(|jwk| JwtKey::from_jwk(jwk).ok())
+            .collect();
+
+        info!(
+            "[jwks] fetched {} key(s) from {:?}",
+            keys.len(),
+            self.source
+        );
+
+        let mut state = self.state.write().unwrap();
+        state.keys = keys;
+        state.fetched_at = Some(Instant::now());
+        Ok(())
+    }
+
+    /// Refreshes stale keys, or keys missing the one a token was signed with
+    ///
+    /// Only one refresh runs at a time, requests arriving meanwhile wait for it instead of
+    /// fetching the keys again.
+    pub(crate) async fn ensure(&self, key_missing: bool) {
+        if !self.needs_refresh(key_missing) {
+            return;
+        }
+
+        let _refreshing = self.refreshing.lock().await;
+        if !self.needs_refresh(key_missing) {
+            return;
+        }
+
+        if let Err(err) = self.refresh().await {
+            warn!(
+                "[jwks] failed to fetch keys from {:?}: {}",
+                self.source, err
+            );
+        }
+    }
+
+    fn needs_refresh(&self, key_missing: bool) -> bool {
+        let cooled_down = self
+            .state
+            .read()
+            .unwrap()
+            .attempted_at
+            .is_none_or(|at| at.elapsed() >= self.cooldown);
+
+        cooled_down && (key_missing || self.is_stale())
+    }
+
+    async fn fetch(&self) -> AppResult<JwkSet> {
+        let body = match &self.source {
+            #[cfg(feature = "reqwest")]
+            JwksSource::Url(url) => reqwest::Client::new()
+                .get(url)
+                .timeout(Duration::from_secs(10))
+                .send()
+                .await?
+                .error_for_status()?
+                .bytes()
+                .await?
+                .to_vec(),
+            JwksSource::File(path) => tokio::fs::read(path).await?,
+        };
+
+        Ok(serde_json::from_slice(&body)?)
+    }
+}
+
+/// Public JWK of an asymmetric key, from its PKCS#1 or SubjectPublicKeyInfo DER
+///
+/// The key id defaults to the RFC 7638 thumbprint of the key.
+pub(crate) fn public_jwk(algorithm: Algorithm, kid: Option<&str>, der: &[u8]) -> AppResult<Jwk> {
+    let parameters =
+        public_key_parameters(der).ok_or_else(|| Error::from(ErrorKind::InvalidKeyFormat))?;
+
+    let matches = match (&parameters, algorithm) {
+        (AlgorithmParameters::RSA(_), alg) => matches!(
+            alg,
+            Algorithm::RS256
+                | Algorithm::RS384
+                | Algorithm::RS512
+                | Algorithm::PS256
+                | Algorithm::PS384
+                | Algorithm::PS512
+        ),
+        (AlgorithmParameters::EllipticCurve(params), Algorithm::ES256) => {
+            params.curve == EllipticCurve::P256
+        }
+        (AlgorithmParameters::EllipticCurve(params), Algorithm::ES384) => {
+            params.curve == EllipticCurve::P384
+        }
+        (AlgorithmParameters::OctetKeyPair(_), Algorithm::EdDSA) => true,
+        _ => false,
+    };
+
+    if !matches {
+        return Err(AppMessage::JwtError(ErrorKind::InvalidAlgorithm.into()));
+    }
+
+    let kid = match kid {
+        Some(kid) => kid.to_string(),
+        None => thumbprint(&parameters).ok_or_else(|| Error::from(ErrorKind::InvalidKeyFormat))?,
+    };
+
+    Ok(Jwk {
+        common: CommonParameters {
+            public_key_use: Some(PublicKeyUse::Signature),
+            key_algorithm: Some(KeyAlgorithm::from_str(&format!("{:?}", algorithm))?),
+            key_id: Some(kid),
+            ..Default::default()
+        },
+        algorithm: parameters,
+    })
+}
+
+/// Algorithm a JWK is used with, inferred from its key type when `alg` is missing
+pub(crate) fn jwk_algorithm(jwk: &Jwk) -> Option<Algorithm> {
+    if let Some(alg) = jwk.common.key_algorithm {
+        return Algorithm::from_str(&alg.to_string()).ok();
+    }
+
+    match &jwk.algorithm {
+        AlgorithmParameters::RSA(_) => Some(Algorithm::RS256),
+        AlgorithmParameters::EllipticCurve(params) => match params.curve {
+            EllipticCurve::P256 => Some(Algorithm::ES256),
+            EllipticCurve::P384 => Some(Algorithm::ES384),
+            _ => None,
+        },
+        AlgorithmParameters::OctetKeyPair(_) => Some(Algorithm::EdDSA),
+        // shared secrets aren't published
+        AlgorithmParameters::OctetKey(_) => None,
+    }
+}
+
+/// RFC 7638 thumbprint of a public key
+pub(crate) fn thumbprint(parameters: &AlgorithmParameters) -> Option<String> {
+    // members in lexicographic order, as required by the RFC
+    let canonical = match parameters {
+        AlgorithmParameters::RSA(params) => {
+            format!(r#"{{"e":"{}","kty":"RSA","n":"{}"}}"#, params.e, params.n)
+        }
+        AlgorithmParameters::EllipticCurve(params) => format!(
+            r#"{{"crv":"{}","kty":"EC","x":"{}","y":"{}"}}"#,
+            curve_name(&params.curve),
+            params.x,
+            params.y
+        ),
+        AlgorithmParameters::OctetKeyPair(params) => format!(
+            r#"{{"crv":"{}","kty":"OKP","x":"{}"}}"#,
+            curve_name(&params.curve),
+            params.x
+        ),
+        AlgorithmParameters::OctetKey(_) => return None,
+    };
+
+    Some(URL_SAFE_NO_PAD.encode(Sha256::digest(canonical.as_bytes())))
+}
+
+/// Body of a PEM document, whatever its label
+pub(crate) fn pem_to_der(pem: &[u8]) -> AppResult<Vec<u8>> {
+    let pem = std::str::from_utf8(pem)?;
+    let body: String = pem
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with("-----"))
+        .collect();
+
+    Ok(STANDARD.decode(body)?)
+}
+
+fn curve_name(curve: &EllipticCurve) -> &'static str {
+    match curve {
+        EllipticCurve::P256 => "P-256",
+        EllipticCurve::P384 => "P-384",
+        EllipticCurve::P521 => "P-521",
+        EllipticCurve::Ed25519 => "Ed25519",
+    }
+}
+
+fn public_key_parameters(der: &[u8]) -> Option<AlgorithmParameters> {
+    let (tag, sequence, _) = der_element(der)?;
+    if tag != SEQUENCE {
+        return None;
+    }
+
+    let (tag, first, rest) = der_element(sequence)?;
+    match tag {
+        // RSAPublicKey ::= SEQUENCE { modulus INTEGER, publicExponent INTEGER }
+        INTEGER => {
+            let (tag, exponent, _) = der_element(rest)?;
+            (tag == INTEGER).then(|| {
+                AlgorithmParameters::RSA(RSAKeyParameters {
+                    n: URL_SAFE_NO_PAD.encode(unsigned(first)),
+                    e: URL_SAFE_NO_PAD.encode(unsigned(exponent)),
+                    ..Default::default()
+                })
+            })
+        }
+        // SubjectPublicKeyInfo ::= SEQUENCE { algorithm AlgorithmIdentifier, subjectPublicKey BIT STRING }
+        SEQUENCE => {
+            let (tag, oid, parameters) = der_element(first)?;
+            let (bits_tag, bits, _) = der_element(rest)?;
+            if tag != OBJECT_IDENTIFIER || bits_tag != BIT_STRING {
+                return None;
+            }
+
+            let key = bits.strip_prefix(&[0])?;
+            match oid {
+                OID_RSA => public_key_parameters(key),
+                OID_EC => {
+                    let (tag, curve, _) = der_element(parameters)?;
+                    let (curve, size) = match (tag, curve) {
+                        (OBJECT_IDENTIFIER, OID_P256) => (EllipticCurve::P256, 32),
+                        (OBJECT_IDENTIFIER, OID_P384) => (EllipticCurve::P384, 48),
+                        _ => return None,
+                    };
+
+                    // uncompressed point: 0x04 || x || y
+                    let point = key.strip_prefix(&[4])?;
+                    if point.len() != size * 2 {
+                        return None;
+                    }
+
+                    Some(AlgorithmParameters::EllipticCurve(
+                        EllipticCurveKeyParameters {
+                            curve,
+                            x: URL_SAFE_NO_PAD.encode(&point[..size]),
+                            y: URL_SAFE_NO_PAD.encode(&point[size..]),
+                            ..Default::default()
+                        },
+                    ))
+                }
+                OID_ED25519 if key.len() == 32 => {
+                    Some(AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+                        curve: EllipticCurve::Ed25519,
+                        x: URL_SAFE_NO_PAD.encode(key),
+                        ..Default::default()
+                    }))
+                }
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Tag, content and remaining input of the DER element at the start of the input
+fn der_element(input: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let (&tag, rest) = input.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+
+    let (length, rest) = match first {
+        0..=0x7f => (first as usize, rest),
+        0x81..=0x84 => {
+            let count = (first & 0x7f) as usize;
+            let (bytes, rest) = rest.split_at_checked(count)?;
+            let length = bytes.iter().fold(0usize, |len, b| (len << 8) | *b as usize);
+            (length, rest)
+        }
+        _ => return None,
+    };
+
+    let (content, rest) = rest.split_at_checked(length)?;
+    Some((tag, content, rest))
+}
+
+/// Big-endian unsigned integer of a DER INTEGER, without its sign byte
+fn unsigned(integer: &[u8]) -> &[u8] {
+    match integer {
+        [0, rest @ ..] if !rest.is_empty() => rest,
+        _ => integer,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::helpers::jwt::tests::get_sample_keys;
+
+    #[test]
+    fn test_rsa_jwk() {
+        let (public_key, _) = get_sample_keys();
+        let der = pem_to_der(public_key.as_bytes()).unwrap();
+        let jwk = public_jwk(Algorithm::RS256, None, &der).unwrap();
+
+        match &jwk.algorithm {
+            AlgorithmParameters::RSA(params) => {
+                assert_eq!(params.e, "AQAB");
+                assert!(params.n.starts_with("45YvzSUf"));
+            }
+            _ => panic!("expected an RSA key"),
+        }
+
+        assert_eq!(jwk.common.key_algorithm, Some(KeyAlgorithm::RS256));
+        assert_eq!(
+            jwk.common.key_id,
+            thumbprint(&jwk.algorithm),
+            "key id defaults to the thumbprint"
+        );
+
+        // a key can't be published for the wrong algorithm
+        assert!(public_jwk(Algorithm::ES256, None, &der).is_err());
+        assert!(public_jwk(Algorithm::HS256, None, &der).is_err());
+    }
+
+    #[test]
+    fn test_thumbprint() {
+        // RFC 7638, section 3.1
+        let parameters = AlgorithmParameters::RSA(RSAKeyParameters {
+            n: "0vx7agoebGcQSuuPiLJXZptN9nndrQmbXEps2aiAFbWhM78LhWx4cbbfAAtVT86zwu1RK7aPFFxuhDR1L6tSoc_BJECPebWKRXjBZCiFV4n3oknjhMstn64tZ_2W-5JsGY4Hc5n9yBXArwl93lqt7_RN5w6Cf0h4QyQ5v-65YGjQR0_FDW2QvzqY368QQMicAtaSqzs8KJZgnYb9c7d0zgdAZHzu6qMQvRL5hajrn1n91CbOpbISD08qNLyrdkt-bFTWhAI4vMQFh6WeZu0fM4lFd2NcRwr3XPksINHaQ-G_xBniIqbw0Ls1jF44-csFCur-kEgU8awapJzKnqDKgw".to_string(),
+            e: "AQAB".to_string(),
+            ..Default::default()
+        });
+
+        assert_eq!(
+            thumbprint(&parameters).unwrap(),
+            "NzbLsXh8uDCcd-6MNwXF4W_7noWXFZAfHkxZsRGC9Xs"
+        );
+    }
+
+    #[test]
+    fn test_der_element() {
+        let long = [vec![0x04, 0x81, 0x80], vec![7; 0x80], vec![0x05, 0x00]].concat();
+        let (tag, content, rest) = der_element(&long).unwrap();
+        assert_eq!((tag, content.len(), rest), (0x04, 0x80, &[0x05, 0x00][..]));
+
+        assert!(der_element(&[0x30, 0x05, 0x00]).is_none());
+        assert!(public_key_parameters(b"not a key").is_none());
+    }
+}
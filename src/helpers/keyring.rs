@@ -0,0 +1,356 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use log::{error, info};
+use serde::Deserialize;
+
+use crate::helpers::jwt::{Algorithm, Jwt, JwtKey};
+use crate::prelude::AppResult;
+
+/// Keys of the tokens this service signs
+///
+/// The active key signs the tokens, retired keys keep verifying (and being published) until
+/// their date so that rotating the active key doesn't invalidate outstanding tokens.
+///
+/// ```ignore
+/// let keyring = JwtKeyring::new(JwtKey::pem(Algorithm::ES256, Some(&new_pem), Some(&new_pub)).kid("2024-06"))
+///     .retire(JwtKey::pem(Algorithm::RS256, None, Some(&old_pub)).kid("2024-01"), retire_at);
+///
+/// let jwt = Jwt::with_keyring(keyring, 60);
+/// ```
+///
+/// Keyrings can be loaded from a JSON file, reloaded at runtime with [Jwt::reload_keyring]:
+///
+/// ```json
+/// {
+///   "active": { "kid": "2024-06", "algorithm": "ES256", "private_key": "2024-06.pem", "public_key": "2024-06.pub.pem" },
+///   "retired": [
+///     { "kid": "2024-01", "algorithm": "RS256", "public_key": "2024-01.pub.pem", "until": "2024-07-01T00:00:00Z" }
+///   ]
+/// }
+/// ```
+///
+/// Key paths are relative to the file, `format` is `pem` (default) or `der` and HMAC keys
+/// have an inline `secret` instead.
+#[derive(Clone)]
+pub struct JwtKeyring {
+    active: JwtKey,
+    retired: Vec<RetiredKey>,
+    /// file the keyring was loaded from
+    path: Option<PathBuf>,
+}
+
+#[derive(Clone)]
+pub struct RetiredKey {
+    pub key: JwtKey,
+    /// tokens signed with the key are rejected from then on
+    pub until: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+struct KeyringFile {
+    active: KeyEntry,
+    #[serde(default)]
+    retired: Vec<RetiredEntry>,
+}
+
+#[derive(Deserialize)]
+struct KeyEntry {
+    kid: Option<String>,
+    algorithm: Algorithm,
+    #[serde(default)]
+    format: KeyFormat,
+    private_key: Option<PathBuf>,
+    public_key: Option<PathBuf>,
+    secret: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct RetiredEntry {
+    #[serde(flatten)]
+    key: KeyEntry,
+    until: DateTime<Utc>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum KeyFormat {
+    #[default]
+    Pem,
+    Der,
+}
+
+impl JwtKeyring {
+    pub fn new(active: JwtKey) -> Self {
+        JwtKeyring {
+            active,
+            retired: vec![],
+            path: None,
+        }
+    }
+
+    /// Keeps verifying the tokens signed with a previous key until the date
+    pub fn retire(mut self, key: JwtKey, until: DateTime<Utc>) -> Self {
+        self.retired.push(RetiredKey { key, until });
+        self
+    }
+
+    /// Signs with the key from now on, retiring the active key until the date
+    pub fn rotate(mut self, key: JwtKey, retire_until: DateTime<Utc>) -> Self {
+        let previous = std::mem::replace(&mut self.active, key);
+        self.retire(previous, retire_until)
+    }
+
+    /// Loads the keyring from a JSON file, failing when a key can't be used
+    pub fn load<P: AsRef<Path>>(path: P) -> AppResult<Self> {
+        let path = path.as_ref();
+        let dir = path.parent().unwrap_or(Path::new("."));
+        let file: KeyringFile = serde_json::from_slice(&fs::read(path)?)?;
+
+        let active = file.active.into_key(dir)?;
+        active.encoding_key()?;
+
+        let mut keyring = JwtKeyring::new(active);
+        keyring.path = Some(path.to_path_buf());
+
+        for entry in file.retired {
+            let key = entry.key.into_key(dir)?;
+            key.decoding_key()?;
+            keyring = keyring.retire(key, entry.until);
+        }
+
+        Ok(keyring)
+    }
+
+    pub fn active(&self) -> &JwtKey {
+        &self.active
+    }
+
+    /// Retired keys still accepted
+    pub fn retired(&self) -> impl Iterator<Item = &RetiredKey> {
+        let now = Utc::now();
+        self.retired
+            .iter()
+            .filter(move |retired| retired.until > now)
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Active key followed by the retired keys still accepted
+    pub fn keys(&self) -> impl Iterator<Item = &JwtKey> {
+        std::iter::once(&self.active).chain(self.retired().map(|retired| &retired.key))
+    }
+}
+
+impl KeyEntry {
+    fn into_key(self, dir: &Path) -> AppResult<JwtKey> {
+        let key = match self.secret {
+            Some(secret) => JwtKey::secret(self.algorithm, secret.as_bytes()),
+            None => {
+                let read =
+                    |path: Option<PathBuf>| path.map(|path| fs::read(dir.join(path))).transpose();
+                let (private_key, public_key) = (read(self.private_key)?, read(self.public_key)?);
+
+                match self.format {
+                    KeyFormat::Pem => JwtKey::pem(
+                        self.algorithm,
+                        private_key.map(String::from_utf8).transpose()?.as_deref(),
+                        public_key.map(String::from_utf8).transpose()?.as_deref(),
+                    ),
+                    KeyFormat::Der => JwtKey::der(
+                        self.algorithm,
+                        private_key.as_deref(),
+                        public_key.as_deref(),
+                    ),
+                }
+            }
+        };
+
+        Ok(match self.kid {
+            Some(kid) => key.kid(&kid),
+            None => key,
+        })
+    }
+}
+
+/// Reloads the keyring file of the app whenever the process receives `SIGHUP`
+pub(crate) async fn reload_on_hangup(jwt: Arc<Jwt>) {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        if jwt.keyring().path().is_none() {
+            return;
+        }
+
+        let mut hangup = match signal(SignalKind::hangup()) {
+            Ok(signal) => signal,
+            Err(err) => {
+                error!("[jwt] failed to listen for keyring reloads: {:?}", err);
+                return;
+            }
+        };
+
+        while hangup.recv().await.is_some() {
+            match jwt.reload_keyring() {
+                Ok(()) => info!("[jwt] keyring reloaded"),
+                Err(err) => error!(
+                    "[jwt] failed to reload keyring, keeping current keys: {}",
+                    err
+                ),
+            }
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = jwt;
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::Duration;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::helpers::jwt::tests::{ec_key, get_sample_keys};
+    use crate::helpers::jwt::{JwtTokenClaims, Validation};
+    use crate::prelude::AppMessage;
+
+    fn claims() -> JwtTokenClaims {
+        let now = Utc::now().timestamp() as usize;
+        JwtTokenClaims {
+            sub: "user-1".to_string(),
+            iat: now,
+            exp: now + 300,
+            iss: "accounts".to_string(),
+            aud: "api".to_string(),
+            jti: "jti".to_string(),
+        }
+    }
+
+    fn validation() -> Validation {
+        let mut validation = Validation::default();
+        validation.validate_aud = false;
+        validation
+    }
+
+    fn rsa_key() -> JwtKey {
+        let (public_key, private_key) = get_sample_keys();
+        JwtKey::pem(Algorithm::RS256, Some(&private_key), Some(&public_key)).kid("2024-01")
+    }
+
+    #[test]
+    fn test_rotation() {
+        let jwt = Jwt::with_keyring(JwtKeyring::new(rsa_key()), 60);
+        let old_token = jwt.generate(claims()).unwrap().access_token;
+
+        jwt.rotate(ec_key().kid("2024-06"), Utc::now() + Duration::hours(1));
+
+        let new_token = jwt.generate(claims()).unwrap().access_token;
+        let header = jsonwebtoken::decode_header(&new_token).unwrap();
+        assert_eq!(header.kid.as_deref(), Some("2024-06"));
+
+        // outstanding tokens keep working
+        for token in [&old_token, &new_token] {
+            assert!(jwt.decode::<JwtTokenClaims>(token, &validation()).is_ok());
+        }
+
+        let kids: Vec<_> = jwt
+            .jwks()
+            .unwrap()
+            .keys
+            .into_iter()
+            .filter_map(|key| key.common.key_id)
+            .collect();
+        assert_eq!(kids, ["2024-06", "2024-01"]);
+
+        // until the retired key expires
+        jwt.reload(JwtKeyring::new(ec_key().kid("2024-06")).retire(rsa_key(), Utc::now()));
+        assert!(jwt
+            .decode::<JwtTokenClaims>(&old_token, &validation())
+            .is_err());
+        assert!(jwt
+            .decode::<JwtTokenClaims>(&new_token, &validation())
+            .is_ok());
+        assert_eq!(jwt.jwks().unwrap().keys.len(), 1);
+    }
+
+    #[test]
+    fn test_rotation_without_kid() {
+        let jwt = Jwt::with_keyring(
+            JwtKeyring::new(JwtKey::secret(Algorithm::HS256, b"2024-01")),
+            60,
+        );
+        let old_token = jwt.generate(claims()).unwrap().access_token;
+        assert_eq!(jsonwebtoken::decode_header(&old_token).unwrap().kid, None);
+
+        jwt.rotate(
+            JwtKey::secret(Algorithm::HS256, b"2024-06"),
+            Utc::now() + Duration::hours(1),
+        );
+
+        // the retired key is tried after the active one
+        let new_token = jwt.generate(claims()).unwrap().access_token;
+        for token in [&old_token, &new_token] {
+            assert!(jwt.decode::<JwtTokenClaims>(token, &validation()).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_load_and_reload() {
+        let dir = std::env::temp_dir().join(format!("keyring-{}", Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let (public_key, private_key) = get_sample_keys();
+        fs::write(dir.join("2024-01.pem"), private_key).unwrap();
+        fs::write(dir.join("2024-01.pub.pem"), public_key).unwrap();
+
+        let path = dir.join("keyring.json");
+        fs::write(
+            &path,
+            r#"{"active": {"kid": "2024-01", "algorithm": "RS256", "private_key": "2024-01.pem", "public_key": "2024-01.pub.pem"}}"#,
+        )
+        .unwrap();
+
+        let jwt = Jwt::with_keyring(JwtKeyring::load(&path).unwrap(), 60);
+        let old_token = jwt.generate(claims()).unwrap().access_token;
+
+        let keyring = r#"{
+            "active": {"kid": "hmac", "algorithm": "HS256", "secret": "shared-secret"},
+            "retired": [
+                {"kid": "2024-01", "algorithm": "RS256", "public_key": "2024-01.pub.pem", "until": "2999-01-01T00:00:00Z"}
+            ]
+        }"#;
+        fs::write(&path, keyring).unwrap();
+        jwt.reload_keyring().unwrap();
+
+        assert_eq!(jwt.signing_key().id().as_deref(), Some("hmac"));
+        assert!(jwt
+            .decode::<JwtTokenClaims>(&old_token, &validation())
+            .is_ok());
+
+        // a broken keyring doesn't replace the current one
+        fs::write(
+            &path,
+            r#"{"active": {"algorithm": "ES256", "secret": "nope"}}"#,
+        )
+        .unwrap();
+        assert!(jwt.reload_keyring().is_err());
+        assert_eq!(jwt.signing_key().algorithm(), Algorithm::HS256);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_reload_without_file() {
+        let jwt = Jwt::with_keyring(JwtKeyring::new(rsa_key()), 60);
+        assert!(matches!(
+            jwt.reload_keyring(),
+            Err(AppMessage::InternalServerErrorMessage(_))
+        ));
+    }
+}
@@ -5,10 +5,15 @@ pub mod fs;
 #[cfg(feature = "hmac")]
 pub mod hmac;
 pub mod http;
+pub mod ip;
 pub mod json;
 pub mod json_message;
 #[cfg(feature = "jwt")]
+pub mod jwks;
+#[cfg(feature = "jwt")]
 pub mod jwt;
+#[cfg(feature = "jwt")]
+pub mod keyring;
 pub mod number;
 pub mod once_lock;
 #[cfg(feature = "crypto")]
@@ -20,6 +25,8 @@ pub mod responder;
 pub mod string;
 pub mod time;
 mod tokio;
+#[cfg(feature = "validator")]
+pub mod validation;
 
 #[cfg(feature = "regex")]
 mod regex;
@@ -1,3 +1,5 @@
+use std::net::IpAddr;
+
 use log::debug;
 use ntex::http::header;
 use ntex::util::Bytes;
@@ -6,7 +8,9 @@ use serde::de::DeserializeOwned;
 use serde_json::{json, Map, Value};
 
 use crate::app_state::MedullahState;
+use crate::helpers::ip::{resolve_forwarded, ClientConnection, IpRanges};
 use crate::http::extractors::client_info::ClientInfo;
+use crate::http::middlewares::request_id::RequestId;
 use crate::results::app_result::IntoAppResult;
 use crate::results::AppResult;
 
@@ -22,9 +26,24 @@ pub trait RequestHelper {
 
     fn json<T: DeserializeOwned>(bytes: Bytes) -> AppResult<T>;
 
+    /// Client ip as a string, see [RequestHelper::ip_addr]
     fn ip(&self) -> Option<String>;
 
+    /// Real client ip, forwarding headers are only honoured from trusted proxies
+    fn ip_addr(&self) -> Option<IpAddr>;
+
+    /// Client ip, scheme and host, forwarding headers are only honoured from trusted proxies
+    fn connection(&self) -> ClientConnection;
+
     fn user_agent(&self) -> Option<String>;
+
+    /// Id assigned to the request by [crate::http::middlewares::request_id::RequestIdentifier]
+    fn request_id(&self) -> Option<String>;
+
+    /// Claims of the authenticated principal, available once the request went through
+    /// [crate::http::middlewares::auth::Authenticate] or the [crate::http::extractors::auth::Auth] extractor
+    #[cfg(feature = "jwt")]
+    fn auth<C: DeserializeOwned + Clone + 'static>(&self) -> AppResult<C>;
 }
 
 impl RequestHelper for HttpRequest {
@@ -61,10 +80,45 @@ impl RequestHelper for HttpRequest {
     }
 
     fn ip(&self) -> Option<String> {
-        self.connection_info()
-            .remote()
-            .map(|v| v.to_string())
-            .or_else(|| self.peer_addr().map(|s| s.to_string()))
+        self.ip_addr().map(|ip| ip.to_string())
+    }
+
+    fn ip_addr(&self) -> Option<IpAddr> {
+        self.connection().ip
+    }
+
+    fn connection(&self) -> ClientConnection {
+        let default_proxies = IpRanges::default();
+        let trusted = self
+            .app_state::<MedullahState>()
+            .map(|app| &app.trusted_proxies)
+            .unwrap_or(&default_proxies);
+
+        let peer = self.peer_addr().map(|addr| addr.ip());
+        let forwarded = resolve_forwarded(peer, self.headers(), trusted);
+
+        let scheme = forwarded
+            .scheme
+            .unwrap_or_else(|| match self.uri().scheme_str() {
+                Some(scheme) => scheme.to_string(),
+                None if self.app_config().secure() => "https".to_string(),
+                None => "http".to_string(),
+            });
+
+        let host = forwarded.host.unwrap_or_else(|| {
+            self.headers()
+                .get(header::HOST)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.to_string())
+                .or_else(|| self.uri().authority().map(|v| v.to_string()))
+                .unwrap_or_else(|| self.app_config().host().to_string())
+        });
+
+        ClientConnection {
+            ip: forwarded.ip,
+            scheme,
+            host,
+        }
     }
 
     fn user_agent(&self) -> Option<String> {
@@ -72,4 +126,16 @@ impl RequestHelper for HttpRequest {
             .get(header::USER_AGENT)
             .map(|ua| ua.to_str().unwrap().to_string())
     }
+
+    fn request_id(&self) -> Option<String> {
+        self.extensions().get::<RequestId>().map(|id| id.0.clone())
+    }
+
+    #[cfg(feature = "jwt")]
+    fn auth<C: DeserializeOwned + Clone + 'static>(&self) -> AppResult<C> {
+        self.extensions()
+            .get::<crate::http::extractors::auth::Auth<C>>()
+            .map(|auth| auth.0.clone())
+            .ok_or(crate::prelude::AppMessage::UnAuthorized)
+    }
 }
@@ -1,9 +1,14 @@
 use std::fmt::{Display, Formatter};
+use std::time::Duration;
 
 use crate::contracts::ResponseCodeContract;
 use crate::enums::ResponseCode;
 use crate::helpers::json::json_empty;
 use crate::helpers::json_message::JsonMessage;
+use crate::http::response::problem::ProblemDetails;
+use crate::http::response::sse::{SseEvent, SseStream, DEFAULT_KEEP_ALIVE};
+use crate::t;
+use futures_util::Stream;
 use ntex::http::{Response, StatusCode};
 use ntex::web::HttpResponse;
 use serde::{Deserialize, Serialize};
@@ -35,6 +40,17 @@ pub struct DeJsonResponse<T> {
     pub timestamp: u64,
     pub message: Option<String>,
     pub data: T,
+    /// set on server errors, see [crate::reporting]
+    #[serde(default)]
+    pub error_id: Option<String>,
+}
+
+impl<T> DeJsonResponse<T> {
+    /// Response code of the envelope, application codes resolve once registered
+    /// with [ResponseCode::register], `None` for codes this app doesn't know
+    pub fn response_code(&self) -> Option<ResponseCode> {
+        ResponseCode::try_from_code(&self.code)
+    }
 }
 
 impl<T: Serialize> Display for JsonResponse<T> {
@@ -87,8 +103,7 @@ impl Responder {
     }
 
     pub fn entity_not_found_message(entity: &str) -> Response {
-        let msg = format!("Such {} does not exists", entity);
-        Self::not_found_message(&msg)
+        Self::not_found_message(&t!("errors.entity_not_found", entity = entity))
     }
 
     pub fn internal_server_error_message(msg: &str) -> Response {
@@ -96,11 +111,11 @@ impl Responder {
     }
 
     pub fn not_found() -> Response {
-        Self::not_found_message("Not Found")
+        Self::not_found_message(&t!("errors.not_found"))
     }
 
     pub fn internal_server_error() -> Response {
-        Self::internal_server_error_message("Internal Server Error")
+        Self::internal_server_error_message(&t!("errors.internal_server_error"))
     }
 
     pub fn message<C: ResponseCodeContract>(msg: &str, code: C) -> Response {
@@ -114,6 +129,11 @@ impl Responder {
         Self::respond(message, code.status())
     }
 
+    /// Send an RFC 9457 `application/problem+json` response, see [ProblemDetails]
+    pub fn problem<C: ResponseCodeContract>(code: C, detail: &str) -> Response {
+        ProblemDetails::new(code, Some(detail)).into_response()
+    }
+
     /// Send a response without the standard response wrapper
     ///
     /// # Arguments
@@ -134,6 +154,56 @@ impl Responder {
             .into_body()
     }
 
+    /// Stream server-sent events, keep-alive comments are sent every 15 seconds of inactivity
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use medullah_web::helpers::responder::Responder;
+    /// use medullah_web::http::response::sse::SseEvent;
+    ///
+    /// let events = futures_util::stream::iter(vec![SseEvent::new("hello").event("greeting")]);
+    /// let response = Responder::sse(events);
+    /// ```
+    pub fn sse<S>(events: S) -> Response
+    where
+        S: Stream<Item = SseEvent> + Unpin + 'static,
+    {
+        Self::sse_with_keep_alive(events, DEFAULT_KEEP_ALIVE)
+    }
+
+    pub fn sse_with_keep_alive<S>(events: S, keep_alive: Duration) -> Response
+    where
+        S: Stream<Item = SseEvent> + Unpin + 'static,
+    {
+        SseStream::new(events, keep_alive).into_response()
+    }
+
+    /// Stream rows as a csv or xlsx attachment, without loading the whole result set
+    ///
+    /// # Examples
+    ///
+    /// ```ignore
+    /// use medullah_web::http::response::export::{self, Export, ExportFormat};
+    ///
+    /// // every row of the result set, a page at a time
+    /// let rows = export::pages(move |page| query(page).load_and_count_pages::<Order>(&mut conn));
+    /// Responder::export(ExportFormat::Csv, rows);
+    ///
+    /// // selected columns under custom headers
+    /// let export = Export::xlsx("orders").column("reference", "Reference").column("total", "Total");
+    /// Responder::export(export, futures_util::stream::iter(orders.into_iter().map(Ok)));
+    /// ```
+    #[cfg(feature = "export")]
+    pub fn export<E, T, S>(export: E, rows: S) -> Response
+    where
+        E: Into<crate::http::response::export::Export>,
+        T: Serialize + 'static,
+        S: Stream<Item = crate::results::AppResult<T>> + Unpin + 'static,
+    {
+        export.into().into_response(rows)
+    }
+
     fn make_response<T: Serialize>(data: T, status: StatusCode) -> Response {
         HttpResponse::build(status).json(&data)
     }
@@ -207,6 +277,24 @@ mod tests {
         assert_eq!(body["data"], data);
     }
 
+    #[tokio::test]
+    async fn test_custom_code() {
+        let code = ResponseCode::register("ORD-404", StatusCode::NOT_FOUND).unwrap();
+        let response = Responder::send_msg(json!(null), code.clone(), "Order not found");
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        let resp_body = collect_raw_body(response).await;
+        let body: DeJsonResponse<serde_json::Value> = serde_json::from_str(&resp_body).unwrap();
+        assert_eq!(body.code, "ORD-404");
+        assert_eq!(body.response_code(), Some(code));
+
+        let body: DeJsonResponse<()> = serde_json::from_str(
+            r#"{"code": "XYZ", "success": false, "timestamp": 0, "message": null, "data": null}"#,
+        )
+        .unwrap();
+        assert_eq!(body.response_code(), None);
+    }
+
     #[tokio::test]
     async fn test_redirect() {
         let url = "http://example.com";
@@ -0,0 +1,245 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+
+use serde::Serialize;
+use serde_json::{Map, Value};
+use validator::{ValidationError, ValidationErrors, ValidationErrorsKind};
+
+use crate::i18n;
+
+/// A single problem found with a field
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct FieldError {
+    pub code: String,
+    pub message: Option<String>,
+    pub params: Map<String, Value>,
+}
+
+/// Problems found with a payload, keyed by field path (e.g. `address.city`, `items[0].name`)
+///
+/// Serialized as `{ "field": [ {"code": .., "message": .., "params": {..}} ] }`.
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct FieldErrors(BTreeMap<String, Vec<FieldError>>);
+
+impl FieldError {
+    pub fn new(code: &str, message: &str) -> Self {
+        FieldError {
+            code: code.to_string(),
+            message: Some(message.to_string()),
+            params: Map::new(),
+        }
+    }
+}
+
+impl FieldErrors {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, field: &str, error: FieldError) {
+        self.0.entry(field.to_string()).or_default().push(error);
+    }
+
+    pub fn get(&self, field: &str) -> Option<&Vec<FieldError>> {
+        self.0.get(field)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn into_inner(self) -> BTreeMap<String, Vec<FieldError>> {
+        self.0
+    }
+
+    /// Turn serde errors into field errors, the field is the json path of the failing value
+    pub fn from_serde(err: serde_path_to_error::Error<serde_json::Error>) -> Self {
+        let inner = err.inner();
+
+        // drop the position, the path says more about where the value is
+        let message = inner.to_string();
+        let message = match message.rfind(" at line ") {
+            Some(index) => message[..index].to_string(),
+            None => message,
+        };
+
+        let path = err.path().to_string();
+        let path = match path.as_str() {
+            "." => String::new(),
+            _ => path,
+        };
+
+        let (field, code) = match message
+            .strip_prefix("missing field `")
+            .and_then(|field| field.strip_suffix('`'))
+        {
+            Some(missing) if path.is_empty() => (missing.to_string(), "required"),
+            Some(missing) => (format!("{}.{}", path, missing), "required"),
+            None if inner.is_syntax() || inner.is_eof() => (String::from("$"), "invalid_json"),
+            None if path.is_empty() => (String::from("$"), "invalid_type"),
+            None => (path, "invalid_type"),
+        };
+
+        let mut errors = FieldErrors::new();
+        errors.add(&field, FieldError::new(code, &message));
+        errors
+    }
+
+    fn extend_from(&mut self, prefix: &str, errors: &ValidationErrors) {
+        for (field, kind) in errors.errors() {
+            let path = match prefix.is_empty() {
+                true => field.to_string(),
+                false => format!("{}.{}", prefix, field),
+            };
+
+            match kind {
+                ValidationErrorsKind::Field(list) => {
+                    for error in list {
+                        self.add(&path, FieldError::from(error));
+                    }
+                }
+                ValidationErrorsKind::Struct(nested) => self.extend_from(&path, nested),
+                ValidationErrorsKind::List(items) => {
+                    for (index, nested) in items {
+                        self.extend_from(&format!("{}[{}]", path, index), nested);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Messages are translated in the current locale, either the validator message (when it is a
+/// catalog key) or the `validation.{code}` message of the catalog, params fill the placeholders
+impl From<&ValidationError> for FieldError {
+    fn from(value: &ValidationError) -> Self {
+        let params: Map<String, Value> = value
+            .params
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect();
+
+        let args: Vec<(&str, String)> = params
+            .iter()
+            .map(|(name, value)| match value {
+                Value::String(value) => (name.as_str(), value.clone()),
+                value => (name.as_str(), value.to_string()),
+            })
+            .collect();
+
+        let locale = i18n::current_locale();
+        let message = match &value.message {
+            Some(message) => {
+                Some(i18n::lookup(&locale, message, &args).unwrap_or_else(|| message.to_string()))
+            }
+            None => i18n::lookup(&locale, &format!("validation.{}", value.code), &args),
+        };
+
+        FieldError {
+            code: value.code.to_string(),
+            message,
+            params,
+        }
+    }
+}
+
+impl From<&ValidationErrors> for FieldErrors {
+    fn from(value: &ValidationErrors) -> Self {
+        let mut errors = FieldErrors::new();
+        errors.extend_from("", value);
+        errors
+    }
+}
+
+impl Display for FieldErrors {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let fields = self
+            .0
+            .iter()
+            .map(|(field, errors)| {
+                let codes = errors
+                    .iter()
+                    .map(|e| e.message.clone().unwrap_or_else(|| e.code.clone()))
+                    .collect::<Vec<_>>();
+
+                format!("{}: {}", field, codes.join(", "))
+            })
+            .collect::<Vec<_>>();
+
+        f.write_str(&fields.join("; "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+    use validator::Validate;
+
+    use super::*;
+
+    #[derive(Debug, Deserialize, Validate)]
+    struct Address {
+        #[validate(length(min = 2))]
+        city: String,
+    }
+
+    #[derive(Debug, Deserialize, Validate)]
+    struct Signup {
+        #[validate(email)]
+        email: String,
+        #[validate(nested)]
+        address: Address,
+        #[validate(nested)]
+        #[serde(default)]
+        contacts: Vec<Address>,
+    }
+
+    fn parse(json: &str) -> Result<Signup, FieldErrors> {
+        let de = &mut serde_json::Deserializer::from_str(json);
+        serde_path_to_error::deserialize(de).map_err(FieldErrors::from_serde)
+    }
+
+    #[test]
+    fn test_validation_errors_are_flattened() {
+        let signup = parse(
+            r#"{"email": "nope", "address": {"city": "A"}, "contacts": [{"city": "Lagos"}, {"city": "B"}]}"#,
+        )
+        .unwrap();
+
+        let errors = FieldErrors::from(&signup.validate().unwrap_err());
+        assert_eq!(errors.get("email").unwrap()[0].code, "email");
+        assert_eq!(errors.get("address.city").unwrap()[0].code, "length");
+        assert_eq!(
+            errors.get("address.city").unwrap()[0].params["min"],
+            Value::from(2)
+        );
+        assert!(errors.get("contacts[1].city").is_some());
+        assert!(errors.get("contacts[0].city").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_validation_messages_are_translated() {
+        let signup = parse(r#"{"email": "nope", "address": {"city": "Dar"}}"#).unwrap();
+        let errors = i18n::with_locale("fr".to_string(), async {
+            FieldErrors::from(&signup.validate().unwrap_err())
+        })
+        .await;
+
+        assert_eq!(
+            errors.get("email").unwrap()[0].message.as_deref(),
+            Some("Doit être une adresse e-mail valide")
+        );
+    }
+
+    #[test]
+    fn test_serde_errors_carry_the_path() {
+        let errors = parse(r#"{"email": "a@b.c", "address": {"city": 5}}"#).unwrap_err();
+        assert_eq!(errors.get("address.city").unwrap()[0].code, "invalid_type");
+
+        let errors = parse(r#"{"email": "a@b.c", "address": {}}"#).unwrap_err();
+        assert_eq!(errors.get("address.city").unwrap()[0].code, "required");
+
+        let errors = parse(r#"{"email": "a@b.c""#).unwrap_err();
+        assert_eq!(errors.get("$").unwrap()[0].code, "invalid_json");
+    }
+}
@@ -0,0 +1,750 @@
+use std::time::Duration;
+
+use log::{debug, warn};
+use ntex::http::StatusCode;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, ACCEPT, AUTHORIZATION, RETRY_AFTER};
+use reqwest::{Client, Method, RequestBuilder};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::contracts::AppErrorContract;
+use crate::enums::ResponseCode;
+#[cfg(feature = "hmac")]
+use crate::helpers::hmac::Hmac;
+use crate::helpers::responder::DeJsonResponse;
+use crate::prelude::{AppMessage, AppResult};
+
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Same header as the one read by the `Idempotency` middleware
+const IDEMPOTENCY_KEY: &str = "idempotency-key";
+
+/// How requests to a service are authenticated
+#[derive(Clone, Default)]
+pub enum ServiceAuth {
+    #[default]
+    None,
+    /// `Authorization: Bearer {token}`, personal access tokens are sent the same way
+    Bearer(String),
+    /// `t={unix timestamp},v1={hex signature}` of `{timestamp}.{body}` sent in `header`,
+    /// as verified by [WebhookConfig::timestamped](crate::http::extractors::verified_webhook::WebhookConfig::timestamped)
+    #[cfg(feature = "hmac")]
+    Hmac { hmac: Hmac, header: String },
+}
+
+/// Retries of idempotent requests failing to connect, timing out or answered with 429/502/503/504
+///
+/// Delays double from `base_delay` up to `max_delay`, a `Retry-After` header (in seconds) wins.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+}
+
+/// Client of a downstream service speaking the [JsonResponse](crate::helpers::responder::JsonResponse) envelope
+///
+/// Build one per service and share it, the underlying connection pool is reused by clones.
+///
+/// ```ignore
+/// let billing = ServiceClient::new("billing", "http://billing.internal/api/v1")
+///     .hmac("shared-secret")
+///     .timeout(Duration::from_secs(5))
+///     .retry(RetryPolicy::new(3));
+///
+/// let invoice: Invoice = billing.get(&format!("/invoices/{}", id)).send().await?;
+///
+/// let invoice: Invoice = billing
+///     .post("/invoices")
+///     .json(&payload)
+///     .idempotency_key(&order.id.to_string())
+///     .send()
+///     .await?;
+/// ```
+#[derive(Clone)]
+pub struct ServiceClient {
+    name: String,
+    base_url: String,
+    http: Client,
+    auth: ServiceAuth,
+    timeout: Duration,
+    retry: RetryPolicy,
+    headers: HeaderMap,
+}
+
+/// A request being built, see [ServiceClient::request]
+pub struct ServiceRequest<'a> {
+    client: &'a ServiceClient,
+    builder: RequestBuilder,
+    idempotent: bool,
+}
+
+/// Error envelope (or unexpected response) returned by a downstream service
+///
+/// When turned into an [AppMessage], client errors other than authentication failures keep
+/// the downstream status, code and message; anything else becomes `502 Bad Gateway`.
+#[derive(Debug)]
+pub struct ServiceError {
+    pub service: String,
+    pub status: StatusCode,
+    /// `None` when the response isn't an envelope or its code is unknown to this app
+    pub code: Option<ResponseCode>,
+    pub message: Option<String>,
+    pub error_id: Option<String>,
+    pub data: Value,
+}
+
+impl RetryPolicy {
+    pub fn new(max_retries: u32) -> Self {
+        RetryPolicy {
+            max_retries,
+            base_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(5),
+        }
+    }
+
+    pub fn none() -> Self {
+        Self::new(0)
+    }
+
+    pub fn base_delay(mut self, delay: Duration) -> Self {
+        self.base_delay = delay;
+        self
+    }
+
+    pub fn max_delay(mut self, delay: Duration) -> Self {
+        self.max_delay = delay;
+        self
+    }
+
+    /// Delay before the given retry, starting at 1
+    pub fn delay(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+        self.base_delay.saturating_mul(factor).min(self.max_delay)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::new(2)
+    }
+}
+
+impl ServiceClient {
+    pub fn new(name: &str, base_url: &str) -> Self {
+        ServiceClient {
+            name: name.to_string(),
+            base_url: base_url.trim_end_matches('/').to_string(),
+            http: Client::new(),
+            auth: ServiceAuth::None,
+            timeout: DEFAULT_TIMEOUT,
+            retry: RetryPolicy::default(),
+            headers: HeaderMap::new(),
+        }
+    }
+
+    /// Client configured from `{PREFIX}_SERVICE_{NAME}_*` variables
+    ///
+    /// `URL` is required; `TOKEN` (bearer or personal access token), `HMAC_SECRET`,
+    /// `TIMEOUT` (seconds) and `RETRIES` are optional.
+    pub fn from_env(env_prefix: &str, name: &str) -> AppResult<Self> {
+        let var = |key: &str| {
+            std::env::var(format!(
+                "{}_SERVICE_{}_{}",
+                env_prefix,
+                name.to_uppercase().replace('-', "_"),
+                key
+            ))
+            .ok()
+            .filter(|value| !value.is_empty())
+        };
+
+        let url = var("URL").ok_or_else(|| {
+            AppMessage::ErrorMessage(
+                format!("base url of service '{}' is not configured", name),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            )
+        })?;
+
+        let mut client = Self::new(name, &url);
+
+        if let Some(token) = var("TOKEN") {
+            client = client.bearer(&token);
+        }
+
+        #[cfg(feature = "hmac")]
+        if let Some(secret) = var("HMAC_SECRET") {
+            client = client.hmac(&secret);
+        }
+
+        if let Some(timeout) = var("TIMEOUT").and_then(|v| v.parse::<u64>().ok()) {
+            client = client.timeout(Duration::from_secs(timeout));
+        }
+
+        if let Some(retries) = var("RETRIES").and_then(|v| v.parse::<u32>().ok()) {
+            client = client.retry(RetryPolicy::new(retries));
+        }
+
+        Ok(client)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    pub fn auth(mut self, auth: ServiceAuth) -> Self {
+        self.auth = auth;
+        self
+    }
+
+    pub fn bearer(self, token: &str) -> Self {
+        self.auth(ServiceAuth::Bearer(token.to_string()))
+    }
+
+    /// Personal access token of the account this service acts as, see [crate::services::pat_service]
+    pub fn pat(self, token: &str) -> Self {
+        self.bearer(token)
+    }
+
+    /// Sign requests with the shared secret, in the default signature header (`x-signature`)
+    #[cfg(feature = "hmac")]
+    pub fn hmac(self, secret: &str) -> Self {
+        use crate::http::extractors::verified_webhook::WebhookConfig;
+        self.hmac_with_header(secret, WebhookConfig::DEFAULT_HEADER)
+    }
+
+    #[cfg(feature = "hmac")]
+    pub fn hmac_with_header(self, secret: &str, header: &str) -> Self {
+        self.auth(ServiceAuth::Hmac {
+            hmac: Hmac::new(secret),
+            header: header.to_lowercase(),
+        })
+    }
+
+    /// Timeout of each attempt, defaults to 30 seconds
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Header sent along every request
+    pub fn header(mut self, name: &'static str, value: &str) -> Self {
+        self.headers.insert(
+            HeaderName::from_static(name),
+            HeaderValue::from_str(value).expect("invalid header value"),
+        );
+        self
+    }
+
+    /// Use a preconfigured client (proxies, connect timeout, tls...)
+    pub fn http_client(mut self, client: Client) -> Self {
+        self.http = client;
+        self
+    }
+
+    pub fn url(&self, path: &str) -> String {
+        format!("{}/{}", self.base_url, path.trim_start_matches('/'))
+    }
+
+    pub fn request(&self, method: Method, path: &str) -> ServiceRequest<'_> {
+        let builder = self
+            .http
+            .request(method, self.url(path))
+            .headers(self.headers.clone())
+            .header(ACCEPT, "application/json")
+            .timeout(self.timeout);
+
+        ServiceRequest {
+            client: self,
+            builder,
+            idempotent: false,
+        }
+    }
+
+    pub fn get(&self, path: &str) -> ServiceRequest<'_> {
+        self.request(Method::GET, path)
+    }
+
+    pub fn post(&self, path: &str) -> ServiceRequest<'_> {
+        self.request(Method::POST, path)
+    }
+
+    pub fn put(&self, path: &str) -> ServiceRequest<'_> {
+        self.request(Method::PUT, path)
+    }
+
+    pub fn patch(&self, path: &str) -> ServiceRequest<'_> {
+        self.request(Method::PATCH, path)
+    }
+
+    pub fn delete(&self, path: &str) -> ServiceRequest<'_> {
+        self.request(Method::DELETE, path)
+    }
+
+    fn authenticate(&self, request: &mut reqwest::Request) -> AppResult<()> {
+        match &self.auth {
+            ServiceAuth::None => {}
+            ServiceAuth::Bearer(token) => {
+                request
+                    .headers_mut()
+                    .insert(AUTHORIZATION, header_value(&format!("Bearer {}", token))?);
+            }
+            #[cfg(feature = "hmac")]
+            ServiceAuth::Hmac { hmac, header } => {
+                use crate::helpers::hmac::HmacAlgorithm;
+
+                let timestamp = chrono::Utc::now().timestamp();
+                let mut signed = format!("{}.", timestamp).into_bytes();
+                if let Some(body) = request.body().and_then(|body| body.as_bytes()) {
+                    signed.extend_from_slice(body);
+                }
+
+                let signature = hex::encode(hmac.sign(HmacAlgorithm::Sha256, &signed)?);
+                let name = HeaderName::from_bytes(header.as_bytes()).map_err(|err| {
+                    AppMessage::ErrorMessage(err.to_string(), StatusCode::INTERNAL_SERVER_ERROR)
+                })?;
+
+                request.headers_mut().insert(
+                    name,
+                    header_value(&format!("t={},v1={}", timestamp, signature))?,
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn decode<T: DeserializeOwned>(
+        &self,
+        response: reqwest::Response,
+    ) -> AppResult<Result<T, ServiceError>> {
+        let status = StatusCode::from_u16(response.status().as_u16())
+            .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        let body = response.bytes().await?;
+
+        let envelope = match serde_json::from_slice::<DeJsonResponse<Value>>(&body) {
+            Ok(envelope) => envelope,
+            Err(_) => {
+                return Ok(Err(ServiceError {
+                    service: self.name.clone(),
+                    status,
+                    code: None,
+                    message: None,
+                    error_id: None,
+                    data: Value::String(String::from_utf8_lossy(&body).to_string()),
+                }))
+            }
+        };
+
+        if status.is_success() && envelope.success {
+            let data = serde_json::from_value(envelope.data).map_err(AppMessage::SerdeError500)?;
+            return Ok(Ok(data));
+        }
+
+        Ok(Err(ServiceError {
+            service: self.name.clone(),
+            status,
+            code: envelope.response_code(),
+            message: envelope.message,
+            error_id: envelope.error_id,
+            data: envelope.data,
+        }))
+    }
+}
+
+impl<'a> ServiceRequest<'a> {
+    pub fn query<Q: Serialize + ?Sized>(mut self, query: &Q) -> Self {
+        self.builder = self.builder.query(query);
+        self
+    }
+
+    pub fn json<B: Serialize + ?Sized>(mut self, body: &B) -> Self {
+        self.builder = self.builder.json(body);
+        self
+    }
+
+    pub fn header(mut self, name: &'static str, value: &str) -> Self {
+        self.builder = self.builder.header(name, value);
+        self
+    }
+
+    /// Timeout of each attempt of this request
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.builder = self.builder.timeout(timeout);
+        self
+    }
+
+    /// Sends an `Idempotency-Key`, making the request safe to retry whatever its method
+    pub fn idempotency_key(mut self, key: &str) -> Self {
+        self.builder = self.builder.header(IDEMPOTENCY_KEY, key);
+        self.idempotent = true;
+        self
+    }
+
+    /// Data of the response envelope, error envelopes become [ServiceError]s
+    pub async fn send<T: DeserializeOwned>(self) -> AppResult<T> {
+        self.try_send().await?.map_err(AppMessage::from)
+    }
+
+    /// Like [ServiceRequest::send], keeping the downstream error apart from transport failures
+    ///
+    /// ```ignore
+    /// match users.get(&format!("/users/{}", id)).try_send::<User>().await? {
+    ///     Ok(user) => Some(user),
+    ///     Err(err) if err.code == Some(ResponseCode::NotFound) => None,
+    ///     Err(err) => return Err(err.into()),
+    /// }
+    /// ```
+    pub async fn try_send<T: DeserializeOwned>(self) -> AppResult<Result<T, ServiceError>> {
+        let client = self.client;
+        let request = self.builder.build()?;
+
+        let retries = match self.idempotent || is_idempotent(request.method()) {
+            true => client.retry.max_retries,
+            false => 0,
+        };
+
+        let mut retry = 0;
+        loop {
+            // bodies are buffered json, requests can always be cloned
+            let mut attempt = request.try_clone().ok_or_else(|| {
+                AppMessage::ErrorMessage(
+                    "streamed requests cannot be sent".to_string(),
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                )
+            })?;
+
+            client.authenticate(&mut attempt)?;
+            debug!(
+                "[service-client][{}] {} {}",
+                client.name,
+                attempt.method(),
+                attempt.url()
+            );
+
+            let result = client.http.execute(attempt).await;
+
+            let retry_after = match &result {
+                Ok(response) if is_retriable(response.status()) => Some(
+                    response
+                        .headers()
+                        .get(RETRY_AFTER)
+                        .and_then(|v| v.to_str().ok())
+                        .and_then(|v| v.trim().parse::<u64>().ok())
+                        .map(Duration::from_secs),
+                ),
+                Err(err) if err.is_connect() || err.is_timeout() => Some(None),
+                _ => None,
+            };
+
+            if let Some(retry_after) = retry_after {
+                if retry < retries {
+                    retry += 1;
+                    let delay = retry_after
+                        .unwrap_or_else(|| client.retry.delay(retry))
+                        .min(client.retry.max_delay);
+
+                    warn!(
+                        "[service-client][{}] {} {} failed, retry {}/{} in {:?}",
+                        client.name,
+                        request.method(),
+                        request.url(),
+                        retry,
+                        retries,
+                        delay
+                    );
+
+                    tokio::time::sleep(delay).await;
+                    continue;
+                }
+            }
+
+            return client.decode(result?).await;
+        }
+    }
+}
+
+impl ServiceError {
+    /// Whether the downstream status is passed on to our own clients
+    fn is_forwarded(&self) -> bool {
+        self.status.is_client_error()
+            && self.status != StatusCode::UNAUTHORIZED
+            && self.status != StatusCode::FORBIDDEN
+            && self.status != StatusCode::PROXY_AUTHENTICATION_REQUIRED
+    }
+}
+
+impl AppErrorContract for ServiceError {
+    fn status(&self) -> StatusCode {
+        match self.is_forwarded() {
+            true => self.status,
+            false => StatusCode::BAD_GATEWAY,
+        }
+    }
+
+    fn code(&self) -> ResponseCode {
+        match (self.is_forwarded(), &self.code) {
+            (true, Some(code)) => code.clone(),
+            _ => ResponseCode::from_status_or_class(self.status()),
+        }
+    }
+
+    fn public_message(&self) -> Option<String> {
+        match self.is_forwarded() {
+            true => self.message.clone(),
+            false => None,
+        }
+    }
+
+    fn details(&self) -> Option<String> {
+        Some(format!(
+            "service '{}' responded {} (code: {:?}, error id: {:?}): {}",
+            self.service,
+            self.status,
+            self.code,
+            self.error_id,
+            self.message.as_deref().unwrap_or_default()
+        ))
+    }
+}
+
+fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::OPTIONS | Method::PUT | Method::DELETE
+    )
+}
+
+fn is_retriable(status: reqwest::StatusCode) -> bool {
+    matches!(status.as_u16(), 429 | 502 | 503 | 504)
+}
+
+fn header_value(value: &str) -> AppResult<HeaderValue> {
+    HeaderValue::from_str(value)
+        .map_err(|err| AppMessage::ErrorMessage(err.to_string(), StatusCode::INTERNAL_SERVER_ERROR))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+    use serde_json::json;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::*;
+    use crate::helpers::responder::JsonResponse;
+
+    #[derive(Debug, Deserialize, PartialEq)]
+    struct Invoice {
+        id: u32,
+        total: f64,
+    }
+
+    fn envelope(code: &str, success: bool, message: &str, data: Value) -> String {
+        serde_json::to_string(&JsonResponse {
+            code: code.to_string(),
+            success,
+            timestamp: 0,
+            message: Some(message.to_string()),
+            data,
+        })
+        .unwrap()
+    }
+
+    /// Answers a connection per response and returns the raw requests
+    async fn stub(
+        responses: Vec<(&'static str, String)>,
+    ) -> (String, tokio::task::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/api", listener.local_addr().unwrap());
+
+        let handle = tokio::spawn(async move {
+            let mut requests = vec![];
+            for (status, body) in responses {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = vec![];
+                let mut buffer = [0u8; 4096];
+
+                loop {
+                    let read = stream.read(&mut buffer).await.unwrap();
+                    request.extend_from_slice(&buffer[..read]);
+
+                    let raw = String::from_utf8_lossy(&request).to_string();
+                    if let Some((head, body)) = raw.split_once("\r\n\r\n") {
+                        let length = head
+                            .lines()
+                            .find_map(|line| {
+                                line.to_lowercase()
+                                    .strip_prefix("content-length:")
+                                    .map(|v| v.trim().parse::<usize>().unwrap())
+                            })
+                            .unwrap_or(0);
+
+                        if body.len() >= length {
+                            break;
+                        }
+                    }
+                }
+
+                let response = format!(
+                    "HTTP/1.1 {}\r\ncontent-type: application/json\r\nretry-after: 0\r\nconnection: close\r\ncontent-length: {}\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+                requests.push(String::from_utf8_lossy(&request).to_string());
+            }
+
+            requests
+        });
+
+        (url, handle)
+    }
+
+    #[tokio::test]
+    async fn test_send() {
+        let body = envelope("000", true, "OK", json!({"id": 7, "total": 12.5}));
+        let (url, requests) = stub(vec![("200 OK", body)]).await;
+
+        let client = ServiceClient::new("billing", &url).bearer("secret");
+        let invoice: Invoice = client
+            .get("/invoices/7")
+            .query(&[("expand", "lines")])
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(invoice, Invoice { id: 7, total: 12.5 });
+
+        let requests = requests.await.unwrap();
+        assert!(requests[0].starts_with("GET /api/invoices/7?expand=lines HTTP/1.1"));
+        assert!(requests[0].contains("authorization: Bearer secret"));
+    }
+
+    #[tokio::test]
+    async fn test_error_envelope() {
+        let body = envelope("008", false, "No such invoice", json!(null));
+        let (url, _) = stub(vec![("404 Not Found", body)]).await;
+
+        let client = ServiceClient::new("billing", &url);
+        let err = client
+            .get("/invoices/8")
+            .try_send::<Invoice>()
+            .await
+            .unwrap()
+            .unwrap_err();
+
+        assert_eq!(err.status, StatusCode::NOT_FOUND);
+        assert_eq!(err.code, Some(ResponseCode::NotFound));
+        assert_eq!(err.message.as_deref(), Some("No such invoice"));
+
+        let err = AppMessage::from(err);
+        assert_eq!(err.status_code(), StatusCode::NOT_FOUND);
+        assert_eq!(err.message(), "No such invoice");
+    }
+
+    #[tokio::test]
+    async fn test_server_errors_become_bad_gateway() {
+        let body = envelope("010", false, "database is down", json!(null));
+        let (url, _) = stub(vec![("500 Internal Server Error", body)]).await;
+
+        let client = ServiceClient::new("billing", &url);
+        let err = client
+            .post("/invoices")
+            .send::<Invoice>()
+            .await
+            .unwrap_err();
+
+        assert_eq!(err.status_code(), StatusCode::BAD_GATEWAY);
+        assert_ne!(err.message(), "database is down");
+    }
+
+    #[tokio::test]
+    async fn test_idempotent_requests_are_retried() {
+        let ok = envelope("000", true, "OK", json!({"id": 1, "total": 3.0}));
+        let (url, requests) = stub(vec![
+            ("503 Service Unavailable", String::new()),
+            ("502 Bad Gateway", String::new()),
+            ("200 OK", ok),
+        ])
+        .await;
+
+        let client = ServiceClient::new("billing", &url).retry(RetryPolicy::new(2));
+        let invoice: Invoice = client
+            .post("/invoices")
+            .json(&json!({"total": 3.0}))
+            .idempotency_key("order-1")
+            .send()
+            .await
+            .unwrap();
+
+        assert_eq!(invoice.id, 1);
+
+        let requests = requests.await.unwrap();
+        assert_eq!(requests.len(), 3);
+        assert!(requests[2].contains("idempotency-key: order-1"));
+        assert!(requests[2].ends_with("{\"total\":3.0}"));
+    }
+
+    #[tokio::test]
+    async fn test_posts_are_not_retried() {
+        let (url, _) = stub(vec![("503 Service Unavailable", String::new())]).await;
+
+        let client = ServiceClient::new("billing", &url).retry(RetryPolicy::new(2));
+        let err = client
+            .post("/invoices")
+            .try_send::<Invoice>()
+            .await
+            .unwrap()
+            .unwrap_err();
+
+        assert_eq!(err.status, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(err.code, None);
+    }
+
+    #[cfg(feature = "hmac")]
+    #[tokio::test]
+    async fn test_hmac_signature() {
+        use crate::http::extractors::verified_webhook::WebhookConfig;
+
+        let ok = envelope("000", true, "OK", json!({"id": 1, "total": 3.0}));
+        let (url, requests) = stub(vec![("200 OK", ok)]).await;
+
+        let client = ServiceClient::new("billing", &url).hmac("shared");
+        let _: Invoice = client
+            .put("/invoices/1")
+            .json(&json!({"total": 3.0}))
+            .send()
+            .await
+            .unwrap();
+
+        let request = requests.await.unwrap().remove(0);
+        let (head, body) = request.split_once("\r\n\r\n").unwrap();
+        let signature = head
+            .lines()
+            .find_map(|line| line.strip_prefix("x-signature: "))
+            .unwrap();
+
+        let config = WebhookConfig::new("shared").timestamped(60);
+        assert!(config.verify(signature, body.as_bytes()).is_ok());
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        let policy = RetryPolicy::new(5).base_delay(Duration::from_millis(100));
+        assert_eq!(policy.delay(1), Duration::from_millis(100));
+        assert_eq!(policy.delay(3), Duration::from_millis(400));
+        assert_eq!(policy.delay(30), Duration::from_secs(5));
+    }
+}
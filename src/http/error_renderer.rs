@@ -0,0 +1,431 @@
+use std::fmt::{Debug, Display, Formatter};
+use std::io;
+use std::str::Utf8Error;
+
+use log::error;
+use ntex::http::body::{BodySize, MessageBody};
+use ntex::http::error::{
+    BlockingError, Canceled, ContentTypeError, HttpError, PayloadError as HttpPayloadError,
+    ResponseError,
+};
+use ntex::http::header::{HeaderValue, ALLOW};
+use ntex::http::StatusCode;
+use ntex::web::error::{
+    JsonPayloadError, PathError, PayloadError, QueryPayloadError, StateExtractorError,
+    UrlGenerationError, UrlencodedError,
+};
+use ntex::web::{
+    DefaultError, ErrorContainer, ErrorRenderer, HttpRequest, HttpResponse, WebResponse,
+    WebResponseError,
+};
+use ntex::ws::error::HandshakeError;
+use ntex_cors::CorsError;
+
+use crate::contracts::ResponseCodeContract;
+use crate::enums::ResponseCode;
+use crate::helpers::responder::Responder;
+use crate::http::middlewares::request_id::REQUEST_ID_HEADER;
+use crate::http::response::problem::into_problem_response;
+use crate::reporting::{self, attach_error_id, ErrorReport};
+
+/// Renders framework-level errors (extractors, payloads, routing...) with the [Responder] envelope
+///
+/// It is the error renderer of the app started by [crate::http::server::start_ntex_server],
+/// services must therefore be registered with [crate::http::ServiceConfig].
+#[derive(Clone, Copy, Default, Debug)]
+pub struct JsonErrorRenderer;
+
+/// Error container of [JsonErrorRenderer]
+pub struct JsonError {
+    cause: Box<dyn WebResponseError<JsonErrorRenderer>>,
+    request_id: Option<String>,
+    error_id: Option<String>,
+    problem_details: bool,
+}
+
+/// Error returned when a handler panicked, details are only logged
+#[derive(Debug)]
+pub struct HandlerPanicked;
+
+impl ErrorRenderer for JsonErrorRenderer {
+    type Container = JsonError;
+}
+
+impl JsonError {
+    pub fn new<T: WebResponseError<JsonErrorRenderer>>(err: T) -> Self {
+        JsonError {
+            cause: Box::new(err),
+            request_id: None,
+            error_id: None,
+            problem_details: false,
+        }
+    }
+
+    pub fn as_response_error(&self) -> &dyn WebResponseError<JsonErrorRenderer> {
+        self.cause.as_ref()
+    }
+
+    pub(crate) fn with_request_id(mut self, request_id: &str) -> Self {
+        self.request_id = Some(request_id.to_string());
+        self
+    }
+
+    pub(crate) fn with_error_id(mut self, error_id: &str) -> Self {
+        self.error_id = Some(error_id.to_string());
+        self
+    }
+
+    pub(crate) fn with_problem_details(mut self, enabled: bool) -> Self {
+        self.problem_details = enabled;
+        self
+    }
+}
+
+impl<T: WebResponseError<JsonErrorRenderer>> From<T> for JsonError {
+    fn from(err: T) -> Self {
+        JsonError::new(err)
+    }
+}
+
+impl ErrorContainer for JsonError {
+    fn error_response(&self, req: &HttpRequest) -> HttpResponse {
+        let response = self.cause.error_response(req);
+        match &self.error_id {
+            Some(error_id) => attach_error_id(response, error_id),
+            None => response,
+        }
+    }
+}
+
+/// Errors escaping app-level middlewares no longer have a request to render against
+impl ResponseError for JsonError {
+    fn error_response(&self) -> HttpResponse {
+        let mut response = render_error(self.cause.status_code(), &self.cause.to_string());
+        if let Some(value) = self
+            .request_id
+            .as_ref()
+            .and_then(|id| HeaderValue::from_str(id).ok())
+        {
+            response.headers_mut().insert(REQUEST_ID_HEADER, value);
+        }
+
+        response = match &self.error_id {
+            Some(error_id) => attach_error_id(response, error_id),
+            None => report_server_error(None, response, &self.cause.to_string()),
+        };
+
+        match self.problem_details {
+            true => into_problem_response(response, self.request_id.as_deref()),
+            false => response,
+        }
+    }
+}
+
+impl Display for JsonError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self.cause, f)
+    }
+}
+
+impl Debug for JsonError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "JsonError({:?})", &self.cause)
+    }
+}
+
+impl Display for HandlerPanicked {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Internal Server Error")
+    }
+}
+
+impl WebResponseError<JsonErrorRenderer> for HandlerPanicked {
+    fn error_response(&self, _: &HttpRequest) -> HttpResponse {
+        Responder::internal_server_error()
+    }
+}
+
+/// Renders an error message with the response code matching the status,
+/// details of server errors are logged rather than sent to the client
+pub fn render_error(status: StatusCode, message: &str) -> HttpResponse {
+    let code = ResponseCode::from_status_or_class(status);
+    match status.is_server_error() {
+        true => {
+            error!("[framework-error] {}", message);
+            Responder::message(
+                status.canonical_reason().unwrap_or("Internal Server Error"),
+                code,
+            )
+        }
+        false => Responder::message(message, code),
+    }
+}
+
+/// [render_error] for a request, server errors are reported and carry an `error_id`
+pub(crate) fn render_request_error(
+    req: &HttpRequest,
+    status: StatusCode,
+    message: &str,
+) -> HttpResponse {
+    report_server_error(Some(req), render_error(status, message), message)
+}
+
+fn report_server_error(
+    req: Option<&HttpRequest>,
+    response: HttpResponse,
+    message: &str,
+) -> HttpResponse {
+    let status = response.status();
+    if !status.is_server_error() {
+        return response;
+    }
+
+    let mut report = ErrorReport::new(
+        status,
+        ResponseCode::from_status_or_class(status).code(),
+        status.canonical_reason().unwrap_or("Internal Server Error"),
+        vec![message.to_string()],
+    );
+
+    if let Some(req) = req {
+        report = report.with_request(req);
+    }
+
+    let response = attach_error_id(response, &report.id);
+    reporting::report(report);
+    response
+}
+
+/// Wraps bodiless error responses produced by ntex itself (e.g. `405` from resources) in the envelope
+pub(crate) fn render_bodiless_error(resp: WebResponse) -> WebResponse {
+    let status = resp.status();
+    if !status.is_client_error() && !status.is_server_error() {
+        return resp;
+    }
+
+    match resp.response().body().size() {
+        BodySize::None | BodySize::Empty | BodySize::Sized(0) => {}
+        _ => return resp,
+    }
+
+    let allow = resp.headers().get(ALLOW).cloned();
+    let mut response = render_request_error(
+        resp.request(),
+        status,
+        status.canonical_reason().unwrap_or("Error"),
+    );
+    if let Some(allow) = allow {
+        response.headers_mut().insert(ALLOW, allow);
+    }
+
+    resp.into_response(response)
+}
+
+macro_rules! render_with_envelope {
+    ($($error:ty),* $(,)?) => {$(
+        impl WebResponseError<JsonErrorRenderer> for $error {
+            fn status_code(&self) -> StatusCode {
+                WebResponseError::<DefaultError>::status_code(self)
+            }
+
+            fn error_response(&self, req: &HttpRequest) -> HttpResponse {
+                render_request_error(
+                    req,
+                    WebResponseError::<JsonErrorRenderer>::status_code(self),
+                    &self.to_string(),
+                )
+            }
+        }
+    )*};
+}
+
+render_with_envelope!(
+    StateExtractorError,
+    JsonPayloadError,
+    PathError,
+    QueryPayloadError,
+    UrlencodedError,
+    UrlGenerationError,
+    PayloadError,
+    HttpPayloadError,
+    ContentTypeError,
+    HttpError,
+    Canceled,
+    Utf8Error,
+    io::Error,
+    serde_json::Error,
+    serde::de::value::Error,
+    CorsError,
+);
+
+impl<E: Debug + 'static> WebResponseError<JsonErrorRenderer> for BlockingError<E> {
+    fn error_response(&self, req: &HttpRequest) -> HttpResponse {
+        render_request_error(req, StatusCode::INTERNAL_SERVER_ERROR, &self.to_string())
+    }
+}
+
+impl WebResponseError<JsonErrorRenderer> for HandshakeError {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            HandshakeError::GetMethodRequired => StatusCode::METHOD_NOT_ALLOWED,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    fn error_response(&self, _: &HttpRequest) -> HttpResponse {
+        let mut response = render_error(
+            WebResponseError::<JsonErrorRenderer>::status_code(self),
+            &self.to_string(),
+        );
+
+        if let HandshakeError::GetMethodRequired = self {
+            response
+                .headers_mut()
+                .insert(ALLOW, HeaderValue::from_static("GET"));
+        }
+
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ntex::web::test::{init_service, read_body, TestRequest};
+    use ntex::web::{self, App};
+    use serde_json::Value;
+
+    use super::*;
+    use crate::helpers::http::TheQueryParams;
+    use crate::http::middlewares::error_boundary::ErrorBoundary;
+    use crate::http::middlewares::request_id::RequestIdentifier;
+
+    async fn body_json(response: WebResponse) -> Value {
+        serde_json::from_slice(&read_body(response).await).unwrap()
+    }
+
+    #[test]
+    fn test_response_code_fallback() {
+        assert_eq!(
+            ResponseCode::from_status_or_class(StatusCode::PAYLOAD_TOO_LARGE).code(),
+            "015"
+        );
+        assert_eq!(
+            ResponseCode::from_status_or_class(StatusCode::LENGTH_REQUIRED).code(),
+            "004"
+        );
+        assert_eq!(
+            ResponseCode::from_status_or_class(StatusCode::BAD_GATEWAY).code(),
+            "023"
+        );
+        assert_eq!(
+            ResponseCode::from_status_or_class(StatusCode::HTTP_VERSION_NOT_SUPPORTED).code(),
+            "010"
+        );
+    }
+
+    #[ntex::test]
+    async fn test_framework_errors_use_envelope() {
+        let app = init_service(
+            App::with(JsonErrorRenderer)
+                .wrap(ErrorBoundary)
+                .wrap(RequestIdentifier)
+                .service(web::resource("/items").route(
+                    web::get().to(|_: TheQueryParams| async { HttpResponse::Ok().finish() }),
+                ))
+                .route(
+                    "/panic",
+                    web::get().to(|| async {
+                        if true {
+                            panic!("boom");
+                        }
+
+                        HttpResponse::Ok().finish()
+                    }),
+                ),
+        )
+        .await;
+
+        let request = TestRequest::with_uri("/items?limit=abc").to_request();
+        let response = app.call(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert!(response.headers().contains_key(REQUEST_ID_HEADER));
+        assert_eq!(body_json(response).await["code"], "004");
+
+        let request = TestRequest::with_uri("/items").method(ntex::http::Method::POST);
+        let response = app.call(request.to_request()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(body_json(response).await["code"], "014");
+
+        let request = TestRequest::with_uri("/panic")
+            .header(REQUEST_ID_HEADER, "req-42")
+            .to_request();
+        let err = app.call(request).await.unwrap_err();
+        let response = ResponseError::error_response(&err);
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(response.headers().get(REQUEST_ID_HEADER).unwrap(), "req-42");
+        let body: Value = match response.body() {
+            ntex::http::body::ResponseBody::Body(ntex::http::body::Body::Bytes(bytes)) => {
+                serde_json::from_slice(bytes).unwrap()
+            }
+            _ => panic!("expected a bytes body"),
+        };
+        assert!(body["error_id"].is_string());
+    }
+
+    #[ntex::test]
+    async fn test_server_errors_carry_error_id() {
+        let app = init_service(
+            App::with(JsonErrorRenderer)
+                .wrap(ErrorBoundary)
+                .wrap(RequestIdentifier)
+                .route(
+                    "/failing",
+                    web::get().to(|| async {
+                        Err::<HttpResponse, _>(crate::prelude::AppMessage::IoError(
+                            io::Error::other("disk is full"),
+                        ))
+                    }),
+                )
+                .route(
+                    "/bodiless",
+                    web::get().to(|| async { HttpResponse::BadGateway().finish() }),
+                )
+                .route(
+                    "/blocking",
+                    web::get().to(|| async {
+                        Err::<HttpResponse, _>(BlockingError::Error(io::Error::other(
+                            "pool is gone",
+                        )))
+                    }),
+                ),
+        )
+        .await;
+
+        let response = app
+            .call(TestRequest::with_uri("/failing").to_request())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        let body = body_json(response).await;
+        assert_eq!(body["code"], "010");
+        assert_eq!(body["message"], "Internal Server Error");
+        assert_eq!(body["error_id"].as_str().unwrap().len(), 36);
+
+        // framework-level server errors are reported too
+        for uri in ["/bodiless", "/blocking"] {
+            let response = app
+                .call(TestRequest::with_uri(uri).to_request())
+                .await
+                .unwrap();
+            assert!(response.status().is_server_error());
+            assert_eq!(
+                body_json(response).await["error_id"]
+                    .as_str()
+                    .unwrap()
+                    .len(),
+                36
+            );
+        }
+    }
+}
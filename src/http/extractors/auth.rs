@@ -0,0 +1,347 @@
+use std::env;
+use std::ops::Deref;
+
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::TokenData;
+use log::debug;
+use ntex::http::header::{AUTHORIZATION, COOKIE};
+use ntex::http::Payload;
+use ntex::web::{FromRequest, HttpRequest};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::helpers::jwt::{Jwt, JwtTokenClaims, Validation};
+use crate::prelude::{AppMessage, AppResult, OnceLockHelper};
+use crate::MEDULLAH;
+
+/// How bearer tokens are found and validated
+///
+/// Loaded from the environment:
+/// - `{PREFIX}_AUTH_ISSUER`: expected `iss`, not checked when missing
+/// - `{PREFIX}_AUTH_AUDIENCE`: comma separated list of accepted `aud`, not checked when missing
+/// - `{PREFIX}_AUTH_COOKIE`: cookie the token is read from when there's no `Authorization` header
+/// - `{PREFIX}_AUTH_LEEWAY`: accepted clock skew (in seconds) when checking `exp`, defaults to 60
+#[derive(Clone, Debug)]
+pub struct AuthConfig {
+    pub issuer: Option<String>,
+    pub audience: Vec<String>,
+    pub cookie: Option<String>,
+    pub leeway: u64,
+}
+
+/// Claims of the authenticated principal, from a JWT or a personal access token
+///
+/// Resolved from the claims stashed by [crate::http::middlewares::auth::Authenticate]
+/// or, when the route isn't guarded, by validating the token of the request.
+///
+/// ```ignore
+/// async fn profile(auth: Auth) -> HttpResult {
+///     let user = user_service.find(&auth.sub)?;
+///     ...
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Auth<C = JwtTokenClaims>(pub C);
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig {
+            issuer: None,
+            audience: vec![],
+            cookie: None,
+            leeway: 60,
+        }
+    }
+}
+
+impl AuthConfig {
+    pub fn from_env(env_prefix: &str) -> Self {
+        let var = |name: &str| {
+            env::var(format!("{}_AUTH_{}", env_prefix, name))
+                .ok()
+                .filter(|v| !v.trim().is_empty())
+        };
+
+        AuthConfig {
+            issuer: var("ISSUER"),
+            audience: var("AUDIENCE")
+                .map(|list| {
+                    list.split(',')
+                        .map(|v| v.trim().to_string())
+                        .filter(|v| !v.is_empty())
+                        .collect()
+                })
+                .unwrap_or_default(),
+            cookie: var("COOKIE"),
+            leeway: var("LEEWAY")
+                .map(|v| v.parse().expect("invalid auth leeway"))
+                .unwrap_or(60),
+        }
+    }
+
+    pub fn validation(&self) -> Validation {
+        // the algorithm is pinned by the key verifying the token, see [Jwt::decode]
+        let mut validation = Validation::default();
+        validation.leeway = self.leeway;
+        validation.set_required_spec_claims(&["exp"]);
+
+        if let Some(issuer) = &self.issuer {
+            validation.set_issuer(&[issuer]);
+        }
+
+        match self.audience.is_empty() {
+            true => validation.validate_aud = false,
+            false => validation.set_audience(&self.audience),
+        }
+
+        validation
+    }
+
+    /// Token sent as `Authorization: Bearer ...` or, when configured, in the auth cookie
+    pub fn token(&self, req: &HttpRequest) -> Option<String> {
+        let bearer = req
+            .headers()
+            .get(AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer ").or(v.strip_prefix("bearer ")))
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty());
+
+        bearer.or_else(|| {
+            let name = self.cookie.as_deref()?;
+            req.headers()
+                .get_all(COOKIE)
+                .filter_map(|v| v.to_str().ok())
+                .find_map(|header| cookie_value(header, name))
+        })
+    }
+
+    pub fn decode<C: DeserializeOwned>(&self, jwt: &Jwt, token: &str) -> AppResult<C> {
+        Self::claims(jwt.decode::<C>(token, &self.validation()))
+    }
+
+    /// Same as [AuthConfig::decode], fetching the keys of the trusted JWKS when needed
+    pub async fn verify<C: DeserializeOwned>(&self, jwt: &Jwt, token: &str) -> AppResult<C> {
+        Self::claims(jwt.verify::<C>(token, &self.validation()).await)
+    }
+
+    fn claims<C>(result: AppResult<TokenData<C>>) -> AppResult<C> {
+        match result {
+            Ok(data) => Ok(data.claims),
+            Err(AppMessage::JwtError(err)) => {
+                debug!("[auth] token rejected: {:?}", err);
+                Err(match err.kind() {
+                    ErrorKind::ExpiredSignature => {
+                        AppMessage::UnAuthorizedMessage("authentication token has expired")
+                    }
+                    ErrorKind::InvalidIssuer | ErrorKind::InvalidAudience => {
+                        AppMessage::UnAuthorizedMessage(
+                            "authentication token was not issued for this service",
+                        )
+                    }
+                    _ => AppMessage::UnAuthorizedMessage("invalid authentication token"),
+                })
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+impl<C> Auth<C> {
+    pub fn into_inner(self) -> C {
+        self.0
+    }
+}
+
+impl<C: DeserializeOwned + Clone + 'static> Auth<C> {
+    /// Claims stashed on the request, authenticating it first if needed
+    ///
+    /// The token is verified once per request, the raw claims are stashed as `Auth<Value>`
+    /// and every other claims type is read from them.
+    pub async fn resolve(req: &HttpRequest) -> AppResult<Self> {
+        if let Some(auth) = req.extensions().get::<Auth<C>>() {
+            return Ok(auth.clone());
+        }
+
+        let stashed = req
+            .extensions()
+            .get::<Auth<Value>>()
+            .map(|raw| raw.0.clone());
+        let claims = match stashed {
+            Some(claims) => claims,
+            None => {
+                let token = MEDULLAH
+                    .app()
+                    .auth
+                    .token(req)
+                    .ok_or(AppMessage::UnAuthorized)?;
+
+                let claims = authenticate::<Value>(&token).await?;
+                req.extensions_mut().insert(Auth(claims.clone()));
+                claims
+            }
+        };
+
+        let auth = Auth(serde_json::from_value::<C>(claims).map_err(|err| {
+            debug!("[auth] claims rejected: {}", err);
+            AppMessage::UnAuthorizedMessage("invalid authentication token")
+        })?);
+        req.extensions_mut().insert(auth.clone());
+        Ok(auth)
+    }
+}
+
+impl<C> Deref for Auth<C> {
+    type Target = C;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<C: DeserializeOwned + Clone + 'static, Err> FromRequest<Err> for Auth<C> {
+    type Error = AppMessage;
+
+    async fn from_request(req: &HttpRequest, _: &mut Payload) -> AppResult<Self> {
+        Auth::resolve(req).await
+    }
+}
+
+/// Claims of the principal a bearer token belongs to
+///
+/// JWTs are validated against the app's [AuthConfig], personal access tokens (recognised by
+/// their prefix) are looked up through [crate::services::pat_service::PatService] and resolve
+/// to the same claims shape, with an extra `scopes` list.
+pub async fn authenticate<C: DeserializeOwned>(token: &str) -> AppResult<C> {
+    let app = MEDULLAH.app();
+
+    #[cfg(feature = "pat")]
+    if let Some(pat_service) = app.services.pat.as_ref().filter(|pat| pat.is_pat(token)) {
+        let pat = pat_service.verify(token).await?;
+        return serde_json::from_value(pat.claims(&app.auth, &app.app_id)).map_err(|err| {
+            debug!("[auth] personal access token {} rejected: {}", pat.id, err);
+            AppMessage::UnAuthorizedMessage("personal access tokens cannot be used here")
+        });
+    }
+
+    app.auth.verify(&app.helpers.jwt, token).await
+}
+
+fn cookie_value(header: &str, name: &str) -> Option<String> {
+    header
+        .split(';')
+        .filter_map(|pair| pair.trim().split_once('='))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| value.trim_matches('"').to_string())
+        .filter(|value| !value.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use ntex::web::test::TestRequest;
+
+    use super::*;
+
+    fn jwt() -> Jwt {
+        let (public_key, private_key) = crate::helpers::jwt::tests::get_sample_keys();
+        Jwt::new(public_key, private_key, 60)
+    }
+
+    fn token(iss: &str, aud: &str, expires_in: i64) -> String {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+
+        let claims = JwtTokenClaims {
+            sub: "user-1".to_string(),
+            iat: now as usize,
+            exp: (now + expires_in) as usize,
+            iss: iss.to_string(),
+            aud: aud.to_string(),
+            jti: "jti".to_string(),
+        };
+
+        jwt().generate(claims).unwrap().access_token
+    }
+
+    fn config() -> AuthConfig {
+        AuthConfig {
+            issuer: Some("accounts".to_string()),
+            audience: vec!["api".to_string()],
+            cookie: Some("session".to_string()),
+            leeway: 0,
+        }
+    }
+
+    #[test]
+    fn test_token_sources() {
+        let config = config();
+
+        let req = TestRequest::default()
+            .header(AUTHORIZATION, "Bearer abc")
+            .header(COOKIE, "session=def")
+            .to_http_request();
+        assert_eq!(config.token(&req), Some("abc".to_string()));
+
+        let req = TestRequest::default()
+            .header(COOKIE, "theme=dark; session=def")
+            .to_http_request();
+        assert_eq!(config.token(&req), Some("def".to_string()));
+
+        let req = TestRequest::default()
+            .header(COOKIE, "session=def")
+            .to_http_request();
+        assert_eq!(AuthConfig::default().token(&req), None);
+    }
+
+    #[ntex::test]
+    async fn test_claims_are_read_from_stashed_raw_claims() {
+        let req = TestRequest::default().to_http_request();
+        req.extensions_mut().insert(Auth(serde_json::json!({
+            "sub": "user-1",
+            "iat": 0,
+            "exp": 0,
+            "iss": "accounts",
+            "aud": "api",
+            "jti": "jti",
+            "roles": ["admin"],
+        })));
+
+        // no token on the request, the stashed claims are used as is
+        let auth = Auth::<JwtTokenClaims>::resolve(&req).await.unwrap();
+        assert_eq!(auth.sub, "user-1");
+        assert!(req.extensions().get::<Auth<JwtTokenClaims>>().is_some());
+
+        let req = TestRequest::default().to_http_request();
+        req.extensions_mut()
+            .insert(Auth(serde_json::json!({ "sub": "user-1" })));
+        assert!(Auth::<JwtTokenClaims>::resolve(&req).await.is_err());
+    }
+
+    #[test]
+    fn test_claims_are_validated() {
+        let (config, jwt) = (config(), jwt());
+
+        let claims: JwtTokenClaims = config.decode(&jwt, &token("accounts", "api", 300)).unwrap();
+        assert_eq!(claims.sub, "user-1");
+
+        let rejected = [
+            token("accounts", "api", -300),
+            token("someone-else", "api", 300),
+            token("accounts", "admin", 300),
+            "not-a-token".to_string(),
+        ];
+
+        for token in rejected {
+            let err = config.decode::<JwtTokenClaims>(&jwt, &token).unwrap_err();
+            assert!(
+                matches!(err, AppMessage::UnAuthorizedMessage(_)),
+                "{:?}",
+                err
+            );
+        }
+    }
+}
@@ -0,0 +1,337 @@
+use std::marker::PhantomData;
+use std::str::FromStr;
+
+use ntex::http::{Payload, StatusCode};
+use ntex::web::{FromRequest, HttpRequest};
+
+use crate::prelude::{AppMessage, AppResult};
+
+/// Comparison applied by a filter, sent as `?field[op]=value` (`eq` when omitted)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FilterOp {
+    Eq,
+    /// comma separated list of values
+    In,
+    Gte,
+    Lte,
+    /// case-insensitive "contains"
+    Like,
+}
+
+/// A field clients are allowed to filter on, see [crate::generate_query_filters]
+pub struct FilterField {
+    pub name: &'static str,
+    pub ops: &'static [FilterOp],
+    /// whether the raw value can be parsed into the field's type
+    pub validate: fn(&str) -> bool,
+}
+
+/// Whitelist of the filters and sort columns an endpoint accepts
+pub trait FilterSchema {
+    const FIELDS: &'static [FilterField];
+    const SORTABLE: &'static [&'static str];
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Filter {
+    pub field: &'static str,
+    pub op: FilterOp,
+    pub values: Vec<String>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SortDirection {
+    Asc,
+    Desc,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Sort {
+    pub column: &'static str,
+    pub direction: SortDirection,
+}
+
+/// Query string validated against a [FilterSchema]
+///
+/// ```text
+/// ?status[in]=active,pending&age[gte]=18&name[like]=ada&sort=-created_at,name&page=2&limit=20
+/// ```
+///
+/// Fields, operators and sort columns that are not declared by the schema are rejected with `400`,
+/// as are values that cannot be parsed into the declared type.
+/// `page`, `limit` and `search` are always accepted.
+pub struct Filters<S> {
+    filters: Vec<Filter>,
+    sort: Vec<Sort>,
+    search: Option<String>,
+    page: Option<i64>,
+    limit: Option<i64>,
+    _schema: PhantomData<S>,
+}
+
+impl FilterOp {
+    pub fn parse(op: &str) -> Option<Self> {
+        match op {
+            "eq" => Some(FilterOp::Eq),
+            "in" => Some(FilterOp::In),
+            "gte" => Some(FilterOp::Gte),
+            "lte" => Some(FilterOp::Lte),
+            "like" => Some(FilterOp::Like),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FilterOp::Eq => "eq",
+            FilterOp::In => "in",
+            FilterOp::Gte => "gte",
+            FilterOp::Lte => "lte",
+            FilterOp::Like => "like",
+        }
+    }
+}
+
+impl Filter {
+    /// Values parsed into the field type, the schema has already checked they can be
+    pub fn parsed<T: FromStr>(&self) -> Vec<T> {
+        self.values.iter().filter_map(|v| v.parse().ok()).collect()
+    }
+
+    pub fn first<T: FromStr>(&self) -> Option<T> {
+        self.values.first().and_then(|v| v.parse().ok())
+    }
+}
+
+impl<S: FilterSchema> Filters<S> {
+    pub fn parse(query: &str) -> AppResult<Self> {
+        let mut filters: Vec<Filter> = vec![];
+        let mut sort = vec![];
+        let mut search = None;
+        let mut page = None;
+        let mut limit = None;
+
+        for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+            match key.as_ref() {
+                "search" => search = Some(value.to_string()).filter(|v| !v.is_empty()),
+                "page" => page = Some(parse_number("page", &value)?),
+                "limit" => limit = Some(parse_number("limit", &value)?),
+                "sort" => sort = parse_sort::<S>(&value)?,
+                key => {
+                    let filter = parse_filter::<S>(key, &value)?;
+                    filters.retain(|f| f.field != filter.field || f.op != filter.op);
+                    filters.push(filter);
+                }
+            }
+        }
+
+        Ok(Filters {
+            filters,
+            sort,
+            search,
+            page,
+            limit,
+            _schema: PhantomData,
+        })
+    }
+
+    pub fn filters(&self) -> &[Filter] {
+        &self.filters
+    }
+
+    pub fn get(&self, field: &str, op: FilterOp) -> Option<&Filter> {
+        self.filters.iter().find(|f| f.field == field && f.op == op)
+    }
+
+    /// Parsed value of the given filter
+    pub fn value<T: FromStr>(&self, field: &str, op: FilterOp) -> Option<T> {
+        self.get(field, op).and_then(|f| f.first())
+    }
+
+    pub fn sort(&self) -> &[Sort] {
+        &self.sort
+    }
+
+    pub fn search(&self) -> Option<&str> {
+        self.search.as_deref()
+    }
+
+    pub fn page(&self) -> i64 {
+        self.page.unwrap_or(1).max(1)
+    }
+
+    pub fn limit(&self) -> i64 {
+        self.limit.unwrap_or(10).clamp(1, 150)
+    }
+}
+
+impl<S: FilterSchema, Err> FromRequest<Err> for Filters<S> {
+    type Error = AppMessage;
+
+    async fn from_request(req: &HttpRequest, _: &mut Payload) -> AppResult<Self> {
+        Filters::parse(req.query_string())
+    }
+}
+
+/// Escapes `%`, `_` and `\` so that user input is matched literally by `LIKE`
+pub fn escape_like(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for char in value.chars() {
+        if matches!(char, '%' | '_' | '\\') {
+            escaped.push('\\');
+        }
+
+        escaped.push(char);
+    }
+
+    escaped
+}
+
+fn parse_filter<S: FilterSchema>(key: &str, value: &str) -> AppResult<Filter> {
+    let (name, op) = match key.split_once('[') {
+        Some((name, op)) => {
+            let op = op
+                .strip_suffix(']')
+                .and_then(FilterOp::parse)
+                .ok_or_else(|| bad_request(format!("invalid filter operator in '{}'", key)))?;
+
+            (name, op)
+        }
+        None => (key, FilterOp::Eq),
+    };
+
+    let field = S::FIELDS
+        .iter()
+        .find(|f| f.name == name)
+        .ok_or_else(|| bad_request(format!("unknown filter '{}'", name)))?;
+
+    if !field.ops.contains(&op) {
+        return Err(bad_request(format!(
+            "filter '{}' does not support '{}'",
+            name,
+            op.as_str()
+        )));
+    }
+
+    let values = match op {
+        FilterOp::In => value
+            .split(',')
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+            .collect::<Vec<_>>(),
+        _ => vec![value.to_string()],
+    };
+
+    let is_valid = match op {
+        // like patterns are matched against text
+        FilterOp::Like => true,
+        _ => !values.is_empty() && values.iter().all(|v| (field.validate)(v)),
+    };
+
+    match is_valid {
+        true => Ok(Filter {
+            field: field.name,
+            op,
+            values,
+        }),
+        false => Err(bad_request(format!("invalid value for filter '{}'", name))),
+    }
+}
+
+fn parse_sort<S: FilterSchema>(value: &str) -> AppResult<Vec<Sort>> {
+    value
+        .split(',')
+        .map(|column| column.trim())
+        .filter(|column| !column.is_empty())
+        .map(|column| {
+            let (name, direction) = match column.strip_prefix('-') {
+                Some(name) => (name, SortDirection::Desc),
+                None => (column, SortDirection::Asc),
+            };
+
+            S::SORTABLE
+                .iter()
+                .find(|sortable| **sortable == name)
+                .map(|column| Sort { column, direction })
+                .ok_or_else(|| bad_request(format!("cannot sort by '{}'", name)))
+        })
+        .collect()
+}
+
+fn parse_number(name: &str, value: &str) -> AppResult<i64> {
+    value
+        .parse()
+        .map_err(|_| bad_request(format!("'{}' must be a number", name)))
+}
+
+fn bad_request(message: String) -> AppMessage {
+    AppMessage::ErrorMessage(message, StatusCode::BAD_REQUEST)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate_query_filters;
+
+    generate_query_filters! {
+        struct UserFilters {
+            name: String [Eq, Like],
+            age: i32 [Eq, Gte, Lte, In],
+        }
+        sort [created_at, name]
+    }
+
+    #[test]
+    fn test_parse() {
+        let filters = Filters::<UserFilters>::parse(
+            "name%5Blike%5D=ad%25a&age[in]=18,21&sort=-created_at,name&page=2&search=x",
+        )
+        .unwrap();
+
+        assert_eq!(
+            filters.get("name", FilterOp::Like).unwrap().values,
+            vec!["ad%a"]
+        );
+        assert_eq!(
+            filters.get("age", FilterOp::In).unwrap().parsed::<i32>(),
+            vec![18, 21]
+        );
+        assert_eq!(
+            filters.sort(),
+            &[
+                Sort {
+                    column: "created_at",
+                    direction: SortDirection::Desc
+                },
+                Sort {
+                    column: "name",
+                    direction: SortDirection::Asc
+                }
+            ]
+        );
+        assert_eq!(filters.page(), 2);
+        assert_eq!(filters.limit(), 10);
+        assert_eq!(filters.search(), Some("x"));
+    }
+
+    #[test]
+    fn test_rejects_what_is_not_whitelisted() {
+        for query in [
+            "email=a@b.c",
+            "name[gte]=a",
+            "age[between]=1",
+            "age=abc",
+            "age[in]=1,x",
+            "sort=password",
+            "limit=ten",
+        ] {
+            let err = Filters::<UserFilters>::parse(query).err().unwrap();
+            assert_eq!(err.status_code(), StatusCode::BAD_REQUEST, "{}", query);
+        }
+    }
+
+    #[test]
+    fn test_escape_like() {
+        assert_eq!(escape_like(r"50%_off\"), r"50\%\_off\\");
+    }
+}
@@ -1,2 +1,11 @@
+#[cfg(feature = "jwt")]
+pub mod auth;
 pub mod client_info;
+pub mod filters;
 pub mod json_body;
+#[cfg(feature = "multipart")]
+pub mod upload;
+#[cfg(feature = "validator")]
+pub mod valid_json;
+#[cfg(feature = "hmac")]
+pub mod verified_webhook;
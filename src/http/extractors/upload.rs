@@ -0,0 +1,596 @@
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use chrono::{Datelike, Utc};
+use futures_util::StreamExt;
+use log::{debug, error};
+use medullah_multipart::MultipartError;
+use ntex::http::header::{HeaderValue, CONTENT_DISPOSITION, CONTENT_TYPE};
+use ntex::http::{HeaderMap, Payload, StatusCode};
+use ntex::web::{FromRequest, HttpRequest};
+use ntex_multipart::{Field, Multipart};
+use sha2::{Digest, Sha256};
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+use crate::prelude::{AppMessage, AppResult, OnceLockHelper};
+use crate::storage::{Storage, StoredFile};
+use crate::MEDULLAH;
+
+/// bytes looked at when sniffing the content type
+const SNIFF_LEN: usize = 512;
+
+/// A file field an endpoint accepts
+pub struct UploadField {
+    pub name: &'static str,
+    /// in bytes
+    pub max_size: u64,
+    /// allowed content types, `image/*` style wildcards are supported, empty allows anything
+    pub mimes: &'static [&'static str],
+    pub required: bool,
+    pub max_files: usize,
+}
+
+/// Whitelist of the file fields an endpoint accepts and where they are stored
+///
+/// ```ignore
+/// struct AvatarUpload;
+///
+/// impl UploadSchema for AvatarUpload {
+///     const FIELDS: &'static [UploadField] = &[UploadField {
+///         name: "avatar",
+///         max_size: 2 * 1024 * 1024,
+///         mimes: &["image/png", "image/jpeg"],
+///         required: true,
+///         max_files: 1,
+///     }];
+///     const DIRECTORY: &'static str = "avatars";
+/// }
+///
+/// async fn upload(uploads: Uploads<AvatarUpload>) -> HttpResult {
+///     let avatar = uploads.file("avatar").unwrap();
+///     ...
+/// }
+/// ```
+pub trait UploadSchema {
+    const FIELDS: &'static [UploadField];
+
+    /// key prefix of stored files
+    const DIRECTORY: &'static str = "uploads";
+
+    /// size limit of each non-file field (in bytes)
+    const MAX_FIELD_SIZE: usize = 64 * 1024;
+
+    /// how many non-file fields a request may carry
+    const MAX_FIELDS: usize = 32;
+
+    fn storage() -> Arc<dyn Storage> {
+        MEDULLAH.app().services.storage.clone()
+    }
+}
+
+/// `multipart/form-data` request whose files have been streamed to the schema's [Storage]
+///
+/// The content type of every file is sniffed from its first bytes, the type declared by the
+/// client is ignored. Oversized files and fields (or too many fields) are rejected with `413`,
+/// disallowed types with `415`, unexpected or missing fields with `400`. Files already stored
+/// are removed when the request is rejected.
+///
+/// The body is parsed by `ntex-multipart`, malformed bodies are reported as
+/// [AppMessage::MultipartError]. [medullah_multipart::Multipart] isn't used as it buffers
+/// whole files in memory before any limit can be checked.
+pub struct Uploads<S> {
+    files: Vec<StoredFile>,
+    fields: HashMap<String, String>,
+    _schema: PhantomData<S>,
+}
+
+impl<S: UploadSchema> Uploads<S> {
+    pub fn files(&self) -> &[StoredFile] {
+        &self.files
+    }
+
+    pub fn into_files(self) -> Vec<StoredFile> {
+        self.files
+    }
+
+    /// First file uploaded with the given field
+    pub fn file(&self, field: &str) -> Option<&StoredFile> {
+        self.files.iter().find(|f| f.field == field)
+    }
+
+    pub fn files_of(&self, field: &str) -> Vec<&StoredFile> {
+        self.files.iter().filter(|f| f.field == field).collect()
+    }
+
+    /// Value of a non-file field
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields.get(name).map(String::as_str)
+    }
+
+    pub fn fields(&self) -> &HashMap<String, String> {
+        &self.fields
+    }
+
+    pub async fn from_payload(content_type: &str, payload: &mut Payload) -> AppResult<Self> {
+        let mime = content_type.split(';').next().unwrap_or_default();
+        let content_type = HeaderValue::from_str(content_type)
+            .ok()
+            .filter(|_| mime.trim().eq_ignore_ascii_case("multipart/form-data"))
+            .ok_or_else(|| {
+                AppMessage::ErrorMessage(
+                    "expected a multipart/form-data body".to_string(),
+                    StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                )
+            })?;
+
+        let mut headers = HeaderMap::new();
+        headers.insert(CONTENT_TYPE, content_type);
+
+        let storage = S::storage();
+        let mut uploads = Uploads {
+            files: vec![],
+            fields: HashMap::new(),
+            _schema: PhantomData,
+        };
+
+        let mut multipart = Multipart::new(&headers, payload.take());
+        if let Err(err) = uploads.read(&mut multipart, storage.as_ref()).await {
+            for file in &uploads.files {
+                if let Err(err) = storage.delete(&file.key).await {
+                    error!("[upload] failed to clean up {}: {:?}", file.key, err);
+                }
+            }
+
+            return Err(err);
+        }
+
+        Ok(uploads)
+    }
+
+    async fn read(&mut self, multipart: &mut Multipart, storage: &dyn Storage) -> AppResult<()> {
+        let mut field_count = 0;
+        while let Some(field) = multipart.next().await {
+            let mut field = field.map_err(MultipartError::NtexError)?;
+            let part = PartHeaders::from_field(&field)?;
+
+            if part.file_name.is_none() {
+                field_count += 1;
+                if field_count > S::MAX_FIELDS {
+                    return Err(too_large(format!(
+                        "at most {} fields can be sent",
+                        S::MAX_FIELDS
+                    )));
+                }
+
+                let value = read_field::<S>(&mut field, &part.name).await?;
+                self.fields.insert(part.name, value);
+                continue;
+            }
+
+            let rule = S::FIELDS
+                .iter()
+                .find(|f| f.name == part.name)
+                .ok_or_else(|| bad_request(format!("unexpected file field '{}'", part.name)))?;
+
+            if self.files_of(rule.name).len() >= rule.max_files {
+                return Err(bad_request(format!(
+                    "at most {} file(s) can be uploaded as '{}'",
+                    rule.max_files, rule.name
+                )));
+            }
+
+            let file = store_file::<S>(&mut field, part, rule, storage).await?;
+            self.files.push(file);
+        }
+
+        for rule in S::FIELDS.iter().filter(|f| f.required) {
+            if self.file(rule.name).is_none() {
+                return Err(bad_request(format!("file '{}' is required", rule.name)));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<S: UploadSchema, Err> FromRequest<Err> for Uploads<S> {
+    type Error = AppMessage;
+
+    async fn from_request(req: &HttpRequest, payload: &mut Payload) -> AppResult<Self> {
+        let content_type = req
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+
+        Self::from_payload(content_type, payload).await
+    }
+}
+
+/// Field name and file name of a part, from its `Content-Disposition`
+struct PartHeaders {
+    name: String,
+    file_name: Option<String>,
+}
+
+impl PartHeaders {
+    fn from_field(field: &Field) -> AppResult<Self> {
+        let invalid = |reason: &str| -> AppMessage {
+            MultipartError::InvalidContentDisposition(reason.to_string()).into()
+        };
+
+        let disposition = field
+            .headers()
+            .get(CONTENT_DISPOSITION)
+            .and_then(|v| v.to_str().ok())
+            .ok_or_else(|| invalid("parts must have a content disposition"))?;
+
+        let (kind, params) = disposition.split_once(';').unwrap_or((disposition, ""));
+        if !kind.trim().eq_ignore_ascii_case("form-data") {
+            return Err(invalid("parts must be form-data"));
+        }
+
+        let mut headers = PartHeaders {
+            name: String::new(),
+            file_name: None,
+        };
+
+        for (name, value) in parse_params(params) {
+            match name.to_ascii_lowercase().as_str() {
+                "name" => headers.name = value,
+                "filename" => headers.file_name = Some(value),
+                _ => {}
+            }
+        }
+
+        match headers.name.is_empty() {
+            true => Err(invalid("part is missing its name")),
+            false => Ok(headers),
+        }
+    }
+}
+
+/// `; name="value"; other=value` pairs, semicolons may appear in quoted values
+fn parse_params(params: &str) -> Vec<(String, String)> {
+    let mut pairs = vec![];
+    let mut chars = params.chars().peekable();
+
+    loop {
+        while chars.next_if(|c| *c == ';' || c.is_whitespace()).is_some() {}
+
+        let name: String = chars.by_ref().take_while(|c| *c != '=').collect();
+        if name.trim().is_empty() {
+            return pairs;
+        }
+
+        let mut value = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' => break,
+                    '\\' => value.extend(chars.next()),
+                    c => value.push(c),
+                }
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| *c != ';') {
+                value.push(c);
+            }
+        }
+
+        pairs.push((name.trim().to_string(), value.trim().to_string()));
+    }
+}
+
+/// Content type of a file, from its magic bytes
+pub fn sniff_mime(head: &[u8]) -> &'static str {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"%PDF-", "application/pdf"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"\x1f\x8b", "application/gzip"),
+        (b"ID3", "audio/mpeg"),
+    ];
+
+    if let Some((_, mime)) = SIGNATURES.iter().find(|(sig, _)| head.starts_with(sig)) {
+        return mime;
+    }
+
+    if head.len() >= 12 && head.starts_with(b"RIFF") {
+        match &head[8..12] {
+            b"WEBP" => return "image/webp",
+            b"WAVE" => return "audio/wav",
+            _ => {}
+        }
+    }
+
+    if head.len() >= 8 && &head[4..8] == b"ftyp" {
+        return "video/mp4";
+    }
+
+    let is_text = match std::str::from_utf8(head) {
+        Ok(text) => !text.contains('\0'),
+        // a multibyte character cut at the end of the sniffed bytes
+        Err(err) => err.error_len().is_none() && head.len() >= SNIFF_LEN,
+    };
+
+    match is_text && !head.is_empty() {
+        true => "text/plain",
+        false => "application/octet-stream",
+    }
+}
+
+fn extension(mime: &str) -> &'static str {
+    match mime {
+        "image/png" => "png",
+        "image/jpeg" => "jpg",
+        "image/gif" => "gif",
+        "image/webp" => "webp",
+        "application/pdf" => "pdf",
+        "application/zip" => "zip",
+        "application/gzip" => "gz",
+        "audio/mpeg" => "mp3",
+        "audio/wav" => "wav",
+        "video/mp4" => "mp4",
+        "text/plain" => "txt",
+        _ => "bin",
+    }
+}
+
+fn is_allowed(rule: &UploadField, mime: &str) -> bool {
+    rule.mimes.is_empty()
+        || rule
+            .mimes
+            .iter()
+            .any(|allowed| match allowed.strip_suffix("/*") {
+                Some(kind) => mime.split('/').next() == Some(kind),
+                None => *allowed == mime,
+            })
+}
+
+async fn read_field<S: UploadSchema>(field: &mut Field, name: &str) -> AppResult<String> {
+    let mut value = vec![];
+    while let Some(chunk) = field.next().await {
+        let chunk = chunk.map_err(MultipartError::NtexError)?;
+        if value.len() + chunk.len() > S::MAX_FIELD_SIZE {
+            return Err(too_large(format!("field '{}' is too large", name)));
+        }
+
+        value.extend_from_slice(&chunk);
+    }
+
+    Ok(String::from_utf8(value)?)
+}
+
+/// Streams the part to a temp file, then hands it over to the storage
+async fn store_file<S: UploadSchema>(
+    field: &mut Field,
+    part: PartHeaders,
+    rule: &UploadField,
+    storage: &dyn Storage,
+) -> AppResult<StoredFile> {
+    let temp = TempFile::new();
+    let mut writer = File::create(&temp.0).await?;
+    let mut hasher = Sha256::new();
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    let mut content_type = None;
+    let mut size = 0u64;
+
+    while let Some(chunk) = field.next().await {
+        let chunk = chunk.map_err(MultipartError::NtexError)?;
+        size += chunk.len() as u64;
+        if size > rule.max_size {
+            return Err(too_large(format!(
+                "'{}' must not exceed {} bytes",
+                rule.name, rule.max_size
+            )));
+        }
+
+        if content_type.is_none() {
+            let missing = SNIFF_LEN - head.len();
+            head.extend_from_slice(&chunk[..chunk.len().min(missing)]);
+            if head.len() == SNIFF_LEN {
+                content_type = Some(check_mime(rule, &head)?);
+            }
+        }
+
+        hasher.update(&chunk);
+        writer.write_all(&chunk).await?;
+    }
+
+    writer.flush().await?;
+    drop(writer);
+
+    let content_type = match content_type {
+        Some(content_type) => content_type,
+        None => check_mime(rule, &head)?,
+    };
+
+    let now = Utc::now();
+    let file = StoredFile {
+        key: format!(
+            "{}/{}/{:02}/{}.{}",
+            S::DIRECTORY.trim_matches('/'),
+            now.year(),
+            now.month(),
+            Uuid::new_v4(),
+            extension(content_type)
+        ),
+        field: part.name,
+        file_name: part.file_name,
+        size,
+        checksum: hex::encode(hasher.finalize()),
+        content_type: content_type.to_string(),
+    };
+
+    storage.put(&temp.0, &file).await?;
+    debug!("[upload] '{}' stored as {}", file.field, file.key);
+    Ok(file)
+}
+
+fn check_mime(rule: &UploadField, head: &[u8]) -> AppResult<&'static str> {
+    let mime = sniff_mime(head);
+    match is_allowed(rule, mime) {
+        true => Ok(mime),
+        false => Err(AppMessage::ErrorMessage(
+            format!("'{}' does not accept {} files", rule.name, mime),
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        )),
+    }
+}
+
+fn bad_request(message: String) -> AppMessage {
+    AppMessage::ErrorMessage(message, StatusCode::BAD_REQUEST)
+}
+
+fn too_large(message: String) -> AppMessage {
+    AppMessage::ErrorMessage(message, StatusCode::PAYLOAD_TOO_LARGE)
+}
+
+/// Removed when dropped, unless the storage moved it away already
+struct TempFile(PathBuf);
+
+impl TempFile {
+    fn new() -> Self {
+        TempFile(std::env::temp_dir().join(format!("medullah-upload-{}", Uuid::new_v4())))
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ntex::util::Bytes;
+    use ntex::web::test::TestRequest;
+
+    use super::*;
+    use crate::storage::LocalStorage;
+
+    struct AvatarUpload;
+
+    impl UploadSchema for AvatarUpload {
+        const FIELDS: &'static [UploadField] = &[UploadField {
+            name: "avatar",
+            max_size: 1024,
+            mimes: &["image/*"],
+            required: true,
+            max_files: 1,
+        }];
+
+        const DIRECTORY: &'static str = "avatars";
+
+        fn storage() -> Arc<dyn Storage> {
+            Arc::new(LocalStorage::new(storage_root()))
+        }
+    }
+
+    fn storage_root() -> PathBuf {
+        std::env::temp_dir().join("medullah-upload-tests")
+    }
+
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+
+    fn body(file: &[u8]) -> Vec<u8> {
+        let mut body = b"preamble\r\n--XyZ\r\n\
+            Content-Disposition: form-data; name=\"caption\"\r\n\r\n\
+            hello\r\n--XyZ\r\n\
+            Content-Disposition: form-data; name=\"avatar\"; filename=\"me.png\"\r\n\
+            Content-Type: text/plain\r\n\r\n"
+            .to_vec();
+        body.extend_from_slice(file);
+        body.extend_from_slice(b"\r\n--XyZ--\r\n");
+        body
+    }
+
+    async fn upload(body: Vec<u8>) -> AppResult<Uploads<AvatarUpload>> {
+        let (_, mut payload) = TestRequest::default()
+            .set_payload(Bytes::from(body))
+            .to_http_parts();
+
+        Uploads::from_payload("multipart/form-data; boundary=XyZ", &mut payload).await
+    }
+
+    #[ntex::test]
+    async fn test_upload() {
+        let uploads = upload(body(PNG)).await.unwrap();
+        assert_eq!(uploads.field("caption"), Some("hello"));
+
+        let avatar = uploads.file("avatar").unwrap();
+        assert_eq!(avatar.content_type, "image/png");
+        assert_eq!(avatar.size, PNG.len() as u64);
+        assert_eq!(avatar.file_name.as_deref(), Some("me.png"));
+        assert_eq!(avatar.checksum, hex::encode(Sha256::digest(PNG)));
+        assert!(avatar.key.starts_with("avatars/") && avatar.key.ends_with(".png"));
+
+        let stored = std::fs::read(storage_root().join(&avatar.key)).unwrap();
+        assert_eq!(stored, PNG);
+        std::fs::remove_file(storage_root().join(&avatar.key)).unwrap();
+    }
+
+    #[ntex::test]
+    async fn test_rejections() {
+        // declared as png, but it's not
+        let err = upload(body(b"just text")).await.err().unwrap();
+        assert_eq!(err.status_code(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        let mut large = PNG.to_vec();
+        large.resize(2048, 0);
+        let err = upload(body(&large)).await.err().unwrap();
+        assert_eq!(err.status_code(), StatusCode::PAYLOAD_TOO_LARGE);
+
+        let err = upload(b"--XyZ--\r\n".to_vec()).await.err().unwrap();
+        assert_eq!(err.status_code(), StatusCode::BAD_REQUEST);
+
+        let err = upload(b"--XyZ\r\nContent-Disposition: form-data".to_vec())
+            .await
+            .err()
+            .unwrap();
+        assert_eq!(err.status_code(), StatusCode::BAD_REQUEST);
+        assert!(matches!(err, AppMessage::MultipartError(_)));
+
+        let mut many = vec![];
+        for index in 0..=AvatarUpload::MAX_FIELDS {
+            many.extend_from_slice(
+                format!(
+                    "--XyZ\r\nContent-Disposition: form-data; name=\"f{}\"\r\n\r\nx\r\n",
+                    index
+                )
+                .as_bytes(),
+            );
+        }
+        many.extend_from_slice(b"--XyZ--\r\n");
+        let err = upload(many).await.err().unwrap();
+        assert_eq!(err.status_code(), StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[test]
+    fn test_parse_params() {
+        assert_eq!(
+            parse_params(" name=\"avatar\"; filename=\"me; \\\"1\\\".png\""),
+            vec![
+                ("name".to_string(), "avatar".to_string()),
+                ("filename".to_string(), "me; \"1\".png".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sniff_mime() {
+        assert_eq!(sniff_mime(PNG), "image/png");
+        assert_eq!(sniff_mime(b"RIFF\0\0\0\0WEBPVP8 "), "image/webp");
+        assert_eq!(sniff_mime(b"\0\0\0\x18ftypmp42"), "video/mp4");
+        assert_eq!(sniff_mime("héllo".as_bytes()), "text/plain");
+        assert_eq!(sniff_mime(b"\0\x01\x02"), "application/octet-stream");
+    }
+}
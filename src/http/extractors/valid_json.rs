@@ -0,0 +1,151 @@
+use std::ops::Deref;
+
+use ntex::http::{Payload, StatusCode};
+use ntex::util::BytesMut;
+use ntex::web::{FromRequest, HttpRequest};
+use serde::de::DeserializeOwned;
+use validator::Validate;
+
+use crate::helpers::validation::FieldErrors;
+use crate::prelude::{AppMessage, AppResult};
+
+/// Json body that has been deserialized and validated
+///
+/// Malformed payloads are rejected with `400`, payloads that fail validation with `422`,
+/// both list the offending fields in the response data. Payloads larger than the limit of
+/// the [ValidJsonConfig] in the scope state (32KB by default) are rejected with `413`.
+///
+/// ```ignore
+/// async fn store(payload: ValidJson<CreateUserForm>) -> HttpResult {
+///     let form = payload.into_inner();
+///     ...
+/// }
+/// ```
+pub struct ValidJson<T>(pub T);
+
+/// Settings of the [ValidJson] extractor, looked up from the scope state
+///
+/// ```ignore
+/// web::scope("/imports")
+///     .state(ValidJsonConfig::default().limit(1_048_576))
+///     .configure(import_routes)
+/// ```
+#[derive(Clone, Debug)]
+pub struct ValidJsonConfig {
+    /// maximum body size (in bytes)
+    limit: usize,
+}
+
+impl ValidJsonConfig {
+    /// Maximum body size (in bytes), defaults to 32KB
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+}
+
+impl Default for ValidJsonConfig {
+    fn default() -> Self {
+        ValidJsonConfig { limit: 32_768 }
+    }
+}
+
+impl<T> ValidJson<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> Deref for ValidJson<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T: DeserializeOwned + Validate> ValidJson<T> {
+    pub fn from_slice(bytes: &[u8]) -> AppResult<Self> {
+        let de = &mut serde_json::Deserializer::from_slice(bytes);
+        let data: T = serde_path_to_error::deserialize(de).map_err(|err| {
+            AppMessage::FieldErrors(FieldErrors::from_serde(err), StatusCode::BAD_REQUEST)
+        })?;
+
+        data.validate()?;
+        Ok(ValidJson(data))
+    }
+}
+
+impl<T: DeserializeOwned + Validate, Err> FromRequest<Err> for ValidJson<T> {
+    type Error = AppMessage;
+
+    async fn from_request(req: &HttpRequest, payload: &mut Payload) -> AppResult<Self> {
+        let limit = req
+            .app_state::<ValidJsonConfig>()
+            .map_or(ValidJsonConfig::default().limit, |config| config.limit);
+
+        let mut bytes = BytesMut::new();
+        while let Some(item) = ntex::util::stream_recv(payload).await {
+            bytes.extend_from_slice(&item?);
+            if bytes.len() > limit {
+                return Err(AppMessage::ErrorMessage(
+                    "json payload is too large".to_string(),
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                ));
+            }
+        }
+
+        Self::from_slice(&bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Deserialize;
+
+    use super::*;
+
+    #[derive(Debug, Deserialize, Validate)]
+    struct Form {
+        #[validate(length(min = 3))]
+        name: String,
+    }
+
+    #[test]
+    fn test_from_slice() {
+        let form = ValidJson::<Form>::from_slice(br#"{"name": "Ada"}"#).unwrap();
+        assert_eq!(form.name, "Ada");
+
+        let err = ValidJson::<Form>::from_slice(br#"{"name": "A"}"#)
+            .err()
+            .unwrap();
+        assert_eq!(err.status_code(), StatusCode::UNPROCESSABLE_ENTITY);
+
+        let err = ValidJson::<Form>::from_slice(br#"{"name": 1}"#)
+            .err()
+            .unwrap();
+        assert_eq!(err.status_code(), StatusCode::BAD_REQUEST);
+    }
+
+    #[ntex::test]
+    async fn test_payload_limit() {
+        use ntex::web::test::TestRequest;
+
+        let (req, mut payload) = TestRequest::post()
+            .state(ValidJsonConfig::default().limit(16))
+            .set_payload(r#"{"name": "Ada Lovelace, Countess of Lovelace"}"#)
+            .to_http_parts();
+
+        let result = <ValidJson<Form> as FromRequest<()>>::from_request(&req, &mut payload).await;
+        assert_eq!(
+            result.err().unwrap().status_code(),
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+
+        let (req, mut payload) = TestRequest::post()
+            .set_payload(r#"{"name": "Ada"}"#)
+            .to_http_parts();
+        let result = <ValidJson<Form> as FromRequest<()>>::from_request(&req, &mut payload).await;
+        assert_eq!(result.unwrap().name, "Ada");
+    }
+}
@@ -0,0 +1,319 @@
+use std::ops::Deref;
+
+use base64::{engine, Engine};
+use chrono::Utc;
+use log::{debug, warn};
+use ntex::http::{Payload, StatusCode};
+use ntex::util::{Bytes, BytesMut};
+use ntex::web::{FromRequest, HttpRequest};
+use serde::de::DeserializeOwned;
+
+use crate::helpers::hmac::{Hmac, HmacAlgorithm};
+use crate::prelude::{AppMessage, AppResult, IntoAppResult};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum SignatureEncoding {
+    #[default]
+    Hex,
+    Base64,
+}
+
+/// How the signature header is laid out
+#[derive(Clone, Debug, PartialEq)]
+pub enum SignatureFormat {
+    /// the header holds the signature only, optionally behind a prefix (e.g. `sha256=`)
+    Plain { prefix: Option<String> },
+    /// `t={unix timestamp},v1={signature}`, the signed content is `{timestamp}.{body}`,
+    /// requests older (or newer) than the tolerance (in seconds) are rejected
+    Timestamped { tolerance: i64 },
+}
+
+/// Webhook verification settings, registered as scope state
+///
+/// # Examples
+///
+/// ```
+/// use medullah_web::http::extractors::verified_webhook::{SignatureEncoding, WebhookConfig};
+///
+/// let github = WebhookConfig::new("secret")
+///     .header("x-hub-signature-256")
+///     .prefix("sha256=");
+///
+/// let stripe = WebhookConfig::new("secret")
+///     .header("stripe-signature")
+///     .timestamped(300);
+///
+/// let shopify = WebhookConfig::new("secret")
+///     .header("x-shopify-hmac-sha256")
+///     .encoding(SignatureEncoding::Base64);
+/// ```
+///
+/// The config is then attached to the scope serving the webhook:
+///
+/// ```ignore
+/// cfg.service(
+///     web::scope("/webhooks/github")
+///         .state(github)
+///         .route("", web::post().to(handle_github)),
+/// );
+/// ```
+#[derive(Clone)]
+pub struct WebhookConfig {
+    hmac: Hmac,
+    header: String,
+    algorithm: HmacAlgorithm,
+    encoding: SignatureEncoding,
+    format: SignatureFormat,
+    /// maximum body size (in bytes)
+    limit: usize,
+}
+
+/// Webhook payload whose HMAC signature has been verified against the raw body
+///
+/// Verification settings are looked up from [WebhookConfig] in the scope state,
+/// any verification failure results in `401`.
+pub struct VerifiedWebhook<T> {
+    data: T,
+    raw: Bytes,
+}
+
+impl WebhookConfig {
+    pub const DEFAULT_HEADER: &'static str = "x-signature";
+
+    pub fn new(secret: &str) -> Self {
+        WebhookConfig {
+            hmac: Hmac::new(secret),
+            header: Self::DEFAULT_HEADER.to_string(),
+            algorithm: HmacAlgorithm::Sha256,
+            encoding: SignatureEncoding::Hex,
+            format: SignatureFormat::Plain { prefix: None },
+            limit: 1_048_576,
+        }
+    }
+
+    pub fn header(mut self, name: &str) -> Self {
+        self.header = name.to_lowercase();
+        self
+    }
+
+    pub fn algorithm(mut self, algorithm: HmacAlgorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    pub fn encoding(mut self, encoding: SignatureEncoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Strip the given prefix (e.g. `sha256=`) from the header value
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.format = SignatureFormat::Plain {
+            prefix: Some(prefix.to_string()),
+        };
+        self
+    }
+
+    /// Expect `t=...,v1=...` signatures, `tolerance` is the accepted clock skew (in seconds)
+    pub fn timestamped(mut self, tolerance: i64) -> Self {
+        self.format = SignatureFormat::Timestamped { tolerance };
+        self
+    }
+
+    /// Maximum body size (in bytes), defaults to 1MB
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Verify the header value against the raw body
+    pub fn verify(&self, header: &str, body: &[u8]) -> AppResult<()> {
+        match &self.format {
+            SignatureFormat::Plain { prefix } => {
+                let signature = match prefix {
+                    Some(prefix) => header.trim().strip_prefix(prefix.as_str()).ok_or(
+                        AppMessage::UnAuthorizedMessage("malformed webhook signature"),
+                    )?,
+                    None => header.trim(),
+                };
+
+                self.verify_signature(body, signature)
+            }
+            SignatureFormat::Timestamped { tolerance } => {
+                let mut timestamp = None;
+                let mut signatures = vec![];
+                for part in header.split(',') {
+                    match part.trim().split_once('=') {
+                        Some(("t", value)) => timestamp = value.parse::<i64>().ok(),
+                        Some(("v1", value)) => signatures.push(value),
+                        _ => {}
+                    }
+                }
+
+                let timestamp = timestamp.ok_or(AppMessage::UnAuthorizedMessage(
+                    "malformed webhook signature",
+                ))?;
+
+                if Utc::now().timestamp().abs_diff(timestamp) > tolerance.max(&0).unsigned_abs() {
+                    return Err(AppMessage::UnAuthorizedMessage(
+                        "webhook signature timestamp is outside the tolerance window",
+                    ));
+                }
+
+                let mut signed = format!("{}.", timestamp).into_bytes();
+                signed.extend_from_slice(body);
+
+                // senders may include several signatures while rotating secrets
+                match signatures
+                    .into_iter()
+                    .any(|signature| self.verify_signature(&signed, signature).is_ok())
+                {
+                    true => Ok(()),
+                    false => Err(AppMessage::UnAuthorizedMessage("invalid webhook signature")),
+                }
+            }
+        }
+    }
+
+    fn verify_signature(&self, content: &[u8], signature: &str) -> AppResult<()> {
+        let signature = match self.encoding {
+            SignatureEncoding::Hex => hex::decode(signature).ok(),
+            SignatureEncoding::Base64 => engine::general_purpose::STANDARD.decode(signature).ok(),
+        }
+        .ok_or(AppMessage::UnAuthorizedMessage(
+            "malformed webhook signature",
+        ))?;
+
+        match self
+            .hmac
+            .verify_bytes(self.algorithm, content, &signature)?
+        {
+            true => Ok(()),
+            false => Err(AppMessage::UnAuthorizedMessage("invalid webhook signature")),
+        }
+    }
+}
+
+impl<T> VerifiedWebhook<T> {
+    pub fn into_inner(self) -> T {
+        self.data
+    }
+
+    /// The raw body, as it was signed
+    pub fn raw(&self) -> &Bytes {
+        &self.raw
+    }
+}
+
+impl<T> Deref for VerifiedWebhook<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        &self.data
+    }
+}
+
+impl<T: DeserializeOwned, Err> FromRequest<Err> for VerifiedWebhook<T> {
+    type Error = AppMessage;
+
+    async fn from_request(req: &HttpRequest, payload: &mut Payload) -> AppResult<Self> {
+        let config =
+            req.app_state::<WebhookConfig>()
+                .ok_or(AppMessage::InternalServerErrorMessage(
+                    "webhook verification is not configured",
+                ))?;
+
+        let header = req
+            .headers()
+            .get(config.header.as_str())
+            .and_then(|v| v.to_str().ok())
+            .ok_or(AppMessage::UnAuthorizedMessage("missing webhook signature"))?
+            .to_string();
+
+        let mut bytes = BytesMut::new();
+        while let Some(item) = ntex::util::stream_recv(payload).await {
+            bytes.extend_from_slice(&item?);
+            if bytes.len() > config.limit {
+                return Err(AppMessage::ErrorMessage(
+                    "webhook payload is too large".to_string(),
+                    StatusCode::PAYLOAD_TOO_LARGE,
+                ));
+            }
+        }
+
+        let raw = bytes.freeze();
+        if let Err(err) = config.verify(&header, &raw) {
+            warn!("[webhook] {} rejected: {:?}", req.path(), err);
+            return Err(err);
+        }
+
+        debug!("[webhook] {} verified", req.path());
+        let data = serde_json::from_slice::<T>(&raw).into_app_result()?;
+        Ok(VerifiedWebhook { data, raw })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BODY: &[u8] = b"{\"event\":\"paid\"}";
+
+    fn sign(config: &WebhookConfig, content: &[u8]) -> Vec<u8> {
+        config.hmac.sign(config.algorithm, content).unwrap()
+    }
+
+    #[test]
+    fn test_plain_hex() {
+        let config = WebhookConfig::new("secret");
+        let signature = hex::encode(sign(&config, BODY));
+
+        assert!(config.verify(&signature, BODY).is_ok());
+        assert!(config.verify(&signature, b"{}").is_err());
+        assert!(config.verify("not-hex", BODY).is_err());
+    }
+
+    #[test]
+    fn test_prefixed_base64_sha512() {
+        let config = WebhookConfig::new("secret")
+            .algorithm(HmacAlgorithm::Sha512)
+            .encoding(SignatureEncoding::Base64)
+            .prefix("sha512=");
+
+        let signature = engine::general_purpose::STANDARD.encode(sign(&config, BODY));
+
+        assert!(config
+            .verify(&format!("sha512={}", signature), BODY)
+            .is_ok());
+        assert!(config.verify(&signature, BODY).is_err());
+    }
+
+    #[test]
+    fn test_timestamped() {
+        let config = WebhookConfig::new("secret").timestamped(300);
+        let now = Utc::now().timestamp();
+
+        let signed = |timestamp: i64| {
+            let mut content = format!("{}.", timestamp).into_bytes();
+            content.extend_from_slice(BODY);
+            hex::encode(sign(&config, &content))
+        };
+
+        let header = format!("t={},v1=deadbeef,v1={}", now, signed(now));
+        assert!(config.verify(&header, BODY).is_ok());
+
+        // replayed outside the tolerance window
+        let stale = now - 600;
+        let header = format!("t={},v1={}", stale, signed(stale));
+        assert!(config.verify(&header, BODY).is_err());
+
+        // extreme timestamps are rejected rather than overflowing
+        for timestamp in [i64::MIN, i64::MAX] {
+            let header = format!("t={},v1={}", timestamp, signed(timestamp));
+            assert!(config.verify(&header, BODY).is_err());
+        }
+
+        let header = format!("v1={}", signed(now));
+        assert!(config.verify(&header, BODY).is_err());
+    }
+}
@@ -1,14 +1,25 @@
 use crate::enums::ResponseCode;
 use crate::helpers::responder::Responder;
+use crate::http::error_renderer::JsonErrorRenderer;
 use crate::http::middlewares::Middleware;
-use crate::http::Method;
+use crate::http::{Method, ServiceConfig};
+use crate::resilience;
+#[cfg(feature = "jwt")]
+use crate::{prelude::OnceLockHelper, results::HttpResult, MEDULLAH};
 use log::info;
-use ntex::http::header;
+use ntex::http::{header, Response};
 use ntex::web::middleware::Logger;
-use ntex::web::ServiceConfig;
+#[cfg(feature = "jwt")]
+use ntex::web::HttpResponse;
 use ntex::{web, web::Route as NtexRoute};
 use ntex_cors::Cors;
 
+/// Served by every app, unless the app routes register the same path
+pub const HEALTH_CHECK_PATH: &str = "/system/docker-health-check";
+/// JWKS of the app, served unless the app routes register the same path
+#[cfg(feature = "jwt")]
+pub const JWKS_PATH: &str = "/.well-known/jwks.json";
+
 pub struct Controller {
     pub path: String,
     pub handler: fn(cfg: &mut ServiceConfig),
@@ -34,27 +45,10 @@ pub fn register_routes(config: &mut ServiceConfig, routes: Vec<Route>) {
             if path.is_empty() {
                 config.service(web::scope("").configure(controller.handler));
             } else if !route.middlewares.is_empty() {
-                let total = route.middlewares.len();
-
-                if total == 1 {
-                    let scope = web::scope(path.as_str())
-                        .wrap(route.middlewares.first().unwrap().middleware())
-                        .configure(controller.handler);
-                    config.service(scope);
-                } else if total == 2 {
-                    let scope = web::scope(path.as_str())
-                        .wrap(route.middlewares.first().unwrap().middleware())
-                        .wrap(route.middlewares.last().unwrap().middleware())
-                        .configure(controller.handler);
-                    config.service(scope);
-                } else {
-                    let scope = web::scope(path.as_str())
-                        .wrap(route.middlewares.first().unwrap().middleware())
-                        .wrap(route.middlewares.get(1).unwrap().middleware())
-                        .wrap(route.middlewares.last().unwrap().middleware())
-                        .configure(controller.handler);
-                    config.service(scope);
-                }
+                let scope = web::scope(path.as_str())
+                    .wrap(Middleware::chain(&route.middlewares))
+                    .configure(controller.handler);
+                config.service(scope);
             } else {
                 config.service(web::scope(path.as_str()).configure(controller.handler));
             }
@@ -67,7 +61,7 @@ pub fn register_routes(config: &mut ServiceConfig, routes: Vec<Route>) {
 pub fn setup_logger() -> Logger {
     Logger::default()
         .exclude("/favicon.ico")
-        .exclude("/system/docker-health-check")
+        .exclude(HEALTH_CHECK_PATH)
 }
 
 pub fn setup_cors(origins: Vec<String>, methods: Vec<Method>) -> Cors {
@@ -103,13 +97,81 @@ pub fn setup_cors(origins: Vec<String>, methods: Vec<Method>) -> Cors {
         .max_age(3600)
 }
 
-pub fn ntex_default_service() -> NtexRoute {
+pub fn ntex_default_service() -> NtexRoute<JsonErrorRenderer> {
     web::to(|| async {
         Responder::message("Requested Resource(s) Not Found", ResponseCode::NotFound)
     })
 }
 
+/// Always `200`, the body tells whether a circuit breaker is open (`degraded`) and holds
+/// the state of the breakers and bulkheads, see [crate::resilience]
+pub async fn health_check() -> Response {
+    Responder::send(resilience::report(), ResponseCode::Ok)
+}
+
+/// Public keys of the tokens this app signs, see [crate::helpers::jwt::Jwt::jwks]
+#[cfg(feature = "jwt")]
+pub async fn jwks() -> HttpResult {
+    let jwks = MEDULLAH.app().helpers.jwt.jwks()?;
+    Ok(HttpResponse::Ok()
+        .header(header::CACHE_CONTROL, "public, max-age=300")
+        .json(&jwks))
+}
+
 pub fn register_middlewares(_config: &mut ServiceConfig) {
     // for middleware in middlewares() {
     // }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::pin::Pin;
+
+    use ntex::http::header::{HeaderName, HeaderValue};
+    use ntex::web::test::{call_service, init_service, TestRequest};
+    use ntex::web::{App, HttpResponse, WebResponse};
+
+    use super::*;
+    use crate::prelude::AppResult;
+
+    fn mark(mut resp: WebResponse) -> Pin<Box<dyn Future<Output = AppResult<WebResponse>>>> {
+        Box::pin(async move {
+            resp.headers_mut().append(
+                HeaderName::from_static("x-mark"),
+                HeaderValue::from_static("1"),
+            );
+            Ok(resp)
+        })
+    }
+
+    fn ping(cfg: &mut ServiceConfig) {
+        cfg.route(
+            "/ping",
+            web::get().to(|| async { HttpResponse::Ok().finish() }),
+        );
+    }
+
+    #[ntex::test]
+    async fn test_every_route_middleware_runs() {
+        let routes = vec![Route {
+            prefix: "/api".to_string(),
+            middlewares: vec![Middleware::After(mark); 4],
+            controllers: vec![Controller {
+                path: "/orders".to_string(),
+                handler: ping,
+            }],
+        }];
+
+        let app = init_service(
+            App::with(JsonErrorRenderer).configure(|cfg| register_routes(cfg, routes)),
+        )
+        .await;
+
+        let response =
+            call_service(&app, TestRequest::with_uri("/api/orders/ping").to_request()).await;
+
+        assert!(response.status().is_success());
+        assert_eq!(response.headers().get_all("x-mark").count(), 4);
+    }
+}
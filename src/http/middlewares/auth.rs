@@ -0,0 +1,62 @@
+use std::marker::PhantomData;
+
+use log::debug;
+use ntex::http::Payload;
+use ntex::web::{HttpRequest, HttpResponse};
+use serde::de::DeserializeOwned;
+
+use crate::helpers::jwt::JwtTokenClaims;
+use crate::http::extractors::auth::Auth;
+use crate::http::middlewares::{AroundMiddleware, Middleware, MiddlewareFuture};
+
+/// Rejects requests of a route group that don't carry a valid bearer token
+///
+/// The claims are stashed on the request, handlers get them through [Auth]
+/// or [crate::helpers::request::RequestHelper::auth] without decoding the token again.
+///
+/// # Examples
+///
+/// ```
+/// use medullah_web::helpers::jwt::JwtTokenClaims;
+/// use medullah_web::http::middlewares::auth::Authenticate;
+///
+/// let middleware = Authenticate::<JwtTokenClaims>::new().middleware();
+/// ```
+pub struct Authenticate<C = JwtTokenClaims> {
+    _claims: PhantomData<fn() -> C>,
+}
+
+impl<C: DeserializeOwned + Clone + 'static> Authenticate<C> {
+    pub fn new() -> Self {
+        Authenticate {
+            _claims: PhantomData,
+        }
+    }
+
+    pub fn middleware(self) -> Middleware {
+        Middleware::around(self)
+    }
+}
+
+impl<C: DeserializeOwned + Clone + 'static> Default for Authenticate<C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C: DeserializeOwned + Clone + 'static> AroundMiddleware for Authenticate<C> {
+    fn before<'a>(
+        &'a self,
+        req: &'a HttpRequest,
+        _payload: &'a mut Payload,
+    ) -> MiddlewareFuture<'a, Option<HttpResponse>> {
+        Box::pin(async move {
+            if let Err(err) = Auth::<C>::resolve(req).await {
+                debug!("[auth] rejected {} {}: {}", req.method(), req.path(), err);
+                return Err(err);
+            }
+
+            Ok(None)
+        })
+    }
+}
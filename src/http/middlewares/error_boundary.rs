@@ -0,0 +1,125 @@
+use std::any::Any;
+use std::panic::AssertUnwindSafe;
+
+use futures_util::FutureExt;
+use log::error;
+use ntex::http::{Method, StatusCode};
+use ntex::service::{Middleware as ServiceMiddleware, Service, ServiceCtx};
+use ntex::web::{WebRequest, WebResponse};
+
+use crate::contracts::ResponseCodeContract;
+use crate::enums::ResponseCode;
+use crate::helpers::request::RequestHelper;
+use crate::http::error_renderer::{
+    render_bodiless_error, HandlerPanicked, JsonError, JsonErrorRenderer,
+};
+use crate::http::middlewares::request_id::RequestId;
+use crate::http::response::problem::{into_problem_response, ProblemDetails};
+use crate::reporting::{self, ErrorReport, ErrorRequestContext};
+
+/// App-level middleware keeping every response inside the json envelope
+///
+/// Panics raised while handling a request are caught, logged with the request id and
+/// turned into a `500` envelope, bodiless error responses produced by ntex itself
+/// (e.g. `405 Method Not Allowed`) are re-rendered with the envelope.
+/// Error responses are turned into problem details when the client asks for them,
+/// panics are reported like any other server error, see [crate::reporting].
+#[derive(Clone, Copy, Default)]
+pub struct ErrorBoundary;
+
+pub struct ErrorBoundaryService<S> {
+    service: S,
+}
+
+impl<S> ServiceMiddleware<S> for ErrorBoundary {
+    type Service = ErrorBoundaryService<S>;
+
+    fn create(&self, service: S) -> Self::Service {
+        ErrorBoundaryService { service }
+    }
+}
+
+impl<S> Service<WebRequest<JsonErrorRenderer>> for ErrorBoundaryService<S>
+where
+    S: Service<WebRequest<JsonErrorRenderer>, Response = WebResponse, Error = JsonError>,
+{
+    type Response = WebResponse;
+    type Error = JsonError;
+
+    ntex::forward_ready!(service);
+
+    async fn call(
+        &self,
+        req: WebRequest<JsonErrorRenderer>,
+        ctx: ServiceCtx<'_, Self>,
+    ) -> Result<Self::Response, Self::Error> {
+        let request_id = req
+            .extensions()
+            .get::<RequestId>()
+            .map(|id| id.0.clone())
+            .unwrap_or_else(|| "-".to_string());
+
+        let method = req.method().clone();
+        let path = req.path().to_string();
+        let problem_details = ProblemDetails::is_wanted(req.headers());
+
+        // resolved through the trusted proxies, like the ip of any other report
+        let (http_req, payload) = req.into_parts();
+        let (ip, user_agent) = (http_req.ip(), http_req.user_agent());
+        let req = WebRequest::from_parts(http_req, payload).unwrap();
+
+        match AssertUnwindSafe(ctx.call(&self.service, req))
+            .catch_unwind()
+            .await
+        {
+            // responses to HEAD requests never carry a body
+            Ok(Ok(resp)) if method == Method::HEAD => Ok(resp),
+            Ok(Ok(resp)) if problem_details => {
+                let resp = render_bodiless_error(resp);
+                let request = resp.request().clone();
+                let response = into_problem_response(resp.into(), Some(&request_id));
+                Ok(WebResponse::new(response, request))
+            }
+            Ok(Ok(resp)) => Ok(render_bodiless_error(resp)),
+            Ok(Err(err)) => Err(err),
+            Err(panic) => {
+                let message = panic_message(panic.as_ref());
+                error!("[panic][{}] {} {}: {}", request_id, method, path, message);
+
+                let report = ErrorReport::new(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    ResponseCode::InternalServerError.code(),
+                    "handler panicked",
+                    vec![message.to_string()],
+                )
+                .with_request_context(ErrorRequestContext {
+                    request_id: Some(request_id),
+                    method: method.to_string(),
+                    path,
+                    // the panic happened past routing, the pattern is unknown here
+                    route: None,
+                    ip,
+                    user_agent,
+                });
+
+                let error = JsonError::new(HandlerPanicked)
+                    .with_error_id(&report.id)
+                    .with_problem_details(problem_details);
+
+                reporting::report(report);
+                Err(error)
+            }
+        }
+    }
+}
+
+fn panic_message(panic: &(dyn Any + Send)) -> &str {
+    if let Some(message) = panic.downcast_ref::<&str>() {
+        return message;
+    }
+
+    match panic.downcast_ref::<String>() {
+        Some(message) => message.as_str(),
+        None => "unknown panic payload",
+    }
+}
@@ -1,8 +1,12 @@
 use crate::http::middlewares::Middleware;
 use log::{debug, error, info};
+use ntex::service::boxed::{self, BoxService};
 use ntex::service::{Middleware as ServiceMiddleware, Service, ServiceCtx};
 use ntex::web;
-use ntex::web::{Error, WebRequest};
+use ntex::web::{WebRequest, WebResponse};
+
+use crate::enums::app_message::AppMessage;
+use crate::http::error_renderer::{JsonError, JsonErrorRenderer};
 
 #[derive(Clone)]
 pub struct MiddlewareExecutor {
@@ -26,6 +30,38 @@ impl<S> ServiceMiddleware<S> for MiddlewareExecutor {
     }
 }
 
+/// Every route middleware in a single scope wrapper, the last one being the outermost
+///
+/// Wrapping a scope changes its type, so the executors are boxed to fold over any number of them.
+#[derive(Clone)]
+pub struct MiddlewareChain {
+    handlers: Vec<Middleware>,
+}
+
+impl MiddlewareChain {
+    pub fn new(handlers: Vec<Middleware>) -> Self {
+        MiddlewareChain { handlers }
+    }
+}
+
+impl<S> ServiceMiddleware<S> for MiddlewareChain
+where
+    S: Service<WebRequest<JsonErrorRenderer>, Response = WebResponse, Error = JsonError> + 'static,
+{
+    type Service = BoxService<WebRequest<JsonErrorRenderer>, WebResponse, JsonError>;
+
+    fn create(&self, service: S) -> Self::Service {
+        self.handlers
+            .iter()
+            .fold(boxed::service(service), |service, handler| {
+                boxed::service(ExecutorMiddlewareInternal {
+                    service,
+                    middleware: handler.clone(),
+                })
+            })
+    }
+}
+
 pub struct ExecutorMiddlewareInternal<S> {
     service: S,
     middleware: Middleware,
@@ -33,11 +69,12 @@ pub struct ExecutorMiddlewareInternal<S> {
 
 impl<S, Err> Service<web::WebRequest<Err>> for ExecutorMiddlewareInternal<S>
 where
-    S: Service<web::WebRequest<Err>, Response = web::WebResponse, Error = web::Error>,
+    S: Service<web::WebRequest<Err>, Response = web::WebResponse, Error = Err::Container>,
     Err: web::ErrorRenderer,
+    Err::Container: From<AppMessage>,
 {
     type Response = web::WebResponse;
-    type Error = web::Error;
+    type Error = Err::Container;
 
     ntex::forward_ready!(service);
 
@@ -46,7 +83,7 @@ where
         request: web::WebRequest<Err>,
         ctx: ServiceCtx<'_, Self>,
     ) -> Result<Self::Response, Self::Error> {
-        let (req, payload) = request.into_parts();
+        let (req, mut payload) = request.into_parts();
         info!("{} {}", req.method(), req.path());
 
         match self.middleware {
@@ -57,7 +94,7 @@ where
                     debug!("calling http controller -> method...");
                     ctx.call(&self.service, request).await
                 }
-                Err(err) => Err(Error::new(err)),
+                Err(err) => Err(err.into()),
             },
 
             // execute after executing handler
@@ -69,7 +106,7 @@ where
                         // log error and return response generated from controller
                         Err(err) => {
                             error!("[middleware-level-error][post-exec] {:?}", err);
-                            Err(Error::new(err))
+                            Err(err.into())
                         }
                     },
                     Err(err) => {
@@ -78,6 +115,26 @@ where
                     }
                 }
             }
+
+            // execute before and after the handler, "before" may short-circuit the handler
+            Middleware::Around(ref mid) => match mid.before(&req, &mut payload).await {
+                Ok(Some(resp)) => {
+                    debug!("middleware responded, skipping http controller...");
+                    Ok(WebResponse::new(resp, req))
+                }
+                Ok(None) => {
+                    let request = WebRequest::from_parts(req, payload).unwrap();
+                    let resp = ctx.call(&self.service, request).await?;
+                    match mid.after(resp).await {
+                        Ok(resp) => Ok(resp),
+                        Err(err) => {
+                            error!("[middleware-level-error][post-exec] {:?}", err);
+                            Err(err.into())
+                        }
+                    }
+                }
+                Err(err) => Err(err.into()),
+            },
         }
     }
 }
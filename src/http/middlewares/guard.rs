@@ -0,0 +1,396 @@
+use std::collections::BTreeSet;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, OnceLock};
+
+use log::{debug, warn};
+use ntex::http::Payload;
+use ntex::web::{HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::http::extractors::auth::Auth;
+use crate::http::middlewares::{AroundMiddleware, Middleware, MiddlewareFuture};
+use crate::prelude::{AppMessage, AppResult};
+
+pub type PermissionFuture<'a> = Pin<Box<dyn Future<Output = AppResult<Permissions>> + Send + 'a>>;
+
+/// Loads the roles and permissions of a subject from wherever the app keeps them
+pub trait PermissionLoader: Send + Sync {
+    fn load<'a>(&'a self, subject: &'a str) -> PermissionFuture<'a>;
+}
+
+/// Roles and permissions held by the authenticated principal
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Permissions {
+    pub roles: BTreeSet<String>,
+    pub permissions: BTreeSet<String>,
+    /// `sub` of the principal, set by [Permissions::resolve]
+    #[serde(skip)]
+    pub subject: Option<String>,
+}
+
+/// What a route requires from the principal
+#[derive(Clone, Debug)]
+pub enum Requirement {
+    /// every listed permission
+    Permissions(Vec<String>),
+    /// any of the listed roles
+    Roles(Vec<String>),
+}
+
+/// Declarative authorization of route groups and individual routes
+///
+/// Roles and permissions are read from the token claims (`roles` and `permissions`) merged with
+/// what the registered [PermissionLoader] returns, personal access tokens are then limited to
+/// their `scopes`.
+/// Loaded permissions are cached in [crate::services::cache_service::CacheService] when redis is enabled.
+/// Denials are logged and answered with [AppMessage::Forbidden].
+///
+/// # Examples
+///
+/// ```
+/// use medullah_web::http::middlewares::guard::{requires, role};
+///
+/// let can_write_orders = requires(&["orders:write"]);
+/// let is_admin = role("admin");
+/// ```
+pub struct Guard {
+    requirement: Requirement,
+}
+
+struct LoaderRegistration {
+    loader: Arc<dyn PermissionLoader>,
+    #[cfg_attr(not(feature = "redis"), allow(dead_code))]
+    ttl: u64,
+}
+
+static LOADER: OnceLock<LoaderRegistration> = OnceLock::new();
+
+/// Shorthand for [Guard::requires]
+pub fn requires(permissions: &[&str]) -> Middleware {
+    Guard::requires(permissions).middleware()
+}
+
+/// Shorthand for [Guard::role]
+pub fn role(role: &str) -> Middleware {
+    Guard::role(role).middleware()
+}
+
+impl Guard {
+    pub const CACHE_PREFIX: &'static str = "permissions:";
+
+    /// Principal must hold every listed permission
+    pub fn requires(permissions: &[&str]) -> Self {
+        Guard {
+            requirement: Requirement::Permissions(to_strings(permissions)),
+        }
+    }
+
+    /// Principal must have the given role
+    pub fn role(role: &str) -> Self {
+        Self::any_role(&[role])
+    }
+
+    /// Principal must have at least one of the listed roles
+    pub fn any_role(roles: &[&str]) -> Self {
+        Guard {
+            requirement: Requirement::Roles(to_strings(roles)),
+        }
+    }
+
+    pub fn middleware(self) -> Middleware {
+        Middleware::around(self)
+    }
+
+    ///
+    ///
+    /// # Arguments
+    ///
+    /// * `loader`: loads the roles & permissions of a subject
+    /// * `ttl`: how long loaded permissions are cached (in seconds)
+    ///
+    /// returns: ()
+    pub fn register_loader<L: PermissionLoader + 'static>(loader: L, ttl: u64) {
+        let registration = LoaderRegistration {
+            loader: Arc::new(loader),
+            ttl,
+        };
+
+        if LOADER.set(registration).is_err() {
+            panic!("permission loader is already registered");
+        }
+    }
+
+    /// Drops the cached permissions of a subject, call it after changing their roles
+    #[cfg(feature = "redis")]
+    pub async fn forget(subject: &str) -> AppResult<i32> {
+        use crate::prelude::OnceLockHelper;
+
+        let key = format!("{}{}", Self::CACHE_PREFIX, subject);
+        crate::MEDULLAH.app().services.cache.delete(&key).await
+    }
+}
+
+impl Permissions {
+    /// Roles and permissions carried by the token claims
+    pub fn from_claims(claims: &Value) -> Self {
+        Permissions {
+            roles: claim_list(claims, "roles").unwrap_or_default(),
+            permissions: claim_list(claims, "permissions").unwrap_or_default(),
+            subject: claims.get("sub").and_then(Value::as_str).map(String::from),
+        }
+    }
+
+    /// Permissions of the authenticated principal, resolved once per request
+    ///
+    /// Personal access tokens (principals with a `scopes` claim) never hold more than their scopes.
+    pub async fn resolve(req: &HttpRequest) -> AppResult<Self> {
+        if let Some(permissions) = req.extensions().get::<Permissions>() {
+            return Ok(permissions.clone());
+        }
+
+        let claims = Auth::<Value>::resolve(req).await?.into_inner();
+        let mut permissions = Permissions::from_claims(&claims);
+
+        if let (Some(registration), Some(subject)) = (LOADER.get(), &permissions.subject) {
+            let loaded = load(registration, subject).await?;
+            permissions.merge(loaded);
+        }
+
+        if let Some(scopes) = claim_list(&claims, "scopes") {
+            permissions.restrict(&scopes);
+        }
+
+        req.extensions_mut().insert(permissions.clone());
+        Ok(permissions)
+    }
+
+    /// Narrows the permissions down to the scopes of a token, roles are only kept by `*` scopes
+    pub fn restrict(&mut self, scopes: &BTreeSet<String>) {
+        if scopes.contains("*") {
+            return;
+        }
+
+        let covers = |set: &BTreeSet<String>, item: &str| set.contains(item) || set.contains("*");
+        let permissions = std::mem::take(&mut self.permissions);
+        self.permissions = permissions
+            .iter()
+            .filter(|permission| covers(scopes, permission))
+            .chain(scopes.iter().filter(|scope| covers(&permissions, scope)))
+            .cloned()
+            .collect();
+        self.roles.clear();
+    }
+
+    pub fn merge(&mut self, other: Permissions) {
+        self.roles.extend(other.roles);
+        self.permissions.extend(other.permissions);
+    }
+
+    pub fn has_role(&self, role: &str) -> bool {
+        self.roles.contains(role)
+    }
+
+    pub fn has_permission(&self, permission: &str) -> bool {
+        self.permissions.contains(permission) || self.permissions.contains("*")
+    }
+
+    /// What is missing to satisfy the requirement, empty when it's satisfied
+    pub fn missing(&self, requirement: &Requirement) -> Vec<String> {
+        match requirement {
+            Requirement::Permissions(permissions) => permissions
+                .iter()
+                .filter(|p| !self.has_permission(p))
+                .cloned()
+                .collect(),
+            Requirement::Roles(roles) => match roles.iter().any(|r| self.has_role(r)) {
+                true => vec![],
+                false => roles.clone(),
+            },
+        }
+    }
+}
+
+impl AroundMiddleware for Guard {
+    fn before<'a>(
+        &'a self,
+        req: &'a HttpRequest,
+        _payload: &'a mut Payload,
+    ) -> MiddlewareFuture<'a, Option<HttpResponse>> {
+        Box::pin(async move {
+            let permissions = Permissions::resolve(req).await?;
+            let missing = permissions.missing(&self.requirement);
+
+            if !missing.is_empty() {
+                warn!(
+                    "[guard] denied {} {} to {}, missing {:?}",
+                    req.method(),
+                    req.path(),
+                    permissions.subject.as_deref().unwrap_or("unknown"),
+                    missing
+                );
+
+                return Err(AppMessage::Forbidden);
+            }
+
+            Ok(None)
+        })
+    }
+}
+
+#[cfg(feature = "redis")]
+async fn load(registration: &LoaderRegistration, subject: &str) -> AppResult<Permissions> {
+    use crate::prelude::OnceLockHelper;
+
+    let cache = &crate::MEDULLAH.app().services.cache;
+    let key = format!("{}{}", Guard::CACHE_PREFIX, subject);
+    if let Some(permissions) = cache.get::<Permissions>(&key).await? {
+        return Ok(permissions);
+    }
+
+    debug!("[guard] loading permissions of {}", subject);
+    let permissions = registration.loader.load(subject).await?;
+    cache
+        .put_with_ttl(&key, &permissions, registration.ttl)
+        .await?;
+
+    Ok(permissions)
+}
+
+#[cfg(not(feature = "redis"))]
+async fn load(registration: &LoaderRegistration, subject: &str) -> AppResult<Permissions> {
+    debug!("[guard] loading permissions of {}", subject);
+    registration.loader.load(subject).await
+}
+
+/// List claim, either an array or space separated (like the oauth `scope` claim)
+fn claim_list(claims: &Value, name: &str) -> Option<BTreeSet<String>> {
+    match claims.get(name) {
+        Some(Value::Array(items)) => Some(
+            items
+                .iter()
+                .filter_map(|v| v.as_str().map(String::from))
+                .collect(),
+        ),
+        Some(Value::String(items)) => Some(items.split_whitespace().map(String::from).collect()),
+        _ => None,
+    }
+}
+
+fn to_strings(items: &[&str]) -> Vec<String> {
+    items.iter().map(|item| item.to_string()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_from_claims() {
+        let permissions = Permissions::from_claims(&json!({
+            "sub": "user-1",
+            "roles": ["admin"],
+            "permissions": "orders:read orders:write",
+        }));
+
+        assert!(permissions.has_role("admin"));
+        assert!(permissions.has_permission("orders:read"));
+        assert!(permissions.has_permission("orders:write"));
+        assert!(!permissions.has_permission("users:write"));
+    }
+
+    #[test]
+    fn test_scoped_token_is_restricted() {
+        let claims = json!({
+            "sub": "user-1",
+            "roles": ["admin"],
+            "permissions": ["orders:read", "orders:write"],
+            "scopes": ["orders:read", "users:write"],
+        });
+
+        let mut permissions = Permissions::from_claims(&claims);
+        // what the loader returns for the owner of the token
+        permissions.merge(Permissions {
+            permissions: BTreeSet::from(["users:read".to_string()]),
+            ..Default::default()
+        });
+        permissions.restrict(&claim_list(&claims, "scopes").unwrap());
+
+        assert!(permissions.has_permission("orders:read"));
+        assert!(!permissions.has_permission("users:write"));
+        assert!(!permissions.has_permission("users:read"));
+        assert_eq!(
+            permissions.missing(&Requirement::Permissions(to_strings(&["orders:write"]))),
+            vec!["orders:write"]
+        );
+        assert!(!permissions.has_role("admin"));
+
+        // an owner holding every permission gets exactly the scopes
+        let mut owner = Permissions {
+            permissions: BTreeSet::from(["*".to_string()]),
+            ..Default::default()
+        };
+        owner.restrict(&BTreeSet::from(["orders:read".to_string()]));
+        assert_eq!(
+            owner.permissions,
+            BTreeSet::from(["orders:read".to_string()])
+        );
+
+        // `*` scopes don't restrict
+        let mut unrestricted = Permissions::from_claims(&claims);
+        unrestricted.restrict(&BTreeSet::from(["*".to_string()]));
+        assert!(unrestricted.has_permission("orders:write"));
+        assert!(unrestricted.has_role("admin"));
+    }
+
+    #[test]
+    fn test_missing() {
+        let permissions = Permissions {
+            roles: BTreeSet::from(["editor".to_string()]),
+            permissions: BTreeSet::from(["orders:read".to_string()]),
+            ..Default::default()
+        };
+
+        let requirement = Requirement::Permissions(to_strings(&["orders:read", "orders:write"]));
+        assert_eq!(permissions.missing(&requirement), vec!["orders:write"]);
+
+        let requirement = Requirement::Roles(to_strings(&["admin", "editor"]));
+        assert!(permissions.missing(&requirement).is_empty());
+
+        let requirement = Requirement::Roles(to_strings(&["admin"]));
+        assert_eq!(permissions.missing(&requirement), vec!["admin"]);
+
+        let wildcard = Permissions {
+            permissions: BTreeSet::from(["*".to_string()]),
+            ..Default::default()
+        };
+        assert!(wildcard
+            .missing(&Requirement::Permissions(to_strings(&["anything"])))
+            .is_empty());
+    }
+
+    #[ntex::test]
+    async fn test_stashed_claims_are_reused() {
+        let req = ntex::web::test::TestRequest::default().to_http_request();
+        // what `Authenticate` leaves on the request, there's no token to verify again
+        req.extensions_mut().insert(Auth(json!({
+            "sub": "user-1",
+            "roles": ["editor"],
+        })));
+
+        let guard = Guard::role("admin");
+        let denied = guard.before(&req, &mut Payload::None).await;
+        assert!(matches!(denied, Err(AppMessage::Forbidden)));
+
+        let permissions = req.extensions().get::<Permissions>().cloned().unwrap();
+        assert_eq!(permissions.subject.as_deref(), Some("user-1"));
+        assert!(Guard::role("editor")
+            .before(&req, &mut Payload::None)
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
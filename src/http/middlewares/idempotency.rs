@@ -0,0 +1,415 @@
+use log::{debug, error, warn};
+use ntex::http::header::{HeaderName, HeaderValue, AUTHORIZATION};
+use ntex::http::{h1, Method, Payload, StatusCode};
+use ntex::util::{Bytes, BytesMut};
+use ntex::web::{HttpRequest, HttpResponse, WebResponse};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tokio::runtime::Handle;
+
+use crate::http::middlewares::response_cache::CachedResponse;
+use crate::http::middlewares::{AroundMiddleware, Middleware, MiddlewareFuture};
+use crate::prelude::{AppMessage, OnceLockHelper};
+use crate::MEDULLAH;
+
+pub const IDEMPOTENCY_KEY: &str = "idempotency-key";
+pub const IDEMPOTENT_REPLAYED: &str = "idempotent-replayed";
+
+/// Resolves the subject (user, client...) idempotency keys are scoped to, see [Idempotency::subject]
+pub type IdempotencySubjectResolver = fn(&HttpRequest) -> Option<String>;
+
+/// Makes POST/PATCH requests carrying an `Idempotency-Key` header safe to retry
+///
+/// The first request acquires a lock and its final response is stored in redis,
+/// replays return the stored response, a replay that arrives while the first request
+/// is still in flight gets `409`, and reusing a key with a different body gets `422`.
+/// Keys are scoped to the `sub` of the authenticated claims, so that a client refreshing its
+/// access token between retries keeps its keys.
+///
+/// # Examples
+///
+/// ```
+/// use medullah_web::http::middlewares::idempotency::Idempotency;
+///
+/// // keep responses for 24 hours
+/// let middleware = Idempotency::new(86_400).middleware();
+/// ```
+#[derive(Clone)]
+pub struct Idempotency {
+    /// how long completed responses are kept (in seconds)
+    ttl: u64,
+    /// how long an in-flight request holds the key (in seconds)
+    lock_ttl: u64,
+    /// prefix prepended to every redis key
+    prefix: String,
+    /// whether requests without the header should be rejected
+    required: bool,
+    /// maximum body size (in bytes) of the fingerprinted requests
+    limit: usize,
+    subject_resolver: Option<IdempotencySubjectResolver>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct IdempotencyRecord {
+    fingerprint: String,
+    response: Option<CachedResponse>,
+}
+
+/// Marks requests that acquired an idempotency lock
+///
+/// A request dropped before [Idempotency::after] settled the key (handler error, client
+/// gone...) releases it, so that the client can retry without waiting for the lock to expire.
+struct IdempotencyLock {
+    key: String,
+    fingerprint: String,
+    settled: bool,
+}
+
+impl Drop for IdempotencyLock {
+    fn drop(&mut self) {
+        if self.settled {
+            return;
+        }
+
+        let key = std::mem::take(&mut self.key);
+        match Handle::try_current() {
+            Ok(handle) => {
+                debug!("[idempotency] releasing unsettled lock: {}", key);
+                handle.spawn(async move {
+                    if let Err(err) = MEDULLAH.redis().delete(&key).await {
+                        error!("[idempotency] failed to release '{}': {:?}", key, err);
+                    }
+                });
+            }
+            Err(_) => warn!("[idempotency] lock '{}' will be held until it expires", key),
+        }
+    }
+}
+
+impl Idempotency {
+    pub const DEFAULT_PREFIX: &'static str = "idempotency";
+
+    ///
+    ///
+    /// # Arguments
+    ///
+    /// * `ttl`: how long completed responses are kept (in seconds)
+    ///
+    /// returns: Idempotency
+    pub fn new(ttl: u64) -> Self {
+        Idempotency {
+            ttl,
+            lock_ttl: 60,
+            prefix: Self::DEFAULT_PREFIX.to_string(),
+            required: false,
+            limit: 1_048_576,
+            subject_resolver: None,
+        }
+    }
+
+    /// How long an in-flight request holds the key (in seconds), defaults to 60 seconds
+    pub fn lock_ttl(mut self, seconds: u64) -> Self {
+        self.lock_ttl = seconds;
+        self
+    }
+
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.to_string();
+        self
+    }
+
+    /// Reject unsafe requests that do not carry the `Idempotency-Key` header
+    pub fn required(mut self) -> Self {
+        self.required = true;
+        self
+    }
+
+    /// Maximum body size (in bytes), larger requests are rejected with `413`, defaults to 1MB
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = limit;
+        self
+    }
+
+    /// Customize how the subject that keys are scoped to is resolved
+    ///
+    /// By default it is the `sub` of the authenticated claims, requests that can't be
+    /// authenticated are scoped to a hash of their `Authorization` header.
+    pub fn subject(mut self, resolver: IdempotencySubjectResolver) -> Self {
+        self.subject_resolver = Some(resolver);
+        self
+    }
+
+    pub fn middleware(self) -> Middleware {
+        Middleware::around(self)
+    }
+
+    fn is_applicable(method: &Method) -> bool {
+        method == Method::POST || method == Method::PATCH
+    }
+
+    async fn key(&self, req: &HttpRequest, idempotency_key: &str) -> String {
+        let subject = match self.subject_resolver {
+            Some(resolver) => resolver(req),
+            None => authenticated_subject(req)
+                .await
+                .or_else(|| default_subject(req)),
+        };
+
+        let subject = subject.unwrap_or_else(|| "anonymous".to_string());
+        format!("{}:{}:{}", self.prefix, subject, idempotency_key)
+    }
+}
+
+impl AroundMiddleware for Idempotency {
+    fn before<'a>(
+        &'a self,
+        req: &'a HttpRequest,
+        payload: &'a mut Payload,
+    ) -> MiddlewareFuture<'a, Option<HttpResponse>> {
+        Box::pin(async move {
+            if !Self::is_applicable(req.method()) {
+                return Ok(None);
+            }
+
+            let idempotency_key = match req.headers().get(IDEMPOTENCY_KEY) {
+                Some(value) => value.to_str().map_err(|_| {
+                    AppMessage::WarningMessage("Idempotency-Key header must be a visible ascii")
+                })?,
+                None if self.required => {
+                    return Err(AppMessage::WarningMessage(
+                        "Idempotency-Key header is required",
+                    ))
+                }
+                None => return Ok(None),
+            };
+
+            // the body is buffered to be fingerprinted, then handed back to the handler
+            let mut bytes = BytesMut::new();
+            while let Some(chunk) = ntex::util::stream_recv(payload).await {
+                bytes.extend_from_slice(&chunk?);
+                if bytes.len() > self.limit {
+                    return Err(AppMessage::ErrorMessage(
+                        "request payload is too large".to_string(),
+                        StatusCode::PAYLOAD_TOO_LARGE,
+                    ));
+                }
+            }
+
+            let bytes = bytes.freeze();
+            let fingerprint = fingerprint(req, &bytes);
+            *payload = replay_payload(bytes);
+
+            let key = self.key(req, idempotency_key).await;
+            let record = IdempotencyRecord {
+                fingerprint: fingerprint.clone(),
+                response: None,
+            };
+
+            if MEDULLAH
+                .redis()
+                .set_nx_ex(&key, &record, self.lock_ttl)
+                .await?
+            {
+                debug!("[idempotency] lock acquired: {}", key);
+                req.extensions_mut().insert(IdempotencyLock {
+                    key,
+                    fingerprint,
+                    settled: false,
+                });
+                return Ok(None);
+            }
+
+            let existing = MEDULLAH.redis().get::<Option<String>>(&key).await?;
+            let existing = match existing {
+                Some(data) => serde_json::from_str::<IdempotencyRecord>(&data)?,
+                None => {
+                    return Err(AppMessage::ErrorMessage(
+                        "A request with this Idempotency-Key has just completed, please retry"
+                            .to_string(),
+                        StatusCode::CONFLICT,
+                    ))
+                }
+            };
+
+            if existing.fingerprint != fingerprint {
+                warn!("[idempotency] key reused with a different payload: {}", key);
+                return Err(AppMessage::ErrorMessage(
+                    "Idempotency-Key has already been used with a different request payload"
+                        .to_string(),
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                ));
+            }
+
+            match existing.response {
+                Some(stored) => {
+                    debug!("[idempotency] replaying: {}", key);
+                    let mut response = stored.into_response();
+                    response.headers_mut().insert(
+                        HeaderName::from_static(IDEMPOTENT_REPLAYED),
+                        HeaderValue::from_static("true"),
+                    );
+
+                    Ok(Some(response))
+                }
+                None => Err(AppMessage::ErrorMessage(
+                    "A request with this Idempotency-Key is still being processed".to_string(),
+                    StatusCode::CONFLICT,
+                )),
+            }
+        })
+    }
+
+    fn after(&self, resp: WebResponse) -> MiddlewareFuture<'_, WebResponse> {
+        Box::pin(async move {
+            let lock = resp.request().extensions_mut().remove::<IdempotencyLock>();
+            let mut lock = match lock {
+                Some(lock) => lock,
+                None => return Ok(resp),
+            };
+
+            // server errors are not final, release the key so that the client can retry
+            if resp.status().is_server_error() {
+                if let Err(err) = MEDULLAH.redis().delete(&lock.key).await {
+                    error!("[idempotency] failed to release '{}': {:?}", lock.key, err);
+                }
+
+                lock.settled = true;
+                return Ok(resp);
+            }
+
+            let (resp, stored) = CachedResponse::capture(resp).await;
+            let result = match stored {
+                Some(stored) => {
+                    let record = IdempotencyRecord {
+                        fingerprint: lock.fingerprint.clone(),
+                        response: Some(stored),
+                    };
+
+                    MEDULLAH
+                        .redis()
+                        .set_ex(&lock.key, &record, self.ttl)
+                        .await
+                        .map(|_| ())
+                }
+                // responses that cannot be stored cannot be replayed either
+                None => MEDULLAH.redis().delete(&lock.key).await.map(|_| ()),
+            };
+
+            if let Err(err) = result {
+                error!("[idempotency] failed to store '{}': {:?}", lock.key, err);
+            }
+
+            lock.settled = true;
+            Ok(resp)
+        })
+    }
+}
+
+/// `sub` of the claims stashed on the request, authenticating it first if needed
+#[cfg(feature = "jwt")]
+async fn authenticated_subject(req: &HttpRequest) -> Option<String> {
+    use crate::helpers::jwt::JwtTokenClaims;
+    use crate::http::extractors::auth::Auth;
+
+    let auth = Auth::<JwtTokenClaims>::resolve(req).await.ok()?;
+    Some(format!("sub:{}", auth.sub))
+}
+
+#[cfg(not(feature = "jwt"))]
+async fn authenticated_subject(_req: &HttpRequest) -> Option<String> {
+    None
+}
+
+fn default_subject(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get(AUTHORIZATION)
+        .map(|value| hex::encode(Sha256::digest(value.as_bytes())))
+}
+
+/// sha256 of the method, path, query and body, stored fingerprints must match across replicas
+fn fingerprint(req: &HttpRequest, body: &Bytes) -> String {
+    let mut hasher = Sha256::new();
+    for part in [req.method().as_str(), req.path(), req.query_string()] {
+        hasher.update(part.as_bytes());
+        hasher.update([0]);
+    }
+
+    hasher.update(body);
+    hex::encode(hasher.finalize())
+}
+
+fn replay_payload(bytes: Bytes) -> Payload {
+    let (_, mut payload) = h1::Payload::create(true);
+    payload.unread_data(bytes);
+    Payload::from(payload)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ntex::web::test::TestRequest;
+
+    #[test]
+    fn test_fingerprint_depends_on_body() {
+        let req = TestRequest::post().uri("/orders").to_http_request();
+
+        let first = fingerprint(&req, &Bytes::from_static(b"{\"amount\": 10}"));
+        let second = fingerprint(&req, &Bytes::from_static(b"{\"amount\": 10}"));
+        let third = fingerprint(&req, &Bytes::from_static(b"{\"amount\": 20}"));
+
+        assert_eq!(first, second);
+        assert_ne!(first, third);
+        assert_eq!(first.len(), 64);
+
+        let other_query = TestRequest::post()
+            .uri("/orders?dry_run=1")
+            .to_http_request();
+        let fourth = fingerprint(&other_query, &Bytes::from_static(b"{\"amount\": 10}"));
+        assert_ne!(first, fourth);
+    }
+
+    #[test]
+    fn test_default_subject() {
+        let anonymous = TestRequest::post().to_http_request();
+        assert!(default_subject(&anonymous).is_none());
+
+        let first = TestRequest::post()
+            .header(AUTHORIZATION, "Bearer abc")
+            .to_http_request();
+        let second = TestRequest::post()
+            .header(AUTHORIZATION, "Bearer xyz")
+            .to_http_request();
+
+        assert!(default_subject(&first).is_some());
+        assert_ne!(default_subject(&first), default_subject(&second));
+    }
+
+    #[cfg(feature = "jwt")]
+    #[ntex::test]
+    async fn test_subject_survives_token_refresh() {
+        use crate::helpers::jwt::JwtTokenClaims;
+        use crate::http::extractors::auth::Auth;
+
+        let idempotency = Idempotency::new(60);
+        let mut keys = vec![];
+
+        for token in ["Bearer abc", "Bearer refreshed"] {
+            let req = TestRequest::post()
+                .header(AUTHORIZATION, token)
+                .to_http_request();
+            req.extensions_mut().insert(Auth(JwtTokenClaims {
+                sub: "user-1".to_string(),
+                iat: 0,
+                exp: 0,
+                iss: "accounts".to_string(),
+                aud: "api".to_string(),
+                jti: token.to_string(),
+            }));
+
+            keys.push(idempotency.key(&req, "payment-1").await);
+        }
+
+        assert_eq!(keys[0], "idempotency:sub:user-1:payment-1");
+        assert_eq!(keys[0], keys[1]);
+    }
+}
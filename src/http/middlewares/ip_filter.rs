@@ -0,0 +1,154 @@
+use std::env;
+use std::net::IpAddr;
+
+use log::warn;
+use ntex::http::Payload;
+use ntex::web::{HttpRequest, HttpResponse};
+
+use crate::helpers::ip::IpRanges;
+use crate::helpers::request::RequestHelper;
+use crate::http::middlewares::{AroundMiddleware, Middleware, MiddlewareFuture};
+use crate::prelude::{AppMessage, OnceLockHelper};
+use crate::MEDULLAH;
+
+/// Restricts a route group to (or away from) the given ip ranges
+///
+/// Works on the client ip resolved through trusted proxies, deny rules take precedence,
+/// and when an allow list is set, only addresses within it are let through.
+///
+/// # Examples
+///
+/// ```
+/// use medullah_web::http::middlewares::ip_filter::IpFilter;
+///
+/// let middleware = IpFilter::new()
+///     .allow("10.0.0.0/8, 192.168.100.0/24")
+///     .deny("10.66.0.0/16")
+///     .middleware();
+/// ```
+#[derive(Clone, Default)]
+pub struct IpFilter {
+    allowed: IpRanges,
+    denied: IpRanges,
+}
+
+impl IpFilter {
+    pub fn new() -> Self {
+        IpFilter::default()
+    }
+
+    /// Loads the lists from `{PREFIX}_{NAME}_IP_ALLOW` and `{PREFIX}_{NAME}_IP_DENY`,
+    /// both are comma-separated and optional
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use medullah_web::http::middlewares::ip_filter::IpFilter;
+    ///
+    /// // reads APP_ADMIN_IP_ALLOW & APP_ADMIN_IP_DENY
+    /// let middleware = IpFilter::from_env("ADMIN").middleware();
+    /// ```
+    pub fn from_env(name: &str) -> Self {
+        let prefix = format!("{}_{}", MEDULLAH.app().app_env_prefix, name);
+        let mut filter = IpFilter::new();
+
+        if let Ok(list) = env::var(format!("{}_IP_ALLOW", prefix)) {
+            filter = filter.allow(&list);
+        }
+
+        if let Ok(list) = env::var(format!("{}_IP_DENY", prefix)) {
+            filter = filter.deny(&list);
+        }
+
+        filter
+    }
+
+    /// Add comma-separated CIDR blocks/addresses to the allow list
+    pub fn allow(mut self, list: &str) -> Self {
+        let ranges = IpRanges::parse(list).expect("invalid ip allow list");
+        self.allowed.extend(ranges);
+        self
+    }
+
+    /// Add comma-separated CIDR blocks/addresses to the deny list
+    pub fn deny(mut self, list: &str) -> Self {
+        let ranges = IpRanges::parse(list).expect("invalid ip deny list");
+        self.denied.extend(ranges);
+        self
+    }
+
+    pub fn middleware(self) -> Middleware {
+        Middleware::around(self)
+    }
+
+    pub fn is_allowed(&self, ip: Option<IpAddr>) -> bool {
+        match ip {
+            Some(ip) if self.denied.contains(&ip) => false,
+            Some(ip) => self.allowed.is_empty() || self.allowed.contains(&ip),
+            // we cannot vouch for a client we cannot identify
+            None => self.allowed.is_empty() && self.denied.is_empty(),
+        }
+    }
+}
+
+impl AroundMiddleware for IpFilter {
+    fn before<'a>(
+        &'a self,
+        req: &'a HttpRequest,
+        _payload: &'a mut Payload,
+    ) -> MiddlewareFuture<'a, Option<HttpResponse>> {
+        Box::pin(async move {
+            let ip = req.ip_addr();
+            if self.is_allowed(ip) {
+                return Ok(None);
+            }
+
+            warn!(
+                "[ip-filter] rejected {} {} from {}",
+                req.method(),
+                req.path(),
+                ip.map(|ip| ip.to_string())
+                    .unwrap_or_else(|| "unknown".to_string())
+            );
+
+            Err(AppMessage::Forbidden)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(value: &str) -> Option<IpAddr> {
+        Some(value.parse().unwrap())
+    }
+
+    #[test]
+    fn test_allow_list() {
+        let filter = IpFilter::new().allow("10.0.0.0/8").allow("192.168.1.5");
+
+        assert!(filter.is_allowed(ip("10.4.3.2")));
+        assert!(filter.is_allowed(ip("192.168.1.5")));
+        assert!(!filter.is_allowed(ip("192.168.1.6")));
+        assert!(!filter.is_allowed(None));
+    }
+
+    #[test]
+    fn test_deny_takes_precedence() {
+        let filter = IpFilter::new().allow("10.0.0.0/8").deny("10.66.0.0/16");
+
+        assert!(filter.is_allowed(ip("10.65.0.1")));
+        assert!(!filter.is_allowed(ip("10.66.0.1")));
+    }
+
+    #[test]
+    fn test_deny_only() {
+        let filter = IpFilter::new().deny("203.0.113.0/24");
+
+        assert!(filter.is_allowed(ip("8.8.8.8")));
+        assert!(!filter.is_allowed(ip("203.0.113.10")));
+        assert!(!filter.is_allowed(None));
+        assert!(IpFilter::new().is_allowed(None));
+    }
+}
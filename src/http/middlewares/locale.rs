@@ -0,0 +1,214 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use log::debug;
+use ntex::http::header::{HeaderValue, ACCEPT_LANGUAGE, CONTENT_LANGUAGE};
+use ntex::service::{Middleware as ServiceMiddleware, Service, ServiceCtx};
+use ntex::web::{HttpRequest, WebRequest, WebResponse};
+
+use crate::http::error_renderer::{JsonError, JsonErrorRenderer};
+use crate::i18n;
+
+type LocaleResolver = Arc<
+    dyn for<'a> Fn(&'a HttpRequest) -> Pin<Box<dyn Future<Output = Option<String>> + 'a>>
+        + Send
+        + Sync,
+>;
+
+/// App-level middleware picking the locale messages of a request are translated in
+///
+/// The locale comes from the resolver (e.g. a claim of the authenticated user) when it yields
+/// a supported locale, then from `Accept-Language`, then the default locale is used.
+/// It is available through [i18n::current_locale] and sent back as `Content-Language`.
+///
+/// It is installed by the server through [crate::http::server::ServerConfig::localize]:
+///
+/// ```
+/// use medullah_web::http::middlewares::locale::Localize;
+///
+/// // `Localize::new().from_claims::<UserClaims>(|claims| claims.locale.clone())` with the `jwt` feature
+/// let localize = Localize::new().resolve_with(|req| {
+///     let locale = req.headers().get("x-user-locale")?;
+///     locale.to_str().ok().map(String::from)
+/// });
+/// ```
+#[derive(Clone, Default)]
+pub struct Localize {
+    resolver: Option<LocaleResolver>,
+}
+
+pub struct LocalizeService<S> {
+    service: S,
+    resolver: Option<LocaleResolver>,
+}
+
+impl Localize {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn resolve_with<F>(mut self, resolver: F) -> Self
+    where
+        F: Fn(&HttpRequest) -> Option<String> + Send + Sync + 'static,
+    {
+        self.resolver = Some(Arc::new(move |req| {
+            let locale = resolver(req);
+            Box::pin(async move { locale })
+        }));
+        self
+    }
+
+    /// Locale of the authenticated user, anonymous requests fall back to `Accept-Language`
+    ///
+    /// The claims are authenticated here when no middleware or extractor did it yet, and
+    /// stashed on the request for the ones that run after.
+    #[cfg(feature = "jwt")]
+    pub fn from_claims<C>(mut self, locale: fn(&C) -> Option<String>) -> Self
+    where
+        C: serde::de::DeserializeOwned + Clone + 'static,
+    {
+        use crate::http::extractors::auth::Auth;
+
+        self.resolver = Some(Arc::new(move |req| {
+            Box::pin(async move {
+                let auth = Auth::<C>::resolve(req).await.ok()?;
+                locale(&auth)
+            })
+        }));
+        self
+    }
+}
+
+impl<S> ServiceMiddleware<S> for Localize {
+    type Service = LocalizeService<S>;
+
+    fn create(&self, service: S) -> Self::Service {
+        LocalizeService {
+            service,
+            resolver: self.resolver.clone(),
+        }
+    }
+}
+
+impl<S> Service<WebRequest<JsonErrorRenderer>> for LocalizeService<S>
+where
+    S: Service<WebRequest<JsonErrorRenderer>, Response = WebResponse, Error = JsonError>,
+{
+    type Response = WebResponse;
+    type Error = JsonError;
+
+    ntex::forward_ready!(service);
+
+    async fn call(
+        &self,
+        req: WebRequest<JsonErrorRenderer>,
+        ctx: ServiceCtx<'_, Self>,
+    ) -> Result<Self::Response, Self::Error> {
+        let (req, payload) = req.into_parts();
+        let resolved = match &self.resolver {
+            Some(resolver) => resolver(&req).await,
+            None => None,
+        };
+        let req = WebRequest::from_parts(req, payload).unwrap();
+
+        let locale = resolved
+            .filter(|locale| i18n::is_supported(locale))
+            .or_else(|| {
+                req.headers()
+                    .get(ACCEPT_LANGUAGE)
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(i18n::negotiate)
+            })
+            .unwrap_or_else(i18n::default_locale);
+
+        debug!("[locale] {} {}: {}", req.method(), req.path(), locale);
+        let header = HeaderValue::from_str(&locale).ok();
+
+        let mut resp = i18n::with_locale(locale, ctx.call(&self.service, req)).await?;
+        if let Some(header) = header {
+            resp.headers_mut().insert(CONTENT_LANGUAGE, header);
+        }
+
+        Ok(resp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ntex::http::StatusCode;
+    use ntex::web::test::{init_service, read_body, TestRequest};
+    use ntex::web::{self, App};
+    use serde_json::Value;
+
+    use super::*;
+    use crate::prelude::AppMessage;
+
+    #[ntex::test]
+    async fn test_messages_are_translated() {
+        let app = init_service(
+            App::with(JsonErrorRenderer)
+                .wrap(Localize::new().resolve_with(|req| {
+                    req.headers()
+                        .get("x-user-locale")
+                        .and_then(|v| v.to_str().ok())
+                        .map(String::from)
+                }))
+                .route(
+                    "/orders/1",
+                    web::get().to(|| async {
+                        Err::<web::HttpResponse, _>(AppMessage::EntityNotFound(
+                            "commande".to_string(),
+                        ))
+                    }),
+                ),
+        )
+        .await;
+
+        let request = TestRequest::with_uri("/orders/1")
+            .header(ACCEPT_LANGUAGE, "fr-FR,fr;q=0.9,en;q=0.5")
+            .to_request();
+        let response = app.call(request).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers().get(CONTENT_LANGUAGE).unwrap(), "fr");
+
+        let body: Value = serde_json::from_slice(&read_body(response).await).unwrap();
+        assert_eq!(body["message"], "L'élément commande n'existe pas");
+
+        // the user's locale wins over the header
+        let request = TestRequest::with_uri("/orders/1")
+            .header(ACCEPT_LANGUAGE, "fr")
+            .header("x-user-locale", "sw")
+            .to_request();
+        let response = app.call(request).await.unwrap();
+        assert_eq!(response.headers().get(CONTENT_LANGUAGE).unwrap(), "sw");
+
+        let request = TestRequest::with_uri("/orders/1").to_request();
+        let response = app.call(request).await.unwrap();
+        let body: Value = serde_json::from_slice(&read_body(response).await).unwrap();
+        assert_eq!(body["message"], "Such commande does not exist");
+    }
+
+    #[cfg(feature = "jwt")]
+    #[ntex::test]
+    async fn test_locale_from_stashed_claims() {
+        use crate::helpers::jwt::JwtTokenClaims;
+        use crate::http::extractors::auth::Auth;
+
+        let localize =
+            Localize::new().from_claims::<JwtTokenClaims>(|claims| Some(claims.sub.clone()));
+        let resolver = localize.resolver.unwrap();
+
+        let req = TestRequest::default().to_http_request();
+        req.extensions_mut().insert(Auth(JwtTokenClaims {
+            sub: "sw".to_string(),
+            iat: 0,
+            exp: 0,
+            iss: "accounts".to_string(),
+            aud: "api".to_string(),
+            jti: "jti".to_string(),
+        }));
+
+        assert_eq!(resolver(&req).await.as_deref(), Some("sw"));
+    }
+}
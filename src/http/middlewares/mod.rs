@@ -1,10 +1,24 @@
-use crate::http::middlewares::executor::MiddlewareExecutor;
+use crate::http::middlewares::executor::{MiddlewareChain, MiddlewareExecutor};
 use crate::results::AppResult;
-use ntex::web::{HttpRequest, WebResponse};
+use ntex::http::Payload;
+use ntex::web::{HttpRequest, HttpResponse, WebResponse};
 use std::future::Future;
 use std::pin::Pin;
+use std::sync::Arc;
 
+#[cfg(feature = "jwt")]
+pub mod auth;
+pub mod error_boundary;
 mod executor;
+#[cfg(feature = "jwt")]
+pub mod guard;
+#[cfg(feature = "redis")]
+pub mod idempotency;
+pub mod ip_filter;
+pub mod locale;
+pub mod request_id;
+#[cfg(feature = "redis")]
+pub mod response_cache;
 
 pub type BeforeMiddlewareHandler =
     fn(HttpRequest) -> Pin<Box<dyn Future<Output = AppResult<HttpRequest>>>>;
@@ -12,14 +26,43 @@ pub type BeforeMiddlewareHandler =
 pub type AfterMiddlewareHandler =
     fn(WebResponse) -> Pin<Box<dyn Future<Output = AppResult<WebResponse>>>>;
 
+pub type AroundMiddlewareHandler = Arc<dyn AroundMiddleware>;
+
+pub type MiddlewareFuture<'a, T> = Pin<Box<dyn Future<Output = AppResult<T>> + 'a>>;
+
+/// Stateful middleware that runs on both sides of the handler
+pub trait AroundMiddleware: Send + Sync {
+    /// Executed before the handler, returning a response skips the handler entirely
+    fn before<'a>(
+        &'a self,
+        req: &'a HttpRequest,
+        payload: &'a mut Payload,
+    ) -> MiddlewareFuture<'a, Option<HttpResponse>>;
+
+    /// Executed after the handler has produced a response
+    fn after(&self, resp: WebResponse) -> MiddlewareFuture<'_, WebResponse> {
+        Box::pin(async move { Ok(resp) })
+    }
+}
+
 #[derive(Clone)]
 pub enum Middleware {
     Before(BeforeMiddlewareHandler),
     After(AfterMiddlewareHandler),
+    Around(AroundMiddlewareHandler),
 }
 
 impl Middleware {
+    pub fn around<M: AroundMiddleware + 'static>(middleware: M) -> Self {
+        Middleware::Around(Arc::new(middleware))
+    }
+
     pub fn middleware(&self) -> MiddlewareExecutor {
         MiddlewareExecutor::new(self.clone())
     }
+
+    /// Wraps a scope with every middleware, the last one being the outermost
+    pub fn chain(middlewares: &[Middleware]) -> MiddlewareChain {
+        MiddlewareChain::new(middlewares.to_vec())
+    }
 }
@@ -0,0 +1,109 @@
+use log::debug;
+use ntex::http::header::{HeaderName, HeaderValue};
+use ntex::service::{Middleware as ServiceMiddleware, Service, ServiceCtx};
+use ntex::web::{WebRequest, WebResponse};
+use uuid::Uuid;
+
+use crate::http::error_renderer::{JsonError, JsonErrorRenderer};
+use crate::http::response::problem::ProblemDetails;
+
+pub const REQUEST_ID_HEADER: HeaderName = HeaderName::from_static("x-request-id");
+
+/// Identifier of the current request, see [RequestIdentifier]
+#[derive(Clone, Debug, PartialEq)]
+pub struct RequestId(pub String);
+
+/// App-level middleware tagging every request with an id
+///
+/// The `X-Request-Id` sent by the client (or a proxy) is reused when it looks sane,
+/// otherwise a new one is generated. The id is available through
+/// [crate::helpers::request::RequestHelper::request_id] and echoed back in the response.
+#[derive(Clone, Copy, Default)]
+pub struct RequestIdentifier;
+
+pub struct RequestIdentifierService<S> {
+    service: S,
+}
+
+impl RequestId {
+    /// Incoming ids are echoed back and logged, so only short ids made of safe characters are kept
+    pub fn parse(value: &str) -> Option<Self> {
+        let value = value.trim();
+        let is_valid = !value.is_empty()
+            && value.len() <= 128
+            && value
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'));
+
+        is_valid.then(|| RequestId(value.to_string()))
+    }
+
+    pub fn generate() -> Self {
+        RequestId(Uuid::new_v4().to_string())
+    }
+}
+
+impl<S> ServiceMiddleware<S> for RequestIdentifier {
+    type Service = RequestIdentifierService<S>;
+
+    fn create(&self, service: S) -> Self::Service {
+        RequestIdentifierService { service }
+    }
+}
+
+impl<S> Service<WebRequest<JsonErrorRenderer>> for RequestIdentifierService<S>
+where
+    S: Service<WebRequest<JsonErrorRenderer>, Response = WebResponse, Error = JsonError>,
+{
+    type Response = WebResponse;
+    type Error = JsonError;
+
+    ntex::forward_ready!(service);
+
+    async fn call(
+        &self,
+        req: WebRequest<JsonErrorRenderer>,
+        ctx: ServiceCtx<'_, Self>,
+    ) -> Result<Self::Response, Self::Error> {
+        let id = req
+            .headers()
+            .get(REQUEST_ID_HEADER)
+            .and_then(|v| v.to_str().ok())
+            .and_then(RequestId::parse)
+            .unwrap_or_else(RequestId::generate);
+
+        debug!("[request-id] {} {} {}", id.0, req.method(), req.path());
+        req.extensions_mut().insert(id.clone());
+        let problem_details = ProblemDetails::is_wanted(req.headers());
+
+        match ctx.call(&self.service, req).await {
+            Ok(mut resp) => {
+                if let Ok(value) = HeaderValue::from_str(&id.0) {
+                    resp.headers_mut().insert(REQUEST_ID_HEADER, value);
+                }
+
+                Ok(resp)
+            }
+            Err(err) => Err(err
+                .with_request_id(&id.0)
+                .with_problem_details(problem_details)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            RequestId::parse(" 4f1c-aa_01.b:2 "),
+            Some(RequestId("4f1c-aa_01.b:2".to_string()))
+        );
+        assert_eq!(RequestId::parse(""), None);
+        assert_eq!(RequestId::parse("has space"), None);
+        assert_eq!(RequestId::parse("<script>"), None);
+        assert_eq!(RequestId::parse(&"a".repeat(129)), None);
+    }
+}
@@ -0,0 +1,358 @@
+use futures_util::StreamExt;
+use log::{debug, error};
+use ntex::http::body::{Body, BodySize, MessageBody, ResponseBody};
+use ntex::http::header::{HeaderName, HeaderValue, CACHE_CONTROL};
+use ntex::http::{Method, Payload, StatusCode};
+use ntex::util::BytesMut;
+use ntex::web::{HttpRequest, HttpResponse, WebResponse};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::http::middlewares::{AroundMiddleware, Middleware, MiddlewareFuture};
+use crate::prelude::{AppResult, OnceLockHelper};
+use crate::MEDULLAH;
+
+pub const X_CACHE: &str = "x-cache";
+
+/// response headers that are never replayed from the cache
+const SKIPPED_HEADERS: [&str; 4] = ["set-cookie", "content-length", "transfer-encoding", X_CACHE];
+
+/// Caches full GET responses in redis, keyed on method, path, normalized query and chosen headers
+///
+/// # Examples
+///
+/// ```
+/// use medullah_web::http::middlewares::response_cache::ResponseCache;
+///
+/// // cache for 5 minutes, separately per tenant
+/// let middleware = ResponseCache::new(300)
+///     .vary_by("x-tenant-id")
+///     .middleware();
+/// ```
+#[derive(Clone)]
+pub struct ResponseCache {
+    /// cache lifetime (in seconds)
+    ttl: u64,
+    /// prefix prepended to every cache key
+    prefix: String,
+    /// request headers whose values become part of the cache key
+    vary_headers: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CachedResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl ResponseCache {
+    pub const DEFAULT_PREFIX: &'static str = "response-cache";
+
+    ///
+    ///
+    /// # Arguments
+    ///
+    /// * `ttl`: cache lifetime (in seconds)
+    ///
+    /// returns: ResponseCache
+    pub fn new(ttl: u64) -> Self {
+        ResponseCache {
+            ttl,
+            prefix: Self::DEFAULT_PREFIX.to_string(),
+            vary_headers: vec![],
+        }
+    }
+
+    /// Use a different key prefix, useful to group routes for purging
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.to_string();
+        self
+    }
+
+    /// Keep separate cache entries per value of the given request header (e.g. user or tenant)
+    pub fn vary_by(mut self, header: &str) -> Self {
+        self.vary_headers.push(header.to_lowercase());
+        self
+    }
+
+    pub fn middleware(self) -> Middleware {
+        Middleware::around(self)
+    }
+
+    /// Computes the cache key of the given request
+    ///
+    /// Keys have the form `{prefix}:{method}:{path}:{query}:{vary-hash}`,
+    /// so everything cached for a path can be purged with [ResponseCache::purge_path]
+    pub fn key(&self, req: &HttpRequest) -> String {
+        // sha256 rather than a std hasher, keys must match across replicas and releases
+        let mut hasher = Sha256::new();
+        for name in &self.vary_headers {
+            hasher.update(name.as_bytes());
+            match req.headers().get(name.as_str()) {
+                Some(value) => {
+                    hasher.update([1]);
+                    hasher.update(value.as_bytes());
+                }
+                None => hasher.update([0]),
+            }
+            hasher.update([0]);
+        }
+
+        format!(
+            "{}:{}:{}:{}:{}",
+            self.prefix,
+            req.method(),
+            req.path(),
+            normalize_query(req.query_string()),
+            hex::encode(hasher.finalize())
+        )
+    }
+
+    /// Purge every cached response whose key starts with `prefix`
+    pub async fn purge(prefix: &str) -> AppResult<i32> {
+        MEDULLAH.cache().delete_by_prefix(prefix).await
+    }
+
+    /// Purge every cached GET response of the given path (and paths beneath it)
+    ///
+    /// Paths match on segment boundaries, purging `/orders/1` leaves `/orders/10` cached.
+    pub async fn purge_path(&self, path: &str) -> AppResult<i32> {
+        let mut purged = 0;
+        for prefix in self.path_prefixes(path) {
+            purged += Self::purge(&prefix).await?;
+        }
+
+        Ok(purged)
+    }
+
+    /// Key prefixes of the path itself and of the paths beneath it
+    fn path_prefixes(&self, path: &str) -> [String; 2] {
+        let base = format!(
+            "{}:{}:{}",
+            self.prefix,
+            Method::GET,
+            path.trim_end_matches('/')
+        );
+
+        [format!("{}:", base), format!("{}/", base)]
+    }
+
+    fn is_cacheable_request(req: &HttpRequest) -> bool {
+        req.method() == Method::GET
+    }
+
+    fn is_cacheable_response(resp: &WebResponse) -> bool {
+        if resp.status() != StatusCode::OK {
+            return false;
+        }
+
+        match resp
+            .headers()
+            .get(CACHE_CONTROL)
+            .and_then(|v| v.to_str().ok())
+        {
+            Some(value) => !(value.contains("no-store") || value.contains("private")),
+            None => true,
+        }
+    }
+}
+
+impl AroundMiddleware for ResponseCache {
+    fn before<'a>(
+        &'a self,
+        req: &'a HttpRequest,
+        _payload: &'a mut Payload,
+    ) -> MiddlewareFuture<'a, Option<HttpResponse>> {
+        Box::pin(async move {
+            if !Self::is_cacheable_request(req) {
+                return Ok(None);
+            }
+
+            let key = self.key(req);
+            match MEDULLAH.cache().get::<CachedResponse>(&key).await {
+                Ok(Some(cached)) => {
+                    debug!("[response-cache] hit: {}", key);
+                    let mut response = cached.into_response();
+                    response.headers_mut().insert(
+                        HeaderName::from_static(X_CACHE),
+                        HeaderValue::from_static("HIT"),
+                    );
+
+                    Ok(Some(response))
+                }
+                Ok(None) => Ok(None),
+                Err(err) => {
+                    // cache outages should not take the endpoint down with them
+                    error!("[response-cache] failed to read '{}': {:?}", key, err);
+                    Ok(None)
+                }
+            }
+        })
+    }
+
+    fn after(&self, resp: WebResponse) -> MiddlewareFuture<'_, WebResponse> {
+        Box::pin(async move {
+            if !Self::is_cacheable_request(resp.request()) || !Self::is_cacheable_response(&resp) {
+                return Ok(resp);
+            }
+
+            let (mut resp, cached) = CachedResponse::capture(resp).await;
+            if let Some(cached) = cached {
+                let key = self.key(resp.request());
+                match MEDULLAH.cache().put_with_ttl(&key, &cached, self.ttl).await {
+                    Ok(_) => debug!("[response-cache] stored: {}", key),
+                    Err(err) => error!("[response-cache] failed to store '{}': {:?}", key, err),
+                }
+            }
+
+            resp.headers_mut().insert(
+                HeaderName::from_static(X_CACHE),
+                HeaderValue::from_static("MISS"),
+            );
+
+            Ok(resp)
+        })
+    }
+}
+
+impl CachedResponse {
+    /// Reads the body of the given response into a storable copy, the response is handed back intact
+    ///
+    /// Streamed and non-utf8 bodies are left untouched and yield `None`
+    pub async fn capture(mut resp: WebResponse) -> (WebResponse, Option<CachedResponse>) {
+        // streamed bodies (sse, file downloads...) are never buffered
+        if !matches!(
+            resp.response().body().size(),
+            BodySize::Sized(_) | BodySize::Empty
+        ) {
+            return (resp, None);
+        }
+
+        let mut body = resp.take_body();
+        let mut bytes = BytesMut::new();
+        while let Some(chunk) = body.next().await {
+            match chunk {
+                Ok(chunk) => bytes.extend_from_slice(&chunk),
+                Err(err) => {
+                    error!("failed to read response body: {:?}", err);
+                    return (resp, None);
+                }
+            }
+        }
+
+        let bytes = bytes.freeze();
+        let cached = std::str::from_utf8(&bytes)
+            .ok()
+            .map(|content| CachedResponse {
+                status: resp.status().as_u16(),
+                headers: resp
+                    .headers()
+                    .iter()
+                    .filter(|(name, _)| !SKIPPED_HEADERS.contains(&name.as_str()))
+                    .filter_map(|(name, value)| {
+                        value
+                            .to_str()
+                            .ok()
+                            .map(|v| (name.to_string(), v.to_string()))
+                    })
+                    .collect(),
+                body: content.to_string(),
+            });
+
+        let resp = resp.map_body(|_, _| ResponseBody::Body(Body::from(bytes)));
+        (resp, cached)
+    }
+
+    pub fn into_response(self) -> HttpResponse {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::OK);
+        let mut builder = HttpResponse::build(status);
+        for (name, value) in &self.headers {
+            builder.header(name.as_str(), value.as_str());
+        }
+
+        builder.body(self.body)
+    }
+}
+
+/// Sorts query pairs, so that `?b=2&a=1` and `?a=1&b=2` share a cache entry
+fn normalize_query(query: &str) -> String {
+    let mut pairs = query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .collect::<Vec<_>>();
+
+    pairs.sort_unstable();
+    pairs.join("&")
+}
+
+#[cfg(test)]
+mod tests {
+    use ntex::web::test::TestRequest;
+
+    use super::*;
+
+    #[test]
+    fn test_normalize_query() {
+        assert_eq!(normalize_query("b=2&a=1"), "a=1&b=2");
+        assert_eq!(normalize_query("a=1&&b=2&"), "a=1&b=2");
+        assert_eq!(normalize_query(""), "");
+    }
+
+    #[test]
+    fn test_key() {
+        let cache = ResponseCache::new(60).vary_by("x-tenant-id");
+        let key = |uri: &str, tenant: Option<&str>| {
+            let req = match tenant {
+                Some(tenant) => TestRequest::with_uri(uri).header("x-tenant-id", tenant),
+                None => TestRequest::with_uri(uri),
+            };
+            cache.key(&req.to_http_request())
+        };
+
+        let orders = key("/orders?b=2&a=1", Some("acme"));
+        assert!(orders.starts_with("response-cache:GET:/orders:a=1&b=2:"));
+        assert_eq!(orders, key("/orders?a=1&b=2", Some("acme")));
+
+        let hash = orders.rsplit(':').next().unwrap();
+        assert_eq!(hash.len(), 64);
+        assert_ne!(orders, key("/orders?a=1&b=2", Some("other")));
+        assert_ne!(orders, key("/orders?a=1&b=2", None));
+        assert_ne!(key("/orders", Some("")), key("/orders", None));
+    }
+
+    #[test]
+    fn test_path_prefixes() {
+        let cache = ResponseCache::new(60);
+        let prefixes = cache.path_prefixes("/orders/1/");
+        assert_eq!(
+            prefixes,
+            [
+                "response-cache:GET:/orders/1:".to_string(),
+                "response-cache:GET:/orders/1/".to_string()
+            ]
+        );
+
+        let other = cache.key(&TestRequest::with_uri("/orders/10").to_http_request());
+        assert!(prefixes.iter().all(|prefix| !other.starts_with(prefix)));
+
+        let nested = cache.key(&TestRequest::with_uri("/orders/1/items").to_http_request());
+        assert!(nested.starts_with(&prefixes[1]));
+    }
+
+    #[test]
+    fn test_cached_response_into_response() {
+        let cached = CachedResponse {
+            status: 200,
+            headers: vec![("content-type".to_string(), "application/json".to_string())],
+            body: "{}".to_string(),
+        };
+
+        let response = cached.into_response();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(
+            response.headers().get("content-type").unwrap(),
+            "application/json"
+        );
+    }
+}
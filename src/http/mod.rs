@@ -1,12 +1,22 @@
-use ntex::web::ServiceConfig;
+use crate::http::error_renderer::JsonErrorRenderer;
 
+#[cfg(feature = "reqwest")]
+pub mod client;
+pub mod error_renderer;
 pub mod extractors;
 pub mod kernel;
 pub mod middlewares;
 pub mod response;
 pub mod server;
+#[cfg(feature = "static")]
+pub mod static_files;
+#[cfg(feature = "websocket")]
+pub mod websocket;
 
 pub use ntex::http::Method;
 pub use ntex_cors::Cors;
 
+/// Service config of the app, errors are rendered by [JsonErrorRenderer]
+pub type ServiceConfig = ntex::web::ServiceConfig<JsonErrorRenderer>;
+
 pub type HttpHandler = fn(cfg: &mut ServiceConfig);
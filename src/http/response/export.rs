@@ -0,0 +1,813 @@
+use std::io::{self, Read, Seek};
+
+use futures_util::{stream, Stream, StreamExt};
+use log::error;
+use ntex::http::header::{CACHE_CONTROL, CONTENT_DISPOSITION, CONTENT_TYPE};
+use ntex::http::{Response, StatusCode};
+use ntex::util::Bytes;
+use ntex::web::HttpResponse;
+use rust_xlsxwriter::{Format, Workbook, Worksheet, XlsxError};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::prelude::{AppMessage, AppResult};
+
+/// Rows buffered into a single chunk of a csv body
+const ROWS_PER_CHUNK: usize = 256;
+
+const FILE_CHUNK_SIZE: usize = 64 * 1024;
+
+type ExportBody = std::pin::Pin<Box<dyn Stream<Item = io::Result<Bytes>>>>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Csv,
+    Xlsx,
+}
+
+/// A field of the rows and the header it is exported under
+///
+/// Fields are serde field names, nested fields are reached with dots (e.g. `customer.name`).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExportColumn {
+    pub field: String,
+    pub header: String,
+}
+
+/// Streams serializable rows as a csv or xlsx attachment, see [crate::helpers::responder::Responder::export]
+///
+/// Columns default to the fields of the first row, in declaration order for plain structs.
+/// Csv text cells starting like a formula (`=`, `+`, `-`, `@`, tab or carriage return) are
+/// prefixed with `'` so spreadsheets don't evaluate them, see [Export::escape_formulas].
+///
+/// ```ignore
+/// let export = Export::xlsx("orders")
+///     .column("reference", "Reference")
+///     .column("customer.name", "Customer")
+///     .column("total", "Total");
+///
+/// Responder::export(export, rows)
+/// ```
+#[derive(Clone, Debug)]
+pub struct Export {
+    format: ExportFormat,
+    filename: String,
+    sheet_name: Option<String>,
+    columns: Vec<ExportColumn>,
+    escape_formulas: bool,
+}
+
+impl ExportFormat {
+    /// Format named by a query parameter or file extension, e.g. `?format=xlsx`
+    pub fn parse(format: &str) -> Option<Self> {
+        match format
+            .trim()
+            .trim_start_matches('.')
+            .to_ascii_lowercase()
+            .as_str()
+        {
+            "csv" => Some(ExportFormat::Csv),
+            "xlsx" | "excel" => Some(ExportFormat::Xlsx),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Xlsx => "xlsx",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Xlsx => {
+                "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"
+            }
+        }
+    }
+}
+
+impl ExportColumn {
+    pub fn new(field: &str, header: &str) -> Self {
+        ExportColumn {
+            field: field.to_string(),
+            header: header.to_string(),
+        }
+    }
+}
+
+impl Export {
+    pub fn new(format: ExportFormat) -> Self {
+        Export {
+            format,
+            filename: "export".to_string(),
+            sheet_name: None,
+            columns: vec![],
+            escape_formulas: true,
+        }
+    }
+
+    pub fn csv(filename: &str) -> Self {
+        Self::new(ExportFormat::Csv).filename(filename)
+    }
+
+    pub fn xlsx(filename: &str) -> Self {
+        Self::new(ExportFormat::Xlsx).filename(filename)
+    }
+
+    pub fn format(&self) -> ExportFormat {
+        self.format
+    }
+
+    /// Name of the downloaded file, the extension of the format is appended when missing
+    pub fn filename(mut self, filename: &str) -> Self {
+        self.filename = filename.to_string();
+        self
+    }
+
+    /// Name of the xlsx worksheet, `Sheet1` by default
+    pub fn sheet_name(mut self, name: &str) -> Self {
+        self.sheet_name = Some(name.to_string());
+        self
+    }
+
+    pub fn column(mut self, field: &str, header: &str) -> Self {
+        self.columns.push(ExportColumn::new(field, header));
+        self
+    }
+
+    /// Selects fields exported under their own name
+    pub fn columns(mut self, fields: &[&str]) -> Self {
+        self.columns
+            .extend(fields.iter().map(|field| ExportColumn::new(field, field)));
+        self
+    }
+
+    /// Whether csv text cells that would be evaluated as formulas are prefixed with `'`,
+    /// only disable it when the file is never opened by a spreadsheet
+    pub fn escape_formulas(mut self, escape: bool) -> Self {
+        self.escape_formulas = escape;
+        self
+    }
+
+    pub fn content_disposition(&self) -> String {
+        let mut filename: String = self
+            .filename
+            .chars()
+            .map(|c| match c.is_ascii_alphanumeric() || "-_. ".contains(c) {
+                true => c,
+                false => '_',
+            })
+            .collect();
+
+        let extension = format!(".{}", self.format.extension());
+        if !filename.to_ascii_lowercase().ends_with(&extension) {
+            filename.push_str(&extension);
+        }
+
+        format!("attachment; filename=\"{}\"", filename)
+    }
+
+    /// Streamed response of the rows
+    ///
+    /// Csv rows are sent as they come, xlsx rows are flushed to a temporary file
+    /// which is sent once the workbook is complete; an error of the rows aborts the download.
+    pub fn into_response<T, S>(self, rows: S) -> Response
+    where
+        T: Serialize + 'static,
+        S: Stream<Item = AppResult<T>> + Unpin + 'static,
+    {
+        let content_disposition = self.content_disposition();
+        let content_type = self.format.content_type();
+
+        let body: ExportBody = match self.format {
+            ExportFormat::Csv => Box::pin(csv_body(self.columns, self.escape_formulas, rows)),
+            ExportFormat::Xlsx => Box::pin(xlsx_body(self.columns, self.sheet_name, rows)),
+        };
+
+        HttpResponse::build(StatusCode::OK)
+            .header(CONTENT_TYPE, content_type)
+            .header(CONTENT_DISPOSITION, content_disposition)
+            .header(CACHE_CONTROL, "no-cache")
+            // prevents nginx from buffering the download
+            .header("x-accel-buffering", "no")
+            .streaming(body)
+    }
+}
+
+impl From<ExportFormat> for Export {
+    fn from(format: ExportFormat) -> Self {
+        Export::new(format)
+    }
+}
+
+/// Rows of the pages loaded by `loader`, from the first page to the last one
+///
+/// Each page is loaded on the blocking thread pool, only one page is held in memory at a time.
+///
+/// ```ignore
+/// let mut conn = MEDULLAH.database().get()?;
+/// let rows = export::pages(move |page| {
+///     orders::table
+///         .order_by(orders::created_at.desc())
+///         .paginate(page)
+///         .per_page(1000)
+///         .load_and_count_pages::<Order>(&mut conn)
+/// });
+///
+/// Ok(Responder::export(ExportFormat::Csv, rows))
+/// ```
+#[cfg(feature = "database")]
+pub fn pages<T, F>(loader: F) -> impl Stream<Item = AppResult<T>> + Unpin
+where
+    T: Send + 'static,
+    F: FnMut(i64) -> crate::results::AppPaginationResult<T> + Send + 'static,
+{
+    page_rows(page_iter(loader))
+}
+
+/// Pages returned by `loader` until the last one, or the first failure
+#[cfg(feature = "database")]
+pub fn page_iter<T, F>(
+    mut loader: F,
+) -> impl Iterator<Item = crate::results::AppPaginationResult<T>> + Send
+where
+    T: Send,
+    F: FnMut(i64) -> crate::results::AppPaginationResult<T> + Send,
+{
+    let mut page = 1;
+    let mut done = false;
+
+    std::iter::from_fn(move || {
+        if done {
+            return None;
+        }
+
+        let result = loader(page);
+        done = match &result {
+            Ok(data) => page >= data.total_pages || data.records.is_empty(),
+            Err(_) => true,
+        };
+
+        page += 1;
+        Some(result)
+    })
+}
+
+/// Rows of an iterator of pages, e.g. of [page_iter], pages are pulled on the blocking thread pool
+#[cfg(feature = "database")]
+pub fn page_rows<T, I>(pages: I) -> impl Stream<Item = AppResult<T>> + Unpin
+where
+    T: Send + 'static,
+    I: Iterator<Item = crate::results::AppPaginationResult<T>> + Send + 'static,
+{
+    let pages = stream::unfold(Some(pages), |pages| async move {
+        let mut pages = pages?;
+        let loaded = tokio::task::spawn_blocking(move || {
+            let page = pages.next();
+            (page, pages)
+        })
+        .await;
+
+        match loaded {
+            Ok((Some(Ok(page)), pages)) => {
+                let rows: Vec<AppResult<T>> = page.records.into_iter().map(Ok).collect();
+                Some((rows, Some(pages)))
+            }
+            Ok((Some(Err(err)), _)) => Some((vec![Err(err)], None)),
+            Ok((None, _)) => None,
+            Err(err) => Some((vec![Err(AppMessage::JoinError(err))], None)),
+        }
+    });
+
+    Box::pin(pages.flat_map(stream::iter))
+}
+
+/// Turns rows into the cells of the selected columns
+struct RowEncoder {
+    columns: Vec<ExportColumn>,
+}
+
+impl RowEncoder {
+    fn new(columns: Vec<ExportColumn>) -> Self {
+        RowEncoder { columns }
+    }
+
+    fn has_columns(&self) -> bool {
+        !self.columns.is_empty()
+    }
+
+    fn headers(&self) -> Vec<&str> {
+        self.columns.iter().map(|c| c.header.as_str()).collect()
+    }
+
+    /// Selects every field of the row when no column was given
+    fn discover<T: Serialize>(&mut self, row: &T) -> AppResult<()> {
+        if self.columns.is_empty() {
+            self.columns = field_names(row)?
+                .iter()
+                .map(|field| ExportColumn::new(field, field))
+                .collect();
+        }
+
+        Ok(())
+    }
+
+    fn cells<T: Serialize>(&self, row: &T) -> AppResult<Vec<Value>> {
+        let row = serde_json::to_value(row)?;
+        if !row.is_object() {
+            return Err(export_error("exported rows must serialize to objects"));
+        }
+
+        let cells = self
+            .columns
+            .iter()
+            .map(|column| {
+                let cell = match column.field.contains('.') {
+                    true => row.pointer(&format!("/{}", column.field.replace('.', "/"))),
+                    false => row.get(&column.field),
+                };
+
+                cell.cloned().unwrap_or(Value::Null)
+            })
+            .collect();
+
+        Ok(cells)
+    }
+}
+
+/// Fields of a row in declaration order
+///
+/// The csv serializer keeps the order of struct fields, rows it cannot write as a single
+/// record (nested structs, maps...) fall back to the keys of their json object.
+fn field_names<T: Serialize>(row: &T) -> AppResult<Vec<String>> {
+    let mut writer = csv::Writer::from_writer(vec![]);
+    if writer.serialize(row).is_ok() {
+        if let Ok(written) = writer.into_inner() {
+            let mut reader = csv::Reader::from_reader(written.as_slice());
+            if let Ok(headers) = reader.headers() {
+                return Ok(headers.iter().map(String::from).collect());
+            }
+        }
+    }
+
+    match serde_json::to_value(row)? {
+        Value::Object(fields) => Ok(fields.keys().cloned().collect()),
+        _ => Err(export_error("exported rows must serialize to objects")),
+    }
+}
+
+struct CsvEncoder {
+    rows: RowEncoder,
+    escape_formulas: bool,
+    wrote_headers: bool,
+    failed: bool,
+}
+
+impl CsvEncoder {
+    fn new(columns: Vec<ExportColumn>, escape_formulas: bool) -> Self {
+        CsvEncoder {
+            rows: RowEncoder::new(columns),
+            escape_formulas,
+            wrote_headers: false,
+            failed: false,
+        }
+    }
+
+    /// Header line of explicitly selected columns, sent before any row is loaded
+    fn preamble(&mut self) -> Option<io::Result<Bytes>> {
+        match self.rows.has_columns() {
+            true => self.encode::<Value>(vec![]),
+            false => None,
+        }
+    }
+
+    /// Chunk of the given rows, `None` once a row failed
+    fn encode<T: Serialize>(&mut self, batch: Vec<AppResult<T>>) -> Option<io::Result<Bytes>> {
+        if self.failed {
+            return None;
+        }
+
+        match self.write(batch) {
+            Ok(chunk) => Some(Ok(chunk)),
+            Err(err) => {
+                self.failed = true;
+                Some(Err(abort(err)))
+            }
+        }
+    }
+
+    fn write<T: Serialize>(&mut self, batch: Vec<AppResult<T>>) -> AppResult<Bytes> {
+        let mut writer = csv::Writer::from_writer(vec![]);
+
+        for row in batch {
+            let row = row?;
+            self.rows.discover(&row)?;
+            self.write_headers(&mut writer)?;
+
+            let cells: Vec<String> = self
+                .rows
+                .cells(&row)?
+                .iter()
+                .map(|cell| match cell {
+                    Value::String(text) if self.escape_formulas => escape_formula(text),
+                    cell => cell_text(cell),
+                })
+                .collect();
+            writer.write_record(&cells).map_err(csv_error)?;
+        }
+
+        self.write_headers(&mut writer)?;
+        let written = writer
+            .into_inner()
+            .map_err(|err| csv_error(err.into_error().into()))?;
+        Ok(Bytes::from(written))
+    }
+
+    fn write_headers(&mut self, writer: &mut csv::Writer<Vec<u8>>) -> AppResult<()> {
+        if !self.wrote_headers && self.rows.has_columns() {
+            writer
+                .write_record(self.rows.headers())
+                .map_err(csv_error)?;
+            self.wrote_headers = true;
+        }
+
+        Ok(())
+    }
+}
+
+fn csv_body<T, S>(
+    columns: Vec<ExportColumn>,
+    escape_formulas: bool,
+    rows: S,
+) -> impl Stream<Item = io::Result<Bytes>>
+where
+    T: Serialize + 'static,
+    S: Stream<Item = AppResult<T>> + Unpin + 'static,
+{
+    let mut encoder = CsvEncoder::new(columns, escape_formulas);
+    let preamble = encoder.preamble();
+
+    let rows = rows
+        .ready_chunks(ROWS_PER_CHUNK)
+        .scan(encoder, |encoder, batch| {
+            futures_util::future::ready(encoder.encode(batch))
+        });
+
+    stream::iter(preamble)
+        .chain(rows)
+        .filter(|chunk| futures_util::future::ready(!matches!(chunk, Ok(c) if c.is_empty())))
+}
+
+fn xlsx_body<T, S>(
+    columns: Vec<ExportColumn>,
+    sheet_name: Option<String>,
+    rows: S,
+) -> impl Stream<Item = io::Result<Bytes>>
+where
+    T: Serialize + 'static,
+    S: Stream<Item = AppResult<T>> + Unpin + 'static,
+{
+    stream::once(write_workbook(columns, sheet_name, rows)).flat_map(|file| match file {
+        Ok(file) => read_chunks(file).left_stream(),
+        Err(err) => stream::iter([Err(abort(err))]).right_stream(),
+    })
+}
+
+/// Writes the rows to a constant memory worksheet and saves the workbook to a temporary file
+async fn write_workbook<T, S>(
+    columns: Vec<ExportColumn>,
+    sheet_name: Option<String>,
+    mut rows: S,
+) -> AppResult<std::fs::File>
+where
+    T: Serialize,
+    S: Stream<Item = AppResult<T>> + Unpin,
+{
+    let mut workbook = Workbook::new();
+    let bold = Format::new().set_bold();
+    let mut encoder = RowEncoder::new(columns);
+
+    let sheet = workbook.add_worksheet_with_constant_memory();
+    if let Some(name) = sheet_name {
+        sheet.set_name(name).map_err(xlsx_error)?;
+    }
+
+    // rows are flushed to disk in order, so the headers must be written before the first row
+    let mut wrote_headers = false;
+    let mut row_num = 1;
+
+    while let Some(row) = rows.next().await {
+        let row = row?;
+        encoder.discover(&row)?;
+
+        if !wrote_headers {
+            sheet
+                .write_row_with_format(0, 0, encoder.headers(), &bold)
+                .map_err(xlsx_error)?;
+            wrote_headers = true;
+        }
+
+        for (col, cell) in encoder.cells(&row)?.iter().enumerate() {
+            write_cell(sheet, row_num, col as u16, cell).map_err(xlsx_error)?;
+        }
+
+        row_num += 1;
+    }
+
+    if !wrote_headers && encoder.has_columns() {
+        sheet
+            .write_row_with_format(0, 0, encoder.headers(), &bold)
+            .map_err(xlsx_error)?;
+    }
+
+    // zipping the worksheets is blocking work
+    tokio::task::spawn_blocking(move || {
+        let mut file = tempfile::tempfile()?;
+        workbook.save_to_writer(&mut file).map_err(xlsx_error)?;
+        file.rewind()?;
+        Ok(file)
+    })
+    .await?
+}
+
+fn write_cell(sheet: &mut Worksheet, row: u32, col: u16, cell: &Value) -> Result<(), XlsxError> {
+    match cell {
+        Value::Null => {}
+        Value::Bool(value) => {
+            sheet.write_boolean(row, col, *value)?;
+        }
+        Value::Number(value) => match value.as_f64() {
+            Some(number) => {
+                sheet.write_number(row, col, number)?;
+            }
+            None => {
+                sheet.write_string(row, col, value.to_string())?;
+            }
+        },
+        value => {
+            sheet.write_string(row, col, cell_text(value))?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads the file in chunks, it is deleted once dropped
+fn read_chunks(file: std::fs::File) -> impl Stream<Item = io::Result<Bytes>> {
+    stream::unfold(Some(file), |file| async move {
+        let mut file = file?;
+        let read = tokio::task::spawn_blocking(move || {
+            let mut chunk = vec![0; FILE_CHUNK_SIZE];
+            let read = file.read(&mut chunk).map(|len| {
+                chunk.truncate(len);
+                chunk
+            });
+
+            (read, file)
+        })
+        .await;
+
+        match read {
+            Ok((Ok(chunk), _)) if chunk.is_empty() => None,
+            Ok((Ok(chunk), file)) => Some((Ok(Bytes::from(chunk)), Some(file))),
+            Ok((Err(err), _)) => Some((Err(err), None)),
+            Err(err) => Some((Err(io::Error::other(err)), None)),
+        }
+    })
+}
+
+fn cell_text(cell: &Value) -> String {
+    match cell {
+        Value::Null => String::new(),
+        Value::String(value) => value.clone(),
+        value => value.to_string(),
+    }
+}
+
+/// Text a spreadsheet shows as is instead of evaluating it as a formula
+fn escape_formula(text: &str) -> String {
+    match text.starts_with(['=', '+', '-', '@', '\t', '\r']) {
+        true => format!("'{}", text),
+        false => text.to_string(),
+    }
+}
+
+/// Headers are already sent when rows fail, the error can only end the body
+fn abort(err: AppMessage) -> io::Error {
+    error!("[export] aborting download: {}", err);
+    io::Error::other(err.to_string())
+}
+
+fn export_error(message: &str) -> AppMessage {
+    AppMessage::ErrorMessage(message.to_string(), StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+fn csv_error(err: csv::Error) -> AppMessage {
+    AppMessage::ErrorMessage(err.to_string(), StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+fn xlsx_error(err: XlsxError) -> AppMessage {
+    AppMessage::ErrorMessage(err.to_string(), StatusCode::INTERNAL_SERVER_ERROR)
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::StreamExt;
+    use ntex::util::BytesMut;
+    use serde::Serialize;
+
+    use super::*;
+
+    #[derive(Serialize)]
+    struct Customer {
+        name: String,
+    }
+
+    #[derive(Serialize)]
+    struct Order {
+        reference: String,
+        total: f64,
+        paid: bool,
+        note: Option<String>,
+    }
+
+    #[derive(Serialize)]
+    struct DetailedOrder {
+        reference: String,
+        customer: Customer,
+    }
+
+    fn orders() -> Vec<AppResult<Order>> {
+        vec![
+            Ok(Order {
+                reference: "ORD-1".to_string(),
+                total: 12.5,
+                paid: true,
+                note: None,
+            }),
+            Ok(Order {
+                reference: "ORD-2".to_string(),
+                total: 3.0,
+                paid: false,
+                note: Some("leave at \"door\", thanks".to_string()),
+            }),
+        ]
+    }
+
+    async fn read_body(mut response: Response) -> io::Result<Vec<u8>> {
+        let mut buffer = BytesMut::new();
+        let mut body = response.take_body();
+
+        while let Some(chunk) = body.next().await {
+            let chunk = chunk.map_err(|err| io::Error::other(err.to_string()))?;
+            buffer.extend_from_slice(&chunk);
+        }
+
+        Ok(buffer.to_vec())
+    }
+
+    #[ntex::test]
+    async fn test_csv() {
+        let response = Export::csv("orders").into_response(stream::iter(orders()));
+        assert_eq!(
+            response.headers().get(CONTENT_DISPOSITION).unwrap(),
+            "attachment; filename=\"orders.csv\""
+        );
+        assert_eq!(
+            response.headers().get(CONTENT_TYPE).unwrap(),
+            "text/csv; charset=utf-8"
+        );
+
+        let body = String::from_utf8(read_body(response).await.unwrap()).unwrap();
+        assert_eq!(
+            body,
+            "reference,total,paid,note\n\
+             ORD-1,12.5,true,\n\
+             ORD-2,3.0,false,\"leave at \"\"door\"\", thanks\"\n"
+        );
+    }
+
+    #[ntex::test]
+    async fn test_csv_columns() {
+        let rows = stream::iter(vec![Ok(DetailedOrder {
+            reference: "ORD-1".to_string(),
+            customer: Customer {
+                name: "Ada".to_string(),
+            },
+        })]);
+
+        let export = Export::csv("orders")
+            .column("customer.name", "Customer")
+            .column("reference", "Reference");
+
+        let body = read_body(export.into_response(rows)).await.unwrap();
+        assert_eq!(body, b"Customer,Reference\nAda,ORD-1\n");
+
+        // headers are still sent without any row
+        let export = Export::csv("orders").columns(&["reference", "total"]);
+        let rows = stream::iter(Vec::<AppResult<Order>>::new());
+        let body = read_body(export.into_response(rows)).await.unwrap();
+        assert_eq!(body, b"reference,total\n");
+    }
+
+    #[ntex::test]
+    async fn test_csv_formulas_are_escaped() {
+        let rows = || {
+            let cells = [
+                "=HYPERLINK(\"http://evil\")",
+                "+1",
+                "-2",
+                "@SUM(A1)",
+                "\tx",
+                "a=b",
+            ];
+            let rows: Vec<AppResult<Order>> = cells
+                .iter()
+                .map(|cell| {
+                    Ok(Order {
+                        reference: cell.to_string(),
+                        total: -3.0,
+                        paid: true,
+                        note: None,
+                    })
+                })
+                .collect();
+            stream::iter(rows)
+        };
+
+        let export = Export::csv("orders").columns(&["reference", "total"]);
+        let body = read_body(export.clone().into_response(rows()))
+            .await
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(body).unwrap(),
+            "reference,total\n\
+             \"'=HYPERLINK(\"\"http://evil\"\")\",-3.0\n\
+             '+1,-3.0\n\
+             '-2,-3.0\n\
+             '@SUM(A1),-3.0\n\
+             '\tx,-3.0\n\
+             a=b,-3.0\n"
+        );
+
+        let body = read_body(export.escape_formulas(false).into_response(rows()))
+            .await
+            .unwrap();
+        assert!(body.starts_with(b"reference,total\n\"=HYPERLINK"));
+    }
+
+    #[ntex::test]
+    async fn test_failing_rows_abort_the_body() {
+        let mut rows = orders();
+        rows.push(Err(AppMessage::InternalServerError));
+
+        let response = Export::csv("orders").into_response(stream::iter(rows));
+        assert!(read_body(response).await.is_err());
+    }
+
+    #[ntex::test]
+    async fn test_xlsx() {
+        let export = Export::xlsx("orders.XLSX").sheet_name("Orders");
+        let response = export.into_response(stream::iter(orders()));
+        assert_eq!(
+            response.headers().get(CONTENT_DISPOSITION).unwrap(),
+            "attachment; filename=\"orders.XLSX\""
+        );
+
+        let body = read_body(response).await.unwrap();
+        // xlsx files are zip archives
+        assert!(body.starts_with(b"PK\x03\x04"));
+    }
+
+    #[test]
+    fn test_format_and_filename() {
+        assert_eq!(ExportFormat::parse("XLSX"), Some(ExportFormat::Xlsx));
+        assert_eq!(ExportFormat::parse(".csv"), Some(ExportFormat::Csv));
+        assert_eq!(ExportFormat::parse("pdf"), None);
+
+        assert_eq!(
+            Export::from(ExportFormat::Xlsx)
+                .filename("report \"2024\"/q1")
+                .content_disposition(),
+            "attachment; filename=\"report _2024__q1.xlsx\""
+        );
+    }
+
+    #[cfg(feature = "database")]
+    #[ntex::test]
+    async fn test_pages() {
+        use crate::database::pagination::PageData;
+
+        let rows = pages(|page| {
+            let records = vec![page * 10 + 1, page * 10 + 2];
+            Ok(PageData::new(records, 3, 6))
+        });
+
+        let rows: Vec<i64> = rows.map(|row| row.unwrap()).collect().await;
+        assert_eq!(rows, vec![11, 12, 21, 22, 31, 32]);
+    }
+}
@@ -1,4 +1,8 @@
 pub mod defs;
+#[cfg(feature = "export")]
+pub mod export;
+pub mod problem;
 pub mod respond;
 pub mod result;
+pub mod sse;
 pub mod r#struct;
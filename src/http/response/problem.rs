@@ -0,0 +1,317 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::RwLock;
+
+use ntex::http::body::{Body, ResponseBody};
+use ntex::http::header::{HeaderMap, HeaderValue, ACCEPT, CONTENT_LENGTH, CONTENT_TYPE};
+use ntex::http::{Response, StatusCode};
+use ntex::web::HttpResponse;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::contracts::ResponseCodeContract;
+use crate::helpers::responder::DeJsonResponse;
+
+pub const PROBLEM_JSON: &str = "application/problem+json";
+
+/// `type` URIs are `{base}{code}` unless another base is configured
+pub const DEFAULT_TYPE_BASE: &str = "urn:medullah:problem:";
+
+static MODE: AtomicU8 = AtomicU8::new(ProblemMode::Negotiate as u8);
+static TYPE_BASE: RwLock<String> = RwLock::new(String::new());
+
+/// When error responses are rendered as RFC 9457 Problem Details instead of the json envelope
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ProblemMode {
+    Never = 0,
+    /// only for clients sending `Accept: application/problem+json`
+    #[default]
+    Negotiate = 1,
+    Always = 2,
+}
+
+/// RFC 9457 `application/problem+json` body
+///
+/// ```json
+/// {
+///   "type": "urn:medullah:problem:013",
+///   "title": "Unprocessable Entity",
+///   "status": 422,
+///   "detail": "Validation Error",
+///   "instance": "urn:request:5f0c...",
+///   "code": "013",
+///   "errors": {"email": [{"code": "email", "message": null, "params": {}}]}
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ProblemDetails {
+    #[serde(rename = "type")]
+    pub type_uri: String,
+    pub title: String,
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    #[serde(flatten)]
+    pub extensions: Map<String, Value>,
+}
+
+impl ProblemMode {
+    pub fn parse(mode: &str) -> Option<Self> {
+        match mode.trim().to_ascii_lowercase().as_str() {
+            "never" => Some(ProblemMode::Never),
+            "negotiate" | "accept" => Some(ProblemMode::Negotiate),
+            "always" => Some(ProblemMode::Always),
+            _ => None,
+        }
+    }
+}
+
+impl ProblemDetails {
+    pub fn new<C: ResponseCodeContract>(code: C, detail: Option<&str>) -> Self {
+        let status = code.status();
+        let mut extensions = Map::new();
+        extensions.insert("code".to_string(), Value::String(code.code().to_string()));
+
+        ProblemDetails {
+            type_uri: Self::type_uri(code.code()),
+            title: status.canonical_reason().unwrap_or("Unknown").to_string(),
+            status: status.as_u16(),
+            detail: detail.map(String::from),
+            instance: None,
+            extensions,
+        }
+    }
+
+    pub fn mode() -> ProblemMode {
+        match MODE.load(Ordering::Relaxed) {
+            0 => ProblemMode::Never,
+            2 => ProblemMode::Always,
+            _ => ProblemMode::Negotiate,
+        }
+    }
+
+    pub fn set_mode(mode: ProblemMode) {
+        MODE.store(mode as u8, Ordering::Relaxed);
+    }
+
+    /// e.g. `https://docs.example.com/problems/`
+    pub fn set_type_base(base: &str) {
+        *TYPE_BASE.write().unwrap() = base.to_string();
+    }
+
+    pub fn type_uri(code: &str) -> String {
+        let base = TYPE_BASE.read().unwrap();
+        match base.is_empty() {
+            true => format!("{}{}", DEFAULT_TYPE_BASE, code),
+            false => format!("{}{}", base, code),
+        }
+    }
+
+    /// Whether errors of a request with the given headers should be rendered as problem details
+    pub fn is_wanted(headers: &HeaderMap) -> bool {
+        match Self::mode() {
+            ProblemMode::Never => false,
+            ProblemMode::Always => true,
+            ProblemMode::Negotiate => headers
+                .get_all(ACCEPT)
+                .filter_map(|v| v.to_str().ok())
+                .flat_map(|v| v.split(','))
+                .any(|v| {
+                    let mut params = v.split(';');
+                    let is_problem = params
+                        .next()
+                        .is_some_and(|mime| mime.trim().eq_ignore_ascii_case(PROBLEM_JSON));
+
+                    // `q=0` explicitly refuses the type
+                    is_problem
+                        && !params.any(|param| {
+                            param.trim().split_once('=').is_some_and(|(name, q)| {
+                                name.trim().eq_ignore_ascii_case("q")
+                                    && q.trim().parse::<f32>().is_ok_and(|q| q <= 0.0)
+                            })
+                        })
+                }),
+        }
+    }
+
+    pub fn with_request_id(mut self, request_id: Option<&str>) -> Self {
+        self.instance = request_id.map(|id| format!("urn:request:{}", id));
+        self
+    }
+
+    pub fn extension<V: Serialize>(mut self, name: &str, value: V) -> Self {
+        let value = serde_json::to_value(value).unwrap_or(Value::Null);
+        self.extensions.insert(name.to_string(), value);
+        self
+    }
+
+    /// Problem details equivalent of an error rendered with the json envelope
+    ///
+    /// Field errors (an object of lists) become the `errors` member, any other data is kept as `data`.
+    pub fn from_envelope(status: StatusCode, body: &[u8]) -> Option<Self> {
+        let envelope: DeJsonResponse<Value> = serde_json::from_slice(body).ok()?;
+
+        let mut extensions = Map::new();
+        extensions.insert("code".to_string(), Value::String(envelope.code.clone()));
+        if let Some(error_id) = envelope.error_id {
+            extensions.insert("error_id".to_string(), Value::String(error_id));
+        }
+
+        match envelope.data {
+            Value::Object(data) if data.is_empty() => {}
+            Value::Object(data) if data.values().all(Value::is_array) => {
+                extensions.insert("errors".to_string(), Value::Object(data));
+            }
+            Value::Null => {}
+            data => {
+                extensions.insert("data".to_string(), data);
+            }
+        }
+
+        Some(ProblemDetails {
+            type_uri: Self::type_uri(&envelope.code),
+            title: status.canonical_reason().unwrap_or("Unknown").to_string(),
+            status: status.as_u16(),
+            detail: envelope.message,
+            instance: None,
+            extensions,
+        })
+    }
+
+    pub fn into_response(self) -> Response {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        let body = serde_json::to_vec(&self).unwrap_or_default();
+
+        HttpResponse::build(status)
+            .content_type(PROBLEM_JSON)
+            .body(body)
+    }
+}
+
+/// Re-renders an error response carrying the json envelope as problem details,
+/// other responses (successes, non-json bodies...) are returned untouched
+pub(crate) fn into_problem_response(response: Response, request_id: Option<&str>) -> Response {
+    let status = response.status();
+    if !status.is_client_error() && !status.is_server_error() {
+        return response;
+    }
+
+    let is_json = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"));
+
+    let problem = match response.body() {
+        ResponseBody::Body(Body::Bytes(bytes)) if is_json => {
+            ProblemDetails::from_envelope(status, bytes)
+        }
+        _ => None,
+    };
+
+    let problem = match problem {
+        Some(problem) => problem.with_request_id(request_id),
+        None => return response,
+    };
+
+    let mut rendered = problem.into_response();
+    for (name, value) in response.headers() {
+        if name != CONTENT_TYPE && name != CONTENT_LENGTH {
+            rendered.headers_mut().append(name.clone(), value.clone());
+        }
+    }
+
+    rendered
+        .headers_mut()
+        .insert(CONTENT_TYPE, HeaderValue::from_static(PROBLEM_JSON));
+
+    rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use ntex::http::body::MessageBody;
+    use ntex::http::header::HeaderName;
+    use serde_json::json;
+
+    use super::*;
+    use crate::enums::ResponseCode;
+    use crate::helpers::responder::Responder;
+
+    fn body(response: &Response) -> Value {
+        match response.body() {
+            ResponseBody::Body(Body::Bytes(bytes)) => serde_json::from_slice(bytes).unwrap(),
+            _ => panic!("expected a bytes body"),
+        }
+    }
+
+    #[test]
+    fn test_is_wanted() {
+        let mut headers = HeaderMap::new();
+        assert!(!ProblemDetails::is_wanted(&headers));
+
+        headers.insert(
+            ACCEPT,
+            HeaderValue::from_static("application/json, application/problem+json;q=0.9"),
+        );
+        assert!(ProblemDetails::is_wanted(&headers));
+
+        headers.insert(
+            ACCEPT,
+            HeaderValue::from_static("application/json, application/problem+json; q=0"),
+        );
+        assert!(!ProblemDetails::is_wanted(&headers));
+
+        headers.insert(
+            ACCEPT,
+            HeaderValue::from_static("application/problem+json;q=0.000"),
+        );
+        assert!(!ProblemDetails::is_wanted(&headers));
+    }
+
+    #[test]
+    fn test_into_problem_response() {
+        let mut response = Responder::send_msg(
+            json!({"email": [{"code": "email"}]}),
+            ResponseCode::UnprocessableEntity,
+            "Validation Error",
+        );
+        response.headers_mut().insert(
+            HeaderName::from_static("x-request-id"),
+            HeaderValue::from_static("abc"),
+        );
+
+        let response = into_problem_response(response, Some("abc"));
+        assert_eq!(response.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(response.headers().get(CONTENT_TYPE).unwrap(), PROBLEM_JSON);
+        assert_eq!(response.headers().get("x-request-id").unwrap(), "abc");
+        assert_eq!(
+            body(&response),
+            json!({
+                "type": "urn:medullah:problem:013",
+                "title": "Unprocessable Entity",
+                "status": 422,
+                "detail": "Validation Error",
+                "instance": "urn:request:abc",
+                "code": "013",
+                "errors": {"email": [{"code": "email"}]},
+            })
+        );
+
+        let ok = into_problem_response(Responder::ok_message("fine"), None);
+        assert_eq!(ok.headers().get(CONTENT_TYPE).unwrap(), "application/json");
+        assert!(ok.body().size() != ntex::http::body::BodySize::Empty);
+    }
+
+    #[test]
+    fn test_new() {
+        let problem = ProblemDetails::new(ResponseCode::NotFound, Some("no such order"))
+            .extension("order", 12);
+
+        assert_eq!(problem.status, 404);
+        assert_eq!(problem.title, "Not Found");
+        assert_eq!(problem.extensions["order"], 12);
+        assert_eq!(ProblemMode::parse("Always"), Some(ProblemMode::Always));
+    }
+}
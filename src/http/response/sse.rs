@@ -0,0 +1,553 @@
+use std::collections::VecDeque;
+use std::convert::Infallible;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use futures_util::{Stream, StreamExt};
+use log::warn;
+use ntex::http::header::{CACHE_CONTROL, CONTENT_TYPE};
+use ntex::http::{Payload, Response};
+use ntex::util::{Bytes, BytesMut};
+use ntex::web::{FromRequest, HttpRequest, HttpResponse};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+use tokio::time::{interval_at, Instant, Interval};
+
+use crate::prelude::{AppMessage, AppResult};
+
+pub const LAST_EVENT_ID: &str = "last-event-id";
+
+/// Interval at which keep-alive comments are sent when no event is emitted
+pub const DEFAULT_KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// Number of events with an id kept to resume reconnecting clients
+pub const DEFAULT_REPLAY_SIZE: usize = 100;
+
+/// A single server-sent event
+///
+/// # Examples
+///
+/// ```
+/// use medullah_web::http::response::sse::SseEvent;
+///
+/// let event = SseEvent::new("50%").event("progress").id("42");
+/// assert_eq!(event.to_string(), "id: 42\nevent: progress\ndata: 50%\n\n");
+/// ```
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SseEvent {
+    pub id: Option<String>,
+    pub event: Option<String>,
+    pub data: String,
+    /// reconnection delay (in milliseconds) the client should use
+    pub retry: Option<u64>,
+}
+
+/// Value of the `Last-Event-ID` header browsers send when reconnecting
+///
+/// [SseChannel::subscribe] and [redis_events] replay the events published after it, other
+/// sources have to emit the events a client missed before attaching it to the live stream:
+///
+/// ```ignore
+/// async fn notifications(last_id: LastEventId) -> Response {
+///     let missed = load_events_after(last_id.0).await;
+///     let live = sse::broadcast_events(SENDER.subscribe());
+///     Responder::sse(futures_util::stream::iter(missed).chain(live))
+/// }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct LastEventId(pub Option<String>);
+
+/// In-process channel of events keeping the last ones with an id, so that reconnecting
+/// clients get the events published while they were away
+///
+/// Clients that missed more events than the channel keeps get the ones still kept.
+///
+/// ```
+/// use medullah_web::http::response::sse::{LastEventId, SseChannel, SseEvent};
+///
+/// let channel = SseChannel::new(100);
+/// channel.send(SseEvent::new("50%").id("1"));
+///
+/// // a client reconnecting with `Last-Event-ID: 0`
+/// let events = channel.subscribe(&LastEventId(Some("0".to_string())));
+/// ```
+#[derive(Clone)]
+pub struct SseChannel {
+    sender: broadcast::Sender<SseEvent>,
+    history: Arc<Mutex<VecDeque<SseEvent>>>,
+    capacity: usize,
+}
+
+/// Frames events and interleaves keep-alive comments while the source is idle
+pub struct SseStream<S> {
+    events: S,
+    keep_alive: Duration,
+    /// started on the first poll, streams may be created outside of a runtime
+    ticker: Option<Interval>,
+}
+
+impl SseEvent {
+    pub fn new(data: &str) -> Self {
+        SseEvent {
+            data: data.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Create an event whose data is the json representation of the given value
+    pub fn json<T: Serialize>(data: &T) -> AppResult<Self> {
+        Ok(SseEvent {
+            data: serde_json::to_string(data)?,
+            ..Default::default()
+        })
+    }
+
+    pub fn id(mut self, id: &str) -> Self {
+        self.id = Some(id.to_string());
+        self
+    }
+
+    pub fn event(mut self, event: &str) -> Self {
+        self.event = Some(event.to_string());
+        self
+    }
+
+    pub fn retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry.as_millis() as u64);
+        self
+    }
+
+    pub fn to_bytes(&self) -> Bytes {
+        let mut frame = BytesMut::new();
+
+        // a line break would end the field and let the value inject other fields
+        if let Some(id) = &self.id {
+            push_field(&mut frame, "id", &id.replace(['\r', '\n'], ""));
+        }
+
+        if let Some(event) = &self.event {
+            push_field(&mut frame, "event", &event.replace(['\r', '\n'], ""));
+        }
+
+        if let Some(retry) = self.retry {
+            push_field(&mut frame, "retry", &retry.to_string());
+        }
+
+        // every line of the payload must be sent as its own data field,
+        // lines end with `\r\n`, `\n` or a lone `\r`
+        for line in self.data.split('\n') {
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            for line in line.split('\r') {
+                push_field(&mut frame, "data", line);
+            }
+        }
+
+        frame.extend_from_slice(b"\n");
+        frame.freeze()
+    }
+}
+
+impl std::fmt::Display for SseEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&String::from_utf8_lossy(&self.to_bytes()))
+    }
+}
+
+impl From<String> for SseEvent {
+    fn from(value: String) -> Self {
+        SseEvent {
+            data: value,
+            ..Default::default()
+        }
+    }
+}
+
+impl From<&str> for SseEvent {
+    fn from(value: &str) -> Self {
+        SseEvent::new(value)
+    }
+}
+
+impl SseChannel {
+    /// Channel keeping the last `capacity` events with an id, live subscribers lagging behind
+    /// by more than `capacity` events skip the missed ones
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity.max(1));
+        SseChannel {
+            sender,
+            history: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    /// Sends the event to the subscribers, returns how many received it
+    pub fn send(&self, event: SseEvent) -> usize {
+        let mut history = self.history.lock().unwrap();
+        if event.id.is_some() && self.capacity > 0 {
+            if history.len() == self.capacity {
+                history.pop_front();
+            }
+
+            history.push_back(event.clone());
+        }
+
+        // sent while holding the history, subscribers can't miss it nor get it twice
+        self.sender.send(event).unwrap_or(0)
+    }
+
+    /// Events published after `last_id` followed by the live ones
+    pub fn subscribe(&self, last_id: &LastEventId) -> impl Stream<Item = SseEvent> {
+        let history = self.history.lock().unwrap();
+        let receiver = self.sender.subscribe();
+        let missed = replayed(history.iter(), last_id);
+        drop(history);
+
+        futures_util::stream::iter(missed).chain(broadcast_events(receiver))
+    }
+}
+
+impl Default for SseChannel {
+    fn default() -> Self {
+        Self::new(DEFAULT_REPLAY_SIZE)
+    }
+}
+
+impl<S> SseStream<S>
+where
+    S: Stream<Item = SseEvent> + Unpin,
+{
+    pub fn new(events: S, keep_alive: Duration) -> Self {
+        SseStream {
+            events,
+            keep_alive,
+            ticker: None,
+        }
+    }
+
+    pub fn into_response(self) -> Response
+    where
+        S: 'static,
+    {
+        HttpResponse::Ok()
+            .header(CONTENT_TYPE, "text/event-stream")
+            .header(CACHE_CONTROL, "no-cache")
+            // prevents nginx from buffering the stream
+            .header("x-accel-buffering", "no")
+            .streaming(self)
+    }
+}
+
+impl<S> Stream for SseStream<S>
+where
+    S: Stream<Item = SseEvent> + Unpin,
+{
+    type Item = Result<Bytes, Infallible>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match self.events.poll_next_unpin(cx) {
+            Poll::Ready(Some(event)) => {
+                if let Some(ticker) = &mut self.ticker {
+                    ticker.reset();
+                }
+
+                return Poll::Ready(Some(Ok(event.to_bytes())));
+            }
+            Poll::Ready(None) => return Poll::Ready(None),
+            Poll::Pending => {}
+        }
+
+        let keep_alive = self.keep_alive;
+        let ticker = self
+            .ticker
+            .get_or_insert_with(|| interval_at(Instant::now() + keep_alive, keep_alive));
+
+        match ticker.poll_tick(cx) {
+            Poll::Ready(_) => Poll::Ready(Some(Ok(Bytes::from_static(b": keep-alive\n\n")))),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+impl<Err> FromRequest<Err> for LastEventId {
+    type Error = AppMessage;
+
+    async fn from_request(req: &HttpRequest, _: &mut Payload) -> AppResult<Self> {
+        Ok(LastEventId(
+            req.headers()
+                .get(LAST_EVENT_ID)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.to_string()),
+        ))
+    }
+}
+
+/// Turns a broadcast receiver into an event stream, lagging receivers skip the missed messages
+pub fn broadcast_events<T>(receiver: broadcast::Receiver<T>) -> impl Stream<Item = SseEvent>
+where
+    T: Into<SseEvent> + Clone + Send + 'static,
+{
+    Box::pin(futures_util::stream::unfold(
+        receiver,
+        |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(value) => return Some((value.into(), receiver)),
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        warn!("[sse] receiver lagged, skipped {} message(s)", skipped);
+                    }
+                    Err(broadcast::error::RecvError::Closed) => return None,
+                }
+            }
+        },
+    ))
+}
+
+/// Subscribes to a Redis channel and turns its messages into an event stream,
+/// so that any replica can emit to connected clients
+///
+/// Messages published with [publish_event] keep their id/event name,
+/// any other message becomes the data of an unnamed event. The events published with an id
+/// after `last_id` are replayed first, see [publish_event].
+#[cfg(feature = "redis")]
+pub async fn redis_events(
+    channel: &str,
+    last_id: &LastEventId,
+) -> AppResult<impl Stream<Item = SseEvent>> {
+    use crate::prelude::OnceLockHelper;
+
+    // subscribed first, events published meanwhile are both replayed and received
+    let stream = crate::redis::Redis::subscribe_stream(channel).await?;
+
+    let missed = match last_id.0.is_some() {
+        true => {
+            let history: Vec<String> = crate::MEDULLAH
+                .redis()
+                .lrange(&history_key(channel), 0, -1)
+                .await?;
+            let history: Vec<SseEvent> = history.into_iter().map(parse_published).collect();
+            replayed(history.iter(), last_id)
+        }
+        false => vec![],
+    };
+
+    let replayed_ids: Vec<String> = missed.iter().filter_map(|e| e.id.clone()).collect();
+    let channel = channel.to_string();
+
+    let live = stream.filter_map(move |received| {
+        let event = match received {
+            Ok(message) => Some(parse_published(message)).filter(|event| {
+                event
+                    .id
+                    .as_ref()
+                    .is_none_or(|id| !replayed_ids.contains(id))
+            }),
+            Err(err) => {
+                warn!("[sse][{}] failed to read message: {:?}", channel, err);
+                None
+            }
+        };
+
+        futures_util::future::ready(event)
+    });
+
+    Ok(futures_util::stream::iter(missed).chain(live))
+}
+
+/// Publishes an event to every [redis_events] stream subscribed to the channel
+///
+/// The last [DEFAULT_REPLAY_SIZE] events with an id are kept in redis to resume clients.
+#[cfg(feature = "redis")]
+pub async fn publish_event(channel: &str, event: &SseEvent) -> AppResult<i32> {
+    use crate::prelude::OnceLockHelper;
+
+    let redis = crate::MEDULLAH.redis();
+    if event.id.is_some() {
+        let key = history_key(channel);
+        redis.rpush(&key, event).await?;
+        redis
+            .ltrim(&key, -(DEFAULT_REPLAY_SIZE as isize), -1)
+            .await?;
+    }
+
+    redis.publish(channel, event).await
+}
+
+#[cfg(feature = "redis")]
+fn history_key(channel: &str) -> String {
+    format!("{}:sse-history", channel)
+}
+
+/// Events following the one with the given id, every kept event when the id is no longer kept
+fn replayed<'a>(
+    history: impl Iterator<Item = &'a SseEvent>,
+    last_id: &LastEventId,
+) -> Vec<SseEvent> {
+    let last_id = match &last_id.0 {
+        Some(id) => id,
+        None => return vec![],
+    };
+
+    let history: Vec<&SseEvent> = history.collect();
+    let start = history
+        .iter()
+        .rposition(|event| event.id.as_ref() == Some(last_id))
+        .map_or(0, |index| index + 1);
+
+    history[start..]
+        .iter()
+        .map(|event| (*event).clone())
+        .collect()
+}
+
+/// Values sent through [crate::redis::Redis::publish] are json encoded,
+/// plain strings are unwrapped rather than being sent with their quotes
+#[cfg(feature = "redis")]
+fn parse_published(message: String) -> SseEvent {
+    if let Ok(event) = serde_json::from_str::<SseEvent>(&message) {
+        return event;
+    }
+
+    match serde_json::from_str::<String>(&message) {
+        Ok(data) => SseEvent::from(data),
+        Err(_) => SseEvent::from(message),
+    }
+}
+
+fn push_field(frame: &mut BytesMut, name: &str, value: &str) {
+    frame.extend_from_slice(name.as_bytes());
+    frame.extend_from_slice(b": ");
+    frame.extend_from_slice(value.as_bytes());
+    frame.extend_from_slice(b"\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::FutureExt;
+
+    use super::*;
+
+    #[test]
+    fn test_event_framing() {
+        let event = SseEvent::new("line 1\nline 2")
+            .id("7")
+            .event("update")
+            .retry(Duration::from_secs(3));
+
+        assert_eq!(
+            event.to_string(),
+            "id: 7\nevent: update\nretry: 3000\ndata: line 1\ndata: line 2\n\n"
+        );
+
+        assert_eq!(SseEvent::new("").to_string(), "data: \n\n");
+
+        let event = SseEvent::new("hi").id("1\r\ndata: injected").event("a\nb");
+        assert_eq!(
+            event.to_string(),
+            "id: 1data: injected\nevent: ab\ndata: hi\n\n"
+        );
+
+        // a lone carriage return ends the line too
+        let event = SseEvent::new("x\revent: admin\rid: 999\r\ny");
+        assert_eq!(
+            event.to_string(),
+            "data: x\ndata: event: admin\ndata: id: 999\ndata: y\n\n"
+        );
+    }
+
+    #[test]
+    fn test_stream_outside_runtime() {
+        let events = futures_util::stream::iter(vec![SseEvent::new("a")]);
+        let _ = SseStream::new(events, DEFAULT_KEEP_ALIVE).into_response();
+    }
+
+    #[tokio::test]
+    async fn test_stream_frames_events() {
+        let events = futures_util::stream::iter(vec![SseEvent::new("a"), SseEvent::new("b")]);
+        let frames = SseStream::new(events, DEFAULT_KEEP_ALIVE)
+            .map(|frame| frame.unwrap())
+            .collect::<Vec<_>>()
+            .await;
+
+        assert_eq!(
+            frames,
+            vec![
+                Bytes::from_static(b"data: a\n\n"),
+                Bytes::from_static(b"data: b\n\n")
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_stream_keep_alive() {
+        let events = futures_util::stream::pending::<SseEvent>();
+        let mut stream = SseStream::new(events, Duration::from_millis(10));
+
+        let frame = stream.next().await.unwrap().unwrap();
+        assert_eq!(frame, Bytes::from_static(b": keep-alive\n\n"));
+    }
+
+    #[cfg(feature = "redis")]
+    #[test]
+    fn test_parse_published() {
+        let event = SseEvent::new("done").event("job");
+        let published = serde_json::to_string(&event).unwrap();
+        assert_eq!(parse_published(published), event);
+
+        assert_eq!(
+            parse_published("\"hello\"".to_string()),
+            SseEvent::new("hello")
+        );
+        assert_eq!(
+            parse_published("{\"progress\":50}".to_string()),
+            SseEvent::new("{\"progress\":50}")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_channel_replays_missed_events() {
+        let channel = SseChannel::new(2);
+        for id in ["1", "2", "3"] {
+            channel.send(SseEvent::new(id).id(id));
+        }
+        channel.send(SseEvent::new("no id"));
+
+        let resume = |id: Option<&str>| LastEventId(id.map(String::from));
+        let next = |events: &mut (dyn Stream<Item = SseEvent> + Unpin)| {
+            events
+                .next()
+                .now_or_never()
+                .flatten()
+                .map(|event| event.data)
+        };
+
+        let mut events = Box::pin(channel.subscribe(&resume(Some("2"))));
+        assert_eq!(next(&mut events), Some("3".to_string()));
+        assert_eq!(next(&mut events), None);
+
+        channel.send(SseEvent::new("4").id("4"));
+        assert_eq!(next(&mut events), Some("4".to_string()));
+
+        // "1" is no longer kept, every kept event is replayed
+        let mut events = Box::pin(channel.subscribe(&resume(Some("1"))));
+        assert_eq!(next(&mut events), Some("3".to_string()));
+        assert_eq!(next(&mut events), Some("4".to_string()));
+
+        // new clients only get the live events
+        let mut events = Box::pin(channel.subscribe(&resume(None)));
+        assert_eq!(next(&mut events), None);
+    }
+
+    #[tokio::test]
+    async fn test_broadcast_events() {
+        let (sender, receiver) = broadcast::channel::<String>(8);
+        let mut events = broadcast_events(receiver);
+
+        sender.send("hello".to_string()).unwrap();
+        drop(sender);
+
+        assert_eq!(events.next().await, Some(SseEvent::new("hello")));
+        assert_eq!(events.next().await, None);
+    }
+}
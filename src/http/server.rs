@@ -7,7 +7,16 @@ use crate::app_setup::{
     get_server_host_config, load_environment_variables, make_app_state, MedullahSetup,
 };
 use crate::env_logger::init_env_logger;
-use crate::http::kernel::{ntex_default_service, register_routes, setup_cors, setup_logger, Route};
+use crate::http::error_renderer::JsonErrorRenderer;
+use crate::http::kernel::{
+    health_check, ntex_default_service, register_routes, setup_cors, setup_logger, Route,
+    HEALTH_CHECK_PATH,
+};
+#[cfg(feature = "jwt")]
+use crate::http::kernel::{jwks, JWKS_PATH};
+use crate::http::middlewares::error_boundary::ErrorBoundary;
+use crate::http::middlewares::locale::Localize;
+use crate::http::middlewares::request_id::RequestIdentifier;
 use crate::http::Method;
 use crate::prelude::{AppResult, MedullahState};
 
@@ -33,14 +42,14 @@ where
     /// list of allowed CORS origins
     pub allowed_methods: Vec<Method>,
 
+    /// how the locale of the requests is picked, `Localize::new()` negotiates `Accept-Language`
+    pub localize: Localize,
+
     pub boot_thread: TB,
 }
 
 #[cfg(feature = "static")]
-pub struct StaticFileConfig {
-    pub path: String,
-    pub dir: String,
-}
+pub use crate::http::static_files::{StaticFileConfig, StaticMount};
 
 pub fn init_bootstrap(service: &str) -> AppResult<()> {
     load_environment_variables(service);
@@ -81,12 +90,24 @@ where
         }
     }
 
+    #[cfg(feature = "websocket")]
+    tokio::spawn(crate::http::websocket::close_on_shutdown());
+
+    #[cfg(feature = "jwt")]
+    tokio::spawn(crate::helpers::keyring::reload_on_hangup(
+        app_state.helpers.jwt.clone(),
+    ));
+
     let boot = config.boot_thread;
     web::HttpServer::new(move || {
         let routes = boot();
-        let app = web::App::new()
+        let app = web::App::with(JsonErrorRenderer)
             .state(app_state.clone())
             .configure(|cfg| register_routes(cfg, routes))
+            .route(HEALTH_CHECK_PATH, web::get().to(health_check))
+            .wrap(config.localize.clone())
+            .wrap(ErrorBoundary)
+            .wrap(RequestIdentifier)
             .wrap(setup_logger())
             .wrap(
                 setup_cors(
@@ -97,15 +118,15 @@ where
             )
             .default_service(ntex_default_service());
 
-        if cfg!(feature = "static") {
-            #[cfg(feature = "static")]
-            {
-                return app.service(ntex_files::Files::new(
-                    &config.static_config.path,
-                    &config.static_config.dir,
-                ));
-            }
-        }
+        #[cfg(feature = "jwt")]
+        let app = app.route(JWKS_PATH, web::get().to(jwks));
+
+        #[cfg(feature = "static")]
+        let app = config
+            .static_config
+            .mounts
+            .iter()
+            .fold(app, |app, mount| app.service(mount.clone().service()));
 
         app
     })
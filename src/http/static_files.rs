@@ -0,0 +1,595 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::UNIX_EPOCH;
+
+use log::debug;
+use ntex::http::body::SizedStream;
+use ntex::http::header::{
+    HeaderValue, ACCEPT, ACCEPT_ENCODING, ACCEPT_RANGES, CACHE_CONTROL, CONTENT_ENCODING,
+    CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG, IF_NONE_MATCH, IF_RANGE, RANGE, VARY,
+};
+use ntex::http::{Method, StatusCode};
+use ntex::util::Bytes;
+use ntex::web::{self, HttpRequest, HttpResponse, Scope};
+use percent_encoding::percent_decode_str;
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+
+use crate::helpers::responder::Responder;
+use crate::http::error_renderer::JsonErrorRenderer;
+
+/// Cache-Control sent with fingerprinted files (e.g. `app.3f9a1c2b.js`)
+pub const IMMUTABLE_CACHE_CONTROL: &str = "public, max-age=31536000, immutable";
+
+const CHUNK_SIZE: usize = 65_536;
+
+/// Decides whether a file name carries a content hash, so it can be cached forever
+pub type FingerprintDetector = fn(&str) -> bool;
+
+/// Static files served by the http server
+///
+/// # Examples
+///
+/// ```
+/// use medullah_web::http::static_files::{StaticFileConfig, StaticMount};
+///
+/// let config = StaticFileConfig::new()
+///     .mount(StaticMount::new("/assets", "./public/assets").cache_control("public, max-age=3600"))
+///     .mount(StaticMount::new("/", "./frontend/dist").spa());
+/// ```
+#[derive(Clone, Default)]
+pub struct StaticFileConfig {
+    pub mounts: Vec<StaticMount>,
+}
+
+/// A directory served under a url prefix, directory listing is never enabled
+#[derive(Clone)]
+pub struct StaticMount {
+    /// url prefix the directory is served under
+    path: String,
+    /// directory files are served from
+    dir: PathBuf,
+    /// file served for directory requests (and as spa fallback)
+    index: String,
+    /// serve the index file for unknown paths, so that client-side routing can take over
+    spa: bool,
+    /// Cache-Control sent with files that are not fingerprinted
+    cache_control: String,
+    /// serve `.br`/`.gz` siblings when the client accepts them
+    precompressed: bool,
+    is_fingerprinted: FingerprintDetector,
+}
+
+/// Part of a file a `Range` header asks for
+#[derive(Debug, PartialEq)]
+enum ByteRange {
+    Full,
+    /// first and last (inclusive) byte
+    Partial(u64, u64),
+    Unsatisfiable,
+}
+
+struct ResolvedFile {
+    path: PathBuf,
+    /// path the content type is guessed from (differs for precompressed siblings)
+    original: PathBuf,
+    encoding: Option<&'static str>,
+    is_index: bool,
+}
+
+impl StaticFileConfig {
+    pub fn new() -> Self {
+        StaticFileConfig::default()
+    }
+
+    pub fn mount(mut self, mount: StaticMount) -> Self {
+        self.mounts.push(mount);
+        self
+    }
+}
+
+impl StaticMount {
+    pub fn new(path: &str, dir: &str) -> Self {
+        StaticMount {
+            path: path.trim_end_matches('/').to_string(),
+            dir: PathBuf::from(dir),
+            index: "index.html".to_string(),
+            spa: false,
+            cache_control: "no-cache".to_string(),
+            precompressed: true,
+            is_fingerprinted,
+        }
+    }
+
+    /// Serve the index file for unknown paths (single-page apps)
+    pub fn spa(mut self) -> Self {
+        self.spa = true;
+        self
+    }
+
+    pub fn index(mut self, file: &str) -> Self {
+        self.index = file.to_string();
+        self
+    }
+
+    /// Cache-Control sent with files that are not fingerprinted, defaults to `no-cache`
+    pub fn cache_control(mut self, value: &str) -> Self {
+        self.cache_control = value.to_string();
+        self
+    }
+
+    /// Whether `.br`/`.gz` siblings should be looked up, enabled by default
+    pub fn precompressed(mut self, enabled: bool) -> Self {
+        self.precompressed = enabled;
+        self
+    }
+
+    /// Customize how fingerprinted (immutable) files are detected
+    pub fn fingerprint_detector(mut self, detector: FingerprintDetector) -> Self {
+        self.is_fingerprinted = detector;
+        self
+    }
+
+    pub fn service(self) -> Scope<JsonErrorRenderer> {
+        let mount = Rc::new(self);
+        web::scope(mount.path.as_str()).default_service(web::to(move |req: HttpRequest| {
+            let mount = mount.clone();
+            async move { mount.serve(&req).await }
+        }))
+    }
+
+    async fn serve(&self, req: &HttpRequest) -> HttpResponse {
+        if req.method() != Method::GET && req.method() != Method::HEAD {
+            return HttpResponse::MethodNotAllowed()
+                .header("allow", "GET, HEAD")
+                .finish();
+        }
+
+        let relative = req
+            .path()
+            .strip_prefix(self.path.as_str())
+            .unwrap_or_default();
+
+        let file = match sanitize(relative) {
+            Some(relative) => self.resolve(req, &relative).await,
+            None => None,
+        };
+
+        let file = match file {
+            Some(file) => file,
+            None if self.spa && wants_index(req) => match self.resolve(req, "").await {
+                Some(file) => file,
+                None => return Responder::not_found_message("Requested Resource(s) Not Found"),
+            },
+            None => return Responder::not_found_message("Requested Resource(s) Not Found"),
+        };
+
+        match self.respond(req, file).await {
+            Ok(response) => response,
+            Err(err) => {
+                debug!("[static] failed to read file: {:?}", err);
+                Responder::not_found_message("Requested Resource(s) Not Found")
+            }
+        }
+    }
+
+    async fn resolve(&self, req: &HttpRequest, relative: &str) -> Option<ResolvedFile> {
+        let mut path = self.dir.join(relative);
+        let mut is_index = relative.is_empty();
+
+        match tokio::fs::metadata(&path).await {
+            Ok(meta) if meta.is_dir() => {
+                path = path.join(&self.index);
+                is_index = true;
+            }
+            Ok(_) => {}
+            Err(_) => return None,
+        }
+
+        if !tokio::fs::metadata(&path)
+            .await
+            .map(|m| m.is_file())
+            .unwrap_or(false)
+        {
+            return None;
+        }
+
+        if self.precompressed {
+            for encoding in accepted_encodings(req) {
+                let extension = match encoding {
+                    "br" => "br",
+                    _ => "gz",
+                };
+
+                let mut sibling = path.clone().into_os_string();
+                sibling.push(".");
+                sibling.push(extension);
+                let sibling = PathBuf::from(sibling);
+
+                if tokio::fs::metadata(&sibling)
+                    .await
+                    .map(|m| m.is_file())
+                    .unwrap_or(false)
+                {
+                    return Some(ResolvedFile {
+                        path: sibling,
+                        original: path,
+                        encoding: Some(encoding),
+                        is_index,
+                    });
+                }
+            }
+        }
+
+        Some(ResolvedFile {
+            original: path.clone(),
+            path,
+            encoding: None,
+            is_index,
+        })
+    }
+
+    async fn respond(&self, req: &HttpRequest, file: ResolvedFile) -> io::Result<HttpResponse> {
+        let meta = tokio::fs::metadata(&file.path).await?;
+        let modified = meta
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|time| time.as_secs())
+            .unwrap_or_default();
+
+        let etag = format!("\"{:x}-{:x}\"", meta.len(), modified);
+        let file_name = file
+            .original
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+
+        // the index file is the entrypoint to fingerprinted assets, it must always be revalidated
+        let cache_control = match file.is_index {
+            true => "no-cache",
+            false if (self.is_fingerprinted)(file_name) => IMMUTABLE_CACHE_CONTROL,
+            false => self.cache_control.as_str(),
+        };
+
+        let mut builder = HttpResponse::Ok();
+        builder
+            .header(CACHE_CONTROL, cache_control)
+            .header(ETAG, etag.as_str())
+            .header(ACCEPT_RANGES, "bytes");
+
+        if self.precompressed {
+            builder.header(VARY, "accept-encoding");
+        }
+
+        let not_modified = req
+            .headers()
+            .get(IF_NONE_MATCH)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| {
+                v.split(',')
+                    .any(|tag| tag.trim() == etag || tag.trim() == "*")
+            })
+            .unwrap_or(false);
+
+        if not_modified {
+            builder.status(StatusCode::NOT_MODIFIED);
+            return Ok(builder.finish());
+        }
+
+        builder.header(CONTENT_TYPE, content_type(&file.original).as_str());
+        if let Some(encoding) = file.encoding {
+            builder.header(CONTENT_ENCODING, encoding);
+        }
+
+        let len = meta.len();
+        let (start, end) = match byte_range(req, len, &etag) {
+            ByteRange::Full => (0, len.saturating_sub(1)),
+            ByteRange::Partial(start, end) => {
+                builder
+                    .status(StatusCode::PARTIAL_CONTENT)
+                    .header(CONTENT_RANGE, format!("bytes {}-{}/{}", start, end, len));
+                (start, end)
+            }
+            ByteRange::Unsatisfiable => {
+                builder
+                    .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                    .header(CONTENT_RANGE, format!("bytes */{}", len));
+                return Ok(builder.finish());
+            }
+        };
+
+        let length = match len {
+            0 => 0,
+            _ => end - start + 1,
+        };
+
+        if req.method() == Method::HEAD {
+            builder.header(CONTENT_LENGTH, HeaderValue::from(length));
+            return Ok(builder.finish());
+        }
+
+        let mut handle = tokio::fs::File::open(&file.path).await?;
+        handle.seek(io::SeekFrom::Start(start)).await?;
+
+        let stream =
+            futures_util::stream::unfold((handle, length), |(mut handle, left)| async move {
+                if left == 0 {
+                    return None;
+                }
+
+                let mut buffer = vec![0; CHUNK_SIZE.min(left as usize)];
+                match handle.read(&mut buffer).await {
+                    Ok(0) => None,
+                    Ok(read) => {
+                        buffer.truncate(read);
+                        Some((Ok(Bytes::from(buffer)), (handle, left - read as u64)))
+                    }
+                    Err(err) => Some((Err(err.into()), (handle, 0))),
+                }
+            });
+
+        Ok(builder.body(SizedStream::new(length, Box::pin(stream))))
+    }
+}
+
+/// Detects content hashes in file names like `app.3f9a1c2b.js` or `index-BhX9kq2L.css`
+pub fn is_fingerprinted(file_name: &str) -> bool {
+    let stem = match file_name.rsplit_once('.') {
+        Some((stem, _)) => stem,
+        None => return false,
+    };
+
+    stem.rsplit(['.', '-'])
+        .next()
+        .filter(|segment| segment.len() != stem.len())
+        .map(|hash| {
+            (8..=64).contains(&hash.len())
+                && hash.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+                && hash.chars().any(|c| c.is_ascii_digit())
+        })
+        .unwrap_or(false)
+}
+
+/// Decodes the requested path, rejecting traversal and hidden files (but `/.well-known`)
+fn sanitize(path: &str) -> Option<String> {
+    let decoded = percent_decode_str(path).decode_utf8().ok()?;
+    let mut segments = vec![];
+
+    for segment in decoded.split('/') {
+        if segment.is_empty() {
+            continue;
+        }
+
+        let hidden = segment.starts_with('.') && !(segments.is_empty() && segment == ".well-known");
+        if hidden || segment.contains('\\') || segment.contains('\0') {
+            return None;
+        }
+
+        segments.push(segment);
+    }
+
+    Some(segments.join("/"))
+}
+
+/// Single range of a `Range: bytes=` header, multiple ranges and a stale `If-Range` get the
+/// whole file
+fn byte_range(req: &HttpRequest, len: u64, etag: &str) -> ByteRange {
+    let header = |name| req.headers().get(name).and_then(|v| v.to_str().ok());
+
+    let spec = match header(RANGE).and_then(|v| v.trim().strip_prefix("bytes=")) {
+        Some(spec) if !spec.contains(',') => spec.trim(),
+        _ => return ByteRange::Full,
+    };
+
+    if header(IF_RANGE).is_some_and(|tag| tag.trim() != etag) {
+        return ByteRange::Full;
+    }
+
+    let (start, end) = match spec.split_once('-') {
+        Some(range) => range,
+        None => return ByteRange::Full,
+    };
+
+    let range = match (start.parse::<u64>(), end) {
+        // the last `end` bytes
+        (Err(_), end) if start.is_empty() => match end.parse::<u64>() {
+            Ok(0) => return ByteRange::Unsatisfiable,
+            Ok(suffix) => (len.saturating_sub(suffix), len.saturating_sub(1)),
+            Err(_) => return ByteRange::Full,
+        },
+        (Ok(start), "") => (start, len.saturating_sub(1)),
+        (Ok(start), end) => match end.parse::<u64>() {
+            Ok(end) if end >= start => (start, end.min(len.saturating_sub(1))),
+            _ => return ByteRange::Full,
+        },
+        (Err(_), _) => return ByteRange::Full,
+    };
+
+    match range.0 < len {
+        true => ByteRange::Partial(range.0, range.1),
+        false => ByteRange::Unsatisfiable,
+    }
+}
+
+/// Encodings the client accepts that siblings may exist for, in order of preference
+fn accepted_encodings(req: &HttpRequest) -> Vec<&'static str> {
+    let accepted = req
+        .headers()
+        .get(ACCEPT_ENCODING)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';');
+            let name = parts.next()?.trim().to_lowercase();
+            let rejected =
+                parts.any(|p| matches!(p.trim(), "q=0" | "q=0.0" | "q=0.00" | "q=0.000"));
+            (!rejected).then_some(name)
+        })
+        .collect::<Vec<_>>();
+
+    ["br", "gzip"]
+        .into_iter()
+        .filter(|encoding| accepted.iter().any(|a| a == encoding))
+        .collect()
+}
+
+/// Whether a missing file should be answered with the spa index: navigations explicitly accept
+/// html, other clients only get it for extension-less paths (a missing `.js` stays a 404)
+fn wants_index(req: &HttpRequest) -> bool {
+    let accept = req.headers().get(ACCEPT).and_then(|v| v.to_str().ok());
+    if accept.is_some_and(|v| v.contains("text/html")) {
+        return true;
+    }
+
+    let name = req.path().rsplit('/').next().unwrap_or_default();
+    !name.contains('.') && accept.is_none_or(|v| v.contains("*/*"))
+}
+
+fn content_type(path: &Path) -> String {
+    let mime = mime_guess::from_path(path).first_or_octet_stream();
+    match mime.type_() == mime_guess::mime::TEXT || mime.subtype() == mime_guess::mime::JAVASCRIPT {
+        true => format!("{}; charset=utf-8", mime),
+        false => mime.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ntex::web::test::TestRequest;
+
+    #[test]
+    fn test_is_fingerprinted() {
+        assert!(is_fingerprinted("app.3f9a1c2b.js"));
+        assert!(is_fingerprinted("index-BhX9kq2L.css"));
+        assert!(!is_fingerprinted("index.html"));
+        assert!(!is_fingerprinted("jquery.min.js"));
+        assert!(!is_fingerprinted("3f9a1c2b.js"));
+        assert!(!is_fingerprinted("README"));
+    }
+
+    #[test]
+    fn test_sanitize() {
+        assert_eq!(sanitize("/css/app.css"), Some("css/app.css".to_string()));
+        assert_eq!(sanitize("/my%20file.txt"), Some("my file.txt".to_string()));
+        assert_eq!(sanitize("//"), Some("".to_string()));
+        assert_eq!(sanitize("/../etc/passwd"), None);
+        assert_eq!(sanitize("/%2e%2e/etc/passwd"), None);
+        assert_eq!(sanitize("/.env"), None);
+        assert_eq!(
+            sanitize("/.well-known/security.txt"),
+            Some(".well-known/security.txt".to_string())
+        );
+        assert_eq!(sanitize("/.well-known/.secret"), None);
+        assert_eq!(sanitize("/docs/.well-known/x"), None);
+    }
+
+    #[test]
+    fn test_byte_range() {
+        let range = |value: &str| {
+            let req = TestRequest::get().header(RANGE, value).to_http_request();
+            byte_range(&req, 100, "\"tag\"")
+        };
+
+        assert_eq!(range("bytes=0-9"), ByteRange::Partial(0, 9));
+        assert_eq!(range("bytes=90-"), ByteRange::Partial(90, 99));
+        assert_eq!(range("bytes=95-200"), ByteRange::Partial(95, 99));
+        assert_eq!(range("bytes=-10"), ByteRange::Partial(90, 99));
+        assert_eq!(range("bytes=-500"), ByteRange::Partial(0, 99));
+        assert_eq!(range("bytes=100-"), ByteRange::Unsatisfiable);
+        assert_eq!(range("bytes=-0"), ByteRange::Unsatisfiable);
+        assert_eq!(range("bytes=0-1,5-6"), ByteRange::Full);
+        assert_eq!(range("bytes=9-3"), ByteRange::Full);
+        assert_eq!(range("items=0-9"), ByteRange::Full);
+
+        let req = TestRequest::get()
+            .header(RANGE, "bytes=0-9")
+            .header(IF_RANGE, "\"stale\"")
+            .to_http_request();
+        assert_eq!(byte_range(&req, 100, "\"tag\""), ByteRange::Full);
+    }
+
+    #[ntex::test]
+    async fn test_range_requests() {
+        use ntex::web::test::{call_service, init_service, read_body};
+        use ntex::web::App;
+
+        let dir = std::env::temp_dir().join(format!("static-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(dir.join(".well-known")).unwrap();
+        std::fs::write(dir.join("video.mp4"), b"0123456789").unwrap();
+        std::fs::write(dir.join(".well-known/security.txt"), b"Contact: sec").unwrap();
+
+        let mount = StaticMount::new("/files", dir.to_str().unwrap());
+        let app = init_service(App::with(JsonErrorRenderer).service(mount.service())).await;
+
+        let req = TestRequest::with_uri("/files/video.mp4")
+            .header(RANGE, "bytes=2-5")
+            .to_request();
+        let resp = call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(resp.headers().get(CONTENT_RANGE).unwrap(), "bytes 2-5/10");
+        assert_eq!(read_body(resp).await, Bytes::from_static(b"2345"));
+
+        let req = TestRequest::with_uri("/files/video.mp4")
+            .header(RANGE, "bytes=10-")
+            .to_request();
+        let resp = call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(resp.headers().get(CONTENT_RANGE).unwrap(), "bytes */10");
+
+        let req = TestRequest::with_uri("/files/video.mp4").to_request();
+        let resp = call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers().get(ACCEPT_RANGES).unwrap(), "bytes");
+        assert_eq!(read_body(resp).await, Bytes::from_static(b"0123456789"));
+
+        let req = TestRequest::with_uri("/files/.well-known/security.txt").to_request();
+        let resp = call_service(&app, req).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(read_body(resp).await, Bytes::from_static(b"Contact: sec"));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_wants_index() {
+        let navigation = TestRequest::with_uri("/settings/profile")
+            .header(ACCEPT, "text/html,application/xhtml+xml,*/*;q=0.8")
+            .to_http_request();
+        assert!(wants_index(&navigation));
+
+        let script = TestRequest::with_uri("/assets/app.js")
+            .header(ACCEPT, "*/*")
+            .to_http_request();
+        assert!(!wants_index(&script));
+        assert!(!wants_index(
+            &TestRequest::with_uri("/logo.png").to_http_request()
+        ));
+
+        assert!(wants_index(
+            &TestRequest::with_uri("/settings").to_http_request()
+        ));
+        let json = TestRequest::with_uri("/settings")
+            .header(ACCEPT, "application/json")
+            .to_http_request();
+        assert!(!wants_index(&json));
+    }
+
+    #[test]
+    fn test_accepted_encodings() {
+        let req = TestRequest::get()
+            .header(ACCEPT_ENCODING, "gzip, deflate, br;q=0")
+            .to_http_request();
+        assert_eq!(accepted_encodings(&req), vec!["gzip"]);
+
+        let req = TestRequest::get()
+            .header(ACCEPT_ENCODING, "br, gzip")
+            .to_http_request();
+        assert_eq!(accepted_encodings(&req), vec!["br", "gzip"]);
+
+        let req = TestRequest::get().to_http_request();
+        assert!(accepted_encodings(&req).is_empty());
+    }
+}
@@ -0,0 +1,341 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Mutex, OnceLock};
+
+use log::{debug, info, warn};
+use ntex::util::{ByteString, Bytes};
+use ntex::web::ws::{CloseCode, CloseReason, Message};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+static WS_HUB: OnceLock<WsHub> = OnceLock::new();
+
+/// Payload that can be delivered to sockets, on this replica or (through redis) on others
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum WsPayload {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+/// Keeps track of the sockets connected to this replica and the rooms they joined
+pub struct WsHub {
+    /// identifies this replica, messages it published itself are not delivered twice
+    #[cfg(feature = "redis")]
+    id: String,
+    /// redis channel broadcasts are fanned-out through
+    #[cfg(feature = "redis")]
+    channel: String,
+    state: Mutex<HubState>,
+    #[cfg(feature = "redis")]
+    listening: std::sync::atomic::AtomicBool,
+}
+
+#[derive(Default)]
+struct HubState {
+    connections: HashMap<Uuid, HubClient>,
+    rooms: HashMap<String, HashSet<Uuid>>,
+}
+
+struct HubClient {
+    sender: mpsc::Sender<Message>,
+    rooms: HashSet<String>,
+}
+
+#[cfg(feature = "redis")]
+#[derive(Serialize, Deserialize)]
+struct WsEnvelope {
+    origin: String,
+    room: String,
+    payload: WsPayload,
+}
+
+impl WsHub {
+    pub const DEFAULT_CHANNEL: &'static str = "medullah:websocket";
+
+    /// redis re-subscription delays, doubled after every failure
+    #[cfg(feature = "redis")]
+    const RESUBSCRIBE_DELAY: std::time::Duration = std::time::Duration::from_secs(1);
+    #[cfg(feature = "redis")]
+    const MAX_RESUBSCRIBE_DELAY: std::time::Duration = std::time::Duration::from_secs(30);
+
+    fn new(channel: &str) -> Self {
+        #[cfg(not(feature = "redis"))]
+        let _ = channel;
+
+        WsHub {
+            #[cfg(feature = "redis")]
+            id: Uuid::new_v4().to_string(),
+            #[cfg(feature = "redis")]
+            channel: channel.to_string(),
+            state: Mutex::new(HubState::default()),
+            #[cfg(feature = "redis")]
+            listening: std::sync::atomic::AtomicBool::new(false),
+        }
+    }
+
+    /// The hub shared by every websocket endpoint of this process
+    pub fn global() -> &'static WsHub {
+        WS_HUB.get_or_init(|| WsHub::new(Self::DEFAULT_CHANNEL))
+    }
+
+    pub(crate) fn register(&self, id: Uuid, sender: mpsc::Sender<Message>) {
+        self.state().connections.insert(
+            id,
+            HubClient {
+                sender,
+                rooms: HashSet::new(),
+            },
+        );
+    }
+
+    pub(crate) fn unregister(&self, id: &Uuid) {
+        let mut state = self.state();
+        if let Some(client) = state.connections.remove(id) {
+            for room in client.rooms {
+                remove_from_room(&mut state, &room, id);
+            }
+        }
+    }
+
+    pub fn join(&self, id: &Uuid, room: &str) {
+        let mut state = self.state();
+        if let Some(client) = state.connections.get_mut(id) {
+            client.rooms.insert(room.to_string());
+            state.rooms.entry(room.to_string()).or_default().insert(*id);
+        }
+    }
+
+    pub fn leave(&self, id: &Uuid, room: &str) {
+        let mut state = self.state();
+        if let Some(client) = state.connections.get_mut(id) {
+            client.rooms.remove(room);
+            remove_from_room(&mut state, room, id);
+        }
+    }
+
+    pub fn rooms_of(&self, id: &Uuid) -> Vec<String> {
+        self.state()
+            .connections
+            .get(id)
+            .map(|client| client.rooms.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Number of sockets in the given room, on this replica
+    pub fn room_size(&self, room: &str) -> usize {
+        self.state().rooms.get(room).map(|r| r.len()).unwrap_or(0)
+    }
+
+    /// Number of sockets connected to this replica
+    pub fn connections(&self) -> usize {
+        self.state().connections.len()
+    }
+
+    /// Send a payload to a single socket connected to this replica
+    pub fn send_to(&self, id: &Uuid, payload: WsPayload) -> bool {
+        let mut state = self.state();
+        deliver(&mut state, id, payload.into_message())
+    }
+
+    /// Send a payload to every socket in the room, on every replica
+    pub async fn broadcast(&self, room: &str, payload: WsPayload) {
+        self.broadcast_local(room, payload.clone());
+
+        #[cfg(feature = "redis")]
+        {
+            use crate::prelude::OnceLockHelper;
+
+            let envelope = WsEnvelope {
+                origin: self.id.clone(),
+                room: room.to_string(),
+                payload,
+            };
+
+            if let Err(err) = crate::MEDULLAH
+                .redis()
+                .publish(&self.channel, &envelope)
+                .await
+            {
+                warn!("[websocket] failed to fan-out to '{}': {:?}", room, err);
+            }
+        }
+    }
+
+    /// Send a payload to every socket in the room, on this replica only
+    pub fn broadcast_local(&self, room: &str, payload: WsPayload) {
+        let message = payload.into_message();
+        let mut state = self.state();
+        let members = match state.rooms.get(room) {
+            Some(members) => members.iter().cloned().collect::<Vec<_>>(),
+            None => return,
+        };
+
+        debug!(
+            "[websocket] broadcasting to {} socket(s) in '{}'",
+            members.len(),
+            room
+        );
+        for id in members {
+            deliver(&mut state, &id, message.clone());
+        }
+    }
+
+    /// Ask every connected socket to close, used on graceful shutdown
+    pub fn close_all(&self) {
+        let mut state = self.state();
+        info!(
+            "[websocket] closing {} connection(s)",
+            state.connections.len()
+        );
+
+        let reason = CloseReason::from((CloseCode::Away, "server is shutting down"));
+        for (_, client) in state.connections.drain() {
+            let _ = client.sender.try_send(Message::Close(Some(reason.clone())));
+        }
+
+        state.rooms.clear();
+    }
+
+    /// Deliver broadcasts published by other replicas to sockets on this replica,
+    /// the subscription is only established once per process and re-established when lost
+    #[cfg(feature = "redis")]
+    pub fn listen(&'static self) {
+        use futures_util::StreamExt;
+        use std::sync::atomic::Ordering;
+
+        if self.listening.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        tokio::spawn(async move {
+            let mut delay = Self::RESUBSCRIBE_DELAY;
+            loop {
+                match crate::redis::Redis::subscribe_stream(&self.channel).await {
+                    Ok(mut stream) => {
+                        delay = Self::RESUBSCRIBE_DELAY;
+                        while let Some(received) = stream.next().await {
+                            let envelope = received
+                                .and_then(|msg| Ok(serde_json::from_str::<WsEnvelope>(&msg)?));
+
+                            match envelope {
+                                Ok(envelope) if envelope.origin != self.id => {
+                                    self.broadcast_local(&envelope.room, envelope.payload)
+                                }
+                                Ok(_) => {}
+                                Err(err) => warn!("[websocket] invalid fan-out message: {:?}", err),
+                            }
+                        }
+
+                        warn!("[websocket] fan-out subscription lost, re-subscribing");
+                    }
+                    Err(err) => warn!(
+                        "[websocket] failed to subscribe for fan-out, retrying in {:?}: {:?}",
+                        delay, err
+                    ),
+                }
+
+                tokio::time::sleep(delay).await;
+                delay = delay.saturating_mul(2).min(Self::MAX_RESUBSCRIBE_DELAY);
+            }
+        });
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, HubState> {
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+impl WsPayload {
+    pub fn json<T: Serialize>(value: &T) -> crate::prelude::AppResult<Self> {
+        Ok(WsPayload::Text(serde_json::to_string(value)?))
+    }
+
+    pub(crate) fn into_message(self) -> Message {
+        match self {
+            WsPayload::Text(text) => Message::Text(ByteString::from(text)),
+            WsPayload::Binary(bytes) => Message::Binary(Bytes::from(bytes)),
+        }
+    }
+}
+
+/// Queue a message for a socket, sockets that cannot keep up are disconnected
+fn deliver(state: &mut HubState, id: &Uuid, message: Message) -> bool {
+    let client = match state.connections.get(id) {
+        Some(client) => client,
+        None => return false,
+    };
+
+    match client.sender.try_send(message) {
+        Ok(_) => true,
+        Err(mpsc::error::TrySendError::Full(_)) => {
+            warn!("[websocket] {} is too slow, disconnecting", id);
+            // dropping the sender ends the connection's writer once its queue is flushed
+            if let Some(client) = state.connections.remove(id) {
+                for room in client.rooms {
+                    remove_from_room(state, &room, id);
+                }
+            }
+
+            false
+        }
+        Err(mpsc::error::TrySendError::Closed(_)) => false,
+    }
+}
+
+fn remove_from_room(state: &mut HubState, room: &str, id: &Uuid) {
+    if let Some(members) = state.rooms.get_mut(room) {
+        members.remove(id);
+        if members.is_empty() {
+            state.rooms.remove(room);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rooms() {
+        let hub = WsHub::new("test");
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        let (sender, mut first_rx) = mpsc::channel(8);
+        hub.register(first, sender);
+        let (sender, mut second_rx) = mpsc::channel(8);
+        hub.register(second, sender);
+
+        hub.join(&first, "orders");
+        hub.join(&second, "orders");
+        hub.join(&second, "payments");
+        assert_eq!(hub.room_size("orders"), 2);
+
+        hub.broadcast_local("payments", WsPayload::Text("paid".to_string()));
+        assert!(first_rx.try_recv().is_err());
+        assert_eq!(
+            second_rx.try_recv().unwrap(),
+            Message::Text(ByteString::from("paid"))
+        );
+
+        hub.leave(&first, "orders");
+        assert_eq!(hub.room_size("orders"), 1);
+
+        hub.unregister(&second);
+        assert_eq!(hub.room_size("orders"), 0);
+        assert_eq!(hub.room_size("payments"), 0);
+        assert_eq!(hub.connections(), 1);
+    }
+
+    #[test]
+    fn test_slow_consumer_is_disconnected() {
+        let hub = WsHub::new("test");
+        let id = Uuid::new_v4();
+        let (sender, _receiver) = mpsc::channel(1);
+        hub.register(id, sender);
+        hub.join(&id, "feed");
+
+        assert!(hub.send_to(&id, WsPayload::Text("1".to_string())));
+        assert!(!hub.send_to(&id, WsPayload::Text("2".to_string())));
+        assert_eq!(hub.connections(), 0);
+        assert_eq!(hub.room_size("feed"), 0);
+    }
+}
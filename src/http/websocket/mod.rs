@@ -0,0 +1,341 @@
+use std::cell::Cell;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use log::{debug, error, warn};
+use ntex::service::{fn_factory_with_config, fn_service};
+use ntex::util::Bytes;
+use ntex::web::ws::{CloseCode, CloseReason, Frame, Message, WsSink};
+use ntex::web::{self, HttpRequest, HttpResponse, Resource};
+use serde::Serialize;
+use tokio::sync::mpsc;
+use uuid::Uuid;
+
+use crate::helpers::jwt::JwtTokenClaims;
+use crate::http::error_renderer::{JsonError, JsonErrorRenderer};
+use crate::http::extractors::auth;
+use crate::prelude::{AppMessage, AppResult, OnceLockHelper};
+use crate::MEDULLAH;
+
+pub use hub::{WsHub, WsPayload};
+pub use ticket::WsTicket;
+
+mod hub;
+mod ticket;
+
+pub type WsFuture<'a, T> = Pin<Box<dyn Future<Output = AppResult<T>> + 'a>>;
+
+/// Handles the lifecycle of sockets connected to a [WsEndpoint]
+pub trait WsHandler: 'static {
+    /// Executed once the socket is connected, typically used to join rooms
+    fn on_connect<'a>(&'a self, _conn: &'a WsConnection) -> WsFuture<'a, ()> {
+        Box::pin(async { Ok(()) })
+    }
+
+    /// Executed for every text/binary message received from the socket
+    fn on_message<'a>(&'a self, conn: &'a WsConnection, message: WsPayload) -> WsFuture<'a, ()>;
+
+    /// Executed once the socket is gone
+    fn on_close(&self, _conn: &WsConnection) {}
+}
+
+/// An authenticated socket
+#[derive(Clone)]
+pub struct WsConnection {
+    id: Uuid,
+    claims: JwtTokenClaims,
+    hub: &'static WsHub,
+}
+
+/// Websocket endpoint registration
+///
+/// Sockets authenticate with the same tokens (JWT or personal access token) as http requests, sent in the `Authorization` header,
+/// or with a [WsTicket] sent as `?ticket=` query parameter since browsers cannot set headers on websocket requests.
+///
+/// # Examples
+///
+/// ```ignore
+/// fn chat_routes(cfg: &mut ServiceConfig) {
+///     cfg.service(WsEndpoint::new("/chat", ChatHandler).resource());
+/// }
+/// ```
+pub struct WsEndpoint<H: WsHandler> {
+    path: String,
+    handler: Rc<H>,
+    config: WsConfig,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct WsConfig {
+    /// how often pings are sent to the socket
+    pub heartbeat_interval: Duration,
+    /// sockets that stay silent (no pong or message) for this long are disconnected
+    pub client_timeout: Duration,
+    /// maximum number of outgoing messages queued per socket,
+    /// sockets whose queue is full are considered too slow and are disconnected
+    pub buffer: usize,
+}
+
+impl Default for WsConfig {
+    fn default() -> Self {
+        WsConfig {
+            heartbeat_interval: Duration::from_secs(10),
+            client_timeout: Duration::from_secs(30),
+            buffer: 64,
+        }
+    }
+}
+
+impl WsConnection {
+    pub fn id(&self) -> &Uuid {
+        &self.id
+    }
+
+    pub fn claims(&self) -> &JwtTokenClaims {
+        &self.claims
+    }
+
+    pub fn join(&self, room: &str) {
+        self.hub.join(&self.id, room);
+    }
+
+    pub fn leave(&self, room: &str) {
+        self.hub.leave(&self.id, room);
+    }
+
+    pub fn rooms(&self) -> Vec<String> {
+        self.hub.rooms_of(&self.id)
+    }
+
+    pub fn send_text(&self, text: &str) -> bool {
+        self.hub
+            .send_to(&self.id, WsPayload::Text(text.to_string()))
+    }
+
+    pub fn send_json<T: Serialize>(&self, value: &T) -> AppResult<bool> {
+        Ok(self.hub.send_to(&self.id, WsPayload::json(value)?))
+    }
+
+    /// Send a payload to every socket in the room, on every replica
+    pub async fn broadcast(&self, room: &str, payload: WsPayload) {
+        self.hub.broadcast(room, payload).await
+    }
+}
+
+impl<H: WsHandler> WsEndpoint<H> {
+    pub fn new(path: &str, handler: H) -> Self {
+        WsEndpoint {
+            path: path.to_string(),
+            handler: Rc::new(handler),
+            config: WsConfig::default(),
+        }
+    }
+
+    pub fn config(mut self, config: WsConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    pub fn resource(self) -> Resource<JsonErrorRenderer> {
+        let hub = WsHub::global();
+
+        #[cfg(feature = "redis")]
+        hub.listen();
+
+        let handler = self.handler;
+        let config = self.config;
+        web::resource(self.path.as_str())
+            .route(web::get().to(move |req: HttpRequest| start(req, handler.clone(), config, hub)))
+    }
+}
+
+async fn start<H: WsHandler>(
+    req: HttpRequest,
+    handler: Rc<H>,
+    config: WsConfig,
+    hub: &'static WsHub,
+) -> Result<HttpResponse, JsonError> {
+    let claims = authenticate(&req).await?;
+    let conn = WsConnection {
+        id: Uuid::new_v4(),
+        claims,
+        hub,
+    };
+
+    debug!("[websocket] {} connecting to {}", conn.id, req.path());
+    web::ws::start(
+        req,
+        fn_factory_with_config(move |sink: WsSink| {
+            let conn = conn.clone();
+            let handler = handler.clone();
+            async move {
+                let last_seen = Rc::new(Cell::new(Instant::now()));
+                let (sender, receiver) = mpsc::channel(config.buffer);
+                conn.hub.register(conn.id, sender);
+
+                ntex::rt::spawn(write_loop(
+                    sink.clone(),
+                    receiver,
+                    last_seen.clone(),
+                    config,
+                ));
+
+                let disconnected = (conn.clone(), handler.clone());
+                ntex::rt::spawn(async move {
+                    sink.on_disconnect().await;
+                    let (conn, handler) = disconnected;
+                    debug!("[websocket] {} disconnected", conn.id);
+                    conn.hub.unregister(&conn.id);
+                    handler.on_close(&conn);
+                });
+
+                if let Err(err) = handler.on_connect(&conn).await {
+                    error!("[websocket] {} on-connect error: {:?}", conn.id, err);
+                }
+
+                Ok::<_, JsonError>(fn_service(move |frame: Frame| {
+                    let conn = conn.clone();
+                    let handler = handler.clone();
+                    let last_seen = last_seen.clone();
+                    async move {
+                        last_seen.set(Instant::now());
+                        let payload = match frame {
+                            Frame::Text(bytes) => match String::from_utf8(bytes.to_vec()) {
+                                Ok(text) => WsPayload::Text(text),
+                                Err(_) => {
+                                    return Ok(Some(Message::Close(Some(CloseReason::from(
+                                        CloseCode::Invalid,
+                                    )))))
+                                }
+                            },
+                            Frame::Binary(bytes) => WsPayload::Binary(bytes.to_vec()),
+                            Frame::Ping(bytes) => return Ok(Some(Message::Pong(bytes))),
+                            Frame::Close(reason) => {
+                                conn.hub.unregister(&conn.id);
+                                return Ok(Some(Message::Close(reason)));
+                            }
+                            Frame::Pong(_) | Frame::Continuation(_) => return Ok(None),
+                        };
+
+                        if let Err(err) = handler.on_message(&conn, payload).await {
+                            error!("[websocket] {} message handler error: {:?}", conn.id, err);
+                        }
+
+                        Ok::<_, AppMessage>(None)
+                    }
+                }))
+            }
+        }),
+    )
+    .await
+}
+
+/// Writes queued messages and heartbeats to the socket
+async fn write_loop(
+    sink: WsSink,
+    mut receiver: mpsc::Receiver<Message>,
+    last_seen: Rc<Cell<Instant>>,
+    config: WsConfig,
+) {
+    let mut heartbeat = tokio::time::interval(config.heartbeat_interval);
+
+    loop {
+        tokio::select! {
+            message = receiver.recv() => match message {
+                Some(Message::Close(reason)) => {
+                    let _ = sink.send(Message::Close(reason)).await;
+                    sink.io().close();
+                    return;
+                }
+                Some(message) => {
+                    if sink.send(message).await.is_err() {
+                        return;
+                    }
+                }
+                // the hub dropped this socket (too slow to keep up)
+                None => {
+                    let reason = CloseReason::from((CloseCode::Policy, "too slow to keep up"));
+                    let _ = sink.send(Message::Close(Some(reason))).await;
+                    sink.io().close();
+                    return;
+                }
+            },
+            _ = heartbeat.tick() => {
+                if last_seen.get().elapsed() > config.client_timeout {
+                    warn!("[websocket] client heartbeat timed out, disconnecting");
+                    sink.io().close();
+                    return;
+                }
+
+                if sink.send(Message::Ping(Bytes::new())).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Validates the token sent in the `Authorization` header or auth cookie, or the `ticket`
+/// query parameter
+async fn authenticate(req: &HttpRequest) -> AppResult<JwtTokenClaims> {
+    if let Some(token) = MEDULLAH.app().auth.token(req) {
+        return auth::authenticate(&token).await;
+    }
+
+    match query_ticket(req.query_string()) {
+        Some(ticket) => WsTicket::redeem(&ticket).await,
+        None => Err(AppMessage::UnAuthorized),
+    }
+}
+
+fn query_ticket(query: &str) -> Option<String> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(name, _)| *name == "ticket")
+        .map(|(_, value)| value.to_string())
+        .filter(|value| !value.is_empty())
+}
+
+/// Close every socket when the process is asked to stop, before workers are shut down
+pub(crate) async fn close_on_shutdown() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut terminate = match signal(SignalKind::terminate()) {
+            Ok(signal) => signal,
+            Err(err) => {
+                error!("[websocket] failed to listen for shutdown: {:?}", err);
+                return;
+            }
+        };
+
+        tokio::select! {
+            _ = tokio::signal::ctrl_c() => {},
+            _ = terminate.recv() => {},
+        }
+    }
+
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+
+    WsHub::global().close_all();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_ticket() {
+        assert_eq!(
+            query_ticket("room=a&ticket=abc123"),
+            Some("abc123".to_string())
+        );
+        assert_eq!(query_ticket("ticket="), None);
+        assert_eq!(query_ticket("room=a&token=abc.def"), None);
+    }
+}
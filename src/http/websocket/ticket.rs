@@ -0,0 +1,127 @@
+use std::time::Duration;
+
+use log::debug;
+use uuid::Uuid;
+
+use crate::helpers::jwt::JwtTokenClaims;
+use crate::prelude::{AppMessage, AppResult};
+
+/// Short-lived, single-use ticket authenticating a websocket connection
+///
+/// Browsers can't set headers on websocket requests, and tokens sent in the url end up in
+/// access logs, so sockets connect with `?ticket=` instead. Tickets are issued by an
+/// authenticated http endpoint and are stored in redis when the feature is enabled,
+/// in process (the socket has to reach the same replica) otherwise.
+///
+/// ```ignore
+/// async fn ws_ticket(auth: Auth) -> HttpResult {
+///     let ticket = WsTicket::issue(&auth).await?;
+///     Ok(Responder::ok(json!({ "ticket": ticket }), "ticket issued"))
+/// }
+/// ```
+pub struct WsTicket;
+
+impl WsTicket {
+    /// How long a ticket can be used for
+    pub const TTL: Duration = Duration::from_secs(30);
+
+    /// Issues a ticket for the authenticated claims
+    pub async fn issue(claims: &JwtTokenClaims) -> AppResult<String> {
+        let ticket = Uuid::new_v4().simple().to_string();
+        store::insert(&ticket, claims).await?;
+
+        debug!("[websocket] ticket issued to {}", claims.sub);
+        Ok(ticket)
+    }
+
+    /// Claims the ticket was issued for, the ticket can't be used again
+    pub(crate) async fn redeem(ticket: &str) -> AppResult<JwtTokenClaims> {
+        store::take(ticket)
+            .await?
+            .ok_or(AppMessage::UnAuthorizedMessage(
+                "invalid or expired websocket ticket",
+            ))
+    }
+}
+
+#[cfg(feature = "redis")]
+mod store {
+    use super::*;
+    use crate::prelude::OnceLockHelper;
+    use crate::MEDULLAH;
+
+    fn key(ticket: &str) -> String {
+        format!("websocket-ticket:{}", ticket)
+    }
+
+    pub(super) async fn insert(ticket: &str, claims: &JwtTokenClaims) -> AppResult<()> {
+        let ttl = WsTicket::TTL.as_secs();
+        MEDULLAH.redis().set_ex(&key(ticket), claims, ttl).await?;
+        Ok(())
+    }
+
+    pub(super) async fn take(ticket: &str) -> AppResult<Option<JwtTokenClaims>> {
+        let key = key(ticket);
+        let claims = match MEDULLAH.redis().get::<Option<String>>(&key).await? {
+            Some(claims) => claims,
+            None => return Ok(None),
+        };
+
+        // only the connection that removed the ticket can use it
+        match MEDULLAH.redis().delete(&key).await? {
+            1 => Ok(Some(serde_json::from_str(&claims)?)),
+            _ => Ok(None),
+        }
+    }
+}
+
+#[cfg(not(feature = "redis"))]
+mod store {
+    use std::collections::HashMap;
+    use std::sync::{Mutex, OnceLock};
+    use std::time::Instant;
+
+    use super::*;
+
+    static TICKETS: OnceLock<Mutex<HashMap<String, (JwtTokenClaims, Instant)>>> = OnceLock::new();
+
+    fn tickets() -> &'static Mutex<HashMap<String, (JwtTokenClaims, Instant)>> {
+        TICKETS.get_or_init(Default::default)
+    }
+
+    pub(super) async fn insert(ticket: &str, claims: &JwtTokenClaims) -> AppResult<()> {
+        let mut tickets = tickets().lock().unwrap();
+        tickets.retain(|_, (_, issued_at)| issued_at.elapsed() < WsTicket::TTL);
+        tickets.insert(ticket.to_string(), (claims.clone(), Instant::now()));
+        Ok(())
+    }
+
+    pub(super) async fn take(ticket: &str) -> AppResult<Option<JwtTokenClaims>> {
+        let ticket = tickets().lock().unwrap().remove(ticket);
+        Ok(ticket
+            .filter(|(_, issued_at)| issued_at.elapsed() < WsTicket::TTL)
+            .map(|(claims, _)| claims))
+    }
+}
+
+#[cfg(all(test, not(feature = "redis")))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_tickets_are_single_use() {
+        let claims = JwtTokenClaims {
+            sub: "user-1".to_string(),
+            iat: 0,
+            exp: 0,
+            iss: "accounts".to_string(),
+            aud: "api".to_string(),
+            jti: "jti".to_string(),
+        };
+
+        let ticket = WsTicket::issue(&claims).await.unwrap();
+        assert_eq!(WsTicket::redeem(&ticket).await.unwrap().sub, "user-1");
+        assert!(WsTicket::redeem(&ticket).await.is_err());
+        assert!(WsTicket::redeem("unknown").await.is_err());
+    }
+}
@@ -0,0 +1,304 @@
+use std::collections::HashMap;
+use std::fs;
+use std::future::Future;
+use std::path::Path;
+use std::sync::{LazyLock, RwLock};
+
+use log::{debug, warn};
+use serde_json::Value;
+
+use crate::results::AppResult;
+
+/// Catalogs shipped with the framework, apps extend or override them from `resources/lang`
+const BUILT_IN: [(&str, &str); 3] = [
+    ("en", include_str!("lang/en.json")),
+    ("fr", include_str!("lang/fr.json")),
+    ("sw", include_str!("lang/sw.json")),
+];
+
+static I18N: LazyLock<RwLock<Catalogs>> = LazyLock::new(|| RwLock::new(Catalogs::built_in()));
+
+tokio::task_local! {
+    static LOCALE: String;
+}
+
+/// Messages of every locale, keyed by dotted path (e.g. `errors.unauthorized`)
+struct Catalogs {
+    default_locale: String,
+    messages: HashMap<String, HashMap<String, String>>,
+}
+
+impl Catalogs {
+    fn built_in() -> Self {
+        let mut catalogs = Catalogs {
+            default_locale: "en".to_string(),
+            messages: HashMap::new(),
+        };
+
+        for (locale, content) in BUILT_IN {
+            let content = serde_json::from_str(content).expect("invalid built-in catalog");
+            catalogs.merge(locale, &content);
+        }
+
+        catalogs
+    }
+
+    fn merge(&mut self, locale: &str, content: &Value) {
+        let messages = self.messages.entry(normalize(locale)).or_default();
+        flatten("", content, messages);
+    }
+
+    fn get(&self, locale: &str, key: &str) -> Option<&String> {
+        let locale = normalize(locale);
+        let primary = locale.split('-').next().unwrap_or_default();
+
+        let found = [locale.as_str(), primary, self.default_locale.as_str()]
+            .into_iter()
+            .find_map(|locale| self.messages.get(locale).and_then(|m| m.get(key)));
+
+        found
+    }
+}
+
+/// Translates `key` in the locale of the current request, see [t!](crate::t)
+///
+/// Missing keys are returned as is.
+pub fn translate(key: &str, args: &[(&str, String)]) -> String {
+    translate_in(&current_locale(), key, args)
+}
+
+pub fn translate_in(locale: &str, key: &str, args: &[(&str, String)]) -> String {
+    lookup(locale, key, args).unwrap_or_else(|| key.to_string())
+}
+
+/// Translation of `key`, falling back to the primary language then to the default locale
+pub fn lookup(locale: &str, key: &str, args: &[(&str, String)]) -> Option<String> {
+    let catalogs = I18N.read().unwrap();
+    let message = catalogs.get(locale, key)?;
+
+    Some(args.iter().fold(message.clone(), |message, (name, value)| {
+        message.replace(&format!("{{{}}}", name), value)
+    }))
+}
+
+/// Locale of the current request, the default locale outside of requests
+pub fn current_locale() -> String {
+    LOCALE
+        .try_with(|locale| locale.clone())
+        .unwrap_or_else(|_| default_locale())
+}
+
+/// Runs `future` with `locale` as the current locale
+pub async fn with_locale<F: Future>(locale: String, future: F) -> F::Output {
+    LOCALE.scope(locale, future).await
+}
+
+pub fn default_locale() -> String {
+    I18N.read().unwrap().default_locale.clone()
+}
+
+pub fn set_default_locale(locale: &str) {
+    I18N.write().unwrap().default_locale = normalize(locale);
+}
+
+pub fn locales() -> Vec<String> {
+    let mut locales: Vec<String> = I18N.read().unwrap().messages.keys().cloned().collect();
+    locales.sort();
+    locales
+}
+
+pub fn is_supported(locale: &str) -> bool {
+    I18N.read()
+        .unwrap()
+        .messages
+        .contains_key(&normalize(locale))
+}
+
+/// Adds messages to the catalog of `locale`, nested objects become dotted keys
+pub fn add_messages(locale: &str, messages: &Value) {
+    I18N.write().unwrap().merge(locale, messages);
+}
+
+/// Loads every `{locale}.json` catalog found in `dir`
+pub fn load_dir<P: AsRef<Path>>(dir: P) -> AppResult<()> {
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().and_then(|e| e.to_str()) != Some("json") {
+            continue;
+        }
+
+        let locale = match path.file_stem().and_then(|s| s.to_str()) {
+            Some(locale) => locale.to_string(),
+            None => continue,
+        };
+
+        let content: Value = serde_json::from_str(&fs::read_to_string(&path)?)?;
+        add_messages(&locale, &content);
+        debug!("[i18n] loaded {}", path.display());
+    }
+
+    Ok(())
+}
+
+/// Best supported locale of an `Accept-Language` header, `None` when nothing matches
+pub fn negotiate(accept_language: &str) -> Option<String> {
+    let mut ranges: Vec<(String, f32)> = accept_language
+        .split(',')
+        .filter_map(|range| {
+            let mut parts = range.split(';');
+            let tag = normalize(parts.next()?.trim());
+            let quality = parts
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+
+            (!tag.is_empty() && tag != "*" && quality > 0.0).then_some((tag, quality))
+        })
+        .collect();
+
+    // stable, ranges of equal quality keep the client's order
+    ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+    ranges.into_iter().find_map(|(tag, _)| {
+        let primary = tag.split('-').next().unwrap_or_default().to_string();
+        [tag, primary].into_iter().find(|tag| is_supported(tag))
+    })
+}
+
+/// Reads `{PREFIX}_DEFAULT_LOCALE` and loads the app catalogs from `resources/lang`, when present
+pub(crate) fn configure(env_prefix: &str) {
+    if let Ok(locale) = std::env::var(format!("{}_DEFAULT_LOCALE", env_prefix)) {
+        set_default_locale(&locale);
+    }
+
+    let dir = crate::helpers::fs::base_path("resources/lang");
+    if dir.is_dir() {
+        if let Err(err) = load_dir(&dir) {
+            warn!(
+                "[i18n] failed to load catalogs from {}: {:?}",
+                dir.display(),
+                err
+            );
+        }
+    }
+}
+
+/// Registers the `t` function, `{{ t(key="emails.welcome", name=user.name) }}`,
+/// an explicit locale can be given with `lang="fr"`
+#[cfg(feature = "templating")]
+pub fn register_tera_function(tera: &mut tera::Tera) {
+    tera.register_function(
+        "t",
+        |args: &HashMap<String, tera::Value>| -> tera::Result<tera::Value> {
+            let key = args
+                .get("key")
+                .and_then(|key| key.as_str())
+                .ok_or_else(|| tera::Error::msg("t() requires a `key` argument"))?;
+
+            let locale = args
+                .get("lang")
+                .and_then(|lang| lang.as_str())
+                .map(String::from)
+                .unwrap_or_else(current_locale);
+
+            let params: Vec<(&str, String)> = args
+                .iter()
+                .filter(|(name, _)| name.as_str() != "key" && name.as_str() != "lang")
+                .map(|(name, value)| {
+                    let value = match value {
+                        tera::Value::String(value) => value.clone(),
+                        value => value.to_string(),
+                    };
+
+                    (name.as_str(), value)
+                })
+                .collect();
+
+            Ok(tera::Value::String(translate_in(&locale, key, &params)))
+        },
+    );
+}
+
+fn flatten(prefix: &str, value: &Value, messages: &mut HashMap<String, String>) {
+    match value {
+        Value::Object(map) => {
+            for (name, value) in map {
+                let key = match prefix.is_empty() {
+                    true => name.clone(),
+                    false => format!("{}.{}", prefix, name),
+                };
+
+                flatten(&key, value, messages);
+            }
+        }
+        Value::String(message) => {
+            messages.insert(prefix.to_string(), message.clone());
+        }
+        value => {
+            messages.insert(prefix.to_string(), value.to_string());
+        }
+    }
+}
+
+fn normalize(locale: &str) -> String {
+    locale.trim().replace('_', "-").to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn test_translate_in() {
+        assert_eq!(
+            translate_in("fr", "errors.validation_failed", &[]),
+            "Erreur de validation"
+        );
+        assert_eq!(
+            translate_in(
+                "sw-TZ",
+                "errors.entity_not_found",
+                &[("entity", "Oda".to_string())]
+            ),
+            "Oda hiyo haipo"
+        );
+
+        // missing in german, then english is used
+        assert_eq!(translate_in("de", "errors.not_found", &[]), "Not Found");
+        assert_eq!(translate_in("fr", "no.such.key", &[]), "no.such.key");
+    }
+
+    #[test]
+    fn test_add_messages() {
+        add_messages(
+            "fr",
+            &json!({"orders": {"shipped": "Commande {id} expédiée"}}),
+        );
+        assert_eq!(
+            translate_in("fr", "orders.shipped", &[("id", "42".to_string())]),
+            "Commande 42 expédiée"
+        );
+    }
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(negotiate("fr-CA,en;q=0.8").as_deref(), Some("fr"));
+        assert_eq!(negotiate("de, sw;q=0.5, en;q=0.7").as_deref(), Some("en"));
+        assert_eq!(negotiate("en;q=0, de").as_deref(), None);
+        assert_eq!(negotiate("*").as_deref(), None);
+    }
+
+    #[tokio::test]
+    async fn test_with_locale() {
+        assert_eq!(crate::t!("errors.not_found"), "Not Found");
+
+        let message = with_locale("fr".to_string(), async {
+            crate::t!("errors.entity_not_found", entity = "commande")
+        })
+        .await;
+
+        assert_eq!(message, "L'élément commande n'existe pas");
+    }
+}
@@ -18,16 +18,22 @@ pub mod app_setup;
 pub mod contracts;
 pub mod env_logger;
 pub mod http;
+pub mod i18n;
 pub mod macros;
 #[cfg(feature = "rabbitmq")]
 pub mod rabbitmq;
+pub mod reporting;
+pub mod resilience;
 pub mod services;
+#[cfg(feature = "multipart")]
+pub mod storage;
 pub mod tokio;
 
 pub static MEDULLAH: OnceLock<MedullahState> = OnceLock::new();
 
 pub mod prelude {
     pub use crate::app_state::MedullahState;
+    pub use crate::contracts::AppErrorContract;
     pub use crate::enums::app_message::AppMessage;
     pub use crate::helpers::once_lock::OnceLockHelper;
     #[cfg(feature = "rabbitmq")]
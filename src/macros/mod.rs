@@ -2,6 +2,8 @@ mod enum_common;
 mod enum_diesel;
 mod enum_diesel_generate;
 mod enum_generate;
+mod query_filters;
+mod translate;
 
 #[allow(unused_imports)]
 pub use enum_common::*;
@@ -9,6 +11,12 @@ pub use enum_common::*;
 #[allow(unused_imports)]
 pub use enum_generate::*;
 
+#[allow(unused_imports)]
+pub use query_filters::*;
+
+#[allow(unused_imports)]
+pub use translate::*;
+
 #[cfg(feature = "database")]
 #[allow(unused_imports)]
 pub use enum_diesel_generate::*;
@@ -0,0 +1,191 @@
+#[macro_export]
+/// Declares a [FilterSchema](crate::http::extractors::filters::FilterSchema), the filters
+/// (with their type and allowed operators) and sort columns an endpoint accepts.
+///
+/// ```ignore
+/// generate_query_filters! {
+///     pub struct UserFilters {
+///         name: String [Eq, Like],
+///         age: i32 [Eq, Gte, Lte, In],
+///     }
+///     sort [created_at, name]
+/// }
+///
+/// async fn index(filters: Filters<UserFilters>) -> HttpResult { ... }
+/// ```
+macro_rules! generate_query_filters {
+    (
+        $vis:vis struct $name:ident {
+            $($field:ident : $ty:ty [$($op:ident),* $(,)?]),* $(,)?
+        }
+        sort [$($sort:ident),* $(,)?]
+    ) => {
+        $vis struct $name;
+
+        impl $crate::http::extractors::filters::FilterSchema for $name {
+            const FIELDS: &'static [$crate::http::extractors::filters::FilterField] = &[$(
+                $crate::http::extractors::filters::FilterField {
+                    name: stringify!($field),
+                    ops: &[$($crate::http::extractors::filters::FilterOp::$op),*],
+                    validate: |value| value.parse::<$ty>().is_ok(),
+                }
+            ),*];
+
+            const SORTABLE: &'static [&'static str] = &[$(stringify!($sort)),*];
+        }
+    };
+}
+
+#[macro_export]
+/// Same as [generate_query_filters], every filter and sort column is also bound to a Diesel
+/// column so that the filters can be applied to the table's boxed query.
+/// Values are always sent as bind parameters.
+///
+/// ```ignore
+/// generate_diesel_query_filters! {
+///     pub struct UserFilters for schema::users {
+///         name: String => schema::users::name [Eq, Like],
+///         age: i32 => schema::users::age [Eq, Gte, In],
+///     }
+///     sort {
+///         created_at => schema::users::created_at,
+///         name => schema::users::name,
+///     }
+/// }
+///
+/// let query = UserFilters::apply(&filters, schema::users::table.into_boxed());
+/// ```
+macro_rules! generate_diesel_query_filters {
+    (
+        $vis:vis struct $name:ident for $($table:ident)::+ {
+            $($field:ident : $ty:ty => $column:path [$($op:ident),* $(,)?]),* $(,)?
+        }
+        sort { $($sort:ident => $sort_column:path),* $(,)? }
+    ) => {
+        $crate::generate_query_filters! {
+            $vis struct $name {
+                $($field : $ty [$($op),*]),*
+            }
+            sort [$($sort),*]
+        }
+
+        impl $name {
+            #[allow(unreachable_patterns)]
+            pub fn apply<'a>(
+                filters: &$crate::http::extractors::filters::Filters<$name>,
+                mut query: $($table)::+::BoxedQuery<'a, diesel::pg::Pg>,
+            ) -> $($table)::+::BoxedQuery<'a, diesel::pg::Pg> {
+                use diesel::prelude::*;
+                use $crate::http::extractors::filters::{FilterOp, SortDirection};
+
+                for filter in filters.filters() {
+                    query = match filter.field {
+                        $(stringify!($field) => match filter.op {
+                            $(FilterOp::$op => $crate::apply_diesel_query_filter!(
+                                $op, query, $column, $ty, filter
+                            ),)*
+                            _ => query,
+                        },)*
+                        _ => query,
+                    };
+                }
+
+                for sort in filters.sort() {
+                    query = match (sort.column, sort.direction) {
+                        $(
+                            (stringify!($sort), SortDirection::Asc) => {
+                                query.then_order_by(($sort_column).asc())
+                            }
+                            (stringify!($sort), SortDirection::Desc) => {
+                                query.then_order_by(($sort_column).desc())
+                            }
+                        )*
+                        _ => query,
+                    };
+                }
+
+                query
+            }
+        }
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! apply_diesel_query_filter {
+    (Eq, $query:ident, $column:path, $ty:ty, $filter:ident) => {
+        match $filter.first::<$ty>() {
+            Some(value) => $query.filter(($column).eq(value)),
+            None => $query,
+        }
+    };
+    (In, $query:ident, $column:path, $ty:ty, $filter:ident) => {
+        $query.filter(($column).eq_any($filter.parsed::<$ty>()))
+    };
+    (Gte, $query:ident, $column:path, $ty:ty, $filter:ident) => {
+        match $filter.first::<$ty>() {
+            Some(value) => $query.filter(($column).ge(value)),
+            None => $query,
+        }
+    };
+    (Lte, $query:ident, $column:path, $ty:ty, $filter:ident) => {
+        match $filter.first::<$ty>() {
+            Some(value) => $query.filter(($column).le(value)),
+            None => $query,
+        }
+    };
+    (Like, $query:ident, $column:path, $ty:ty, $filter:ident) => {
+        match $filter.values.first() {
+            Some(value) => $query.filter(($column).ilike(format!(
+                "%{}%",
+                $crate::http::extractors::filters::escape_like(value)
+            ))),
+            None => $query,
+        }
+    };
+}
+
+#[cfg(all(test, feature = "database"))]
+mod tests {
+    use diesel::pg::Pg;
+    use diesel::prelude::*;
+
+    use crate::http::extractors::filters::Filters;
+
+    diesel::table! {
+        users (id) {
+            id -> Int4,
+            name -> Text,
+            age -> Int4,
+            created_at -> Timestamp,
+        }
+    }
+
+    generate_diesel_query_filters! {
+        struct UserFilters for users {
+            name: String => users::name [Eq, Like],
+            age: i32 => users::age [Gte, In],
+        }
+        sort {
+            created_at => users::created_at,
+        }
+    }
+
+    #[test]
+    fn test_apply() {
+        let filters =
+            Filters::<UserFilters>::parse("name[like]=ad_a&age[gte]=18&sort=-created_at").unwrap();
+
+        let query = UserFilters::apply(&filters, users::table.into_boxed());
+        let sql = diesel::debug_query::<Pg, _>(&query).to_string();
+
+        assert!(sql.contains(r#""users"."name" ILIKE $1"#), "{}", sql);
+        assert!(sql.contains(r#""users"."age" >= $2"#), "{}", sql);
+        assert!(
+            sql.contains(r#"ORDER BY "users"."created_at" DESC"#),
+            "{}",
+            sql
+        );
+        assert!(sql.contains(r#"["%ad\\_a%", 18]"#), "{}", sql);
+    }
+}
@@ -0,0 +1,15 @@
+#[macro_export]
+/// Translates a message key in the locale of the current request, see [crate::i18n]
+///
+/// ```ignore
+/// t!("errors.unauthorized");
+/// t!("errors.entity_not_found", entity = "order");
+/// ```
+macro_rules! t {
+    ($key:expr) => {
+        $crate::i18n::translate($key, &[])
+    };
+    ($key:expr, $($name:ident = $value:expr),+ $(,)?) => {
+        $crate::i18n::translate($key, &[$((stringify!($name), $value.to_string())),+])
+    };
+}
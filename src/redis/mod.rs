@@ -2,18 +2,21 @@ use crate::prelude::{AppMessage, AppResult, OnceLockHelper};
 use crate::redis::conn::establish_redis_connection;
 use crate::results::redis_result::RedisResultToAppResult;
 use crate::MEDULLAH;
-use futures_util::StreamExt;
+use futures_util::{Stream, StreamExt};
 use log::{error, info};
 use redis::{AsyncCommands, FromRedisValue};
 use serde::Serialize;
 use std::future::Future;
 use std::num::{NonZeroU64, NonZeroUsize};
+use std::pin::Pin;
 use std::time::Duration;
 use tokio::runtime::Handle;
 use tokio::time;
 
 pub mod conn;
 
+pub type RedisMessageStream = Pin<Box<dyn Stream<Item = AppResult<String>> + Send>>;
+
 pub struct Redis {
     pool: deadpool_redis::Pool,
 }
@@ -40,6 +43,42 @@ impl Redis {
         conn.set(key, content).await.into_app_result()
     }
 
+    /// Set a value that expires after the given number of seconds
+    pub async fn set_ex<T: Serialize>(
+        &self,
+        key: &str,
+        value: &T,
+        seconds: u64,
+    ) -> AppResult<String> {
+        let content = serde_json::to_string(value)?;
+        let mut conn = self.redis().await?;
+        conn.set_ex(key, content, seconds).await.into_app_result()
+    }
+
+    /// Set a value only if the key does not exist yet, the key expires after the given number of seconds
+    ///
+    /// returns `true` when the value was set
+    pub async fn set_nx_ex<T: Serialize>(
+        &self,
+        key: &str,
+        value: &T,
+        seconds: u64,
+    ) -> AppResult<bool> {
+        let content = serde_json::to_string(value)?;
+        let mut conn = self.redis().await?;
+        let result: Option<String> = redis::cmd("SET")
+            .arg(key)
+            .arg(content)
+            .arg("NX")
+            .arg("EX")
+            .arg(seconds)
+            .query_async(&mut *conn)
+            .await
+            .into_app_result()?;
+
+        Ok(result.is_some())
+    }
+
     pub async fn get<T: FromRedisValue>(&self, key: &str) -> AppResult<T> {
         let mut conn = self.redis().await?;
         conn.get(key).await.into_app_result()
@@ -50,6 +89,34 @@ impl Redis {
         conn.del(key).await.into_app_result()
     }
 
+    /// Delete every key starting with the given prefix, returns number of deleted keys
+    pub async fn delete_by_prefix(&self, prefix: &str) -> AppResult<i32> {
+        let pattern = prefix.chars().fold(String::new(), |mut acc, ch| {
+            // escape glob-style characters, the prefix must be matched literally
+            if matches!(ch, '*' | '?' | '[' | ']' | '\\') {
+                acc.push('\\');
+            }
+            acc.push(ch);
+            acc
+        });
+
+        let mut conn = self.redis().await?;
+        let keys: Vec<String> = {
+            let iter = conn
+                .scan_match::<_, String>(format!("{}*", pattern))
+                .await
+                .into_app_result()?;
+
+            iter.collect().await
+        };
+
+        if keys.is_empty() {
+            return Ok(0);
+        }
+
+        conn.del(keys).await.into_app_result()
+    }
+
     pub async fn publish<T: Serialize>(&self, channel: &str, data: &T) -> AppResult<i32> {
         let content = serde_json::to_string(data)?;
         let mut conn = self.redis().await?;
@@ -144,6 +211,12 @@ impl Redis {
         conn.lrem(key, count, content).await.into_app_result()
     }
 
+    /// Trim a list to the elements between `start` and `stop` (inclusive)
+    pub async fn ltrim(&self, key: &str, start: isize, stop: isize) -> AppResult<()> {
+        let mut conn = self.redis().await?;
+        conn.ltrim(key, start, stop).await.into_app_result()
+    }
+
     /// Flush all keys in the database
     pub async fn flush_all(&self) -> AppResult<()> {
         let mut conn = self.redis().await?;
@@ -220,17 +293,11 @@ impl Redis {
         F: FnMut(AppResult<String>) -> Fut + Copy + Send + 'static,
         Fut: Future<Output = AppResult<()>> + Send + 'static,
     {
-        let client = establish_redis_connection(&MEDULLAH.app().app_env_prefix);
-        let mut pubsub = client.get_async_pubsub().await?;
-        info!("[subscriber] subscribing to: {}", channel);
-
-        pubsub.subscribe(&[channel.clone()]).await?;
-        let mut stream = pubsub.into_on_message();
+        let mut stream = Self::subscribe_stream(&channel).await?;
 
-        while let Some(msg) = stream.next().await {
+        while let Some(received) = stream.next().await {
             let channel_clone = channel.clone();
             Handle::current().spawn(async move {
-                let received = msg.get_payload::<String>().into_app_result();
                 if let Err(err) = func(received).await {
                     error!("[subscriber][{}] executor error: {:?}", channel_clone, err);
                 }
@@ -239,4 +306,22 @@ impl Redis {
 
         Ok(())
     }
+
+    /// Subscribes to a Redis channel, returning the received messages as a stream
+    ///
+    /// The subscription ends when the stream is dropped
+    ///
+    /// **Note:** this method will establish new redis connection
+    pub async fn subscribe_stream(channel: &str) -> AppResult<RedisMessageStream> {
+        let client = establish_redis_connection(&MEDULLAH.app().app_env_prefix);
+        let mut pubsub = client.get_async_pubsub().await?;
+        info!("[subscriber] subscribing to: {}", channel);
+
+        pubsub.subscribe(channel).await?;
+        let stream = pubsub
+            .into_on_message()
+            .map(|msg| msg.get_payload::<String>().into_app_result());
+
+        Ok(Box::pin(stream))
+    }
 }
@@ -0,0 +1,87 @@
+use std::path::{Path, PathBuf};
+
+use tokio::fs::{create_dir_all, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+use crate::reporting::{ErrorReport, ErrorSink, ReportFuture};
+
+/// Appends reports to a file, one json document per line
+pub struct FileSink {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl FileSink {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        FileSink {
+            path: path.into(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl ErrorSink for FileSink {
+    fn name(&self) -> &str {
+        "file"
+    }
+
+    fn send<'a>(&'a self, report: &'a ErrorReport) -> ReportFuture<'a> {
+        Box::pin(async move {
+            let mut line = serde_json::to_vec(report)?;
+            line.push(b'\n');
+
+            // keeps lines of concurrent reports from interleaving
+            let _guard = self.lock.lock().await;
+            if let Some(parent) = self.path.parent() {
+                create_dir_all(parent).await?;
+            }
+
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+                .await?;
+
+            file.write_all(&line).await?;
+            // tokio files write in the background, the report must be on disk once sent
+            file.flush().await?;
+            Ok(())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ntex::http::StatusCode;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_send() {
+        let path = std::env::temp_dir()
+            .join(format!("medullah-{}", uuid::Uuid::new_v4()))
+            .join("errors.log");
+
+        let sink = FileSink::new(&path);
+        let first = ErrorReport::new(StatusCode::INTERNAL_SERVER_ERROR, "010", "boom", vec![]);
+        let second = ErrorReport::new(StatusCode::BAD_GATEWAY, "023", "bang", vec![]);
+        sink.send(&first).await.unwrap();
+        sink.send(&second).await.unwrap();
+
+        let content = tokio::fs::read_to_string(&path).await.unwrap();
+        let lines: Vec<ErrorReport> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+
+        assert_eq!(lines, vec![first, second]);
+        tokio::fs::remove_dir_all(path.parent().unwrap())
+            .await
+            .unwrap();
+    }
+}
@@ -0,0 +1,550 @@
+use std::collections::HashMap;
+use std::env;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Utc};
+use log::{debug, error};
+use ntex::http::body::{Body, ResponseBody};
+use ntex::http::header::CONTENT_LENGTH;
+use ntex::http::{Response, StatusCode};
+use ntex::web::HttpRequest;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use sha2::{Digest, Sha256};
+use uuid::Uuid;
+
+use crate::helpers::request::RequestHelper;
+use crate::results::AppResult;
+use crate::MEDULLAH;
+
+pub mod file;
+#[cfg(feature = "rabbitmq")]
+pub mod rabbitmq;
+#[cfg(feature = "redis")]
+pub mod redis;
+#[cfg(feature = "reqwest")]
+pub mod webhook;
+
+pub use file::FileSink;
+#[cfg(feature = "rabbitmq")]
+pub use rabbitmq::RabbitMqSink;
+#[cfg(feature = "redis")]
+pub use redis::RedisSink;
+#[cfg(feature = "reqwest")]
+pub use webhook::WebhookSink;
+
+pub type ReportFuture<'a> = Pin<Box<dyn Future<Output = AppResult<()>> + Send + 'a>>;
+
+/// A server error, as logged and handed to the [ErrorSink]s
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ErrorReport {
+    /// returned to the client as `error_id`
+    pub id: String,
+    pub status: u16,
+    pub code: String,
+    pub message: String,
+    /// the error followed by its sources
+    pub chain: Vec<String>,
+    /// identical errors (same status, code, message and route pattern) share a fingerprint,
+    /// stable across replicas
+    pub fingerprint: String,
+    pub request: Option<ErrorRequestContext>,
+    pub occurred_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct ErrorRequestContext {
+    pub request_id: Option<String>,
+    pub method: String,
+    pub path: String,
+    /// matched route pattern, e.g. `/orders/{id}`
+    #[serde(default)]
+    pub route: Option<String>,
+    pub ip: Option<String>,
+    pub user_agent: Option<String>,
+}
+
+/// Destination of error reports
+pub trait ErrorSink: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn send<'a>(&'a self, report: &'a ErrorReport) -> ReportFuture<'a>;
+}
+
+/// Limits applied to a single sink
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SinkPolicy {
+    /// at most `n` reports per window, extra reports are dropped
+    pub rate_limit: Option<(u32, Duration)>,
+    /// reports sharing a fingerprint with one sent within the window are dropped
+    pub dedup_window: Option<Duration>,
+}
+
+/// Sends server errors to the registered sinks, in the background
+#[derive(Default)]
+pub struct ErrorReporter {
+    sinks: RwLock<Vec<Arc<PolicedSink>>>,
+}
+
+struct PolicedSink {
+    sink: Box<dyn ErrorSink>,
+    policy: SinkPolicy,
+    state: Mutex<SinkState>,
+}
+
+#[derive(Default)]
+struct SinkState {
+    window_started_at: Option<Instant>,
+    sent_in_window: u32,
+    last_seen: HashMap<String, Instant>,
+}
+
+impl ErrorReport {
+    pub fn new(status: StatusCode, code: &str, message: &str, chain: Vec<String>) -> Self {
+        let mut report = ErrorReport {
+            id: Uuid::new_v4().to_string(),
+            status: status.as_u16(),
+            code: code.to_string(),
+            message: message.to_string(),
+            chain,
+            fingerprint: String::new(),
+            request: None,
+            occurred_at: Utc::now(),
+        };
+
+        report.fingerprint = report.compute_fingerprint();
+        report
+    }
+
+    pub fn with_request(self, req: &HttpRequest) -> Self {
+        self.with_request_context(ErrorRequestContext {
+            request_id: req.request_id(),
+            method: req.method().to_string(),
+            path: req.path().to_string(),
+            route: route_pattern(req),
+            ip: req.ip(),
+            user_agent: req.user_agent(),
+        })
+    }
+
+    pub fn with_request_context(mut self, context: ErrorRequestContext) -> Self {
+        self.request = Some(context);
+        self.fingerprint = self.compute_fingerprint();
+        self
+    }
+
+    pub fn log(&self) {
+        let (request_id, route) = match &self.request {
+            Some(ctx) => (
+                ctx.request_id.as_deref().unwrap_or("-"),
+                format!("{} {}", ctx.method, ctx.path),
+            ),
+            None => ("-", "-".to_string()),
+        };
+
+        error!(
+            "[error-report][{}][{}] {} {} ({}): {}",
+            self.id,
+            request_id,
+            route,
+            self.status,
+            self.code,
+            self.chain.join(" <- ")
+        );
+    }
+
+    fn compute_fingerprint(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.status.to_be_bytes());
+        let route = self
+            .request
+            .as_ref()
+            .map(|ctx| (ctx.method.as_str(), ctx.route.as_ref().unwrap_or(&ctx.path)));
+
+        let mut parts = vec![self.code.as_str(), self.message.as_str()];
+        if let Some((method, route)) = route {
+            parts.extend([method, route.as_str()]);
+        }
+
+        for part in parts {
+            hasher.update(part.as_bytes());
+            hasher.update([0]);
+        }
+
+        hex::encode(hasher.finalize())
+    }
+}
+
+/// Path of the request with its matched segments replaced by their names,
+/// so that `/orders/1` and `/orders/2` are reported as `/orders/{id}`
+fn route_pattern(req: &HttpRequest) -> Option<String> {
+    let params = req.match_info();
+    if params.is_empty() {
+        return None;
+    }
+
+    let mut params = params.iter().collect::<Vec<_>>();
+    let route = req
+        .path()
+        .split('/')
+        .map(
+            |segment| match params.iter().position(|(_, value)| *value == segment) {
+                Some(index) => format!("{{{}}}", params.remove(index).0),
+                None => segment.to_string(),
+            },
+        )
+        .collect::<Vec<_>>()
+        .join("/");
+
+    Some(route)
+}
+
+impl SinkPolicy {
+    pub fn rate_limit(mut self, max: u32, per: Duration) -> Self {
+        self.rate_limit = Some((max, per));
+        self
+    }
+
+    pub fn dedup(mut self, window: Duration) -> Self {
+        self.dedup_window = Some(window);
+        self
+    }
+
+    /// Reads `{PREFIX}_ERROR_REPORT_RATE_LIMIT` (reports per minute)
+    /// and `{PREFIX}_ERROR_REPORT_DEDUP_WINDOW` (in seconds)
+    pub fn from_env(env_prefix: &str) -> Self {
+        let var = |name: &str| {
+            env::var(format!("{}_ERROR_REPORT_{}", env_prefix, name))
+                .ok()
+                .and_then(|v| v.parse::<u64>().ok())
+        };
+
+        SinkPolicy {
+            rate_limit: var("RATE_LIMIT").map(|max| (max as u32, Duration::from_secs(60))),
+            dedup_window: var("DEDUP_WINDOW").map(Duration::from_secs),
+        }
+    }
+}
+
+impl ErrorReporter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_sink<S: ErrorSink + 'static>(&self, sink: S, policy: SinkPolicy) {
+        self.sinks.write().unwrap().push(Arc::new(PolicedSink {
+            sink: Box::new(sink),
+            policy,
+            state: Mutex::new(SinkState::default()),
+        }));
+    }
+
+    pub fn has_sinks(&self) -> bool {
+        !self.sinks.read().unwrap().is_empty()
+    }
+
+    /// Hands the report to the sinks without waiting for them,
+    /// reports are dropped when there's no runtime to send them from
+    pub fn report(&self, report: ErrorReport) {
+        let sinks = self.admitted_sinks(&report);
+        if sinks.is_empty() {
+            return;
+        }
+
+        match tokio::runtime::Handle::try_current() {
+            Ok(handle) => {
+                handle.spawn(async move {
+                    for sink in sinks {
+                        sink.send(&report).await;
+                    }
+                });
+            }
+            Err(_) => debug!("[error-report][{}] no runtime, report dropped", report.id),
+        }
+    }
+
+    /// Sends the report to the sinks, returns the number of sinks it was sent to
+    pub async fn dispatch(&self, report: &ErrorReport) -> usize {
+        let sinks = self.admitted_sinks(report);
+        let mut sent = 0;
+        for sink in sinks {
+            if sink.send(report).await {
+                sent += 1;
+            }
+        }
+
+        sent
+    }
+
+    fn admitted_sinks(&self, report: &ErrorReport) -> Vec<Arc<PolicedSink>> {
+        self.sinks
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|sink| sink.admit(report, Instant::now()))
+            .cloned()
+            .collect()
+    }
+}
+
+impl PolicedSink {
+    fn admit(&self, report: &ErrorReport, now: Instant) -> bool {
+        let mut state = self.state.lock().unwrap();
+
+        if let Some(window) = self.policy.dedup_window {
+            state
+                .last_seen
+                .retain(|_, seen_at| now.duration_since(*seen_at) < window);
+
+            if state.last_seen.contains_key(&report.fingerprint) {
+                debug!(
+                    "[error-report][{}] duplicate report {} skipped",
+                    self.sink.name(),
+                    report.id
+                );
+                return false;
+            }
+        }
+
+        if let Some((max, per)) = self.policy.rate_limit {
+            let window_is_over = state
+                .window_started_at
+                .is_none_or(|started_at| now.duration_since(started_at) >= per);
+
+            if window_is_over {
+                state.window_started_at = Some(now);
+                state.sent_in_window = 0;
+            }
+
+            if state.sent_in_window >= max {
+                debug!(
+                    "[error-report][{}] rate limited, report {} skipped",
+                    self.sink.name(),
+                    report.id
+                );
+                return false;
+            }
+
+            state.sent_in_window += 1;
+        }
+
+        if self.policy.dedup_window.is_some() {
+            state.last_seen.insert(report.fingerprint.clone(), now);
+        }
+
+        true
+    }
+
+    async fn send(&self, report: &ErrorReport) -> bool {
+        match self.sink.send(report).await {
+            Ok(_) => true,
+            Err(err) => {
+                error!(
+                    "[error-report][{}] failed to send report {}: {:?}",
+                    self.sink.name(),
+                    report.id,
+                    err
+                );
+                false
+            }
+        }
+    }
+}
+
+/// Logs the report and hands it to the app's sinks
+pub(crate) fn report(report: ErrorReport) {
+    report.log();
+    if let Some(app) = MEDULLAH.get() {
+        app.services.errors.report(report);
+    }
+}
+
+/// Adds the error id to a json envelope response, other responses are returned untouched
+pub(crate) fn attach_error_id(response: Response, error_id: &str) -> Response {
+    let envelope = match response.body() {
+        ResponseBody::Body(Body::Bytes(bytes)) => {
+            serde_json::from_slice::<Map<String, Value>>(bytes).ok()
+        }
+        _ => None,
+    };
+
+    match envelope {
+        Some(mut envelope) => {
+            envelope.insert("error_id".to_string(), Value::String(error_id.to_string()));
+
+            let mut response = response.set_body(Body::from(
+                serde_json::to_vec(&envelope).unwrap_or_default(),
+            ));
+            response.headers_mut().remove(CONTENT_LENGTH);
+            response
+        }
+        None => response,
+    }
+}
+
+/// Reporter with the sinks listed in `{PREFIX}_ERROR_REPORT_SINKS` (comma separated), all sharing
+/// the [SinkPolicy::from_env] policy:
+/// - `file`: json lines appended to `{PREFIX}_ERROR_REPORT_FILE` (`storage/errors.log` by default)
+/// - `redis`: pushed to the `{PREFIX}_ERROR_REPORT_REDIS_LIST` list (`errors` by default)
+/// - `rabbitmq`: published to `{PREFIX}_ERROR_REPORT_EXCHANGE` with the
+///   `{PREFIX}_ERROR_REPORT_ROUTING_KEY` routing key (`errors` by default)
+/// - `webhook`: posted to `{PREFIX}_ERROR_REPORT_WEBHOOK_URL`
+pub(crate) fn make_error_reporter(
+    env_prefix: &str,
+    #[cfg(feature = "redis")] redis: Arc<crate::redis::Redis>,
+    #[cfg(feature = "rabbitmq")] rabbitmq: Arc<tokio::sync::Mutex<crate::rabbitmq::RabbitMQ>>,
+) -> Arc<ErrorReporter> {
+    let var = |name: &str| env::var(format!("{}_ERROR_REPORT_{}", env_prefix, name)).ok();
+
+    let reporter = ErrorReporter::new();
+    let policy = SinkPolicy::from_env(env_prefix);
+    let sinks = var("SINKS").unwrap_or_default();
+
+    for sink in sinks.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        match sink {
+            "file" => reporter.add_sink(
+                FileSink::new(crate::helpers::fs::base_path(
+                    var("FILE").unwrap_or_else(|| "storage/errors.log".to_string()),
+                )),
+                policy,
+            ),
+            #[cfg(feature = "redis")]
+            "redis" => reporter.add_sink(
+                RedisSink::new(
+                    redis.clone(),
+                    &var("REDIS_LIST").unwrap_or_else(|| "errors".to_string()),
+                ),
+                policy,
+            ),
+            #[cfg(feature = "rabbitmq")]
+            "rabbitmq" => reporter.add_sink(
+                RabbitMqSink::new(
+                    rabbitmq.clone(),
+                    &var("EXCHANGE").expect("error report exchange is not configured"),
+                    &var("ROUTING_KEY").unwrap_or_else(|| "errors".to_string()),
+                ),
+                policy,
+            ),
+            #[cfg(feature = "reqwest")]
+            "webhook" => reporter.add_sink(
+                WebhookSink::new(
+                    &var("WEBHOOK_URL").expect("error report webhook is not configured"),
+                ),
+                policy,
+            ),
+            sink => panic!("unsupported error report sink '{}'", sink),
+        }
+    }
+
+    Arc::new(reporter)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use ntex::web::test::TestRequest;
+
+    use super::*;
+    use crate::helpers::responder::Responder;
+
+    #[derive(Clone, Default)]
+    struct CountingSink(Arc<AtomicUsize>);
+
+    impl ErrorSink for CountingSink {
+        fn name(&self) -> &str {
+            "counting"
+        }
+
+        fn send<'a>(&'a self, _report: &'a ErrorReport) -> ReportFuture<'a> {
+            Box::pin(async move {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            })
+        }
+    }
+
+    fn report(message: &str) -> ErrorReport {
+        ErrorReport::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "010",
+            message,
+            vec![message.to_string()],
+        )
+    }
+
+    #[tokio::test]
+    async fn test_dedup() {
+        let sink = CountingSink::default();
+        let reporter = ErrorReporter::new();
+        reporter.add_sink(
+            sink.clone(),
+            SinkPolicy::default().dedup(Duration::from_secs(60)),
+        );
+
+        assert_eq!(reporter.dispatch(&report("db is down")).await, 1);
+        assert_eq!(reporter.dispatch(&report("db is down")).await, 0);
+        assert_eq!(reporter.dispatch(&report("disk is full")).await, 1);
+        assert_eq!(sink.0.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit() {
+        let sink = CountingSink::default();
+        let reporter = ErrorReporter::new();
+        reporter.add_sink(
+            sink.clone(),
+            SinkPolicy::default().rate_limit(2, Duration::from_secs(60)),
+        );
+
+        for i in 0..5 {
+            reporter.dispatch(&report(&format!("error {}", i))).await;
+        }
+
+        assert_eq!(sink.0.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        let context = ErrorRequestContext {
+            method: "GET".to_string(),
+            path: "/orders".to_string(),
+            ..Default::default()
+        };
+
+        let a = report("db is down").with_request_context(context.clone());
+        let b = report("db is down").with_request_context(context);
+        assert_ne!(a.id, b.id);
+        assert_eq!(a.fingerprint, b.fingerprint);
+        assert_ne!(a.fingerprint, report("db is down").fingerprint);
+        assert_eq!(a.fingerprint.len(), 64);
+
+        // different ids of the same route share the fingerprint
+        let request = |id: &'static str| {
+            TestRequest::with_uri(&format!("/orders/{}/items", id))
+                .param("id", id)
+                .to_http_request()
+        };
+
+        let first = report("db is down").with_request(&request("1"));
+        let second = report("db is down").with_request(&request("2"));
+        let route = first.request.as_ref().and_then(|ctx| ctx.route.as_deref());
+        assert_eq!(route, Some("/orders/{id}/items"));
+        assert_eq!(first.fingerprint, second.fingerprint);
+    }
+
+    #[test]
+    fn test_attach_error_id() {
+        let response = attach_error_id(Responder::internal_server_error(), "abc");
+        let body: Value = match response.body() {
+            ResponseBody::Body(Body::Bytes(bytes)) => serde_json::from_slice(bytes).unwrap(),
+            _ => panic!("expected a bytes body"),
+        };
+
+        assert_eq!(body["error_id"], "abc");
+        assert_eq!(body["code"], "010");
+    }
+}
@@ -0,0 +1,40 @@
+use std::sync::Arc;
+
+use tokio::sync::Mutex;
+
+use crate::rabbitmq::RabbitMQ;
+use crate::reporting::{ErrorReport, ErrorSink, ReportFuture};
+
+/// Publishes reports (as json) to a RabbitMQ exchange
+pub struct RabbitMqSink {
+    rabbitmq: Arc<Mutex<RabbitMQ>>,
+    exchange: String,
+    routing_key: String,
+}
+
+impl RabbitMqSink {
+    pub fn new(rabbitmq: Arc<Mutex<RabbitMQ>>, exchange: &str, routing_key: &str) -> Self {
+        RabbitMqSink {
+            rabbitmq,
+            exchange: exchange.to_string(),
+            routing_key: routing_key.to_string(),
+        }
+    }
+}
+
+impl ErrorSink for RabbitMqSink {
+    fn name(&self) -> &str {
+        "rabbitmq"
+    }
+
+    fn send<'a>(&'a self, report: &'a ErrorReport) -> ReportFuture<'a> {
+        Box::pin(async move {
+            let payload = serde_json::to_vec(report)?;
+            self.rabbitmq
+                .lock()
+                .await
+                .publish(&self.exchange, &self.routing_key, &payload)
+                .await
+        })
+    }
+}
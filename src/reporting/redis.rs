@@ -0,0 +1,39 @@
+use std::sync::Arc;
+
+use crate::redis::Redis;
+use crate::reporting::{ErrorReport, ErrorSink, ReportFuture};
+
+/// Pushes reports to the head of a Redis list, the list is capped to `max_len` reports
+pub struct RedisSink {
+    redis: Arc<Redis>,
+    list: String,
+    max_len: isize,
+}
+
+impl RedisSink {
+    pub fn new(redis: Arc<Redis>, list: &str) -> Self {
+        RedisSink {
+            redis,
+            list: list.to_string(),
+            max_len: 1000,
+        }
+    }
+
+    pub fn max_len(mut self, max_len: isize) -> Self {
+        self.max_len = max_len;
+        self
+    }
+}
+
+impl ErrorSink for RedisSink {
+    fn name(&self) -> &str {
+        "redis"
+    }
+
+    fn send<'a>(&'a self, report: &'a ErrorReport) -> ReportFuture<'a> {
+        Box::pin(async move {
+            self.redis.queue(&self.list, report).await?;
+            self.redis.ltrim(&self.list, 0, self.max_len - 1).await
+        })
+    }
+}
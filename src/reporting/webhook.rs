@@ -0,0 +1,158 @@
+use std::time::Duration;
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::Client;
+
+use crate::helpers::reqwest::ReqwestResponseError;
+use crate::prelude::AppMessage;
+use crate::reporting::{ErrorReport, ErrorSink, ReportFuture};
+
+/// Posts reports (as json) to a webhook
+pub struct WebhookSink {
+    url: String,
+    headers: HeaderMap,
+    timeout: Duration,
+    client: Client,
+}
+
+impl WebhookSink {
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+    pub fn new(url: &str) -> Self {
+        WebhookSink {
+            url: url.to_string(),
+            headers: HeaderMap::new(),
+            timeout: Self::DEFAULT_TIMEOUT,
+            client: Client::new(),
+        }
+    }
+
+    /// How long a report may take to be delivered, a hanging webhook would otherwise
+    /// pile up background tasks
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Header sent along every report, e.g. an authorization token
+    pub fn header(mut self, name: &'static str, value: &str) -> Self {
+        self.headers.insert(
+            HeaderName::from_static(name),
+            HeaderValue::from_str(value).expect("invalid header value"),
+        );
+        self
+    }
+}
+
+impl ErrorSink for WebhookSink {
+    fn name(&self) -> &str {
+        "webhook"
+    }
+
+    fn send<'a>(&'a self, report: &'a ErrorReport) -> ReportFuture<'a> {
+        Box::pin(async move {
+            let response = self
+                .client
+                .post(&self.url)
+                .headers(self.headers.clone())
+                .timeout(self.timeout)
+                .json(report)
+                .send()
+                .await?;
+
+            match response.status().is_success() {
+                true => Ok(()),
+                false => Err(AppMessage::ReqwestResponseError(
+                    ReqwestResponseError::create(response.status(), response.text().await?),
+                )),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ntex::http::StatusCode;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use super::*;
+
+    /// Accepts a single request, answers with `status` and returns the raw request
+    async fn stub(status: &'static str) -> (String, tokio::task::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hooks/errors", listener.local_addr().unwrap());
+
+        let handle = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut request = vec![];
+            let mut buffer = [0u8; 4096];
+
+            loop {
+                let read = stream.read(&mut buffer).await.unwrap();
+                request.extend_from_slice(&buffer[..read]);
+
+                let raw = String::from_utf8_lossy(&request).to_string();
+                if let Some((head, body)) = raw.split_once("\r\n\r\n") {
+                    let length = head
+                        .lines()
+                        .find_map(|line| {
+                            line.to_lowercase()
+                                .strip_prefix("content-length:")
+                                .map(|v| v.trim().parse::<usize>().unwrap())
+                        })
+                        .unwrap_or(0);
+
+                    if body.len() >= length {
+                        break;
+                    }
+                }
+            }
+
+            let response = format!("HTTP/1.1 {}\r\ncontent-length: 0\r\n\r\n", status);
+            stream.write_all(response.as_bytes()).await.unwrap();
+            String::from_utf8_lossy(&request).to_string()
+        });
+
+        (url, handle)
+    }
+
+    #[tokio::test]
+    async fn test_send() {
+        let (url, request) = stub("200 OK").await;
+        let report = ErrorReport::new(StatusCode::INTERNAL_SERVER_ERROR, "010", "boom", vec![]);
+
+        let sink = WebhookSink::new(&url).header("x-webhook-token", "secret");
+        sink.send(&report).await.unwrap();
+
+        let request = request.await.unwrap();
+        assert!(request.starts_with("POST /hooks/errors HTTP/1.1"));
+        assert!(request.contains("x-webhook-token: secret"));
+
+        let body = request.split_once("\r\n\r\n").unwrap().1;
+        assert_eq!(serde_json::from_str::<ErrorReport>(body).unwrap(), report);
+    }
+
+    #[tokio::test]
+    async fn test_send_failure() {
+        let (url, _) = stub("503 Service Unavailable").await;
+        let report = ErrorReport::new(StatusCode::INTERNAL_SERVER_ERROR, "010", "boom", vec![]);
+
+        assert!(WebhookSink::new(&url).send(&report).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_send_timeout() {
+        // accepts the connection but never answers
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/hooks/errors", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            let (_stream, _) = listener.accept().await.unwrap();
+            tokio::time::sleep(Duration::from_secs(5)).await;
+        });
+
+        let report = ErrorReport::new(StatusCode::INTERNAL_SERVER_ERROR, "010", "boom", vec![]);
+        let sink = WebhookSink::new(&url).timeout(Duration::from_millis(100));
+        assert!(sink.send(&report).await.is_err());
+    }
+}
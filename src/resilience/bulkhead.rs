@@ -0,0 +1,168 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::warn;
+use serde::Serialize;
+use tokio::sync::Semaphore;
+
+use crate::prelude::{AppMessage, AppResult};
+use crate::resilience::{register_bulkhead, Rejection};
+
+/// Limits the number of concurrent calls to a dependency
+///
+/// Calls beyond the limit wait for up to `max_wait` for a slot, then fail with a `503`.
+/// Clones share their slots, bulkheads are registered by name and reported by the health check.
+///
+/// ```ignore
+/// static SMTP: LazyLock<Bulkhead> =
+///     LazyLock::new(|| Bulkhead::new("smtp", 10).max_wait(Duration::from_secs(2)));
+///
+/// SMTP.call(mailer.send()).await?;
+/// ```
+#[derive(Clone)]
+pub struct Bulkhead {
+    name: Arc<str>,
+    max_concurrent: usize,
+    max_wait: Duration,
+    slots: Arc<Semaphore>,
+    rejected: Arc<AtomicU64>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct BulkheadSnapshot {
+    pub name: String,
+    pub max_concurrent: usize,
+    pub in_flight: usize,
+    /// calls rejected since the bulkhead was created
+    pub rejected: u64,
+}
+
+impl Bulkhead {
+    pub fn new(name: &str, max_concurrent: usize) -> Self {
+        let bulkhead = Bulkhead {
+            name: Arc::from(name),
+            max_concurrent,
+            max_wait: Duration::ZERO,
+            slots: Arc::new(Semaphore::new(max_concurrent)),
+            rejected: Arc::new(AtomicU64::new(0)),
+        };
+
+        register_bulkhead(&bulkhead);
+        bulkhead
+    }
+
+    /// How long calls wait for a slot, calls are rejected right away by default
+    pub fn max_wait(mut self, max_wait: Duration) -> Self {
+        self.max_wait = max_wait;
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.max_concurrent - self.slots.available_permits()
+    }
+
+    pub fn available(&self) -> usize {
+        self.slots.available_permits()
+    }
+
+    pub fn snapshot(&self) -> BulkheadSnapshot {
+        BulkheadSnapshot {
+            name: self.name.to_string(),
+            max_concurrent: self.max_concurrent,
+            in_flight: self.in_flight(),
+            rejected: self.rejected.load(Ordering::Relaxed),
+        }
+    }
+
+    pub async fn call<F, T>(&self, call: F) -> AppResult<T>
+    where
+        F: Future<Output = AppResult<T>>,
+    {
+        let permit = match self.max_wait.is_zero() {
+            true => self.slots.try_acquire().ok(),
+            false => tokio::time::timeout(self.max_wait, self.slots.acquire())
+                .await
+                .ok()
+                .and_then(|permit| permit.ok()),
+        };
+
+        let _permit = match permit {
+            Some(permit) => permit,
+            None => {
+                self.rejected.fetch_add(1, Ordering::Relaxed);
+                warn!(
+                    "[bulkhead][{}] rejected a call, {} in flight",
+                    self.name,
+                    self.in_flight()
+                );
+
+                return Err(AppMessage::from(Rejection::BulkheadFull(
+                    self.name.to_string(),
+                )));
+            }
+        };
+
+        call.await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ntex::http::StatusCode;
+    use tokio::sync::oneshot;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn test_rejects_beyond_limit() {
+        let bulkhead = Bulkhead::new("test-bulkhead", 1);
+        let (release, released) = oneshot::channel::<()>();
+
+        let running = tokio::spawn({
+            let bulkhead = bulkhead.clone();
+            async move {
+                bulkhead
+                    .call(async {
+                        released.await.unwrap();
+                        Ok(())
+                    })
+                    .await
+            }
+        });
+
+        while bulkhead.in_flight() == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        let err = bulkhead.call(async { Ok(()) }).await.unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(bulkhead.snapshot().rejected, 1);
+
+        release.send(()).unwrap();
+        running.await.unwrap().unwrap();
+
+        assert_eq!(bulkhead.available(), 1);
+        bulkhead.call(async { Ok(()) }).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_waits_for_a_slot() {
+        let bulkhead = Bulkhead::new("test-bulkhead-wait", 1).max_wait(Duration::from_secs(5));
+
+        let first = bulkhead.call(async {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            Ok(1)
+        });
+        let second = bulkhead.call(async { Ok(2) });
+
+        let (first, second) = tokio::join!(first, second);
+        assert_eq!((first.unwrap(), second.unwrap()), (1, 2));
+        assert_eq!(bulkhead.snapshot().rejected, 0);
+    }
+}
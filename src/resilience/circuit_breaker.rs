@@ -0,0 +1,392 @@
+use std::collections::VecDeque;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use serde::Serialize;
+
+use crate::prelude::{AppMessage, AppResult};
+use crate::resilience::{register_circuit, Rejection};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    /// calls go through, outcomes are recorded
+    Closed,
+    /// calls fail fast until `open_for` has elapsed
+    Open,
+    /// a few trial calls go through, the circuit closes once they all succeed
+    HalfOpen,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct CircuitBreakerConfig {
+    /// failure rate (between 0 and 1) of the recorded calls opening the circuit
+    pub failure_rate: f64,
+    /// calls recorded before the failure rate is considered
+    pub minimum_calls: usize,
+    /// number of most recent calls the failure rate is computed on
+    pub window: usize,
+    pub open_for: Duration,
+    /// trial calls let through while half-open
+    pub half_open_calls: u32,
+    /// whether an error counts as a failure, server errors only by default
+    pub is_failure: fn(&AppMessage) -> bool,
+}
+
+/// Stops calling a failing dependency for a while
+///
+/// Clones share their state, breakers are registered by name and reported by the health check.
+///
+/// ```ignore
+/// static BILLING: LazyLock<CircuitBreaker> = LazyLock::new(|| CircuitBreaker::new("billing"));
+///
+/// let invoice = BILLING.call(billing.get("/invoices/1").send::<Invoice>()).await?;
+/// ```
+#[derive(Clone)]
+pub struct CircuitBreaker {
+    name: Arc<str>,
+    config: CircuitBreakerConfig,
+    inner: Arc<Mutex<Circuit>>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+pub struct CircuitSnapshot {
+    pub name: String,
+    pub state: CircuitState,
+    pub failure_rate: f64,
+    pub recorded_calls: usize,
+    /// seconds before an open circuit lets trial calls through
+    pub retry_in: Option<u64>,
+}
+
+struct Circuit {
+    state: CircuitState,
+    /// outcomes of the latest calls, `true` for failures
+    outcomes: VecDeque<bool>,
+    opened_at: Option<Instant>,
+    trials: u32,
+    trial_successes: u32,
+}
+
+/// Releases the trial slot of a call dropped before completing
+struct Trial<'a> {
+    breaker: &'a CircuitBreaker,
+    done: bool,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        CircuitBreakerConfig {
+            failure_rate: 0.5,
+            minimum_calls: 10,
+            window: 20,
+            open_for: Duration::from_secs(30),
+            half_open_calls: 3,
+            is_failure: |err| err.status_code().is_server_error(),
+        }
+    }
+}
+
+impl CircuitBreakerConfig {
+    pub fn failure_rate(mut self, rate: f64) -> Self {
+        self.failure_rate = rate;
+        self
+    }
+
+    pub fn minimum_calls(mut self, calls: usize) -> Self {
+        self.minimum_calls = calls;
+        self
+    }
+
+    pub fn window(mut self, calls: usize) -> Self {
+        self.window = calls;
+        self
+    }
+
+    pub fn open_for(mut self, duration: Duration) -> Self {
+        self.open_for = duration;
+        self
+    }
+
+    pub fn half_open_calls(mut self, calls: u32) -> Self {
+        self.half_open_calls = calls;
+        self
+    }
+
+    pub fn is_failure(mut self, is_failure: fn(&AppMessage) -> bool) -> Self {
+        self.is_failure = is_failure;
+        self
+    }
+}
+
+impl CircuitBreaker {
+    pub fn new(name: &str) -> Self {
+        Self::with_config(name, CircuitBreakerConfig::default())
+    }
+
+    pub fn with_config(name: &str, config: CircuitBreakerConfig) -> Self {
+        let breaker = CircuitBreaker {
+            name: Arc::from(name),
+            config,
+            inner: Arc::new(Mutex::new(Circuit {
+                state: CircuitState::Closed,
+                outcomes: VecDeque::with_capacity(config.window),
+                opened_at: None,
+                trials: 0,
+                trial_successes: 0,
+            })),
+        };
+
+        register_circuit(&breaker);
+        breaker
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn config(&self) -> &CircuitBreakerConfig {
+        &self.config
+    }
+
+    pub fn state(&self) -> CircuitState {
+        let mut circuit = self.inner.lock().unwrap();
+        self.refresh(&mut circuit);
+        circuit.state
+    }
+
+    pub fn snapshot(&self) -> CircuitSnapshot {
+        let mut circuit = self.inner.lock().unwrap();
+        self.refresh(&mut circuit);
+
+        let retry_in = match circuit.state {
+            CircuitState::Open => circuit
+                .opened_at
+                .map(|at| self.config.open_for.saturating_sub(at.elapsed()).as_secs()),
+            _ => None,
+        };
+
+        CircuitSnapshot {
+            name: self.name.to_string(),
+            state: circuit.state,
+            failure_rate: failure_rate(&circuit.outcomes),
+            recorded_calls: circuit.outcomes.len(),
+            retry_in,
+        }
+    }
+
+    /// Runs the call unless the circuit is open, in which case it fails with a `503`
+    pub async fn call<F, T>(&self, call: F) -> AppResult<T>
+    where
+        F: Future<Output = AppResult<T>>,
+    {
+        let is_trial = self.acquire()?;
+        let mut trial = Trial {
+            breaker: self,
+            done: !is_trial,
+        };
+
+        let result = call.await;
+        let failed = result
+            .as_ref()
+            .is_err_and(|err| (self.config.is_failure)(err));
+
+        trial.done = true;
+        self.record(failed, is_trial);
+        result
+    }
+
+    /// Closes the circuit and forgets the recorded calls
+    pub fn reset(&self) {
+        let mut circuit = self.inner.lock().unwrap();
+        circuit.state = CircuitState::Closed;
+        circuit.outcomes.clear();
+        circuit.opened_at = None;
+        circuit.trials = 0;
+        circuit.trial_successes = 0;
+    }
+
+    /// Whether the call may go through, and whether it is a trial call
+    fn acquire(&self) -> AppResult<bool> {
+        let mut circuit = self.inner.lock().unwrap();
+        self.refresh(&mut circuit);
+
+        match circuit.state {
+            CircuitState::Closed => Ok(false),
+            CircuitState::HalfOpen if circuit.trials < self.config.half_open_calls => {
+                circuit.trials += 1;
+                Ok(true)
+            }
+            _ => Err(AppMessage::from(Rejection::CircuitOpen(
+                self.name.to_string(),
+            ))),
+        }
+    }
+
+    fn record(&self, failed: bool, is_trial: bool) {
+        let mut circuit = self.inner.lock().unwrap();
+
+        match circuit.state {
+            CircuitState::HalfOpen if is_trial => {
+                circuit.trials = circuit.trials.saturating_sub(1);
+                if failed {
+                    self.open(&mut circuit);
+                    return;
+                }
+
+                circuit.trial_successes += 1;
+                if circuit.trial_successes >= self.config.half_open_calls {
+                    info!("[circuit-breaker][{}] closed", self.name);
+                    circuit.state = CircuitState::Closed;
+                    circuit.outcomes.clear();
+                    circuit.opened_at = None;
+                }
+            }
+            CircuitState::Closed => {
+                circuit.outcomes.push_back(failed);
+                while circuit.outcomes.len() > self.config.window {
+                    circuit.outcomes.pop_front();
+                }
+
+                if circuit.outcomes.len() >= self.config.minimum_calls
+                    && failure_rate(&circuit.outcomes) >= self.config.failure_rate
+                {
+                    self.open(&mut circuit);
+                }
+            }
+            // calls started before the circuit opened
+            _ => {}
+        }
+    }
+
+    fn open(&self, circuit: &mut Circuit) {
+        warn!(
+            "[circuit-breaker][{}] opened for {:?} (failure rate: {:.2})",
+            self.name,
+            self.config.open_for,
+            failure_rate(&circuit.outcomes)
+        );
+
+        circuit.state = CircuitState::Open;
+        circuit.opened_at = Some(Instant::now());
+        circuit.trials = 0;
+        circuit.trial_successes = 0;
+    }
+
+    /// Moves an open circuit to half-open once `open_for` has elapsed
+    fn refresh(&self, circuit: &mut Circuit) {
+        let elapsed = circuit
+            .opened_at
+            .is_some_and(|at| at.elapsed() >= self.config.open_for);
+
+        if circuit.state == CircuitState::Open && elapsed {
+            info!("[circuit-breaker][{}] half-open", self.name);
+            circuit.state = CircuitState::HalfOpen;
+            circuit.trials = 0;
+            circuit.trial_successes = 0;
+        }
+    }
+}
+
+impl Drop for Trial<'_> {
+    fn drop(&mut self) {
+        if !self.done {
+            let mut circuit = self.breaker.inner.lock().unwrap();
+            circuit.trials = circuit.trials.saturating_sub(1);
+        }
+    }
+}
+
+fn failure_rate(outcomes: &VecDeque<bool>) -> f64 {
+    match outcomes.is_empty() {
+        true => 0.0,
+        false => outcomes.iter().filter(|failed| **failed).count() as f64 / outcomes.len() as f64,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ntex::http::StatusCode;
+
+    use super::*;
+
+    async fn fail() -> AppResult<()> {
+        Err(AppMessage::InternalServerError)
+    }
+
+    async fn succeed() -> AppResult<()> {
+        Ok(())
+    }
+
+    fn config() -> CircuitBreakerConfig {
+        CircuitBreakerConfig::default()
+            .minimum_calls(4)
+            .window(4)
+            .half_open_calls(2)
+    }
+
+    #[tokio::test]
+    async fn test_opens_on_failure_rate() {
+        let breaker = CircuitBreaker::with_config("test-opens", config());
+
+        breaker.call(succeed()).await.unwrap();
+        breaker.call(succeed()).await.unwrap();
+        let _ = breaker.call(fail()).await;
+        assert_eq!(breaker.state(), CircuitState::Closed);
+
+        let _ = breaker.call(fail()).await;
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert_eq!(breaker.snapshot().failure_rate, 0.5);
+        assert_eq!(breaker.snapshot().recorded_calls, 4);
+
+        // fails fast without running the call
+        let err = breaker.call(succeed()).await.unwrap_err();
+        assert_eq!(err.status_code(), StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn test_client_errors_are_not_failures() {
+        let breaker = CircuitBreaker::with_config("test-client-errors", config());
+
+        for _ in 0..4 {
+            let _ = breaker
+                .call(async { Err::<(), _>(AppMessage::EntityNotFound("order".to_string())) })
+                .await;
+        }
+
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+
+    #[tokio::test]
+    async fn test_half_open() {
+        let config = config().open_for(Duration::ZERO);
+        let breaker = CircuitBreaker::with_config("test-half-open", config);
+
+        for _ in 0..4 {
+            let _ = breaker.call(fail()).await;
+        }
+
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+
+        // a failing trial opens the circuit again
+        let _ = breaker.call(fail()).await;
+        assert_eq!(breaker.inner.lock().unwrap().state, CircuitState::Open);
+
+        breaker.call(succeed()).await.unwrap();
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        breaker.call(succeed()).await.unwrap();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert_eq!(breaker.snapshot().recorded_calls, 0);
+    }
+
+    #[tokio::test]
+    async fn test_registry() {
+        let breaker = CircuitBreaker::new("test-registry");
+        breaker.call(succeed()).await.unwrap();
+
+        let registered = crate::resilience::circuit("test-registry").unwrap();
+        assert_eq!(registered.snapshot().recorded_calls, 1);
+    }
+}
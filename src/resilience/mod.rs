@@ -0,0 +1,151 @@
+use std::collections::BTreeMap;
+use std::sync::{LazyLock, RwLock};
+
+use log::Level;
+use ntex::http::StatusCode;
+use serde::Serialize;
+
+use crate::contracts::AppErrorContract;
+use crate::enums::ResponseCode;
+
+pub mod bulkhead;
+pub mod circuit_breaker;
+
+pub use bulkhead::{Bulkhead, BulkheadSnapshot};
+pub use circuit_breaker::{CircuitBreaker, CircuitBreakerConfig, CircuitSnapshot, CircuitState};
+
+/// Breakers and bulkheads created so far, reported by the health check
+static REGISTRY: LazyLock<RwLock<Registry>> = LazyLock::new(Default::default);
+
+#[derive(Default)]
+struct Registry {
+    circuits: BTreeMap<String, CircuitBreaker>,
+    bulkheads: BTreeMap<String, Bulkhead>,
+}
+
+/// A call rejected without reaching the dependency, rendered as `503 Service Unavailable`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Rejection {
+    CircuitOpen(String),
+    BulkheadFull(String),
+}
+
+/// State of every registered breaker and bulkhead
+#[derive(Clone, Debug, Serialize)]
+pub struct ResilienceReport {
+    /// `degraded` when a circuit isn't closed
+    pub status: &'static str,
+    pub circuits: Vec<CircuitSnapshot>,
+    pub bulkheads: Vec<BulkheadSnapshot>,
+}
+
+impl AppErrorContract for Rejection {
+    fn status(&self) -> StatusCode {
+        StatusCode::SERVICE_UNAVAILABLE
+    }
+
+    fn code(&self) -> ResponseCode {
+        ResponseCode::ServiceUnavailable
+    }
+
+    fn public_message(&self) -> Option<String> {
+        None
+    }
+
+    fn log_level(&self) -> Level {
+        Level::Warn
+    }
+
+    fn details(&self) -> Option<String> {
+        Some(match self {
+            Rejection::CircuitOpen(name) => format!("circuit '{}' is open", name),
+            Rejection::BulkheadFull(name) => format!("bulkhead '{}' is full", name),
+        })
+    }
+}
+
+pub fn circuit(name: &str) -> Option<CircuitBreaker> {
+    REGISTRY.read().unwrap().circuits.get(name).cloned()
+}
+
+pub fn bulkhead(name: &str) -> Option<Bulkhead> {
+    REGISTRY.read().unwrap().bulkheads.get(name).cloned()
+}
+
+pub fn report() -> ResilienceReport {
+    let registry = REGISTRY.read().unwrap();
+    let circuits: Vec<CircuitSnapshot> = registry.circuits.values().map(|c| c.snapshot()).collect();
+
+    let status = match circuits.iter().all(|c| c.state == CircuitState::Closed) {
+        true => "up",
+        false => "degraded",
+    };
+
+    ResilienceReport {
+        status,
+        circuits,
+        bulkheads: registry.bulkheads.values().map(|b| b.snapshot()).collect(),
+    }
+}
+
+/// Registers the breaker, replacing any other breaker of the same name
+fn register_circuit(breaker: &CircuitBreaker) {
+    REGISTRY
+        .write()
+        .unwrap()
+        .circuits
+        .insert(breaker.name().to_string(), breaker.clone());
+}
+
+fn register_bulkhead(bulkhead: &Bulkhead) {
+    REGISTRY
+        .write()
+        .unwrap()
+        .bulkheads
+        .insert(bulkhead.name().to_string(), bulkhead.clone());
+}
+
+#[cfg(test)]
+mod tests {
+    use ntex::web::test::{call_service, init_service, read_body, TestRequest};
+    use ntex::web::{self, App};
+    use serde_json::Value;
+
+    use super::*;
+    use crate::http::error_renderer::JsonErrorRenderer;
+    use crate::http::kernel::{health_check, HEALTH_CHECK_PATH};
+    use crate::prelude::AppMessage;
+
+    #[ntex::test]
+    async fn test_health_check() {
+        let config = CircuitBreakerConfig::default().minimum_calls(1);
+        let breaker = CircuitBreaker::with_config("test-health", config);
+        let _ = breaker
+            .call(async { Err::<(), _>(AppMessage::InternalServerError) })
+            .await;
+
+        Bulkhead::new("test-health", 4);
+
+        let app = init_service(
+            App::with(JsonErrorRenderer).route(HEALTH_CHECK_PATH, web::get().to(health_check)),
+        )
+        .await;
+
+        let request = TestRequest::with_uri(HEALTH_CHECK_PATH).to_request();
+        let response = call_service(&app, request).await;
+        assert!(response.status().is_success());
+
+        let body: Value = serde_json::from_slice(&read_body(response).await).unwrap();
+        assert_eq!(body["data"]["status"], "degraded");
+
+        let circuits = body["data"]["circuits"].as_array().unwrap();
+        let circuit = circuits
+            .iter()
+            .find(|c| c["name"] == "test-health")
+            .unwrap();
+        assert_eq!(circuit["state"], "open");
+
+        let bulkheads = body["data"]["bulkheads"].as_array().unwrap();
+        assert!(bulkheads.iter().any(|b| b["name"] == "test-health"));
+    }
+}
@@ -30,7 +30,15 @@ impl CacheService {
         self.redis.set(key, value).await
     }
 
-    pub async fn get<T: DeserializeOwned>(&mut self, key: &str) -> AppResult<Option<T>> {
+    /// Cache a value that expires after `ttl` seconds
+    pub async fn put_with_ttl<T>(&self, key: &str, value: &T, ttl: u64) -> AppResult<String>
+    where
+        T: Serialize,
+    {
+        self.redis.set_ex(key, value, ttl).await
+    }
+
+    pub async fn get<T: DeserializeOwned>(&self, key: &str) -> AppResult<Option<T>> {
         let data = self.redis.get::<Option<String>>(key).await?;
 
         match data {
@@ -45,6 +53,11 @@ impl CacheService {
         self.redis.delete(key).await
     }
 
+    /// Remove every cached entry whose key starts with `prefix`
+    pub async fn delete_by_prefix(&self, prefix: &str) -> AppResult<i32> {
+        self.redis.delete_by_prefix(prefix).await
+    }
+
     pub async fn get_or_put<Val, Fun, Fut>(&self, key: &str, setter: Fun) -> AppResult<Val>
     where
         Val: Serialize + DeserializeOwned + Clone,
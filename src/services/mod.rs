@@ -2,3 +2,5 @@
 pub mod cache_service;
 #[cfg(feature = "mailer")]
 pub mod mail_service;
+#[cfg(feature = "pat")]
+pub mod pat_service;
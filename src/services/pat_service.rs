@@ -0,0 +1,598 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Duration, Utc};
+use log::{debug, error};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use uuid::Uuid;
+
+use crate::helpers::hmac::Hmac;
+use crate::http::extractors::auth::AuthConfig;
+use crate::prelude::{AppMessage, AppResult};
+
+pub type PatFuture<'a, T> = Pin<Box<dyn Future<Output = AppResult<T>> + Send + 'a>>;
+
+/// Personal access token as persisted, the plain token is never stored
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PersonalAccessToken {
+    pub id: Uuid,
+    /// principal the token authenticates as, becomes the `sub` claim
+    pub subject: String,
+    pub name: String,
+    pub token_hash: String,
+    pub scopes: Vec<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub last_used_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A freshly generated token, `token` is only available at this point and must be shown to the user
+#[derive(Clone, Debug, Serialize)]
+pub struct IssuedPersonalAccessToken {
+    pub token: String,
+    pub record: PersonalAccessToken,
+}
+
+/// Where personal access tokens are persisted, looked up by the hash of the plain token
+pub trait PatStore: Send + Sync {
+    fn insert<'a>(&'a self, token: &'a PersonalAccessToken) -> PatFuture<'a, ()>;
+
+    fn find_by_hash<'a>(&'a self, hash: &'a str) -> PatFuture<'a, Option<PersonalAccessToken>>;
+
+    fn list<'a>(&'a self, subject: &'a str) -> PatFuture<'a, Vec<PersonalAccessToken>>;
+
+    /// Records when the token was last used
+    fn touch<'a>(&'a self, token: &'a PersonalAccessToken, at: DateTime<Utc>) -> PatFuture<'a, ()>;
+
+    /// Deletes the token, returns `false` when the subject has no such token
+    fn revoke<'a>(&'a self, subject: &'a str, id: Uuid) -> PatFuture<'a, bool>;
+}
+
+/// Issues and verifies personal access tokens
+///
+/// Tokens look like `{prefix}_{43 url-safe chars}`, the prefix comes from `{PREFIX}_AUTH_PAT_PREFIX`
+/// and is how [crate::http::extractors::auth::Auth] tells them apart from JWTs.
+/// Only the HMAC (keyed with the app key) of a token is stored.
+///
+/// The store is picked with `{PREFIX}_AUTH_PAT_STORE` (`redis`, `database` or `none`),
+/// personal access tokens are disabled when no store is available.
+pub struct PatService {
+    prefix: String,
+    hmac: Hmac,
+    store: Arc<dyn PatStore>,
+}
+
+impl PersonalAccessToken {
+    pub fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|at| at <= Utc::now())
+    }
+
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|s| s == scope || s == "*")
+    }
+
+    /// Claims of the principal, shaped like [crate::helpers::jwt::JwtTokenClaims] plus `scopes`
+    pub fn claims(&self, config: &AuthConfig, app_id: &str) -> Value {
+        json!({
+            "sub": self.subject,
+            "iat": self.created_at.timestamp(),
+            "exp": self.expires_at.map_or(u32::MAX as i64, |at| at.timestamp()),
+            "iss": config.issuer.as_deref().unwrap_or(app_id),
+            "aud": config.audience.first().map(String::as_str).unwrap_or(app_id),
+            "jti": self.id.to_string(),
+            "scopes": self.scopes,
+        })
+    }
+}
+
+impl PatService {
+    pub fn new(prefix: &str, app_key: &str, store: Arc<dyn PatStore>) -> Self {
+        PatService {
+            prefix: format!("{}_", prefix.trim_end_matches('_')),
+            hmac: Hmac::new(app_key),
+            store,
+        }
+    }
+
+    pub fn store(&self) -> &Arc<dyn PatStore> {
+        &self.store
+    }
+
+    /// Whether the token carries the personal access token prefix
+    pub fn is_pat(&self, token: &str) -> bool {
+        token.starts_with(&self.prefix)
+    }
+
+    pub fn hash(&self, token: &str) -> AppResult<String> {
+        self.hmac.hash(&token.to_string())
+    }
+
+    pub async fn issue(
+        &self,
+        subject: &str,
+        name: &str,
+        scopes: Vec<String>,
+        expires_at: Option<DateTime<Utc>>,
+    ) -> AppResult<IssuedPersonalAccessToken> {
+        let token = self.generate()?;
+        let record = PersonalAccessToken {
+            id: Uuid::new_v4(),
+            subject: subject.to_string(),
+            name: name.to_string(),
+            token_hash: self.hash(&token)?,
+            scopes,
+            expires_at,
+            last_used_at: None,
+            created_at: Utc::now(),
+        };
+
+        self.store.insert(&record).await?;
+        Ok(IssuedPersonalAccessToken { token, record })
+    }
+
+    /// Looks the token up, rejects unknown and expired ones and records its use
+    pub async fn verify(&self, token: &str) -> AppResult<PersonalAccessToken> {
+        let record = self.store.find_by_hash(&self.hash(token)?).await?.ok_or(
+            AppMessage::UnAuthorizedMessage("invalid personal access token"),
+        )?;
+
+        if record.is_expired() {
+            return Err(AppMessage::UnAuthorizedMessage(
+                "personal access token has expired",
+            ));
+        }
+
+        // writing on every request is wasteful, a minute of precision is enough
+        let now = Utc::now();
+        let is_stale = record
+            .last_used_at
+            .is_none_or(|at| now - at > Duration::minutes(1));
+
+        if is_stale {
+            if let Err(err) = self.store.touch(&record, now).await {
+                error!("[pat] failed to record use of {}: {:?}", record.id, err);
+            }
+        }
+
+        debug!("[pat] {} authenticated as {}", record.id, record.subject);
+        Ok(record)
+    }
+
+    pub async fn list(&self, subject: &str) -> AppResult<Vec<PersonalAccessToken>> {
+        self.store.list(subject).await
+    }
+
+    pub async fn revoke(&self, subject: &str, id: Uuid) -> AppResult<bool> {
+        self.store.revoke(subject, id).await
+    }
+
+    fn generate(&self) -> AppResult<String> {
+        let mut bytes = [0u8; 32];
+        getrandom::fill(&mut bytes).map_err(|err| {
+            error!("[pat] failed to generate a token: {}", err);
+            AppMessage::InternalServerErrorMessage("failed to generate personal access token")
+        })?;
+
+        Ok(format!("{}{}", self.prefix, URL_SAFE_NO_PAD.encode(bytes)))
+    }
+}
+
+#[cfg(feature = "redis")]
+pub use redis_store::RedisPatStore;
+
+#[cfg(feature = "redis")]
+mod redis_store {
+    use redis::{AsyncCommands, SetExpiry, SetOptions};
+
+    use super::*;
+    use crate::prelude::Redis;
+    use crate::results::redis_result::RedisResultToAppResult;
+
+    /// Stores tokens as `{namespace}:token:{hash}` json entries (expiring with the token),
+    /// indexed by `{namespace}:subject:{subject}` sets of hashes
+    pub struct RedisPatStore {
+        redis: Arc<Redis>,
+        namespace: String,
+    }
+
+    impl RedisPatStore {
+        pub fn new(redis: Arc<Redis>, namespace: &str) -> Self {
+            RedisPatStore {
+                redis,
+                namespace: namespace.to_string(),
+            }
+        }
+
+        fn token_key(&self, hash: &str) -> String {
+            format!("{}:token:{}", self.namespace, hash)
+        }
+
+        fn subject_key(&self, subject: &str) -> String {
+            format!("{}:subject:{}", self.namespace, subject)
+        }
+    }
+
+    impl PatStore for RedisPatStore {
+        fn insert<'a>(&'a self, token: &'a PersonalAccessToken) -> PatFuture<'a, ()> {
+            Box::pin(async move {
+                let content = serde_json::to_string(token)?;
+                let mut conn = self.redis.redis().await?;
+
+                let options = match token.expires_at {
+                    Some(at) => SetOptions::default()
+                        .with_expiration(SetExpiry::EXAT(at.timestamp().max(0) as u64)),
+                    None => SetOptions::default(),
+                };
+
+                conn.set_options::<_, _, ()>(self.token_key(&token.token_hash), content, options)
+                    .await
+                    .into_app_result()?;
+
+                conn.sadd::<_, _, ()>(self.subject_key(&token.subject), &token.token_hash)
+                    .await
+                    .into_app_result()
+            })
+        }
+
+        fn find_by_hash<'a>(&'a self, hash: &'a str) -> PatFuture<'a, Option<PersonalAccessToken>> {
+            Box::pin(async move {
+                let content: Option<String> = self.redis.get(&self.token_key(hash)).await?;
+                match content {
+                    None => Ok(None),
+                    Some(content) => Ok(Some(serde_json::from_str(&content)?)),
+                }
+            })
+        }
+
+        fn list<'a>(&'a self, subject: &'a str) -> PatFuture<'a, Vec<PersonalAccessToken>> {
+            Box::pin(async move {
+                let mut conn = self.redis.redis().await?;
+                let hashes: Vec<String> = conn
+                    .smembers::<_, Vec<String>>(self.subject_key(subject))
+                    .await
+                    .into_app_result()?;
+
+                let mut tokens = vec![];
+                for hash in hashes {
+                    match self.find_by_hash(&hash).await? {
+                        Some(token) => tokens.push(token),
+                        // expired, drop it from the index
+                        None => conn
+                            .srem::<_, _, ()>(self.subject_key(subject), &hash)
+                            .await
+                            .into_app_result()?,
+                    }
+                }
+
+                tokens.sort_by_key(|token| token.created_at);
+                Ok(tokens)
+            })
+        }
+
+        fn touch<'a>(
+            &'a self,
+            token: &'a PersonalAccessToken,
+            at: DateTime<Utc>,
+        ) -> PatFuture<'a, ()> {
+            Box::pin(async move {
+                let mut token = token.clone();
+                token.last_used_at = Some(at);
+
+                let content = serde_json::to_string(&token)?;
+                let options = SetOptions::default().with_expiration(SetExpiry::KEEPTTL);
+                let mut conn = self.redis.redis().await?;
+                conn.set_options::<_, _, ()>(self.token_key(&token.token_hash), content, options)
+                    .await
+                    .into_app_result()
+            })
+        }
+
+        fn revoke<'a>(&'a self, subject: &'a str, id: Uuid) -> PatFuture<'a, bool> {
+            Box::pin(async move {
+                let token = self.list(subject).await?.into_iter().find(|t| t.id == id);
+                let token = match token {
+                    Some(token) => token,
+                    None => return Ok(false),
+                };
+
+                let mut conn = self.redis.redis().await?;
+                conn.del::<_, ()>(self.token_key(&token.token_hash))
+                    .await
+                    .into_app_result()?;
+
+                conn.srem::<_, _, ()>(self.subject_key(subject), &token.token_hash)
+                    .await
+                    .into_app_result()?;
+
+                Ok(true)
+            })
+        }
+    }
+}
+
+#[cfg(feature = "database")]
+pub use database_store::DatabasePatStore;
+
+#[cfg(feature = "database")]
+mod database_store {
+    use diesel::sql_types::{Array, Nullable, Text, Timestamptz, Uuid as SqlUuid};
+    use diesel::{sql_query, QueryableByName, RunQueryDsl};
+
+    use super::*;
+    use crate::database::{DBPool, DatabaseConnectionHelper, OptionalResult};
+    use crate::tokio::Tokio;
+
+    /// Stores tokens in a Postgres table (`personal_access_tokens` by default):
+    ///
+    /// ```sql
+    /// CREATE TABLE personal_access_tokens
+    /// (
+    ///     id           UUID PRIMARY KEY,
+    ///     subject      TEXT        NOT NULL,
+    ///     name         TEXT        NOT NULL,
+    ///     token_hash   TEXT        NOT NULL UNIQUE,
+    ///     scopes       TEXT[]      NOT NULL DEFAULT '{}',
+    ///     expires_at   TIMESTAMPTZ NULL,
+    ///     last_used_at TIMESTAMPTZ NULL,
+    ///     created_at   TIMESTAMPTZ NOT NULL DEFAULT NOW()
+    /// );
+    ///
+    /// CREATE INDEX personal_access_tokens_subject_index ON personal_access_tokens (subject);
+    /// ```
+    pub struct DatabasePatStore {
+        pool: DBPool,
+        table: String,
+    }
+
+    #[derive(QueryableByName)]
+    struct PatRow {
+        #[diesel(sql_type = SqlUuid)]
+        id: Uuid,
+        #[diesel(sql_type = Text)]
+        subject: String,
+        #[diesel(sql_type = Text)]
+        name: String,
+        #[diesel(sql_type = Text)]
+        token_hash: String,
+        #[diesel(sql_type = Array<Text>)]
+        scopes: Vec<String>,
+        #[diesel(sql_type = Nullable<Timestamptz>)]
+        expires_at: Option<DateTime<Utc>>,
+        #[diesel(sql_type = Nullable<Timestamptz>)]
+        last_used_at: Option<DateTime<Utc>>,
+        #[diesel(sql_type = Timestamptz)]
+        created_at: DateTime<Utc>,
+    }
+
+    impl From<PatRow> for PersonalAccessToken {
+        fn from(row: PatRow) -> Self {
+            PersonalAccessToken {
+                id: row.id,
+                subject: row.subject,
+                name: row.name,
+                token_hash: row.token_hash,
+                scopes: row.scopes,
+                expires_at: row.expires_at,
+                last_used_at: row.last_used_at,
+                created_at: row.created_at,
+            }
+        }
+    }
+
+    impl DatabasePatStore {
+        pub fn new(pool: DBPool) -> Self {
+            Self::with_table(pool, "personal_access_tokens")
+        }
+
+        pub fn with_table(pool: DBPool, table: &str) -> Self {
+            DatabasePatStore {
+                pool,
+                table: table.to_string(),
+            }
+        }
+
+        async fn run<F, R>(&self, func: F) -> AppResult<R>
+        where
+            F: FnOnce(&DBPool, &str) -> AppResult<R> + Send + 'static,
+            R: Send + 'static,
+        {
+            let (pool, table) = (self.pool.clone(), self.table.clone());
+            Tokio::blk(move || func(&pool, &table)).await?
+        }
+    }
+
+    const COLUMNS: &str =
+        "id, subject, name, token_hash, scopes, expires_at, last_used_at, created_at";
+
+    impl PatStore for DatabasePatStore {
+        fn insert<'a>(&'a self, token: &'a PersonalAccessToken) -> PatFuture<'a, ()> {
+            let token = token.clone();
+            Box::pin(self.run(move |pool, table| {
+                sql_query(format!(
+                    "INSERT INTO {} ({}) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+                    table, COLUMNS
+                ))
+                .bind::<SqlUuid, _>(token.id)
+                .bind::<Text, _>(token.subject)
+                .bind::<Text, _>(token.name)
+                .bind::<Text, _>(token.token_hash)
+                .bind::<Array<Text>, _>(token.scopes)
+                .bind::<Nullable<Timestamptz>, _>(token.expires_at)
+                .bind::<Nullable<Timestamptz>, _>(token.last_used_at)
+                .bind::<Timestamptz, _>(token.created_at)
+                .execute(&mut pool.connection()?)?;
+
+                Ok(())
+            }))
+        }
+
+        fn find_by_hash<'a>(&'a self, hash: &'a str) -> PatFuture<'a, Option<PersonalAccessToken>> {
+            let hash = hash.to_string();
+            Box::pin(self.run(move |pool, table| {
+                let row = sql_query(format!(
+                    "SELECT {} FROM {} WHERE token_hash = $1",
+                    COLUMNS, table
+                ))
+                .bind::<Text, _>(hash)
+                .get_result::<PatRow>(&mut pool.connection()?)
+                .optional()?;
+
+                Ok(row.map(PersonalAccessToken::from))
+            }))
+        }
+
+        fn list<'a>(&'a self, subject: &'a str) -> PatFuture<'a, Vec<PersonalAccessToken>> {
+            let subject = subject.to_string();
+            Box::pin(self.run(move |pool, table| {
+                let rows = sql_query(format!(
+                    "SELECT {} FROM {} WHERE subject = $1 ORDER BY created_at",
+                    COLUMNS, table
+                ))
+                .bind::<Text, _>(subject)
+                .load::<PatRow>(&mut pool.connection()?)?;
+
+                Ok(rows.into_iter().map(PersonalAccessToken::from).collect())
+            }))
+        }
+
+        fn touch<'a>(
+            &'a self,
+            token: &'a PersonalAccessToken,
+            at: DateTime<Utc>,
+        ) -> PatFuture<'a, ()> {
+            let id = token.id;
+            Box::pin(self.run(move |pool, table| {
+                sql_query(format!(
+                    "UPDATE {} SET last_used_at = $1 WHERE id = $2",
+                    table
+                ))
+                .bind::<Timestamptz, _>(at)
+                .bind::<SqlUuid, _>(id)
+                .execute(&mut pool.connection()?)?;
+
+                Ok(())
+            }))
+        }
+
+        fn revoke<'a>(&'a self, subject: &'a str, id: Uuid) -> PatFuture<'a, bool> {
+            let subject = subject.to_string();
+            Box::pin(self.run(move |pool, table| {
+                let deleted = sql_query(format!(
+                    "DELETE FROM {} WHERE id = $1 AND subject = $2",
+                    table
+                ))
+                .bind::<SqlUuid, _>(id)
+                .bind::<Text, _>(subject)
+                .execute(&mut pool.connection()?)?;
+
+                Ok(deleted > 0)
+            }))
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    #[derive(Default)]
+    pub(crate) struct MemoryPatStore {
+        tokens: Mutex<Vec<PersonalAccessToken>>,
+    }
+
+    impl PatStore for MemoryPatStore {
+        fn insert<'a>(&'a self, token: &'a PersonalAccessToken) -> PatFuture<'a, ()> {
+            self.tokens.lock().unwrap().push(token.clone());
+            Box::pin(async { Ok(()) })
+        }
+
+        fn find_by_hash<'a>(&'a self, hash: &'a str) -> PatFuture<'a, Option<PersonalAccessToken>> {
+            let tokens = self.tokens.lock().unwrap();
+            let token = tokens.iter().find(|t| t.token_hash == hash).cloned();
+            Box::pin(async { Ok(token) })
+        }
+
+        fn list<'a>(&'a self, subject: &'a str) -> PatFuture<'a, Vec<PersonalAccessToken>> {
+            let tokens = self.tokens.lock().unwrap();
+            let tokens = tokens
+                .iter()
+                .filter(|t| t.subject == subject)
+                .cloned()
+                .collect();
+            Box::pin(async { Ok(tokens) })
+        }
+
+        fn touch<'a>(
+            &'a self,
+            token: &'a PersonalAccessToken,
+            at: DateTime<Utc>,
+        ) -> PatFuture<'a, ()> {
+            let mut tokens = self.tokens.lock().unwrap();
+            if let Some(token) = tokens.iter_mut().find(|t| t.id == token.id) {
+                token.last_used_at = Some(at);
+            }
+            Box::pin(async { Ok(()) })
+        }
+
+        fn revoke<'a>(&'a self, subject: &'a str, id: Uuid) -> PatFuture<'a, bool> {
+            let mut tokens = self.tokens.lock().unwrap();
+            let count = tokens.len();
+            tokens.retain(|t| t.id != id || t.subject != subject);
+            let revoked = tokens.len() != count;
+            Box::pin(async move { Ok(revoked) })
+        }
+    }
+
+    pub(crate) fn service() -> PatService {
+        PatService::new("mdl", "app-key", Arc::new(MemoryPatStore::default()))
+    }
+
+    #[tokio::test]
+    async fn test_issue_and_verify() {
+        let service = service();
+        let issued = service
+            .issue("user-1", "ci", vec!["repo:read".to_string()], None)
+            .await
+            .unwrap();
+
+        assert!(service.is_pat(&issued.token));
+        assert_eq!(issued.token.len(), "mdl_".len() + 43);
+        assert_ne!(issued.record.token_hash, issued.token);
+
+        let record = service.verify(&issued.token).await.unwrap();
+        assert_eq!(record.subject, "user-1");
+        assert!(record.has_scope("repo:read"));
+        assert!(!record.has_scope("repo:write"));
+
+        let listed = service.list("user-1").await.unwrap();
+        assert!(listed[0].last_used_at.is_some());
+
+        let err = service.verify("mdl_unknown").await.unwrap_err();
+        assert!(matches!(err, AppMessage::UnAuthorizedMessage(_)));
+
+        assert!(service.revoke("user-1", record.id).await.unwrap());
+        assert!(service.verify(&issued.token).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_expired_tokens_are_rejected() {
+        let service = service();
+        let expired_at = Utc::now() - Duration::seconds(1);
+        let issued = service
+            .issue("user-1", "ci", vec![], Some(expired_at))
+            .await
+            .unwrap();
+
+        let err = service.verify(&issued.token).await.unwrap_err();
+        assert!(matches!(
+            err,
+            AppMessage::UnAuthorizedMessage("personal access token has expired")
+        ));
+    }
+}
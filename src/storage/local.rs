@@ -0,0 +1,71 @@
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+use log::debug;
+use ntex::util::Bytes;
+use tokio::fs;
+
+use crate::storage::{validate_key, Storage, StorageFuture, StoredFile};
+
+/// Keeps files on the local filesystem, under `root`
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        LocalStorage { root: root.into() }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn path(&self, key: &str) -> PathBuf {
+        self.root.join(key)
+    }
+}
+
+impl Storage for LocalStorage {
+    fn put<'a>(&'a self, source: &'a Path, file: &'a StoredFile) -> StorageFuture<'a, ()> {
+        Box::pin(async move {
+            validate_key(&file.key)?;
+
+            let destination = self.path(&file.key);
+            if let Some(parent) = destination.parent() {
+                fs::create_dir_all(parent).await?;
+            }
+
+            // renaming fails when the temp dir is on another device
+            if fs::rename(source, &destination).await.is_err() {
+                fs::copy(source, &destination).await?;
+                fs::remove_file(source).await?;
+            }
+
+            debug!("[storage] stored {}", destination.display());
+            Ok(())
+        })
+    }
+
+    fn get<'a>(&'a self, key: &'a str) -> StorageFuture<'a, Option<Bytes>> {
+        Box::pin(async move {
+            validate_key(key)?;
+            match fs::read(self.path(key)).await {
+                Ok(content) => Ok(Some(Bytes::from(content))),
+                Err(err) if err.kind() == ErrorKind::NotFound => Ok(None),
+                Err(err) => Err(err.into()),
+            }
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> StorageFuture<'a, bool> {
+        Box::pin(async move {
+            validate_key(key)?;
+            match fs::remove_file(self.path(key)).await {
+                Ok(_) => Ok(true),
+                Err(err) if err.kind() == ErrorKind::NotFound => Ok(false),
+                Err(err) => Err(err.into()),
+            }
+        })
+    }
+}
@@ -0,0 +1,81 @@
+use std::env;
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use ntex::util::Bytes;
+use serde::{Deserialize, Serialize};
+
+use crate::helpers::fs::base_path;
+use crate::results::AppResult;
+
+pub mod local;
+pub mod s3;
+
+pub use local::LocalStorage;
+pub use s3::{S3Config, S3Storage};
+
+pub type StorageFuture<'a, T> = Pin<Box<dyn Future<Output = AppResult<T>> + 'a>>;
+
+/// Metadata of a file that has been persisted to a [Storage]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StoredFile {
+    /// location of the file within the storage
+    pub key: String,
+    /// form field the file was uploaded with
+    pub field: String,
+    /// name the client gave the file
+    pub file_name: Option<String>,
+    /// size in bytes
+    pub size: u64,
+    /// hex encoded sha256 of the content
+    pub checksum: String,
+    /// sniffed from the content
+    pub content_type: String,
+}
+
+/// Where uploaded files end up
+pub trait Storage: Send + Sync {
+    /// Moves the (temporary) file at `source` to `file.key`
+    fn put<'a>(&'a self, source: &'a Path, file: &'a StoredFile) -> StorageFuture<'a, ()>;
+
+    fn get<'a>(&'a self, key: &'a str) -> StorageFuture<'a, Option<Bytes>>;
+
+    /// Returns `false` when there was nothing to delete
+    fn delete<'a>(&'a self, key: &'a str) -> StorageFuture<'a, bool>;
+}
+
+/// Storage picked by `{PREFIX}_STORAGE_DRIVER`:
+/// - `local` (default): files are kept under `{PREFIX}_STORAGE_ROOT` (`storage` by default),
+///   relative to [base_path]
+/// - `s3`: any S3-compatible service, see [S3Config::from_env]
+pub fn make_storage(env_prefix: &str) -> Arc<dyn Storage> {
+    let var = |name: &str| env::var(format!("{}_STORAGE_{}", env_prefix, name)).ok();
+
+    match var("DRIVER").as_deref().unwrap_or("local") {
+        "local" => Arc::new(LocalStorage::new(base_path(
+            var("ROOT").unwrap_or_else(|| "storage".to_string()),
+        ))),
+        "s3" => Arc::new(S3Storage::new(S3Config::from_env(env_prefix))),
+        driver => panic!("unsupported storage driver '{}'", driver),
+    }
+}
+
+/// Rejects keys that could escape the storage root
+pub(crate) fn validate_key(key: &str) -> AppResult<()> {
+    let is_valid = !key.is_empty()
+        && !key.starts_with('/')
+        && !key.contains('\\')
+        && key
+            .split('/')
+            .all(|s| !s.is_empty() && s != "." && s != "..");
+
+    match is_valid {
+        true => Ok(()),
+        false => Err(crate::prelude::AppMessage::ErrorMessage(
+            format!("invalid storage key '{}'", key),
+            ntex::http::StatusCode::BAD_REQUEST,
+        )),
+    }
+}
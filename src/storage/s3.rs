@@ -0,0 +1,384 @@
+use std::env;
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use futures_util::stream;
+use hmac::{Hmac, Mac};
+use log::{debug, error};
+use ntex::http::StatusCode;
+use ntex::util::Bytes;
+use reqwest::header::{HeaderMap, HeaderValue, CONTENT_LENGTH, CONTENT_TYPE};
+use reqwest::{Body, Client, Method, Response, Url};
+use sha2::{Digest, Sha256};
+use tokio::io::AsyncReadExt;
+
+use crate::prelude::{AppMessage, AppResult};
+use crate::storage::{validate_key, Storage, StorageFuture, StoredFile};
+
+/// sha256 of an empty body
+const EMPTY_PAYLOAD_HASH: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+#[derive(Clone, Debug)]
+pub struct S3Config {
+    /// e.g. `https://s3.eu-west-1.amazonaws.com` or `http://localhost:9000` for MinIO
+    pub endpoint: String,
+    pub region: String,
+    pub bucket: String,
+    pub access_key: String,
+    pub secret_key: String,
+    /// address the bucket as `{endpoint}/{bucket}` instead of `{bucket}.{endpoint host}`,
+    /// which is what MinIO and most self-hosted services expect
+    pub path_style: bool,
+}
+
+/// Keeps files in an S3-compatible bucket, requests are signed with AWS Signature Version 4
+pub struct S3Storage {
+    config: S3Config,
+    client: Client,
+}
+
+impl S3Config {
+    /// Reads `{PREFIX}_S3_ENDPOINT`, `{PREFIX}_S3_REGION`, `{PREFIX}_S3_BUCKET`,
+    /// `{PREFIX}_S3_ACCESS_KEY`, `{PREFIX}_S3_SECRET_KEY` and `{PREFIX}_S3_PATH_STYLE` (defaults to true)
+    pub fn from_env(env_prefix: &str) -> Self {
+        let var = |name: &str| env::var(format!("{}_S3_{}", env_prefix, name));
+
+        S3Config {
+            endpoint: var("ENDPOINT").unwrap(),
+            region: var("REGION").unwrap_or_else(|_| "us-east-1".to_string()),
+            bucket: var("BUCKET").unwrap(),
+            access_key: var("ACCESS_KEY").unwrap(),
+            secret_key: var("SECRET_KEY").unwrap(),
+            path_style: var("PATH_STYLE").map(|v| v == "true").unwrap_or(true),
+        }
+    }
+
+    pub fn object_url(&self, key: &str) -> AppResult<Url> {
+        let endpoint = self.endpoint.trim_end_matches('/');
+        let url = match self.path_style {
+            true => format!("{}/{}/{}", endpoint, self.bucket, uri_encode(key, false)),
+            false => {
+                let (scheme, host) = endpoint.split_once("://").unwrap_or(("https", endpoint));
+                format!(
+                    "{}://{}.{}/{}",
+                    scheme,
+                    self.bucket,
+                    host,
+                    uri_encode(key, false)
+                )
+            }
+        };
+
+        Url::parse(&url).map_err(|err| {
+            AppMessage::ErrorMessage(
+                format!("invalid s3 url '{}': {}", url, err),
+                StatusCode::INTERNAL_SERVER_ERROR,
+            )
+        })
+    }
+
+    /// Headers authenticating the request, `payload_hash` is the hex sha256 of the body
+    pub fn sign(
+        &self,
+        method: &Method,
+        url: &Url,
+        payload_hash: &str,
+        now: DateTime<Utc>,
+    ) -> HeaderMap {
+        let amz_date = now.format("%Y%m%dT%H%M%SZ").to_string();
+        let date = now.format("%Y%m%d").to_string();
+
+        let host = match url.port() {
+            Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+            None => url.host_str().unwrap_or_default().to_string(),
+        };
+
+        let canonical_request = format!(
+            "{}\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+            method.as_str(),
+            url.path(),
+            host,
+            payload_hash,
+            amz_date,
+            SIGNED_HEADERS,
+            payload_hash
+        );
+
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+            amz_date,
+            scope,
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+
+        let key = signing_key(&self.secret_key, &date, &self.region, "s3");
+        let signature = hex::encode(hmac_sha256(&key, string_to_sign.as_bytes()));
+
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            self.access_key, scope, SIGNED_HEADERS, signature
+        );
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-amz-date", header_value(&amz_date));
+        headers.insert("x-amz-content-sha256", header_value(payload_hash));
+        headers.insert("authorization", header_value(&authorization));
+        headers
+    }
+}
+
+const SIGNED_HEADERS: &str = "host;x-amz-content-sha256;x-amz-date";
+
+impl S3Storage {
+    pub fn new(config: S3Config) -> Self {
+        S3Storage {
+            config,
+            client: Client::new(),
+        }
+    }
+
+    pub fn config(&self) -> &S3Config {
+        &self.config
+    }
+
+    async fn send(
+        &self,
+        method: Method,
+        key: &str,
+        payload_hash: &str,
+        extra_headers: HeaderMap,
+        body: Option<Body>,
+    ) -> AppResult<Response> {
+        validate_key(key)?;
+
+        let url = self.config.object_url(key)?;
+        let headers = self.config.sign(&method, &url, payload_hash, Utc::now());
+
+        debug!("[s3] {} {}", method, url);
+        let mut request = self
+            .client
+            .request(method, url)
+            .headers(headers)
+            .headers(extra_headers);
+
+        if let Some(body) = body {
+            request = request.body(body);
+        }
+
+        Ok(request.send().await?)
+    }
+}
+
+impl Storage for S3Storage {
+    fn put<'a>(&'a self, source: &'a Path, file: &'a StoredFile) -> StorageFuture<'a, ()> {
+        Box::pin(async move {
+            let reader = tokio::fs::File::open(source).await?;
+            let body = Body::wrap_stream(stream::unfold(reader, |mut reader| async move {
+                let mut buffer = vec![0u8; 64 * 1024];
+                match reader.read(&mut buffer).await {
+                    Ok(0) => None,
+                    Ok(read) => {
+                        buffer.truncate(read);
+                        Some((Ok(buffer), reader))
+                    }
+                    Err(err) => Some((Err(err), reader)),
+                }
+            }));
+
+            let mut headers = HeaderMap::new();
+            headers.insert(CONTENT_LENGTH, HeaderValue::from(file.size));
+            headers.insert(CONTENT_TYPE, header_value(&file.content_type));
+
+            // the checksum is the sha256 of the content, which is what the signature needs
+            let response = self
+                .send(Method::PUT, &file.key, &file.checksum, headers, Some(body))
+                .await?;
+
+            ensure_success(response, &file.key).await?;
+            tokio::fs::remove_file(source).await?;
+            Ok(())
+        })
+    }
+
+    fn get<'a>(&'a self, key: &'a str) -> StorageFuture<'a, Option<Bytes>> {
+        Box::pin(async move {
+            let response = self
+                .send(Method::GET, key, EMPTY_PAYLOAD_HASH, HeaderMap::new(), None)
+                .await?;
+
+            if response.status() == reqwest::StatusCode::NOT_FOUND {
+                return Ok(None);
+            }
+
+            let response = ensure_success(response, key).await?;
+            Ok(Some(Bytes::from(response.bytes().await?.to_vec())))
+        })
+    }
+
+    fn delete<'a>(&'a self, key: &'a str) -> StorageFuture<'a, bool> {
+        Box::pin(async move {
+            let response = self
+                .send(
+                    Method::DELETE,
+                    key,
+                    EMPTY_PAYLOAD_HASH,
+                    HeaderMap::new(),
+                    None,
+                )
+                .await?;
+
+            // S3 answers 204 whether the object existed or not
+            ensure_success(response, key).await?;
+            Ok(true)
+        })
+    }
+}
+
+async fn ensure_success(response: Response, key: &str) -> AppResult<Response> {
+    if response.status().is_success() {
+        return Ok(response);
+    }
+
+    let status = response.status();
+    let body = response.text().await.unwrap_or_default();
+    error!(
+        "[s3] request for '{}' failed with {}: {}",
+        key, status, body
+    );
+
+    Err(AppMessage::ErrorMessage(
+        format!("storage request failed with status {}", status.as_u16()),
+        StatusCode::BAD_GATEWAY,
+    ))
+}
+
+fn signing_key(secret: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let key = hmac_sha256(format!("AWS4{}", secret).as_bytes(), date.as_bytes());
+    let key = hmac_sha256(&key, region.as_bytes());
+    let key = hmac_sha256(&key, service.as_bytes());
+    hmac_sha256(&key, b"aws4_request")
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac accepts keys of any size");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn header_value(value: &str) -> HeaderValue {
+    HeaderValue::from_str(value).expect("invalid header value")
+}
+
+/// Percent-encodes everything but unreserved characters (and `/` unless `encode_slash`)
+fn uri_encode(value: &str, encode_slash: bool) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            b'/' if !encode_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn config(path_style: bool) -> S3Config {
+        S3Config {
+            endpoint: "http://localhost:9000".to_string(),
+            region: "us-east-1".to_string(),
+            bucket: "uploads".to_string(),
+            access_key: "minio".to_string(),
+            secret_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+            path_style,
+        }
+    }
+
+    #[test]
+    fn test_signing_key() {
+        // example from the AWS Signature Version 4 documentation
+        let key = signing_key(
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            "20150830",
+            "us-east-1",
+            "iam",
+        );
+
+        assert_eq!(
+            hex::encode(key),
+            "c4afb1cc5771d871763a393e44b703571b55cc28424d1a5e86da6ed3c154a4b9"
+        );
+    }
+
+    #[test]
+    fn test_object_url() {
+        let url = config(true).object_url("avatars/my photo.png").unwrap();
+        assert_eq!(
+            url.as_str(),
+            "http://localhost:9000/uploads/avatars/my%20photo.png"
+        );
+
+        let url = config(false).object_url("a.png").unwrap();
+        assert_eq!(url.as_str(), "http://uploads.localhost:9000/a.png");
+    }
+
+    #[test]
+    fn test_sign() {
+        let config = config(true);
+        let url = config.object_url("a.png").unwrap();
+        let now = Utc.with_ymd_and_hms(2025, 1, 2, 3, 4, 5).unwrap();
+        let headers = config.sign(&Method::GET, &url, EMPTY_PAYLOAD_HASH, now);
+
+        assert_eq!(headers["x-amz-date"], "20250102T030405Z");
+        let authorization = headers["authorization"].to_str().unwrap();
+        assert!(authorization.starts_with(
+            "AWS4-HMAC-SHA256 Credential=minio/20250102/us-east-1/s3/aws4_request, \
+             SignedHeaders=host;x-amz-content-sha256;x-amz-date, Signature="
+        ));
+
+        // same input, same signature
+        let again = config.sign(&Method::GET, &url, EMPTY_PAYLOAD_HASH, now);
+        assert_eq!(again["authorization"], headers["authorization"]);
+    }
+
+    /// Against a MinIO server configured through `MINIO_S3_*`, see [S3Config::from_env]:
+    ///
+    /// `MINIO_S3_ENDPOINT=http://localhost:9000 MINIO_S3_BUCKET=uploads MINIO_S3_ACCESS_KEY=minio
+    /// MINIO_S3_SECRET_KEY=minio-secret cargo test --features multipart minio -- --ignored`
+    #[tokio::test]
+    #[ignore = "needs a MinIO server"]
+    async fn test_minio_round_trip() {
+        let storage = S3Storage::new(S3Config::from_env("MINIO"));
+
+        let content = b"hello from medullah";
+        let source = env::temp_dir().join(format!("medullah-s3-{}", uuid::Uuid::new_v4()));
+        std::fs::write(&source, content).unwrap();
+
+        let file = StoredFile {
+            key: format!("tests/{}/hello world.txt", uuid::Uuid::new_v4()),
+            field: "file".to_string(),
+            file_name: Some("hello world.txt".to_string()),
+            size: content.len() as u64,
+            checksum: hex::encode(Sha256::digest(content)),
+            content_type: "text/plain".to_string(),
+        };
+
+        storage.put(&source, &file).await.unwrap();
+        assert!(!source.exists());
+
+        let stored = storage.get(&file.key).await.unwrap();
+        assert_eq!(stored.as_deref(), Some(&content[..]));
+
+        assert!(storage.delete(&file.key).await.unwrap());
+        assert_eq!(storage.get(&file.key).await.unwrap(), None);
+    }
+}